@@ -0,0 +1,93 @@
+//! How the engine copes with long snakes.  Collisions and free cells are
+//! looked up on grids, so a tick with a snake of 100,000 cells should
+//! take about as long as one with a snake of ten.
+//!
+//! ```text
+//! cargo bench
+//! ```
+
+#[macro_use]
+extern crate criterion;
+extern crate snek_engine;
+
+use criterion::{BenchmarkId, Criterion};
+
+use snek_engine::{Direction, GameState};
+
+/// Room for the longest snake, with some to spare.
+const SIZE: usize = 320;
+
+const LENGTHS: [usize; 4] = [10, 1_000, 10_000, 100_000];
+
+/// Which way to go from `pos` to follow a cycle through every cell of
+/// the arena: east and west along the rows, leaving out the first
+/// column, then back north up it.  `SIZE` is even, so the last row
+/// heads west into the first column.
+fn cycle(pos: (usize, usize)) -> Direction {
+    let (x, y) = pos;
+    if x == 0 {
+        if y == 0 { Direction::East } else { Direction::North }
+    } else if y.is_multiple_of(2) {
+        if x == SIZE - 1 { Direction::South } else { Direction::East }
+    } else if x == 1 && y != SIZE - 1 {
+        Direction::South
+    } else {
+        Direction::West
+    }
+}
+
+/// Move the snake one cell along the cycle, which it never leaves and so
+/// never runs into itself on.
+fn step(state: &mut GameState) {
+    let head = state.get_snake().positions().next().unwrap();
+    state.set_user_direction(cycle(head));
+    state.tick().unwrap();
+}
+
+/// A game with a snake `length` cells long and no food to grow it any
+/// longer.
+fn long_snake(length: usize) -> GameState {
+    let mut state = GameState::with_seed(SIZE, SIZE, 1);
+    state.set_food_target(0);
+    state.set_force_grow(true);
+    while state.get_snake().len() < length {
+        step(&mut state);
+    }
+    state.set_force_grow(false);
+    state
+}
+
+fn tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick");
+    for &length in LENGTHS.iter() {
+        let mut state = long_snake(length);
+        group.bench_function(BenchmarkId::from_parameter(length), |b| {
+            b.iter(|| step(&mut state))
+        });
+    }
+    group.finish();
+}
+
+/// Asking whether a cell is taken, against walking the body to find out
+/// as ticks used to.  The cell asked about is just behind the tail, so a
+/// walk has to go all the way.
+fn occupancy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("occupancy");
+    for &length in LENGTHS.iter() {
+        let state = long_snake(length);
+        let snake = state.get_snake();
+        let tail = snake.positions().last().unwrap();
+        let behind = (tail.0 - 1, tail.1);
+
+        group.bench_function(BenchmarkId::new("grid", length), |b| {
+            b.iter(|| state.occupant(criterion::black_box(behind)))
+        });
+        group.bench_function(BenchmarkId::new("walk", length), |b| {
+            b.iter(|| snake.positions().any(|pos| pos == criterion::black_box(behind)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tick, occupancy);
+criterion_main!(benches);
@@ -0,0 +1,234 @@
+"""Python bindings for snek_engine, through its C API.
+
+Build the library first with `cargo build` in this directory, or point
+SNEK_ENGINE_CAPI at a copy built elsewhere.
+
+    import snek
+
+    game = snek.Game(32, 32, seed=42)
+    game.set_direction(0, snek.Direction.SOUTH)
+    while game.tick() == snek.Status.PLAYING:
+        print(game.snake(0))
+"""
+
+import ctypes
+import enum
+import os
+from ctypes import POINTER, byref, c_bool, c_char, c_char_p, c_size_t, c_uint8, c_uint32, c_uint64, c_void_p
+
+_LIBRARY = os.environ.get(
+    'SNEK_ENGINE_CAPI',
+    os.path.join(os.path.dirname(os.path.abspath(__file__)), 'target', 'debug', 'libsnek_engine_capi.so'))
+
+snek_capi = ctypes.CDLL(_LIBRARY)
+
+
+class Slice(ctypes.Structure):
+    _fields_ = [
+        ("ptr", POINTER(c_char)),
+        ("len", c_size_t),
+    ]
+
+    def as_string(self):
+        return ctypes.string_at(self.ptr, self.len).decode('utf-8')
+
+
+class Cell(ctypes.Structure):
+    _fields_ = [
+        ("x", c_uint32),
+        ("y", c_uint32),
+    ]
+
+
+class Object(ctypes.Structure):
+    _fields_ = [
+        ("x", c_uint32),
+        ("y", c_uint32),
+        ("kind", c_uint32),
+        ("direction", c_uint32),
+        ("link_x", c_uint32),
+        ("link_y", c_uint32),
+        ("lifetime", c_uint64),
+    ]
+
+    def __repr__(self):
+        return "Object({}, ({}, {}))".format(ObjectKind(self.kind).name, self.x, self.y)
+
+
+class Direction(enum.IntEnum):
+    NORTH = 1
+    SOUTH = 2
+    WEST = 3
+    EAST = 4
+
+
+class Status(enum.IntEnum):
+    PLAYING = 0
+    DIED = 1
+    WON = 2
+    DRAW = 3
+    ELIMINATED = 4
+    BOARD_FULL = 5
+
+
+class ObjectKind(enum.IntEnum):
+    FOOD = 1
+    WALL = 2
+    BONUS_FOOD = 3
+    SHRINK = 4
+    FAST = 5
+    SLOW = 6
+    GHOST = 7
+    PORTAL = 8
+    OBSTACLE = 9
+
+
+_ERROR = POINTER(c_uint32)
+_GAME = c_void_p
+
+for _name, _argtypes, _restype in [
+        ('snek_error_from_name', (c_char_p, _ERROR), c_uint32),
+        ('snek_error_name', (c_uint32,), Slice),
+        ('snek_game_new', (c_uint32, c_uint32, c_uint32, c_uint64, _ERROR), _GAME),
+        ('snek_game_restore', (POINTER(c_uint8), c_size_t, _ERROR), _GAME),
+        ('snek_game_free', (_GAME,), None),
+        ('snek_game_arena_size', (_GAME, POINTER(c_uint32), POINTER(c_uint32), _ERROR), None),
+        ('snek_game_player_count', (_GAME, _ERROR), c_uint32),
+        ('snek_game_set_direction', (_GAME, c_uint32, c_uint32, _ERROR), None),
+        ('snek_game_tick', (_GAME, _ERROR), c_uint32),
+        ('snek_game_is_alive', (_GAME, c_uint32, _ERROR), c_bool),
+        ('snek_game_snake_cells', (_GAME, c_uint32, POINTER(Cell), c_size_t, _ERROR), c_size_t),
+        ('snek_game_objects', (_GAME, POINTER(Object), c_size_t, _ERROR), c_size_t),
+        ('snek_game_snapshot', (_GAME, POINTER(c_uint8), c_size_t, _ERROR), c_size_t),
+        ('snek_game_checksum', (_GAME, _ERROR), c_uint64),
+]:
+    _function = getattr(snek_capi, _name)
+    _function.argtypes = _argtypes
+    _function.restype = _restype
+
+
+class SnekError(Exception):
+    __slots__ = ['_number']
+
+    def __init__(self, number):
+        self._number = number
+        super(SnekError, self).__init__('errno({}): {}'.format(self._number, self.get_name()))
+
+    @classmethod
+    def raise_for_errno(cls, errno):
+        if errno > 0:
+            raise SnekError(errno)
+
+    @classmethod
+    def from_name(cls, name):
+        errno = c_uint32(0)
+        rv = snek_capi.snek_error_from_name(name.encode('utf-8'), byref(errno))
+        if errno.value > 0:
+            raise KeyError("Unknown error name {}".format(name))
+        return SnekError(rv)
+
+    def get_name(self):
+        return snek_capi.snek_error_name(self._number).as_string()
+
+    def errno(self):
+        return self._number
+
+    def __eq__(self, other):
+        if not isinstance(other, SnekError):
+            return False
+        return self._number == other._number
+
+    def __hash__(self):
+        return hash(self._number)
+
+
+SnekError.InvalidArgument = SnekError.from_name("InvalidArgument")
+SnekError.InvalidSize = SnekError.from_name("InvalidSize")
+SnekError.UnknownPlayer = SnekError.from_name("UnknownPlayer")
+SnekError.BufferTooSmall = SnekError.from_name("BufferTooSmall")
+SnekError.BadSnapshot = SnekError.from_name("BadSnapshot")
+
+
+def _call(function, *args):
+    errno = c_uint32(0)
+    rv = function(*(args + (byref(errno),)))
+    SnekError.raise_for_errno(errno.value)
+    return rv
+
+
+def _fill(function, ctype, *args):
+    """Ask how many items there are, then fetch them into an array."""
+    count = _call(function, *(args + (None, 0)))
+    items = (ctype * count)()
+    _call(function, *(args + (items, count)))
+    return items
+
+
+class Game(object):
+    """A game of snek, which must be closed, or left to the garbage
+    collector, to free it."""
+
+    __slots__ = ['_ptr']
+
+    def __init__(self, width, height, players=1, seed=0):
+        self._ptr = None
+        self._ptr = _call(snek_capi.snek_game_new, width, height, players, seed)
+
+    @classmethod
+    def restore(cls, snapshot):
+        """A game carried on from the bytes `snapshot` returned."""
+        game = cls.__new__(cls)
+        game._ptr = None
+        buf = (c_uint8 * len(snapshot)).from_buffer_copy(snapshot)
+        game._ptr = _call(snek_capi.snek_game_restore, buf, len(snapshot))
+        return game
+
+    def close(self):
+        if self._ptr is not None:
+            snek_capi.snek_game_free(self._ptr)
+            self._ptr = None
+
+    def __del__(self):
+        if hasattr(self, '_ptr'):
+            self.close()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    @property
+    def arena_size(self):
+        width, height = c_uint32(0), c_uint32(0)
+        _call(snek_capi.snek_game_arena_size, self._ptr, byref(width), byref(height))
+        return (width.value, height.value)
+
+    @property
+    def player_count(self):
+        return _call(snek_capi.snek_game_player_count, self._ptr)
+
+    def set_direction(self, player, direction):
+        _call(snek_capi.snek_game_set_direction, self._ptr, player, int(direction))
+
+    def tick(self):
+        return Status(_call(snek_capi.snek_game_tick, self._ptr))
+
+    def is_alive(self, player):
+        return _call(snek_capi.snek_game_is_alive, self._ptr, player)
+
+    def snake(self, player=0):
+        """A player's snake as (x, y) pairs, head first."""
+        cells = _fill(snek_capi.snek_game_snake_cells, Cell, self._ptr, player)
+        return [(cell.x, cell.y) for cell in cells]
+
+    def objects(self):
+        """Everything on the board other than snakes, as `Object`s."""
+        return list(_fill(snek_capi.snek_game_objects, Object, self._ptr))
+
+    def snapshot(self):
+        """The game as bytes, which `Game.restore` reads back."""
+        return bytes(_fill(snek_capi.snek_game_snapshot, c_uint8, self._ptr))
+
+    def checksum(self):
+        return _call(snek_capi.snek_game_checksum, self._ptr)
@@ -0,0 +1,257 @@
+#ifndef SNEK_ENGINE_H
+#define SNEK_ENGINE_H
+
+/* Generated by cbindgen from src/lib.rs; don't edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+enum SnekDirection {
+  SNEK_DIRECTION_NORTH = 1,
+  SNEK_DIRECTION_SOUTH = 2,
+  SNEK_DIRECTION_WEST = 3,
+  SNEK_DIRECTION_EAST = 4,
+};
+typedef uint32_t SnekDirection;
+
+enum SnekError {
+  /*
+   * A null pointer, or a number out of range.
+   */
+  SNEK_ERROR_INVALID_ARGUMENT = 1,
+  /*
+   * An arena with no cells, or more than `MAX_ARENA_CELLS`.
+   */
+  SNEK_ERROR_INVALID_SIZE = 2,
+  SNEK_ERROR_UNKNOWN_PLAYER = 3,
+  SNEK_ERROR_BUFFER_TOO_SMALL = 4,
+  /*
+   * Bytes which aren't a snapshot of a game that could happen.
+   */
+  SNEK_ERROR_BAD_SNAPSHOT = 5,
+  /*
+   * Something went wrong inside the engine.
+   */
+  SNEK_ERROR_INVALID = 1094795585,
+};
+typedef uint32_t SnekError;
+
+enum SnekObjectKind {
+  SNEK_OBJECT_KIND_FOOD = 1,
+  SNEK_OBJECT_KIND_WALL = 2,
+  SNEK_OBJECT_KIND_BONUS_FOOD = 3,
+  SNEK_OBJECT_KIND_SHRINK = 4,
+  SNEK_OBJECT_KIND_FAST = 5,
+  SNEK_OBJECT_KIND_SLOW = 6,
+  SNEK_OBJECT_KIND_GHOST = 7,
+  SNEK_OBJECT_KIND_PORTAL = 8,
+  SNEK_OBJECT_KIND_OBSTACLE = 9,
+};
+typedef uint32_t SnekObjectKind;
+
+/*
+ * How the game stands after a tick.
+ */
+enum SnekStatus {
+  SNEK_STATUS_PLAYING = 0,
+  /*
+   * The only snake died.
+   */
+  SNEK_STATUS_DIED = 1,
+  /*
+   * One snake is left; `snek_game_is_alive` says which.
+   */
+  SNEK_STATUS_WON = 2,
+  /*
+   * The last snakes died together.
+   */
+  SNEK_STATUS_DRAW = 3,
+  /*
+   * Every snake died, playing to the last.
+   */
+  SNEK_STATUS_ELIMINATED = 4,
+  /*
+   * There's no food left and nowhere to put any.
+   */
+  SNEK_STATUS_BOARD_FULL = 5,
+  SNEK_STATUS_INVALID = 1094795585,
+};
+typedef uint32_t SnekStatus;
+
+typedef struct snek_game snek_game;
+
+/*
+ * A string which isn't NUL-terminated.
+ */
+typedef struct snek_str {
+  const uint8_t *ptr;
+  uintptr_t len;
+} snek_str;
+
+typedef struct snek_cell {
+  uint32_t x;
+  uint32_t y;
+} snek_cell;
+
+typedef struct snek_object {
+  uint32_t x;
+  uint32_t y;
+  /*
+   * A `SnekObjectKind`.
+   */
+  uint32_t kind;
+  /*
+   * The `SnekDirection` an obstacle is sliding in, otherwise zero.
+   */
+  uint32_t direction;
+  /*
+   * Where a portal's other end is, otherwise zero.
+   */
+  uint32_t link_x;
+  uint32_t link_y;
+  /*
+   * Ticks until the object goes away, or zero if it stays for good.
+   */
+  uint64_t lifetime;
+} snek_object;
+
+/*
+ * # Safety
+ *
+ * `name` must be null or a NUL-terminated string, and `error` null or
+ * writable.
+ */
+SnekError snek_error_from_name(const char *name, uint32_t *error);
+
+/*
+ * The name of an error; `INVALID` for a number which isn't one.
+ */
+snek_str snek_error_name(uint32_t error);
+
+/*
+ * A new game in a bounded arena, with a snake for each player.  Free it
+ * with `snek_game_free`.
+ *
+ * # Safety
+ *
+ * `error` must be null or writable.
+ */
+snek_game *snek_game_new(uint32_t width,
+                         uint32_t height,
+                         uint32_t players,
+                         uint64_t seed,
+                         uint32_t *error);
+
+/*
+ * A game carried on from a snapshot taken by `snek_game_snapshot`.
+ *
+ * # Safety
+ *
+ * `input` must be null or point to `input_length` readable bytes, and
+ * `error` must be null or writable.
+ */
+snek_game *snek_game_restore(const uint8_t *input, uintptr_t input_length, uint32_t *error);
+
+/*
+ * # Safety
+ *
+ * `game` must be null or a game not yet freed.
+ */
+void snek_game_free(snek_game *game);
+
+/*
+ * # Safety
+ *
+ * `game` must be null or a live game; `width`, `height` and `error`
+ * must each be null or writable.
+ */
+void snek_game_arena_size(const snek_game *game, uint32_t *width, uint32_t *height, uint32_t *error);
+
+/*
+ * # Safety
+ *
+ * `game` must be null or a live game, and `error` null or writable.
+ */
+uint32_t snek_game_player_count(const snek_game *game, uint32_t *error);
+
+/*
+ * Steer a player's snake from the next tick on, by `SnekDirection`.
+ * Turning back into the neck is ignored, as it is in the game.
+ *
+ * # Safety
+ *
+ * `game` must be null or a live game, and `error` null or writable.
+ */
+void snek_game_set_direction(snek_game *game, uint32_t player_id, uint32_t direction, uint32_t *error);
+
+/*
+ * Move the game on a tick, returning a `SnekStatus`.  Once the game is
+ * over, this keeps saying how it ended.
+ *
+ * # Safety
+ *
+ * `game` must be null or a live game, and `error` null or writable.
+ */
+SnekStatus snek_game_tick(snek_game *game, uint32_t *error);
+
+/*
+ * # Safety
+ *
+ * `game` must be null or a live game, and `error` null or writable.
+ */
+bool snek_game_is_alive(const snek_game *game, uint32_t player_id, uint32_t *error);
+
+/*
+ * Copy a player's snake into `out`, head first.  Returns how long the
+ * snake is.
+ *
+ * # Safety
+ *
+ * `game` must be null or a live game, `out` null or writable for
+ * `capacity` cells, and `error` null or writable.
+ */
+uintptr_t snek_game_snake_cells(const snek_game *game,
+                                uint32_t player_id,
+                                snek_cell *out,
+                                uintptr_t capacity,
+                                uint32_t *error);
+
+/*
+ * Copy everything on the board other than snakes into `out`, in order
+ * of position.  Returns how many objects there are.
+ *
+ * # Safety
+ *
+ * `game` must be null or a live game, `out` null or writable for
+ * `capacity` objects, and `error` null or writable.
+ */
+uintptr_t snek_game_objects(const snek_game *game,
+                            snek_object *out,
+                            uintptr_t capacity,
+                            uint32_t *error);
+
+/*
+ * Write the game out as a binary snapshot, which `snek_game_restore`
+ * reads back.  Returns how many bytes it takes.
+ *
+ * # Safety
+ *
+ * `game` must be null or a live game, `out` null or writable for
+ * `capacity` bytes, and `error` null or writable.
+ */
+uintptr_t snek_game_snapshot(const snek_game *game,
+                             uint8_t *out,
+                             uintptr_t capacity,
+                             uint32_t *error);
+
+/*
+ * A hash of the whole game, for checking two games are the same.
+ *
+ * # Safety
+ *
+ * `game` must be null or a live game, and `error` null or writable.
+ */
+uint64_t snek_game_checksum(const snek_game *game, uint32_t *error);
+
+#endif /* SNEK_ENGINE_H */
@@ -0,0 +1,509 @@
+//! A C interface to snek_engine, for driving games from other languages.
+//! `snek.py` wraps it for Python, and `snek_engine.h` declares it for C.
+//!
+//! Every function which can fail takes an `error` pointer last.  On
+//! failure a `SnekError` is written through it, unless it's null, and the
+//! function returns a harmless default.  Functions which fill a caller's
+//! buffer return how many items there are; with a null buffer they write
+//! nothing, and with one too small they fail with `BufferTooSmall`, so the
+//! caller can ask first and then allocate.
+//!
+//! A panic must not unwind out into the caller, which would abort it, so
+//! each function catches any and fails with `Invalid` instead.
+
+extern crate snek_engine;
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use snek_engine::snapshot::Snapshot;
+use snek_engine::{Direction, GameObject, GameOver, GameState, PlayerId, Topology, MAX_ARENA_CELLS};
+
+/// Run `f`, turning a panic into `SnekError::Invalid`.
+fn guard<T, F: FnOnce() -> Result<T, SnekError>>(f: F) -> Result<T, SnekError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(Err(SnekError::Invalid))
+}
+
+fn err_unwrap<T>(result: Result<T, SnekError>, error: *mut u32, default: T) -> T {
+    match result {
+        Ok(ok) => ok,
+        Err(err) => {
+            if !error.is_null() {
+                unsafe { *error = err as u32 };
+            }
+            default
+        },
+    }
+}
+
+static SNEK_ERROR_INVALID: &str = "INVALID";
+static SNEK_ERROR_INVALID_ARGUMENT: &str = "InvalidArgument";
+static SNEK_ERROR_INVALID_SIZE: &str = "InvalidSize";
+static SNEK_ERROR_UNKNOWN_PLAYER: &str = "UnknownPlayer";
+static SNEK_ERROR_BUFFER_TOO_SMALL: &str = "BufferTooSmall";
+static SNEK_ERROR_BAD_SNAPSHOT: &str = "BadSnapshot";
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SnekError {
+    /// A null pointer, or a number out of range.
+    InvalidArgument = 1,
+    /// An arena with no cells, or more than `MAX_ARENA_CELLS`.
+    InvalidSize = 2,
+    UnknownPlayer = 3,
+    BufferTooSmall = 4,
+    /// Bytes which aren't a snapshot of a game that could happen.
+    BadSnapshot = 5,
+
+    /// Something went wrong inside the engine.
+    Invalid = 0x41414141,
+}
+
+impl SnekError {
+    pub fn from_number(number: u32) -> SnekError {
+        match number {
+            1 => SnekError::InvalidArgument,
+            2 => SnekError::InvalidSize,
+            3 => SnekError::UnknownPlayer,
+            4 => SnekError::BufferTooSmall,
+            5 => SnekError::BadSnapshot,
+            _ => SnekError::Invalid,
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<SnekError> {
+        let errors = [
+            SnekError::InvalidArgument,
+            SnekError::InvalidSize,
+            SnekError::UnknownPlayer,
+            SnekError::BufferTooSmall,
+            SnekError::BadSnapshot,
+        ];
+        errors.iter().cloned().find(|err| err.get_name().as_bytes() == name)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            SnekError::Invalid => SNEK_ERROR_INVALID,
+            SnekError::InvalidArgument => SNEK_ERROR_INVALID_ARGUMENT,
+            SnekError::InvalidSize => SNEK_ERROR_INVALID_SIZE,
+            SnekError::UnknownPlayer => SNEK_ERROR_UNKNOWN_PLAYER,
+            SnekError::BufferTooSmall => SNEK_ERROR_BUFFER_TOO_SMALL,
+            SnekError::BadSnapshot => SNEK_ERROR_BAD_SNAPSHOT,
+        }
+    }
+}
+
+/// A string which isn't NUL-terminated.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct snek_str {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl snek_str {
+    fn new(text: &'static str) -> snek_str {
+        snek_str {
+            ptr: text.as_ptr(),
+            len: text.len(),
+        }
+    }
+}
+
+/// # Safety
+///
+/// `name` must be null or a NUL-terminated string, and `error` null or
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_error_from_name(name: *const c_char, error: *mut u32) -> SnekError {
+    let result = guard(|| {
+        if name.is_null() {
+            Err(SnekError::InvalidArgument)
+        } else {
+            SnekError::from_name(CStr::from_ptr(name).to_bytes()).ok_or(SnekError::InvalidArgument)
+        }
+    });
+    err_unwrap(result, error, SnekError::Invalid)
+}
+
+/// The name of an error; `INVALID` for a number which isn't one.
+#[no_mangle]
+pub extern "C" fn snek_error_name(error: u32) -> snek_str {
+    snek_str::new(SnekError::from_number(error).get_name())
+}
+
+// --- //
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SnekDirection {
+    North = 1,
+    South = 2,
+    West = 3,
+    East = 4,
+}
+
+fn direction_code(dir: Direction) -> SnekDirection {
+    match dir {
+        Direction::North => SnekDirection::North,
+        Direction::South => SnekDirection::South,
+        Direction::West => SnekDirection::West,
+        Direction::East => SnekDirection::East,
+    }
+}
+
+/// How the game stands after a tick.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SnekStatus {
+    Playing = 0,
+    /// The only snake died.
+    Died = 1,
+    /// One snake is left; `snek_game_is_alive` says which.
+    Won = 2,
+    /// The last snakes died together.
+    Draw = 3,
+    /// Every snake died, playing to the last.
+    Eliminated = 4,
+    /// There's no food left and nowhere to put any.
+    BoardFull = 5,
+
+    Invalid = 0x41414141,
+}
+
+fn status(game_over: GameOver) -> SnekStatus {
+    match game_over {
+        GameOver::Died(_) => SnekStatus::Died,
+        GameOver::Winner(_) => SnekStatus::Won,
+        GameOver::Draw => SnekStatus::Draw,
+        GameOver::Eliminated => SnekStatus::Eliminated,
+        GameOver::BoardFull => SnekStatus::BoardFull,
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SnekObjectKind {
+    Food = 1,
+    Wall = 2,
+    BonusFood = 3,
+    Shrink = 4,
+    Fast = 5,
+    Slow = 6,
+    Ghost = 7,
+    Portal = 8,
+    Obstacle = 9,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct snek_cell {
+    pub x: u32,
+    pub y: u32,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct snek_object {
+    pub x: u32,
+    pub y: u32,
+    /// A `SnekObjectKind`.
+    pub kind: u32,
+    /// The `SnekDirection` an obstacle is sliding in, otherwise zero.
+    pub direction: u32,
+    /// Where a portal's other end is, otherwise zero.
+    pub link_x: u32,
+    pub link_y: u32,
+    /// Ticks until the object goes away, or zero if it stays for good.
+    pub lifetime: u64,
+}
+
+fn object(pos: (usize, usize), obj: GameObject, lifetime: Option<u64>) -> snek_object {
+    let (kind, direction, link) = match obj {
+        GameObject::Food => (SnekObjectKind::Food, 0, (0, 0)),
+        GameObject::Wall => (SnekObjectKind::Wall, 0, (0, 0)),
+        GameObject::BonusFood => (SnekObjectKind::BonusFood, 0, (0, 0)),
+        GameObject::Shrink => (SnekObjectKind::Shrink, 0, (0, 0)),
+        GameObject::Fast => (SnekObjectKind::Fast, 0, (0, 0)),
+        GameObject::Slow => (SnekObjectKind::Slow, 0, (0, 0)),
+        GameObject::Ghost => (SnekObjectKind::Ghost, 0, (0, 0)),
+        GameObject::Portal(other) => (SnekObjectKind::Portal, 0, other),
+        GameObject::Obstacle(dir) => (SnekObjectKind::Obstacle, direction_code(dir) as u32, (0, 0)),
+    };
+    snek_object {
+        x: pos.0 as u32,
+        y: pos.1 as u32,
+        kind: kind as u32,
+        direction,
+        link_x: link.0 as u32,
+        link_y: link.1 as u32,
+        lifetime: lifetime.unwrap_or(0),
+    }
+}
+
+// ----
+
+#[allow(non_camel_case_types)]
+pub struct snek_game {
+    state: GameState,
+    /// How the game ended, so ticking a finished game changes nothing.
+    over: Option<GameOver>,
+}
+
+unsafe fn game_ref<'a>(game: *const snek_game) -> Result<&'a snek_game, SnekError> {
+    game.as_ref().ok_or(SnekError::InvalidArgument)
+}
+
+unsafe fn game_mut<'a>(game: *mut snek_game) -> Result<&'a mut snek_game, SnekError> {
+    game.as_mut().ok_or(SnekError::InvalidArgument)
+}
+
+fn player(game: &snek_game, player: u32) -> Result<PlayerId, SnekError> {
+    if (player as usize) < game.state.player_count() {
+        Ok(PlayerId(player as usize))
+    } else {
+        Err(SnekError::UnknownPlayer)
+    }
+}
+
+/// Copy `items` into `out`, which has room for `capacity` of them,
+/// returning how many there are.
+unsafe fn fill<T, I>(items: I, out: *mut T, capacity: usize) -> Result<usize, SnekError>
+    where I: ExactSizeIterator<Item = T>
+{
+    let count = items.len();
+    if out.is_null() {
+        return Ok(count);
+    }
+    if capacity < count {
+        return Err(SnekError::BufferTooSmall);
+    }
+    for (idx, item) in items.enumerate() {
+        ptr::write(out.add(idx), item);
+    }
+    Ok(count)
+}
+
+fn snek_game_new_helper(width: u32, height: u32, players: u32, seed: u64) -> Result<*mut snek_game, SnekError> {
+    let cells = (width as usize).checked_mul(height as usize);
+    if width == 0 || height == 0 || cells.is_none_or(|cells| MAX_ARENA_CELLS < cells) {
+        return Err(SnekError::InvalidSize);
+    }
+    if players == 0 {
+        return Err(SnekError::InvalidArgument);
+    }
+
+    let state = GameState::with_players(width as usize, height as usize, seed, Topology::Bounded, players as usize);
+    Ok(Box::into_raw(Box::new(snek_game { state, over: None })))
+}
+
+/// A new game in a bounded arena, with a snake for each player.  Free it
+/// with `snek_game_free`.
+///
+/// # Safety
+///
+/// `error` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_new(
+    width: u32,
+    height: u32,
+    players: u32,
+    seed: u64,
+    error: *mut u32,
+) -> *mut snek_game {
+    err_unwrap(guard(|| snek_game_new_helper(width, height, players, seed)), error, ptr::null_mut())
+}
+
+unsafe fn snek_game_restore_helper(input: *const u8, input_length: usize) -> Result<*mut snek_game, SnekError> {
+    if input.is_null() {
+        return Err(SnekError::InvalidArgument);
+    }
+
+    let mut input = slice::from_raw_parts(input, input_length);
+    let snapshot = Snapshot::read_binary(&mut input).map_err(|_| SnekError::BadSnapshot)?;
+    let state = GameState::restore(&snapshot).map_err(|_| SnekError::BadSnapshot)?;
+    Ok(Box::into_raw(Box::new(snek_game { state, over: None })))
+}
+
+/// A game carried on from a snapshot taken by `snek_game_snapshot`.
+///
+/// # Safety
+///
+/// `input` must be null or point to `input_length` readable bytes, and
+/// `error` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_restore(input: *const u8, input_length: usize, error: *mut u32) -> *mut snek_game {
+    err_unwrap(guard(|| snek_game_restore_helper(input, input_length)), error, ptr::null_mut())
+}
+
+/// # Safety
+///
+/// `game` must be null or a game not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_free(game: *mut snek_game) {
+    if !game.is_null() {
+        let _ = guard(|| {
+            drop(Box::from_raw(game));
+            Ok(())
+        });
+    }
+}
+
+/// # Safety
+///
+/// `game` must be null or a live game; `width`, `height` and `error`
+/// must each be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_arena_size(game: *const snek_game, width: *mut u32, height: *mut u32, error: *mut u32) {
+    let result = guard(|| game_ref(game).map(|game| {
+        let (w, h) = game.state.arena_size();
+        if !width.is_null() {
+            *width = w as u32;
+        }
+        if !height.is_null() {
+            *height = h as u32;
+        }
+    }));
+    err_unwrap(result, error, ())
+}
+
+/// # Safety
+///
+/// `game` must be null or a live game, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_player_count(game: *const snek_game, error: *mut u32) -> u32 {
+    let result = guard(|| game_ref(game).map(|game| game.state.player_count() as u32));
+    err_unwrap(result, error, 0)
+}
+
+/// Steer a player's snake from the next tick on, by `SnekDirection`.
+/// Turning back into the neck is ignored, as it is in the game.
+///
+/// # Safety
+///
+/// `game` must be null or a live game, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_set_direction(game: *mut snek_game, player_id: u32, direction: u32, error: *mut u32) {
+    let result = guard(|| game_mut(game).and_then(|game| {
+        let id = player(game, player_id)?;
+        let dir = match direction {
+            1 => Direction::North,
+            2 => Direction::South,
+            3 => Direction::West,
+            4 => Direction::East,
+            _ => return Err(SnekError::InvalidArgument),
+        };
+        game.state.set_direction(id, dir);
+        Ok(())
+    }));
+    err_unwrap(result, error, ())
+}
+
+/// Move the game on a tick, returning a `SnekStatus`.  Once the game is
+/// over, this keeps saying how it ended.
+///
+/// # Safety
+///
+/// `game` must be null or a live game, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_tick(game: *mut snek_game, error: *mut u32) -> SnekStatus {
+    let result = guard(|| game_mut(game).map(|game| {
+        if game.over.is_none() {
+            game.over = game.state.tick().err();
+        }
+        game.over.map_or(SnekStatus::Playing, status)
+    }));
+    err_unwrap(result, error, SnekStatus::Invalid)
+}
+
+/// # Safety
+///
+/// `game` must be null or a live game, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_is_alive(game: *const snek_game, player_id: u32, error: *mut u32) -> bool {
+    let result = guard(|| game_ref(game).and_then(|game| Ok(game.state.is_alive(player(game, player_id)?))));
+    err_unwrap(result, error, false)
+}
+
+/// Copy a player's snake into `out`, head first.  Returns how long the
+/// snake is.
+///
+/// # Safety
+///
+/// `game` must be null or a live game, `out` null or writable for
+/// `capacity` cells, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_snake_cells(
+    game: *const snek_game,
+    player_id: u32,
+    out: *mut snek_cell,
+    capacity: usize,
+    error: *mut u32,
+) -> usize {
+    let result = guard(|| game_ref(game).and_then(|game| {
+        let snake = game.state.snake(player(game, player_id)?).unwrap();
+        let cells: Vec<_> = snake.positions().map(|(x, y)| snek_cell { x: x as u32, y: y as u32 }).collect();
+        fill(cells.into_iter(), out, capacity)
+    }));
+    err_unwrap(result, error, 0)
+}
+
+/// Copy everything on the board other than snakes into `out`, in order
+/// of position.  Returns how many objects there are.
+///
+/// # Safety
+///
+/// `game` must be null or a live game, `out` null or writable for
+/// `capacity` objects, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_objects(
+    game: *const snek_game,
+    out: *mut snek_object,
+    capacity: usize,
+    error: *mut u32,
+) -> usize {
+    let result = guard(|| game_ref(game).and_then(|game| {
+        let objects: Vec<_> = game.state.object_iter().map(|(pos, &obj, lifetime)| object(pos, obj, lifetime)).collect();
+        fill(objects.into_iter(), out, capacity)
+    }));
+    err_unwrap(result, error, 0)
+}
+
+/// Write the game out as a binary snapshot, which `snek_game_restore`
+/// reads back.  Returns how many bytes it takes.
+///
+/// # Safety
+///
+/// `game` must be null or a live game, `out` null or writable for
+/// `capacity` bytes, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_snapshot(
+    game: *const snek_game,
+    out: *mut u8,
+    capacity: usize,
+    error: *mut u32,
+) -> usize {
+    let result = guard(|| game_ref(game).and_then(|game| {
+        let mut bytes = Vec::new();
+        // writing to memory can't fail.
+        game.state.snapshot().write_binary(&mut bytes).unwrap();
+        fill(bytes.into_iter(), out, capacity)
+    }));
+    err_unwrap(result, error, 0)
+}
+
+/// A hash of the whole game, for checking two games are the same.
+///
+/// # Safety
+///
+/// `game` must be null or a live game, and `error` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn snek_game_checksum(game: *const snek_game, error: *mut u32) -> u64 {
+    let result = guard(|| game_ref(game).map(|game| game.state.checksum()));
+    err_unwrap(result, error, 0)
+}
@@ -0,0 +1,78 @@
+"""Tests for the Python bindings.  Run `cargo build && python3 test.py`
+from this directory."""
+
+import unittest
+
+from snek import Direction, Game, ObjectKind, SnekError, Status
+
+
+class ErrorTests(unittest.TestCase):
+    def test_names_round_trip(self):
+        self.assertEqual(SnekError.BufferTooSmall.get_name(), "BufferTooSmall")
+        self.assertEqual(SnekError.from_name("BadSnapshot"), SnekError.BadSnapshot)
+        with self.assertRaises(KeyError):
+            SnekError.from_name("x")
+
+    def test_bad_arguments_raise(self):
+        with self.assertRaises(SnekError) as caught:
+            Game(0, 10)
+        self.assertEqual(caught.exception, SnekError.InvalidSize)
+        # too big to play in, rather than running out of memory.
+        with self.assertRaises(SnekError) as caught:
+            Game(65535, 65535)
+        self.assertEqual(caught.exception, SnekError.InvalidSize)
+
+        with Game(10, 10) as game:
+            with self.assertRaises(SnekError) as caught:
+                game.set_direction(0, 7)
+            self.assertEqual(caught.exception, SnekError.InvalidArgument)
+            with self.assertRaises(SnekError) as caught:
+                game.snake(1)
+            self.assertEqual(caught.exception, SnekError.UnknownPlayer)
+
+
+class GameTests(unittest.TestCase):
+    def test_new_game(self):
+        with Game(10, 8, players=2, seed=42) as game:
+            self.assertEqual(game.arena_size, (10, 8))
+            self.assertEqual(game.player_count, 2)
+            self.assertEqual(len(game.snake(0)), 1)
+            self.assertTrue(game.is_alive(1))
+            # food goes down on the first tick.
+            self.assertEqual(game.objects(), [])
+            game.tick()
+            self.assertEqual([obj.kind for obj in game.objects()], [ObjectKind.FOOD])
+
+    def test_playing_until_the_end(self):
+        with Game(10, 10, seed=42) as game:
+            game.set_direction(0, Direction.NORTH)
+            (x, y), = game.snake(0)
+            for _ in range(y):
+                self.assertEqual(game.tick(), Status.PLAYING)
+            self.assertEqual(game.snake(0)[0], (x, 0))
+
+            self.assertEqual(game.tick(), Status.DIED)
+            self.assertFalse(game.is_alive(0))
+            self.assertEqual(game.tick(), Status.DIED)
+
+    def test_snapshots_carry_on(self):
+        with Game(16, 16, seed=7) as game:
+            for _ in range(3):
+                game.tick()
+            snapshot = game.snapshot()
+            with Game.restore(snapshot) as copy:
+                self.assertEqual(copy.checksum(), game.checksum())
+                for direction in [Direction.SOUTH, Direction.WEST]:
+                    for player in [game, copy]:
+                        player.set_direction(0, direction)
+                        player.tick()
+                self.assertEqual(copy.snake(0), game.snake(0))
+                self.assertEqual(copy.checksum(), game.checksum())
+
+        with self.assertRaises(SnekError) as caught:
+            Game.restore(b"not a snapshot")
+        self.assertEqual(caught.exception, SnekError.BadSnapshot)
+
+
+if __name__ == '__main__':
+    unittest.main()
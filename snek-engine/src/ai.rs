@@ -0,0 +1,599 @@
+//! Computer players.
+//!
+//! A `Controller` looks at the game and picks a direction for one snake
+//! before each tick.  `Greedy` heads for whichever food it can reach
+//! first, `AStar` only goes for food when it could still reach its own
+//! tail after eating it, and `Hamiltonian` follows a cycle through every
+//! cell of the arena, which is slow but never fatal.  A `Pilot` flies a
+//! snake with one of them while its player has let go.
+//!
+//! All of them know that the cells a snake occupies free up one by one as
+//! its tail moves on, so a path may run through a body which will have
+//! gone by the time the head gets there.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+
+use super::{Area, Direction, GameObject, GameState, PlayerId, Position};
+
+pub trait Controller {
+    /// A short name for menus and reports.
+    fn name(&self) -> &'static str;
+
+    /// The direction `player` should head in from the next tick, or
+    /// `None` to carry on as it is.
+    fn choose(&mut self, state: &GameState, player: PlayerId) -> Option<Direction>;
+}
+
+/// The names `by_name` knows.
+pub const NAMES: [&str; 3] = ["greedy", "astar", "hamiltonian"];
+
+/// One of the bundled controllers, by the name it reports.
+pub fn by_name(name: &str) -> Option<Box<dyn Controller>> {
+    match name {
+        "greedy" => Some(Box::new(Greedy)),
+        "astar" => Some(Box::new(AStar)),
+        "hamiltonian" => Some(Box::new(Hamiltonian::new())),
+        _ => None,
+    }
+}
+
+/// A computer player ready to take over one snake, for autopilot and
+/// demo modes.
+pub struct Pilot {
+    controller: Box<dyn Controller>,
+    /// Whether it's steering, rather than the player.
+    pub flying: bool,
+}
+
+impl Pilot {
+    pub fn new(controller: Box<dyn Controller>, flying: bool) -> Pilot {
+        Pilot { controller, flying }
+    }
+
+    /// Steer `player`'s snake if flying.  Call this once a tick, just
+    /// before `GameState::tick`.
+    pub fn steer(&mut self, state: &mut GameState, player: PlayerId) {
+        if !self.flying {
+            return;
+        }
+        if let Some(dir) = self.controller.choose(state, player) {
+            state.set_direction(player, dir);
+        }
+    }
+}
+
+/// Takes the shortest path to the nearest food, whatever happens after.
+/// When no food can be reached it heads wherever there is most room.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Greedy;
+
+impl Controller for Greedy {
+    fn name(&self) -> &'static str {
+        "greedy"
+    }
+
+    fn choose(&mut self, state: &GameState, player: PlayerId) -> Option<Direction> {
+        let board = Board::new(state, player)?;
+        let free_at = board.free_now();
+        let reached = board.search(&free_at, board.head(), 1, Some(board.back));
+
+        board.food.iter()
+            .filter_map(|&food| reached[board.index(food)])
+            .min_by_key(|&(steps, _)| steps)
+            .map(|(_, dir)| dir)
+            .or_else(|| board.roomiest(&free_at))
+            .or_else(|| board.desperate())
+    }
+}
+
+/// Takes an A* path to the nearest food it can eat without boxing itself
+/// in, judged by whether it could still reach its tail afterwards.  When
+/// there is no such food it follows its tail around until there is.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AStar;
+
+impl Controller for AStar {
+    fn name(&self) -> &'static str {
+        "astar"
+    }
+
+    fn choose(&mut self, state: &GameState, player: PlayerId) -> Option<Direction> {
+        let board = Board::new(state, player)?;
+        let free_at = board.free_now();
+
+        let mut food = board.food.clone();
+        food.sort_by_key(|&pos| board.distance(board.head(), pos));
+        for target in food {
+            if let Some(path) = board.astar(&free_at, target) {
+                if board.safe_after(&path) {
+                    return board.direction_to(board.head(), path[0]);
+                }
+            }
+        }
+
+        board.chase_tail(&free_at)
+            .or_else(|| board.roomiest(&free_at))
+            .or_else(|| board.desperate())
+    }
+}
+
+/// Follows a fixed cycle through every cell of the arena, so its body
+/// is always behind it.  There is only such a cycle when the width or
+/// height is even; otherwise, or when something else is in the way, it
+/// plays like `AStar`.
+#[derive(Debug, Clone, Default)]
+pub struct Hamiltonian {
+    /// The arena size `cycle` was built for.
+    built_for: (usize, usize),
+    /// Which way to go from each cell.
+    cycle: Option<Vec<Direction>>,
+}
+
+impl Hamiltonian {
+    pub fn new() -> Hamiltonian {
+        Hamiltonian::default()
+    }
+}
+
+impl Controller for Hamiltonian {
+    fn name(&self) -> &'static str {
+        "hamiltonian"
+    }
+
+    fn choose(&mut self, state: &GameState, player: PlayerId) -> Option<Direction> {
+        let board = Board::new(state, player)?;
+        let size = (board.area.width, board.area.height);
+        if self.built_for != size {
+            self.built_for = size;
+            self.cycle = hamiltonian_cycle(size.0, size.1);
+        }
+
+        if let Some(ref cycle) = self.cycle {
+            let dir = cycle[board.index(board.head())];
+            let free_at = board.free_now();
+            let next = board.step(board.head(), dir);
+            if dir != board.back && next.is_some_and(|pos| free_at[board.index(pos)] <= 1) {
+                return Some(dir);
+            }
+        }
+        AStar.choose(state, player)
+    }
+}
+
+/// Which way to go from each cell, indexed by `y * width + x`, to visit
+/// every cell and come back without leaving the arena.
+fn hamiltonian_cycle(width: usize, height: usize) -> Option<Vec<Direction>> {
+    if width < 2 || height < 2 || !(width.is_multiple_of(2) || height.is_multiple_of(2)) {
+        return None;
+    }
+    if !height.is_multiple_of(2) {
+        // build it on its side and turn it back.
+        let turned = hamiltonian_cycle(height, width)?;
+        let mut cycle = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                cycle.push(match turned[x * height + y] {
+                    Direction::North => Direction::West,
+                    Direction::South => Direction::East,
+                    Direction::West => Direction::North,
+                    Direction::East => Direction::South,
+                });
+            }
+        }
+        return Some(cycle);
+    }
+
+    // east along the top row, back and forth down the rest leaving the
+    // first column free, and up the first column to the start.
+    let mut cycle = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            cycle.push(if y == 0 {
+                if x + 1 < width { Direction::East } else { Direction::South }
+            } else if x == 0 {
+                Direction::North
+            } else if y.is_multiple_of(2) {
+                if x + 1 < width { Direction::East } else { Direction::South }
+            } else if 1 < x {
+                Direction::West
+            } else if y + 1 < height {
+                Direction::South
+            } else {
+                Direction::West
+            });
+        }
+    }
+    Some(cycle)
+}
+
+const DIRECTIONS: [Direction; 4] = [Direction::North, Direction::South, Direction::West, Direction::East];
+
+/// Never free: walls.
+const NEVER: usize = usize::MAX;
+
+/// The game as one snake sees it.
+struct Board {
+    area: Area,
+    /// Our body, head first.
+    body: VecDeque<Position>,
+    /// The way we can't turn.
+    back: Direction,
+    /// For each cell, the first step on which something other than us
+    /// is out of its way.
+    others: Vec<usize>,
+    food: Vec<Position>,
+    /// Cells a rival's head could move into next tick.
+    danger: Vec<Position>,
+}
+
+impl Board {
+    /// `None` if `player` is dead or doesn't exist.
+    fn new(state: &GameState, player: PlayerId) -> Option<Board> {
+        let me = state.players.get(player.0).filter(|me| me.death.is_none())?;
+        let area = state.arena_size;
+
+        let mut board = Board {
+            area,
+            body: me.snake.positions().map(|(x, y)| Position(x, y)).collect(),
+            back: me.snake.neck_direction().negate(),
+            others: vec![0; area.width * area.height],
+            food: Vec::new(),
+            danger: Vec::new(),
+        };
+
+        for (&pos, obj) in state.objects.iter() {
+            match *obj {
+                GameObject::Food | GameObject::BonusFood => board.food.push(pos),
+                // pills are harmless, but portals and moving obstacles
+                // are more than the search knows how to follow.
+                GameObject::Shrink | GameObject::Fast | GameObject::Slow | GameObject::Ghost => (),
+                GameObject::Wall | GameObject::Portal(_) | GameObject::Obstacle(_) => board.block(pos, NEVER),
+            }
+        }
+
+        let rivals = state.players.iter()
+            .enumerate()
+            .filter(|&(idx, other)| idx != player.0 && other.death.is_none());
+        for (_, other) in rivals {
+            let body: Vec<_> = other.snake.positions().collect();
+            for (idx, &(x, y)) in body.iter().enumerate() {
+                board.block(Position(x, y), body.len() - idx + 1);
+            }
+            let head = Position(body[0].0, body[0].1);
+            for &dir in DIRECTIONS.iter() {
+                board.danger.extend(board.step(head, dir));
+            }
+        }
+
+        Some(board)
+    }
+
+    fn head(&self) -> Position {
+        self.body[0]
+    }
+
+    fn index(&self, pos: Position) -> usize {
+        (pos.1 - self.area.y_off) * self.area.width + (pos.0 - self.area.x_off)
+    }
+
+    fn block(&mut self, pos: Position, until: usize) {
+        if self.area.contains(&pos) {
+            let idx = self.index(pos);
+            self.others[idx] = self.others[idx].max(until);
+        }
+    }
+
+    /// One step from `pos`, if that stays in the arena.
+    fn step(&self, pos: Position, dir: Direction) -> Option<Position> {
+        self.area.step(pos, dir).filter(|next| self.area.contains(next))
+    }
+
+    fn direction_to(&self, from: Position, to: Position) -> Option<Direction> {
+        DIRECTIONS.iter().cloned().find(|&dir| self.step(from, dir) == Some(to))
+    }
+
+    /// The fewest steps between two cells, ignoring what's in the way.
+    fn distance(&self, a: Position, b: Position) -> usize {
+        let axis = |a: usize, b: usize, size: usize, wraps: bool| {
+            let straight = a.abs_diff(b);
+            if wraps { straight.min(size - straight) } else { straight }
+        };
+        axis(a.0, b.0, self.area.width, self.area.topology.wraps_horizontally()) +
+            axis(a.1, b.1, self.area.height, self.area.topology.wraps_vertically())
+    }
+
+    /// When each cell is free, with `body` in the arena and `others`
+    /// around it.  A snake's head can't move into the cell its tail is
+    /// leaving, so the segment `idx` cells from the head is in the way
+    /// until step `len - idx + 1`.
+    fn free_at(&self, body: &VecDeque<Position>, others: &[usize]) -> Vec<usize> {
+        let mut free_at = others.to_vec();
+        for (idx, &pos) in body.iter().enumerate() {
+            let cell = self.index(pos);
+            free_at[cell] = free_at[cell].max(body.len() - idx + 1);
+        }
+        free_at
+    }
+
+    /// When each cell is free as things stand, keeping out of the way of
+    /// rivals' heads, since running into one as it moves kills us both.
+    fn free_now(&self) -> Vec<usize> {
+        let mut free_at = self.free_at(&self.body, &self.others);
+        for &pos in self.danger.iter() {
+            let cell = self.index(pos);
+            free_at[cell] = free_at[cell].max(2);
+        }
+        free_at
+    }
+
+    /// Breadth-first search from `start`, which is reached on step
+    /// `first_step - 1`.  Gives the step each cell is first reached on
+    /// and the first move on the way there.
+    fn search(&self, free_at: &[usize], start: Position, first_step: usize, back: Option<Direction>)
+        -> Vec<Option<(usize, Direction)>>
+    {
+        let mut reached = vec![None; free_at.len()];
+        let mut queue = VecDeque::new();
+
+        for &dir in DIRECTIONS.iter().filter(|&&dir| Some(dir) != back) {
+            if let Some(next) = self.step(start, dir) {
+                let cell = self.index(next);
+                if free_at[cell] <= first_step && reached[cell].is_none() {
+                    reached[cell] = Some((first_step, dir));
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        while let Some(pos) = queue.pop_front() {
+            let (steps, first) = reached[self.index(pos)].unwrap();
+            for &dir in DIRECTIONS.iter() {
+                if let Some(next) = self.step(pos, dir) {
+                    let cell = self.index(next);
+                    if free_at[cell] <= steps + 1 && reached[cell].is_none() {
+                        reached[cell] = Some((steps + 1, first));
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        reached
+    }
+
+    /// The shortest path from the head to `target`, not counting the
+    /// head itself.
+    fn astar(&self, free_at: &[usize], target: Position) -> Option<Vec<Position>> {
+        let start = self.head();
+        let mut came_from = vec![None; free_at.len()];
+        let mut best = vec![NEVER; free_at.len()];
+        let mut open = BinaryHeap::new();
+
+        best[self.index(start)] = 0;
+        open.push(Reverse((self.distance(start, target), 0, start)));
+
+        while let Some(Reverse((_, steps, pos))) = open.pop() {
+            if pos == target {
+                let mut path = vec![pos];
+                let mut cell = self.index(pos);
+                while let Some(prev) = came_from[cell] {
+                    if prev == start {
+                        break;
+                    }
+                    path.push(prev);
+                    cell = self.index(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if best[self.index(pos)] < steps {
+                continue;
+            }
+
+            for &dir in DIRECTIONS.iter() {
+                if pos == start && dir == self.back {
+                    continue;
+                }
+                if let Some(next) = self.step(pos, dir) {
+                    let cell = self.index(next);
+                    if free_at[cell] <= steps + 1 && steps + 1 < best[cell] {
+                        best[cell] = steps + 1;
+                        came_from[cell] = Some(pos);
+                        open.push(Reverse((steps + 1 + self.distance(next, target), steps + 1, next)));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether, after following `path` and eating the food at its end,
+    /// we could still get round to our own tail.
+    fn safe_after(&self, path: &[Position]) -> bool {
+        let mut body: VecDeque<_> = path.iter().rev().cloned().collect();
+        body.extend(self.body.iter().cloned());
+        body.truncate(self.body.len() + 1);
+
+        let others = self.elapse(path.len());
+        self.tail_reachable(&body, &others).is_some()
+    }
+
+    /// How many steps it would take the head of `body` to get to where
+    /// its tail is now.
+    fn tail_reachable(&self, body: &VecDeque<Position>, others: &[usize]) -> Option<usize> {
+        if body.len() < 2 {
+            return Some(0);
+        }
+        let free_at = self.free_at(body, others);
+        let reached = self.search(&free_at, body[0], 1, None);
+        reached[self.index(*body.back().unwrap())].map(|(steps, _)| steps)
+    }
+
+    /// `others` as it will be after `steps` ticks.
+    fn elapse(&self, steps: usize) -> Vec<usize> {
+        self.others.iter()
+            .map(|&until| if until == NEVER { NEVER } else { until.saturating_sub(steps) })
+            .collect()
+    }
+
+    /// The move after which our tail is still in reach, and as far away
+    /// as possible, to stall until some food is safe to eat.
+    fn chase_tail(&self, free_at: &[usize]) -> Option<Direction> {
+        let others = self.elapse(1);
+        self.moves(free_at)
+            .filter_map(|(dir, next)| {
+                let mut body = self.body.clone();
+                body.push_front(next);
+                if !self.food.contains(&next) {
+                    body.pop_back();
+                }
+                self.tail_reachable(&body, &others).map(|steps| (steps, dir))
+            })
+            .max_by_key(|&(steps, _)| steps)
+            .map(|(_, dir)| dir)
+    }
+
+    /// The move with the most cells reachable after it.
+    fn roomiest(&self, free_at: &[usize]) -> Option<Direction> {
+        self.moves(free_at)
+            .map(|(dir, next)| {
+                let room = self.search(free_at, next, 2, None).iter().filter(|cell| cell.is_some()).count();
+                (room, dir)
+            })
+            .max_by_key(|&(room, _)| room)
+            .map(|(_, dir)| dir)
+    }
+
+    /// Any move which doesn't kill us for certain, for when every other
+    /// one is a gamble on where a rival goes.
+    fn desperate(&self) -> Option<Direction> {
+        let free_at = self.free_at(&self.body, &self.others);
+        let first = self.moves(&free_at).next();
+        first.map(|(dir, _)| dir)
+    }
+
+    /// The moves which don't kill us straight away, and where they lead.
+    fn moves<'a>(&'a self, free_at: &'a [usize]) -> impl Iterator<Item = (Direction, Position)> + 'a {
+        DIRECTIONS.iter()
+            .cloned()
+            .filter(move |&dir| dir != self.back)
+            .filter_map(move |dir| self.step(self.head(), dir).map(|next| (dir, next)))
+            .filter(move |&(_, next)| free_at[self.index(next)] <= 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::GameOver;
+
+    fn play(controller: &mut dyn Controller, mut state: GameState, ticks: u64) -> Result<usize, GameOver> {
+        for _ in 0..ticks {
+            if let Some(dir) = controller.choose(&state, PlayerId(0)) {
+                state.set_user_direction(dir);
+            }
+            state.tick()?;
+        }
+        Ok(state.get_snake().len())
+    }
+
+    #[test]
+    fn cycles_visit_every_cell() {
+        for &(width, height) in [(2, 2), (4, 6), (6, 3), (5, 8), (7, 2)].iter() {
+            let cycle = hamiltonian_cycle(width, height).unwrap();
+            let mut seen = vec![false; width * height];
+            let (mut x, mut y) = (0, 0);
+            for _ in 0..width * height {
+                assert!(!seen[y * width + x], "{}x{} visits ({}, {}) twice", width, height, x, y);
+                seen[y * width + x] = true;
+                let pos = Position(x, y).adjacent(cycle[y * width + x]).unwrap();
+                x = pos.0;
+                y = pos.1;
+                assert!(x < width && y < height, "{}x{} leaves the arena", width, height);
+            }
+            assert_eq!((x, y), (0, 0));
+        }
+        assert!(hamiltonian_cycle(5, 7).is_none());
+        assert!(hamiltonian_cycle(1, 4).is_none());
+    }
+
+    #[test]
+    fn pilots_only_steer_while_flying() {
+        struct North;
+        impl Controller for North {
+            fn name(&self) -> &'static str {
+                "north"
+            }
+
+            fn choose(&mut self, _state: &GameState, _player: PlayerId) -> Option<Direction> {
+                Some(Direction::North)
+            }
+        }
+
+        // the snake starts at (2, 5) heading east.
+        let mut state = GameState::with_seed(10, 10, 1);
+        let mut pilot = Pilot::new(Box::new(North), false);
+        pilot.steer(&mut state, PlayerId(0));
+        state.tick().unwrap();
+        assert_eq!(state.get_snake().positions().next(), Some((3, 5)));
+
+        pilot.flying = true;
+        pilot.steer(&mut state, PlayerId(0));
+        state.tick().unwrap();
+        assert_eq!(state.get_snake().positions().next(), Some((3, 4)));
+    }
+
+    #[test]
+    fn greedy_goes_for_the_food() {
+        let mut state = GameState::with_seed(10, 10, 1);
+        state.tick().unwrap();
+        let food = state.object_iter()
+            .find(|&(_, obj, _)| *obj == GameObject::Food)
+            .map(|(pos, _, _)| pos)
+            .unwrap();
+        let head = state.get_snake().positions().next().unwrap();
+        let distance = food.0.abs_diff(head.0) + food.1.abs_diff(head.1);
+
+        let mut greedy = Greedy;
+        for _ in 0..distance {
+            state.set_user_direction(greedy.choose(&state, PlayerId(0)).unwrap());
+            state.tick().unwrap();
+        }
+        assert_eq!(state.get_snake().len(), 2);
+    }
+
+    #[test]
+    fn controllers_avoid_walls() {
+        // a wall right ahead of the snake.
+        let mut state = GameState::with_seed(10, 10, 1);
+        let head = state.get_snake().positions().next().unwrap();
+        state.place_object((head.0 + 1, head.1), GameObject::Wall, None);
+
+        for name in NAMES.iter() {
+            let mut controller = by_name(name).unwrap();
+            let dir = controller.choose(&state, PlayerId(0)).unwrap();
+            assert!(dir != Direction::East, "{} heads into the wall", name);
+        }
+    }
+
+    #[test]
+    fn hamiltonian_survives() {
+        let mut hamiltonian = Hamiltonian::new();
+        let length = play(&mut hamiltonian, GameState::with_seed(8, 8, 3), 600).unwrap();
+        assert!(10 < length);
+    }
+
+    #[test]
+    fn astar_outlasts_greedy() {
+        let outlasted = (0..5).filter(|&seed| {
+            let greedy = play(&mut Greedy, GameState::with_seed(10, 10, seed), 600);
+            let astar = play(&mut AStar, GameState::with_seed(10, 10, seed), 600);
+            match (greedy, astar) {
+                (Err(_), Ok(_)) => true,
+                (Ok(greedy), Ok(astar)) => greedy <= astar,
+                _ => false,
+            }
+        }).count();
+        assert!(4 <= outlasted, "astar only did as well as greedy in {} games", outlasted);
+    }
+}
@@ -0,0 +1,942 @@
+//! An environment for training agents by reinforcement learning, in the
+//! manner of gym: `reset` starts an episode, and `step` plays a tick of
+//! it and says what the agent sees, what it earned and whether it's over.
+//!
+//! The agent plays the first snake.  It steers left or right of the way
+//! it's heading rather than by compass point, so it can never ask to turn
+//! back on itself.  What it sees is one of the `Observation` encodings,
+//! written into a buffer the environment keeps, and what it earns is up
+//! to a `Reward`; by default, `Rewards`.
+//!
+//! `VecEnv` steps a batch of environments on worker threads, starting a
+//! new episode in each one as soon as its last has finished.
+//!
+//! Once an episode is under way, a step doesn't allocate: `reset` makes
+//! room for the snake to fill the arena and for an object in every cell.
+
+use std::any::Any;
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+
+use super::level::Level;
+use super::rules::Rules;
+use super::{Area, Direction, Event, GameObject, GameOver, GameState, PlayerId, Position};
+
+/// Which way to go next, relative to the way the snake is heading.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Action {
+    Straight,
+    Left,
+    Right,
+}
+
+/// Every action, in the order `Action::index` numbers them.
+pub const ACTIONS: [Action; 3] = [Action::Straight, Action::Left, Action::Right];
+
+impl Action {
+    /// The action numbered `index`, as a policy picks it.
+    pub fn from_index(index: usize) -> Option<Action> {
+        ACTIONS.get(index).cloned()
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Action::Straight => 0,
+            Action::Left => 1,
+            Action::Right => 2,
+        }
+    }
+
+    /// The way a snake heading `heading` goes after this action.
+    pub fn direction(self, heading: Direction) -> Direction {
+        match (self, heading) {
+            (Action::Straight, _) => heading,
+            (Action::Left, Direction::North) | (Action::Right, Direction::South) => Direction::West,
+            (Action::Left, Direction::South) | (Action::Right, Direction::North) => Direction::East,
+            (Action::Left, Direction::East) | (Action::Right, Direction::West) => Direction::North,
+            (Action::Left, Direction::West) | (Action::Right, Direction::East) => Direction::South,
+        }
+    }
+}
+
+/// How the game is shown to the agent, as a flat list of numbers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Observation {
+    /// Four planes over the whole arena, each row by row: the agent's
+    /// head, every snake's body, food, and walls.  A cell is 1.0 where
+    /// the plane's thing is and 0.0 elsewhere.
+    Planes,
+    /// Three planes, body, food and walls, over the square reaching this
+    /// many cells out from the head, turned so the snake is heading up
+    /// it.  Beyond the edge of the arena counts as wall.
+    Window(usize),
+    /// `FEATURES` numbers summing up the snake's surroundings.
+    Features,
+}
+
+/// How many numbers `Observation::Features` gives: whether going
+/// straight, left or right would be fatal; which of north, south, west
+/// and east the snake is heading; whether the nearest food is north,
+/// south, west or east of the head; and how much of the arena the snake
+/// fills.
+pub const FEATURES: usize = 12;
+
+impl Observation {
+    /// How many numbers there are in an arena this size.
+    pub fn size(&self, width: usize, height: usize) -> usize {
+        match *self {
+            Observation::Planes => 4 * width * height,
+            Observation::Window(radius) => 3 * (2 * radius + 1) * (2 * radius + 1),
+            Observation::Features => FEATURES,
+        }
+    }
+}
+
+/// How an episode ended.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Ending {
+    GameOver(GameOver),
+    /// It lasted `EnvConfig::max_steps` steps without the game ending.
+    OutOfSteps,
+}
+
+/// Pays the agent for each step.  Implement this to shape rewards in ways
+/// `Rewards` can't.
+pub trait Reward {
+    /// A new episode has started in `state`.
+    fn reset(&mut self, _state: &GameState) {}
+
+    /// What the step which left the game as `state` is worth.  `events`
+    /// is what happened during it, and `ending` how the episode ended if
+    /// it did.
+    fn reward(&mut self, state: &GameState, events: &[Event], ending: Option<Ending>) -> f32;
+}
+
+/// Rewards for the usual milestones, and for heading towards food.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rewards {
+    /// For each piece of food, bonus food included.
+    pub food: f32,
+    pub death: f32,
+    /// For filling the board.
+    pub filled: f32,
+    /// For every step; make it negative to hurry the agent along.
+    pub step: f32,
+    /// For a step which brings the head nearer to food; one which takes
+    /// it further away costs as much.
+    pub approach: f32,
+}
+
+impl Default for Rewards {
+    /// One for food, minus one for dying, and nothing for anything else.
+    fn default() -> Rewards {
+        Rewards {
+            food: 1.0,
+            death: -1.0,
+            filled: 1.0,
+            step: 0.0,
+            approach: 0.0,
+        }
+    }
+}
+
+impl Reward for Rewards {
+    fn reward(&mut self, state: &GameState, events: &[Event], ending: Option<Ending>) -> f32 {
+        let mut reward = self.step;
+
+        let mut ate = false;
+        for event in events.iter() {
+            match *event {
+                Event::AteFood(PlayerId(0), _) |
+                Event::Collected(PlayerId(0), GameObject::BonusFood, _) => {
+                    reward += self.food;
+                    ate = true;
+                },
+                _ => (),
+            }
+        }
+
+        match ending {
+            Some(Ending::GameOver(GameOver::BoardFull)) => reward += self.filled,
+            Some(Ending::GameOver(_)) => reward += self.death,
+            Some(Ending::OutOfSteps) | None => (),
+        }
+
+        // once the food is eaten, the nearest is somewhere else entirely.
+        if !ate && ending.is_none() {
+            let snake = state.get_snake();
+            let head = snake.head_pos;
+            let before = state.arena_size.step(head, snake.neck_direction().negate());
+            let now = nearest_food(state, head).map(|food| distance(head, food));
+            let then = before.and_then(|before| nearest_food(state, before).map(|food| distance(before, food)));
+            if let (Some(now), Some(then)) = (now, then) {
+                match now.cmp(&then) {
+                    cmp::Ordering::Less => reward += self.approach,
+                    cmp::Ordering::Greater => reward -= self.approach,
+                    cmp::Ordering::Equal => (),
+                }
+            }
+        }
+
+        reward
+    }
+}
+
+/// What an environment plays, and how the agent sees it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EnvConfig {
+    /// The game to play when there's no level.
+    pub rules: Rules,
+    /// A level to play instead of a game set up by `rules`.
+    pub level: Option<Level>,
+    pub observation: Observation,
+    /// Episodes still going after this many steps are cut short.
+    pub max_steps: u64,
+}
+
+impl Default for EnvConfig {
+    /// The usual game in a 16x16 arena, seen as planes, for up to 10000
+    /// steps.
+    fn default() -> EnvConfig {
+        EnvConfig {
+            rules: Rules { width: 16, height: 16, ..Rules::default() },
+            level: None,
+            observation: Observation::Planes,
+            max_steps: 10_000,
+        }
+    }
+}
+
+/// More about an episode than the reward says.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Info {
+    /// Steps taken so far.
+    pub steps: u64,
+    /// How long the snake is.
+    pub length: usize,
+    pub food_eaten: u64,
+    /// All the reward so far.
+    pub total_reward: f32,
+    /// How the episode ended, once it has.
+    pub ending: Option<Ending>,
+}
+
+/// A single game for an agent to play, one episode after another.
+pub struct Env<R = Rewards> {
+    config: EnvConfig,
+    reward: R,
+    state: GameState,
+    events: Vec<Event>,
+    observation: Vec<f32>,
+    info: Info,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Env {
+        Env::with_reward(config, Rewards::default())
+    }
+}
+
+impl<R: Reward> Env<R> {
+    /// An environment already playing an episode with seed 0.
+    pub fn with_reward(config: EnvConfig, reward: R) -> Env<R> {
+        let state = new_game(&config, 0);
+        let mut env = Env {
+            config,
+            reward,
+            state,
+            // room for the most that happens in a tick, near enough.
+            events: Vec::with_capacity(32),
+            observation: Vec::new(),
+            info: Info::default(),
+        };
+        env.reset(0);
+        env
+    }
+
+    /// Start a new episode with the game seeded by `seed`, returning what
+    /// the agent sees first.
+    pub fn reset(&mut self, seed: u64) -> &[f32] {
+        self.state = new_game(&self.config, seed);
+        self.state.reserve_arena();
+        let (width, height) = self.state.arena_size();
+        self.observation.resize(self.config.observation.size(width, height), 0.0);
+        self.info = Info {
+            length: self.state.get_snake().len(),
+            ..Info::default()
+        };
+        self.reward.reset(&self.state);
+        self.observe();
+        &self.observation
+    }
+
+    /// Play a tick with the agent doing `action`.  Returns what it sees
+    /// afterwards, what it earned, whether the episode is over, and more
+    /// about how it's going.  Stepping a finished episode does nothing and
+    /// earns nothing; `reset` starts another.
+    pub fn step(&mut self, action: Action) -> (&[f32], f32, bool, Info) {
+        if self.info.ending.is_some() {
+            return (&self.observation, 0.0, true, self.info);
+        }
+
+        let heading = self.state.get_snake().neck_direction();
+        self.state.set_direction(PlayerId(0), action.direction(heading));
+        self.events.clear();
+        let result = self.state.tick_into(&mut self.events);
+
+        self.info.steps += 1;
+        self.info.ending = match result {
+            Err(game_over) => Some(Ending::GameOver(game_over)),
+            Ok(()) if self.config.max_steps <= self.info.steps => Some(Ending::OutOfSteps),
+            Ok(()) => None,
+        };
+        let reward = self.reward.reward(&self.state, &self.events, self.info.ending);
+        self.info.length = self.state.get_snake().len();
+        self.info.food_eaten = self.state.food_eaten;
+        self.info.total_reward += reward;
+
+        self.observe();
+        (&self.observation, reward, self.info.ending.is_some(), self.info)
+    }
+
+    /// What the agent sees now.
+    pub fn observation(&self) -> &[f32] {
+        &self.observation
+    }
+
+    pub fn info(&self) -> Info {
+        self.info
+    }
+
+    /// The game being played, for showing it.
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn config(&self) -> &EnvConfig {
+        &self.config
+    }
+
+    fn observe(&mut self) {
+        let out = &mut self.observation[..];
+        match self.config.observation {
+            Observation::Planes => planes(&self.state, out),
+            Observation::Window(radius) => window(&self.state, radius, out),
+            Observation::Features => features(&self.state, out),
+        }
+    }
+}
+
+fn new_game(config: &EnvConfig, seed: u64) -> GameState {
+    match config.level {
+        Some(ref level) => level.new_game(seed),
+        None => GameState::with_rules(&config.rules, seed, 1),
+    }
+}
+
+/// Fill `out` in as `Observation::Planes` says.
+fn planes(state: &GameState, out: &mut [f32]) {
+    const HEAD: usize = 0;
+    const BODY: usize = 1;
+    const FOOD: usize = 2;
+    const WALL: usize = 3;
+
+    let area = state.arena_size;
+    let cells = area.width * area.height;
+    for value in out.iter_mut() {
+        *value = 0.0;
+    }
+
+    for (idx, player) in state.players.iter().enumerate() {
+        if player.death.is_some() {
+            continue;
+        }
+        for (segment, pos) in player.snake.cells.iter().enumerate() {
+            if let Some(cell) = area.index(pos) {
+                let plane = if idx == 0 && segment == 0 { HEAD } else { BODY };
+                out[plane * cells + cell] = 1.0;
+            }
+        }
+    }
+
+    for (pos, object) in state.objects.iter() {
+        let plane = match *object {
+            GameObject::Food | GameObject::BonusFood => FOOD,
+            GameObject::Wall | GameObject::Obstacle(_) => WALL,
+            _ => continue,
+        };
+        if let Some(cell) = area.index(pos) {
+            out[plane * cells + cell] = 1.0;
+        }
+    }
+}
+
+/// Fill `out` in as `Observation::Window` says.
+fn window(state: &GameState, radius: usize, out: &mut [f32]) {
+    let side = 2 * radius + 1;
+    let cells = side * side;
+    let snake = state.get_snake();
+    let head = snake.head_pos;
+    // one cell ahead, and one cell to the right, as steps across and down.
+    let (ahead, right) = match snake.neck_direction() {
+        Direction::North => ((0, -1), (1, 0)),
+        Direction::South => ((0, 1), (-1, 0)),
+        Direction::West => ((-1, 0), (0, -1)),
+        Direction::East => ((1, 0), (0, 1)),
+    };
+
+    for row in 0..side {
+        for col in 0..side {
+            let forward = radius as isize - row as isize;
+            let across = col as isize - radius as isize;
+            let dx = forward * ahead.0 + across * right.0;
+            let dy = forward * ahead.1 + across * right.1;
+
+            let (body, food, wall) = match offset(&state.arena_size, head, dx, dy) {
+                None => (false, false, true),
+                Some(pos) => {
+                    let body = state.players.iter()
+                        .enumerate()
+                        .filter(|&(_, player)| player.death.is_none())
+                        .any(|(idx, _)| {
+                            let own_head = if idx == 0 && pos == head { 1 } else { 0 };
+                            own_head < state.segments_at(idx, pos)
+                        });
+                    match state.objects.get(&pos) {
+                        Some(&GameObject::Food) | Some(&GameObject::BonusFood) => (body, true, false),
+                        Some(&GameObject::Wall) | Some(&GameObject::Obstacle(_)) => (body, false, true),
+                        _ => (body, false, false),
+                    }
+                },
+            };
+
+            let cell = row * side + col;
+            out[cell] = flag(body);
+            out[cells + cell] = flag(food);
+            out[2 * cells + cell] = flag(wall);
+        }
+    }
+}
+
+/// Fill `out` in as `Observation::Features` says.
+fn features(state: &GameState, out: &mut [f32]) {
+    let snake = state.get_snake();
+    let heading = snake.neck_direction();
+    let head = snake.head_pos;
+
+    for (idx, action) in ACTIONS.iter().enumerate() {
+        out[idx] = flag(is_fatal(state, action.direction(heading)));
+    }
+
+    let compass = [Direction::North, Direction::South, Direction::West, Direction::East];
+    for (idx, &dir) in compass.iter().enumerate() {
+        out[3 + idx] = flag(dir == heading);
+    }
+
+    let food = nearest_food(state, head);
+    out[7] = flag(food.is_some_and(|food| food.1 < head.1));
+    out[8] = flag(food.is_some_and(|food| head.1 < food.1));
+    out[9] = flag(food.is_some_and(|food| food.0 < head.0));
+    out[10] = flag(food.is_some_and(|food| head.0 < food.0));
+
+    let (width, height) = state.arena_size();
+    out[11] = snake.len() as f32 / (width * height) as f32;
+}
+
+/// Whether the agent's snake would die heading `dir` next tick, as far as
+/// can be told without knowing what the other snakes will do.
+fn is_fatal(state: &GameState, dir: Direction) -> bool {
+    let pos = match state.arena_size.step(state.get_snake().head_pos, dir) {
+        Some(pos) if state.arena_size.contains(&pos) => pos,
+        _ => return true,
+    };
+
+    match state.objects.get(&pos) {
+        Some(&GameObject::Wall) | Some(&GameObject::Obstacle(_)) => true,
+        _ => state.players.iter()
+            .enumerate()
+            .filter(|&(idx, player)| player.death.is_none() && !(idx == 0 && 0 < player.ghost))
+            .any(|(idx, _)| 0 < state.segments_at(idx, pos)),
+    }
+}
+
+/// The cell `dx` across and `dy` down from `pos`, wrapping around the
+/// edges the topology allows, or `None` if that's off the board.
+fn offset(area: &Area, pos: Position, dx: isize, dy: isize) -> Option<Position> {
+    let shift = |value: usize, offset: usize, by: isize, size: usize, wraps: bool| {
+        let moved = value as isize - offset as isize + by;
+        if wraps {
+            Some(offset + moved.rem_euclid(size as isize) as usize)
+        } else if 0 <= moved && moved < size as isize {
+            Some(offset + moved as usize)
+        } else {
+            None
+        }
+    };
+
+    let x = shift(pos.0, area.x_off, dx, area.width, area.topology.wraps_horizontally())?;
+    let y = shift(pos.1, area.y_off, dy, area.height, area.topology.wraps_vertically())?;
+    Some(Position(x, y))
+}
+
+/// The food nearest `pos`, not counting any way round a wrapped edge.
+fn nearest_food(state: &GameState, pos: Position) -> Option<Position> {
+    state.objects.iter()
+        .filter(|&(_, object)| *object == GameObject::Food || *object == GameObject::BonusFood)
+        .map(|(&food, _)| food)
+        .min_by_key(|&food| distance(pos, food))
+}
+
+fn distance(a: Position, b: Position) -> usize {
+    a.0.abs_diff(b.0) + a.1.abs_diff(b.1)
+}
+
+fn flag(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// Environments which step together, shared out between threads.  Each
+/// starts a new episode as soon as its last has finished, so there's
+/// always something to step.
+pub struct VecEnv<R: Reward + Send + 'static = Rewards> {
+    shared: Arc<Shared<R>>,
+    workers: Vec<thread::JoinHandle<()>>,
+    count: usize,
+    observation_size: usize,
+    observations: Vec<f32>,
+    rewards: Vec<f32>,
+    dones: Vec<bool>,
+    infos: Vec<Info>,
+}
+
+struct Shared<R> {
+    /// One batch per thread, the caller's first.
+    batches: Vec<Mutex<Batch<R>>>,
+    start: Barrier,
+    finish: Barrier,
+    quit: AtomicBool,
+    /// Why a worker's batch panicked, for the caller to panic with in
+    /// turn once every thread has finished the step.
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl<R: Reward> Shared<R> {
+    /// Step batch `idx`.  A panic, from a `Reward` say, is caught so that
+    /// the thread still gets to the end of the step; otherwise the others
+    /// would wait for it forever.
+    fn step(&self, idx: usize, stride: u64) -> thread::Result<()> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.batches[idx].lock().unwrap().step(stride)))
+    }
+}
+
+/// The environments one thread steps, and what came of the last step.
+struct Batch<R> {
+    envs: Vec<Env<R>>,
+    /// The seed for each environment's next episode.
+    seeds: Vec<u64>,
+    actions: Vec<Action>,
+    rewards: Vec<f32>,
+    dones: Vec<bool>,
+    infos: Vec<Info>,
+}
+
+impl<R: Reward> Batch<R> {
+    /// Step every environment, moving on to the seed `stride` along when
+    /// one starts a new episode.
+    fn step(&mut self, stride: u64) {
+        for (idx, env) in self.envs.iter_mut().enumerate() {
+            let (_, reward, done, info) = env.step(self.actions[idx]);
+            self.rewards[idx] = reward;
+            self.dones[idx] = done;
+            self.infos[idx] = info;
+            if done {
+                env.reset(self.seeds[idx]);
+                self.seeds[idx] = self.seeds[idx].wrapping_add(stride);
+            }
+        }
+    }
+
+    /// Start every environment afresh, the first with `seed`.
+    fn reset(&mut self, seed: u64, stride: u64) {
+        for (idx, env) in self.envs.iter_mut().enumerate() {
+            let seed = seed.wrapping_add(idx as u64);
+            env.reset(seed);
+            self.seeds[idx] = seed.wrapping_add(stride);
+        }
+    }
+}
+
+impl VecEnv {
+    pub fn new(config: EnvConfig, count: usize, threads: usize, seed: u64) -> VecEnv {
+        VecEnv::with_reward(config, count, threads, seed, Rewards::default())
+    }
+}
+
+impl<R: Reward + Clone + Send + 'static> VecEnv<R> {
+    /// `count` environments, stepped by up to `threads` threads counting
+    /// the caller's.  Environment `i` is seeded `seed + i`, and each new
+    /// episode it starts is seeded `count` further on, so a run goes the
+    /// same however many threads step it.  Panics if `count` is zero.
+    pub fn with_reward(config: EnvConfig, count: usize, threads: usize, seed: u64, reward: R) -> VecEnv<R> {
+        assert!(0 < count);
+        let per_thread = count.div_ceil(cmp::max(1, threads));
+
+        let mut batches = Vec::new();
+        for first in (0..count).step_by(per_thread) {
+            let size = cmp::min(per_thread, count - first);
+            let envs = (0..size).map(|_| Env::with_reward(config.clone(), reward.clone())).collect();
+            let mut batch = Batch {
+                envs,
+                seeds: vec![0; size],
+                actions: vec![Action::Straight; size],
+                rewards: vec![0.0; size],
+                dones: vec![false; size],
+                infos: vec![Info::default(); size],
+            };
+            batch.reset(seed.wrapping_add(first as u64), count as u64);
+            batches.push(Mutex::new(batch));
+        }
+
+        let threads = batches.len();
+        let shared = Arc::new(Shared {
+            batches,
+            start: Barrier::new(threads),
+            finish: Barrier::new(threads),
+            quit: AtomicBool::new(false),
+            panic: Mutex::new(None),
+        });
+        let workers = (1..threads).map(|idx| {
+            let shared = shared.clone();
+            let stride = count as u64;
+            thread::spawn(move || loop {
+                shared.start.wait();
+                if shared.quit.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Err(panic) = shared.step(idx, stride) {
+                    *shared.panic.lock().unwrap() = Some(panic);
+                }
+                shared.finish.wait();
+            })
+        }).collect();
+
+        let observation_size = shared.batches[0].lock().unwrap().envs[0].observation().len();
+        let mut env = VecEnv {
+            shared,
+            workers,
+            count,
+            observation_size,
+            observations: vec![0.0; count * observation_size],
+            rewards: vec![0.0; count],
+            dones: vec![false; count],
+            infos: vec![Info::default(); count],
+        };
+        env.gather();
+        env
+    }
+
+    /// How many environments there are.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// How many numbers each environment's observation has.
+    pub fn observation_size(&self) -> usize {
+        self.observation_size
+    }
+
+    /// Start every environment afresh, environment `i` with seed
+    /// `seed + i`.  Returns what they see, one after another.
+    pub fn reset(&mut self, seed: u64) -> &[f32] {
+        let mut first = 0;
+        for batch in self.shared.batches.iter() {
+            let mut batch = batch.lock().unwrap();
+            batch.reset(seed.wrapping_add(first as u64), self.count as u64);
+            first += batch.envs.len();
+        }
+        self.gather();
+        &self.observations
+    }
+
+    /// Step every environment, environment `i` doing `actions[i]`.
+    /// Returns what each sees, one after another, then what each earned,
+    /// whether its episode ended, and more about the episode.  Where an
+    /// episode ended, the next has already started: the observation is
+    /// the new episode's first, but the info is about the one that ended.
+    /// Panics unless there's an action for every environment, and if
+    /// stepping any of them panics, on whichever thread; after that the
+    /// batch can't be stepped or reset again.
+    pub fn step(&mut self, actions: &[Action]) -> (&[f32], &[f32], &[bool], &[Info]) {
+        assert_eq!(actions.len(), self.count);
+        let mut first = 0;
+        for batch in self.shared.batches.iter() {
+            let mut batch = batch.lock().unwrap();
+            let size = batch.envs.len();
+            batch.actions.copy_from_slice(&actions[first..first + size]);
+            first += size;
+        }
+
+        // the workers step the other batches meanwhile.
+        self.shared.start.wait();
+        let stepped = self.shared.step(0, self.count as u64);
+        self.shared.finish.wait();
+
+        let worker = self.shared.panic.lock().unwrap().take();
+        if let Some(panic) = stepped.err().or(worker) {
+            panic::resume_unwind(panic);
+        }
+
+        self.gather();
+        (&self.observations, &self.rewards, &self.dones, &self.infos)
+    }
+
+    /// Copy the batches' results out, in order.
+    fn gather(&mut self) {
+        let size = self.observation_size;
+        let mut first = 0;
+        for batch in self.shared.batches.iter() {
+            let batch = batch.lock().unwrap();
+            for (idx, env) in batch.envs.iter().enumerate() {
+                let at = (first + idx) * size;
+                self.observations[at..at + size].copy_from_slice(env.observation());
+            }
+
+            let end = first + batch.envs.len();
+            self.rewards[first..end].copy_from_slice(&batch.rewards);
+            self.dones[first..end].copy_from_slice(&batch.dones);
+            self.infos[first..end].copy_from_slice(&batch.infos);
+            first = end;
+        }
+    }
+}
+
+impl<R: Reward + Send + 'static> Drop for VecEnv<R> {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::SeqCst);
+        self.shared.start.wait();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::super::ai::{Controller, Hamiltonian};
+    use super::super::rules::Rules;
+    use super::super::sorted_map::SortedMap;
+    use super::super::{DeathCause, Event, GameObject, GameOver, GameState, PlayerId, Topology};
+    use super::{Action, EnvConfig, Ending, Env, Observation, Reward, VecEnv, ACTIONS, FEATURES};
+
+    /// Counts allocations made on the thread which asks, so tests running
+    /// alongside don't get in the way.
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    fn config(observation: Observation) -> EnvConfig {
+        EnvConfig {
+            rules: Rules { width: 8, height: 8, ..Rules::default() },
+            observation,
+            ..EnvConfig::default()
+        }
+    }
+
+    #[test]
+    fn actions_turn_relative_to_the_heading() {
+        use super::super::Direction::*;
+        assert_eq!(Action::Left.direction(North), West);
+        assert_eq!(Action::Right.direction(North), East);
+        assert_eq!(Action::Left.direction(East), North);
+        assert_eq!(Action::Right.direction(West), North);
+        assert_eq!(Action::Straight.direction(South), South);
+        for &action in ACTIONS.iter() {
+            assert_eq!(Action::from_index(action.index()), Some(action));
+        }
+        assert_eq!(Action::from_index(3), None);
+    }
+
+    #[test]
+    fn episodes_end_with_the_game() {
+        let mut env = Env::new(config(Observation::Features));
+        assert_eq!(env.reset(5).len(), FEATURES);
+
+        // the snake starts at (2, 4) heading east, into the edge.
+        let mut steps = 0;
+        loop {
+            let (_, reward, done, info) = env.step(Action::Straight);
+            steps += 1;
+            if done {
+                assert_eq!(reward, -1.0);
+                assert_eq!(info.ending, Some(Ending::GameOver(GameOver::Died(DeathCause::Boundary((7, 4))))));
+                assert_eq!(info.steps, steps);
+                break;
+            }
+        }
+        assert_eq!(env.step(Action::Left).1, 0.0);
+        assert!(env.step(Action::Left).2);
+
+        env.reset(5);
+        assert_eq!(env.info().steps, 0);
+        assert_eq!(env.info().ending, None);
+    }
+
+    #[test]
+    fn long_episodes_are_cut_short() {
+        let mut env = Env::new(EnvConfig {
+            rules: Rules { topology: Topology::Torus, ..config(Observation::Features).rules },
+            max_steps: 3,
+            ..config(Observation::Features)
+        });
+        env.step(Action::Straight);
+        env.step(Action::Straight);
+        let (_, _, done, info) = env.step(Action::Straight);
+        assert!(done);
+        assert_eq!(info.ending, Some(Ending::OutOfSteps));
+    }
+
+    #[test]
+    fn observations_show_the_board() {
+        let mut env = Env::new(config(Observation::Planes));
+        env.state.objects = SortedMap::new();
+        env.state.index_cells();
+        env.state.place_object((3, 4), GameObject::Wall, None);
+        env.state.place_object((2, 2), GameObject::Food, None);
+        env.reward.approach = 0.5;
+        env.observe();
+        let planes = env.observation();
+        assert_eq!(planes.len(), 4 * 64);
+        assert_eq!(planes[4 * 8 + 2], 1.0);
+        assert_eq!(planes[2 * 64 + 2 * 8 + 2], 1.0);
+        assert_eq!(planes[3 * 64 + 4 * 8 + 3], 1.0);
+        assert_eq!(planes[64..2 * 64].iter().sum::<f32>(), 0.0);
+
+        // heading east, the wall is just ahead and the food two cells
+        // to the left.
+        env.config.observation = Observation::Window(2);
+        env.observation.resize(3 * 25, 0.0);
+        env.observe();
+        let window = env.observation();
+        assert_eq!(window[2 * 25 + 5 + 2], 1.0);
+        assert_eq!(window[25 + 2 * 5], 1.0);
+        assert_eq!(window[..25].iter().sum::<f32>(), 0.0);
+
+        env.config.observation = Observation::Features;
+        env.observation.resize(FEATURES, 0.0);
+        env.observe();
+        assert_eq!(env.observation(), &[
+            1.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+            1.0, 0.0, 0.0, 0.0,
+            1.0 / 64.0,
+        ][..]);
+
+        // turning left heads for the food.
+        let (_, reward, _, _) = env.step(Action::Left);
+        assert_eq!(reward, 0.5);
+    }
+
+    #[test]
+    fn steps_dont_allocate() {
+        for &observation in [Observation::Planes, Observation::Window(3), Observation::Features].iter() {
+            let mut env = Env::new(config(observation));
+            let mut controller = Hamiltonian::new();
+            let mut allocated = 0;
+            for step in 0..500 {
+                let heading = env.state().get_snake().neck_direction();
+                let dir = controller.choose(env.state(), PlayerId(0)).unwrap_or(heading);
+                let action = ACTIONS.iter().cloned().find(|action| action.direction(heading) == dir).unwrap();
+
+                let before = allocations();
+                let done = env.step(action).2;
+                // the first step sizes the buffers.
+                if 0 < step {
+                    allocated += allocations() - before;
+                }
+                assert!(!done);
+            }
+            assert!(10 < env.info().food_eaten);
+            assert_eq!(allocated, 0);
+        }
+    }
+
+    #[test]
+    fn batches_step_the_same_on_any_number_of_threads() {
+        let setup = config(Observation::Window(2));
+        let mut alone = VecEnv::new(setup.clone(), 5, 1, 7);
+        let mut shared = VecEnv::new(setup, 5, 3, 7);
+        assert_eq!(alone.len(), 5);
+        assert_eq!(alone.observation_size(), 75);
+
+        let mut finished = 0;
+        for step in 0..200 {
+            let actions: Vec<_> = (0..5).map(|idx| ACTIONS[(step + idx) % 3]).collect();
+            let one = alone.step(&actions);
+            let many = shared.step(&actions);
+            assert_eq!(one, many);
+            finished += one.2.iter().filter(|&&done| done).count();
+        }
+        assert!(10 < finished);
+
+        assert_eq!(alone.reset(3), shared.reset(3));
+        let mut single = Env::new(config(Observation::Window(2)));
+        assert_eq!(&alone.reset(3)[75..150], single.reset(4));
+    }
+
+    /// Panics when asked about the game seeded with its seed.
+    #[derive(Clone)]
+    struct Explodes(u64);
+
+    impl Reward for Explodes {
+        fn reward(&mut self, state: &GameState, _: &[Event], _: Option<Ending>) -> f32 {
+            assert!(state.seed() != self.0, "boom");
+            0.0
+        }
+    }
+
+    #[test]
+    fn panics_while_stepping_reach_the_caller() {
+        // the last environment is a worker's, and the first the caller's.
+        for &seed in [11, 7].iter() {
+            let mut envs = VecEnv::with_reward(config(Observation::Features), 5, 3, 7, Explodes(seed));
+            let actions = [Action::Straight; 5];
+            let panic = panic::catch_unwind(AssertUnwindSafe(|| {
+                envs.step(&actions);
+            })).unwrap_err();
+            assert_eq!(panic.downcast_ref::<&str>(), Some(&"boom"));
+            // and the workers still shut down.
+            drop(envs);
+        }
+    }
+}
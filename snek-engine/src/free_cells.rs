@@ -0,0 +1,149 @@
+/// The cells of the arena with nothing in them, for placing food.
+///
+/// Each cell counts the snake segments and objects in it, and a Fenwick
+/// tree over the empty cells finds the `n`th of them in O(log n).  Cells
+/// are always counted in order, so it doesn't matter how the board came
+/// to be the way it is: a restored game places food exactly where the
+/// original would have.
+#[derive(Clone, Debug)]
+pub struct FreeCells {
+    counts: Vec<u32>,
+    /// How many cells are empty, as a 1-based Fenwick tree.
+    tree: Vec<usize>,
+    free: usize,
+}
+
+impl FreeCells {
+    /// An index of `cells` cells, all of them empty.
+    pub fn new(cells: usize) -> FreeCells {
+        let mut tree = vec![0; cells + 1];
+        for idx in 1..=cells {
+            tree[idx] += 1;
+            let parent = idx + lowest_bit(idx);
+            if parent <= cells {
+                tree[parent] += tree[idx];
+            }
+        }
+
+        FreeCells {
+            counts: vec![0; cells],
+            tree,
+            free: cells,
+        }
+    }
+
+    /// The number of empty cells.
+    pub fn len(&self) -> usize {
+        self.free
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free == 0
+    }
+
+    pub fn is_free(&self, cell: usize) -> bool {
+        self.counts[cell] == 0
+    }
+
+    /// Put something in a cell, which may already hold something else.
+    pub fn occupy(&mut self, cell: usize) {
+        self.counts[cell] += 1;
+        if self.counts[cell] == 1 {
+            self.update(cell, false);
+        }
+    }
+
+    /// Take one thing out of a cell.  Panics if there's nothing there.
+    pub fn vacate(&mut self, cell: usize) {
+        assert!(0 < self.counts[cell]);
+        self.counts[cell] -= 1;
+        if self.counts[cell] == 0 {
+            self.update(cell, true);
+        }
+    }
+
+    /// The `n`th empty cell, counting from zero in cell order.
+    pub fn nth(&self, n: usize) -> Option<usize> {
+        if self.free <= n {
+            return None;
+        }
+
+        // walk down the tree to the last cell with at most `n` empty
+        // cells before and including it; the one after is the answer.
+        let mut pos = 0;
+        let mut rest = n + 1;
+        let mut step = self.counts.len().next_power_of_two();
+        while 0 < step {
+            let next = pos + step;
+            if next < self.tree.len() && self.tree[next] < rest {
+                pos = next;
+                rest -= self.tree[next];
+            }
+            step >>= 1;
+        }
+        Some(pos)
+    }
+
+    fn update(&mut self, cell: usize, freed: bool) {
+        let mut idx = cell + 1;
+        while idx < self.tree.len() {
+            if freed {
+                self.tree[idx] += 1;
+            } else {
+                self.tree[idx] -= 1;
+            }
+            idx += lowest_bit(idx);
+        }
+
+        if freed {
+            self.free += 1;
+        } else {
+            self.free -= 1;
+        }
+    }
+}
+
+fn lowest_bit(idx: usize) -> usize {
+    idx & idx.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FreeCells;
+
+    #[test]
+    fn finds_the_nth_free_cell() {
+        let mut cells = FreeCells::new(10);
+        assert_eq!(cells.len(), 10);
+        assert_eq!(cells.nth(9), Some(9));
+        assert_eq!(cells.nth(10), None);
+
+        for &cell in [0, 3, 4, 9].iter() {
+            cells.occupy(cell);
+        }
+        assert_eq!(cells.len(), 6);
+        let free: Vec<_> = (0..cells.len()).map(|n| cells.nth(n).unwrap()).collect();
+        assert_eq!(free, vec![1, 2, 5, 6, 7, 8]);
+        assert!(!cells.is_free(3));
+        assert!(cells.is_free(5));
+    }
+
+    #[test]
+    fn cells_stay_taken_until_empty() {
+        let mut cells = FreeCells::new(3);
+        cells.occupy(1);
+        cells.occupy(1);
+        cells.vacate(1);
+        assert!(!cells.is_free(1));
+        assert_eq!(cells.len(), 2);
+        cells.vacate(1);
+        assert_eq!(cells.nth(1), Some(1));
+
+        for cell in 0..3 {
+            cells.occupy(cell);
+        }
+        assert!(cells.is_empty());
+        assert_eq!(cells.nth(0), None);
+        assert!(FreeCells::new(0).is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Buffering turns between ticks.
+//!
+//! A player can press two keys inside one tick, say up then left to turn
+//! a corner.  Keeping only the last of them loses the first turn, so each
+//! player gets an `InputQueue` which hands the game one turn a tick, in
+//! the order they were pressed.
+
+use std::cmp;
+use std::collections::VecDeque;
+
+use super::{Direction, GameState, PlayerId};
+
+/// How many turns a queue holds unless asked for another depth.
+pub const DEFAULT_DEPTH: usize = 3;
+
+/// The turns one player has asked for which the snake hasn't made yet.
+#[derive(Debug, Clone)]
+pub struct InputQueue {
+    turns: VecDeque<Direction>,
+    depth: usize,
+}
+
+impl InputQueue {
+    pub fn new() -> InputQueue {
+        InputQueue::with_depth(DEFAULT_DEPTH)
+    }
+
+    /// A queue holding at most `depth` turns.  Panics if `depth` is zero.
+    pub fn with_depth(depth: usize) -> InputQueue {
+        assert!(0 < depth);
+        InputQueue {
+            turns: VecDeque::with_capacity(depth),
+            depth,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The number of turns waiting.
+    pub fn len(&self) -> usize {
+        self.turns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    /// Forget every waiting turn, for a new game or when the computer
+    /// takes over.
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+
+    /// Queue a turn for `player`'s snake, and say whether it was kept.
+    ///
+    /// A turn is checked against the last one queued, or the way the
+    /// snake is heading when there's none: carrying straight on changes
+    /// nothing and turning back into the neck isn't allowed, so both are
+    /// dropped, as are turns for a full queue or a dead snake.
+    pub fn push(&mut self, state: &GameState, player: PlayerId, turn: Direction) -> bool {
+        let heading = match self.turns.back() {
+            Some(&heading) => heading,
+            None => match state.snake(player) {
+                Some(snake) if state.is_alive(player) => snake.neck_direction(),
+                _ => return false,
+            },
+        };
+
+        if self.depth <= self.turns.len() || turn == heading || turn == heading.negate() {
+            return false;
+        }
+        self.turns.push_back(turn);
+        true
+    }
+
+    /// Steer `player`'s snake with the next waiting turn.  Call this once
+    /// a tick, just before `GameState::tick`.
+    pub fn steer(&mut self, state: &mut GameState, player: PlayerId) {
+        if let Some(turn) = self.turns.pop_front() {
+            state.set_direction(player, turn);
+        }
+    }
+}
+
+impl Default for InputQueue {
+    fn default() -> InputQueue {
+        InputQueue::new()
+    }
+}
+
+/// The player WASD steers: the second, or with one player, the only one,
+/// so that either hand can play alone.  The arrows always steer the first.
+pub fn wasd_player(players: usize) -> PlayerId {
+    PlayerId(cmp::min(1, players.saturating_sub(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Topology;
+
+    fn head(state: &GameState, player: PlayerId) -> (usize, usize) {
+        state.snake(player).unwrap().positions().next().unwrap()
+    }
+
+    #[test]
+    fn a_double_tap_makes_both_turns() {
+        // the snake starts at (5, 5) heading east.
+        let mut state = GameState::with_seed(20, 10, 1);
+        let mut queue = InputQueue::new();
+        assert!(queue.push(&state, PlayerId(0), Direction::North));
+        assert!(queue.push(&state, PlayerId(0), Direction::West));
+        assert_eq!(queue.len(), 2);
+
+        queue.steer(&mut state, PlayerId(0));
+        state.tick().unwrap();
+        assert_eq!(head(&state, PlayerId(0)), (5, 4));
+
+        queue.steer(&mut state, PlayerId(0));
+        state.tick().unwrap();
+        assert_eq!(head(&state, PlayerId(0)), (4, 4));
+        assert!(queue.is_empty());
+
+        // with nothing queued the snake carries on.
+        queue.steer(&mut state, PlayerId(0));
+        state.tick().unwrap();
+        assert_eq!(head(&state, PlayerId(0)), (3, 4));
+    }
+
+    #[test]
+    fn reversals_are_checked_against_the_queue() {
+        let state = GameState::with_seed(20, 10, 1);
+        let mut queue = InputQueue::new();
+
+        // heading east, so west is back into the neck, and east is no turn.
+        assert!(!queue.push(&state, PlayerId(0), Direction::West));
+        assert!(!queue.push(&state, PlayerId(0), Direction::East));
+
+        // after turning north, south is the reversal and west is fine.
+        assert!(queue.push(&state, PlayerId(0), Direction::North));
+        assert!(!queue.push(&state, PlayerId(0), Direction::South));
+        assert!(!queue.push(&state, PlayerId(0), Direction::North));
+        assert!(queue.push(&state, PlayerId(0), Direction::West));
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn the_queue_is_bounded() {
+        let state = GameState::with_seed(20, 10, 1);
+        let mut queue = InputQueue::with_depth(2);
+        assert!(queue.push(&state, PlayerId(0), Direction::North));
+        assert!(queue.push(&state, PlayerId(0), Direction::West));
+        assert!(!queue.push(&state, PlayerId(0), Direction::South));
+        assert_eq!(queue.len(), queue.depth());
+
+        queue.clear();
+        assert!(queue.push(&state, PlayerId(0), Direction::South));
+    }
+
+    #[test]
+    fn dead_snakes_take_no_turns() {
+        let mut state = GameState::with_players(20, 10, 1, Topology::Bounded, 3);
+        state.set_direction(PlayerId(2), Direction::North);
+        while state.is_alive(PlayerId(2)) {
+            state.tick().unwrap();
+        }
+
+        let mut queue = InputQueue::new();
+        assert!(!queue.push(&state, PlayerId(2), Direction::West));
+        assert!(!queue.push(&state, PlayerId(7), Direction::West));
+    }
+}
@@ -0,0 +1,525 @@
+//! Levels loaded from text maps.
+//!
+//! A level file is a header of `key: value` lines, a `---` separator, and
+//! then the map itself, one line per row:
+//!
+//! ```text
+//! name: The Box
+//! size: 32x32
+//! start: 8,16 east
+//! length: 3
+//! food: 1
+//! goal: 15
+//! wrap: none
+//! ---
+//! ################################
+//! #..............................#
+//! ...
+//! ```
+//!
+//! In the map, `#` is a wall, `.` is empty floor and `*` is a piece of
+//! food present from the start.  `start` is where the snake's head is and
+//! which way it's heading; the rest of its `length` trails out behind it.
+//! `food` is how many pieces of food are kept on the board at once, and
+//! the optional `goal` is how many must be eaten to finish the level.
+//! The optional `wrap` is one of `none`, `horizontal`, `vertical` or
+//! `both`, saying which edges of the map lead round to the opposite side.
+//!
+//! The optional `power-ups` lists how often power-ups appear, as in
+//! `power-ups: bonus-food 20, ghost 5`: each kind from `powerup::NAMES`
+//! and its chance in a thousand of appearing on any tick.  The map can
+//! also hold obstacles from the start, drawn as `>`, `<`, `^` or `v` for
+//! the way they're sliding.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::{
+    direction_from_name,
+    Area,
+    Direction,
+    FreeCells,
+    GameObject,
+    GameRng,
+    GameState,
+    Occupancy,
+    Player,
+    Position,
+    Scratch,
+    Snake,
+    SortedMap,
+    Topology,
+    WinCondition,
+};
+use super::powerup::{self, SpawnRates};
+use super::rules::SpeedCurve;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LevelError {
+    /// 1-based, or 0 if the error isn't about any particular line.
+    pub line: usize,
+    /// 1-based, or 0 if the error is about the line as a whole.
+    pub column: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ErrorKind {
+    Io(io::ErrorKind),
+    /// A header line without a `key: value` shape.
+    MalformedHeader,
+    UnknownKey(String),
+    DuplicateKey(String),
+    MissingKey(&'static str),
+    /// The value doesn't parse; says what was expected.
+    BadValue(&'static str),
+    /// No `---` line between the header and the map.
+    MissingMap,
+    RowLength { expected: usize, found: usize },
+    RowCount { expected: usize, found: usize },
+    UnknownTile(char),
+    /// Part of the snake is off the map or would start inside a wall.
+    SnakeBlocked,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Level {
+    name: String,
+    width: usize,
+    height: usize,
+    start: (usize, usize),
+    direction: Direction,
+    length: usize,
+    food: usize,
+    goal: Option<u64>,
+    topology: Topology,
+    walls: Vec<(usize, usize)>,
+    initial_food: Vec<(usize, usize)>,
+    obstacles: Vec<((usize, usize), Direction)>,
+    spawn_rates: SpawnRates,
+}
+
+impl Level {
+    pub fn load(path: &Path) -> Result<Level, LevelError> {
+        let io_error = |err: io::Error| LevelError { line: 0, column: 0, kind: ErrorKind::Io(err.kind()) };
+
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(io_error)?;
+        Level::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Level, LevelError> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+
+        let mut header = BTreeMap::new();
+        let separator_line = loop {
+            let (lineno, line) = match lines.next() {
+                Some(line) => line,
+                None => return Err(error(text.lines().count(), 0, ErrorKind::MissingMap)),
+            };
+            if line.trim() == "---" {
+                break lineno;
+            }
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None => return Err(error(lineno, 1, ErrorKind::MalformedHeader)),
+            };
+            let key = line[..colon].trim();
+            let value = line[colon + 1..].trim();
+            let value_column = column_of(line, colon + 1 + (line[colon + 1..].len() - line[colon + 1..].trim_start().len()));
+
+            if !["name", "size", "start", "length", "food", "goal", "wrap", "power-ups"].contains(&key) {
+                return Err(error(lineno, 1, ErrorKind::UnknownKey(key.to_string())));
+            }
+            if header.insert(key, (lineno, value_column, value)).is_some() {
+                return Err(error(lineno, 1, ErrorKind::DuplicateKey(key.to_string())));
+            }
+        };
+
+        let field = |key: &'static str| {
+            header.get(key).cloned()
+                .ok_or_else(|| error(separator_line, 0, ErrorKind::MissingKey(key)))
+        };
+
+        let name = field("name")?.2.to_string();
+
+        let (lineno, column, size) = field("size")?;
+        let (width, height) = parse_pair(size, 'x')
+            .filter(|&(w, h)| 0 < w && 0 < h)
+            .ok_or_else(|| error(lineno, column, ErrorKind::BadValue("a size like 32x24")))?;
+
+        let (start_line, start_column, start) = field("start")?;
+        let bad_start = || error(start_line, start_column, ErrorKind::BadValue("a start like 8,12 east"));
+        let mut words = start.split_whitespace();
+        let start = words.next().and_then(|pos| parse_pair(pos, ',')).ok_or_else(bad_start)?;
+        let direction = words.next().and_then(direction_from_name).ok_or_else(bad_start)?;
+        if words.next().is_some() {
+            return Err(bad_start());
+        }
+
+        let (lineno, column, length) = field("length")?;
+        let length = length.parse().ok()
+            .filter(|&length| 0 < length)
+            .ok_or_else(|| error(lineno, column, ErrorKind::BadValue("a positive length")))?;
+
+        let (lineno, column, food) = field("food")?;
+        let food = food.parse()
+            .map_err(|_| error(lineno, column, ErrorKind::BadValue("a number of pieces of food")))?;
+
+        let goal = match header.get("goal") {
+            Some(&(lineno, column, goal)) => {
+                let goal = goal.parse()
+                    .map_err(|_| error(lineno, column, ErrorKind::BadValue("a number of pieces of food")))?;
+                Some(goal)
+            },
+            None => None,
+        };
+
+        let topology = match header.get("wrap") {
+            Some(&(lineno, column, wrap)) => match wrap {
+                "none" => Topology::Bounded,
+                "horizontal" => Topology::WrapHorizontal,
+                "vertical" => Topology::WrapVertical,
+                "both" => Topology::Torus,
+                _ => return Err(error(lineno, column, ErrorKind::BadValue("none, horizontal, vertical or both"))),
+            },
+            None => Topology::Bounded,
+        };
+
+        let mut spawn_rates = SpawnRates::default();
+        if let Some(&(lineno, column, rates)) = header.get("power-ups") {
+            let bad_rates = || error(lineno, column, ErrorKind::BadValue("power-ups like bonus-food 20, ghost 5"));
+            for rate in rates.split(',') {
+                let mut words = rate.split_whitespace();
+                let (name, rate) = match (words.next(), words.next().and_then(|rate| rate.parse().ok()), words.next()) {
+                    (Some(name), Some(rate), None) if rate <= 1000 => (name, rate),
+                    _ => return Err(bad_rates()),
+                };
+                if !powerup::NAMES.contains(&name) || spawn_rates.get(name) != Some(0) {
+                    return Err(bad_rates());
+                }
+                spawn_rates.set(name, rate);
+            }
+        }
+
+        let mut walls = Vec::new();
+        let mut initial_food = Vec::new();
+        let mut obstacles = Vec::new();
+        let mut rows = 0;
+        let mut last_line = separator_line;
+        for (lineno, line) in lines {
+            last_line = lineno;
+            if height <= rows {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Err(error(lineno, 0, ErrorKind::RowCount { expected: height, found: rows + 1 }));
+            }
+
+            let found = line.chars().count();
+            if found != width {
+                return Err(error(lineno, 0, ErrorKind::RowLength { expected: width, found }));
+            }
+            for (x, tile) in line.chars().enumerate() {
+                match tile {
+                    '#' => walls.push((x, rows)),
+                    '*' => initial_food.push((x, rows)),
+                    '^' => obstacles.push(((x, rows), Direction::North)),
+                    'v' => obstacles.push(((x, rows), Direction::South)),
+                    '<' => obstacles.push(((x, rows), Direction::West)),
+                    '>' => obstacles.push(((x, rows), Direction::East)),
+                    '.' => (),
+                    other => return Err(error(lineno, x + 1, ErrorKind::UnknownTile(other))),
+                }
+            }
+            rows += 1;
+        }
+        if rows < height {
+            return Err(error(last_line + 1, 0, ErrorKind::RowCount { expected: height, found: rows }));
+        }
+
+        let level = Level {
+            name,
+            width,
+            height,
+            start,
+            direction,
+            length,
+            food,
+            goal,
+            topology,
+            walls,
+            initial_food,
+            obstacles,
+            spawn_rates,
+        };
+
+        // every cell of the snake must be on open floor.
+        let area = level.area();
+        let mut pos = Some(Position(start.0, start.1));
+        for _ in 0..length {
+            let cell = match pos {
+                Some(cell) if area.contains(&cell) => cell,
+                _ => return Err(bad_start()),
+            };
+            let (x, y) = cell.to_tuple();
+            let blocked = level.walls.contains(&(x, y)) ||
+                level.initial_food.contains(&(x, y)) ||
+                level.obstacles.iter().any(|&(pos, _)| pos == (x, y));
+            if blocked {
+                return Err(error(separator_line + 1 + y, x + 1, ErrorKind::SnakeBlocked));
+            }
+            pos = area.step(cell, direction.negate());
+        }
+
+        Ok(level)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// How many pieces of food must be eaten to finish the level, if the
+    /// level can be finished at all.
+    pub fn goal(&self) -> Option<u64> {
+        self.goal
+    }
+
+    fn area(&self) -> Area {
+        Area {
+            x_off: 0,
+            y_off: 0,
+            width: self.width,
+            height: self.height,
+            topology: self.topology,
+        }
+    }
+
+    /// Start a game on this level.
+    pub fn new_game(&self, seed: u64) -> GameState {
+        let mut objects = BTreeMap::new();
+        for &(x, y) in self.walls.iter() {
+            objects.insert(Position(x, y), GameObject::Wall);
+        }
+        for &(x, y) in self.initial_food.iter() {
+            objects.insert(Position(x, y), GameObject::Food);
+        }
+        for &((x, y), dir) in self.obstacles.iter() {
+            objects.insert(Position(x, y), GameObject::Obstacle(dir));
+        }
+
+        let mut body = VecDeque::new();
+        for _ in 0..self.length {
+            body.push_back(self.direction);
+        }
+
+        let mut state = GameState {
+            arena_size: self.area(),
+            players: vec![Player {
+                snake: Snake::new(Position(self.start.0, self.start.1), body, self.area()),
+                direction: self.direction,
+                death: None,
+                ghost: 0,
+            }],
+            win_condition: WinCondition::LastSnakeStanding,
+            objects: objects.into_iter().collect(),
+            lifetimes: SortedMap::new(),
+            spawn_rates: self.spawn_rates,
+            force_grow: false,
+            food_target: self.food,
+            free_cells: FreeCells::new(0),
+            occupied: Occupancy::new(0, 0),
+            legacy_food: false,
+            scratch: Scratch::default(),
+            rng: GameRng::new(seed),
+            speed: SpeedCurve::default(),
+            speed_effect: None,
+            start_length: self.length,
+            food_eaten: 0,
+        };
+        state.index_cells();
+        state
+    }
+}
+
+const BUILTIN: &[&str] = &[
+    include_str!("../levels/01-open.txt"),
+    include_str!("../levels/02-box.txt"),
+    include_str!("../levels/03-pillars.txt"),
+    include_str!("../levels/04-corridors.txt"),
+];
+
+/// The levels that ship with the game, in the order they're played.
+pub fn builtin() -> Vec<Level> {
+    BUILTIN.iter()
+        .map(|text| Level::parse(text).expect("built-in levels are valid"))
+        .collect()
+}
+
+/// A sequence of levels, played one after the other.
+pub struct Campaign {
+    levels: Vec<Level>,
+    current: usize,
+}
+
+impl Campaign {
+    /// Panics if `levels` is empty.
+    pub fn new(levels: Vec<Level>) -> Campaign {
+        assert!(!levels.is_empty());
+        Campaign { levels, current: 0 }
+    }
+
+    pub fn builtin() -> Campaign {
+        Campaign::new(builtin())
+    }
+
+    pub fn current(&self) -> &Level {
+        &self.levels[self.current]
+    }
+
+    /// Which level we're on, from zero.
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    /// Whether eating `food_eaten` pieces of food finishes the current
+    /// level.
+    pub fn is_complete(&self, food_eaten: u64) -> bool {
+        match self.current().goal {
+            Some(goal) => goal <= food_eaten,
+            None => false,
+        }
+    }
+
+    /// Move on to the next level, or return `None` if that was the last.
+    pub fn advance(&mut self) -> Option<&Level> {
+        if self.current + 1 < self.levels.len() {
+            self.current += 1;
+            Some(&self.levels[self.current])
+        } else {
+            None
+        }
+    }
+
+    /// Go back to the first level.
+    pub fn restart(&mut self) {
+        self.current = 0;
+    }
+}
+
+fn error(line: usize, column: usize, kind: ErrorKind) -> LevelError {
+    LevelError { line, column, kind }
+}
+
+/// 1-based column of the character at byte offset `offset`.
+fn column_of(line: &str, offset: usize) -> usize {
+    line[..offset].chars().count() + 1
+}
+
+fn parse_pair(text: &str, sep: char) -> Option<(usize, usize)> {
+    let mut parts = text.splitn(2, sep);
+    let a = parts.next()?.trim().parse().ok()?;
+    let b = parts.next()?.trim().parse().ok()?;
+    Some((a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builtin, Campaign, ErrorKind, Level, LevelError};
+    use super::super::{DeathCause, Direction, GameObject, GameOver};
+
+    const TINY: &str = "\
+name: Tiny
+size: 6x4
+start: 3,1 east
+length: 2
+food: 1
+---
+######
+#....#
+#.*..#
+######
+";
+
+    #[test]
+    fn builtin_levels_parse() {
+        let levels = builtin();
+        assert_eq!(levels.len(), 4);
+        for level in levels.iter() {
+            level.new_game(1);
+        }
+    }
+
+    #[test]
+    fn walls_kill() {
+        let level = Level::parse(TINY).unwrap();
+        assert_eq!(level.name(), "Tiny");
+        assert_eq!(level.size(), (6, 4));
+
+        let mut state = level.new_game(3);
+        assert_eq!(state.get_snake().positions().collect::<Vec<_>>(), vec![(3, 1), (2, 1)]);
+        state.tick().unwrap();
+        assert_eq!(state.tick(), Err(GameOver::Died(DeathCause::Wall((5, 1)))));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let parse_err = |text: &str| Level::parse(text).unwrap_err();
+
+        let text = TINY.replace("#.*..#", "#.?..#");
+        assert_eq!(parse_err(&text), LevelError { line: 9, column: 3, kind: ErrorKind::UnknownTile('?') });
+
+        let text = TINY.replace("size: 6x4", "size: six by four");
+        assert_eq!(parse_err(&text), LevelError { line: 2, column: 7, kind: ErrorKind::BadValue("a size like 32x24") });
+
+        let text = TINY.replace("#....#", "#...#");
+        assert_eq!(parse_err(&text), LevelError { line: 8, column: 0, kind: ErrorKind::RowLength { expected: 6, found: 5 } });
+
+        let text = TINY.replace("start: 3,1", "start: 2,2");
+        assert_eq!(parse_err(&text), LevelError { line: 9, column: 3, kind: ErrorKind::SnakeBlocked });
+
+        let text = TINY.replace("food: 1\n", "");
+        assert_eq!(parse_err(&text), LevelError { line: 5, column: 0, kind: ErrorKind::MissingKey("food") });
+    }
+
+    #[test]
+    fn power_ups_and_obstacles() {
+        let text = TINY.replace("food: 1\n", "food: 1\npower-ups: ghost 5, portal 1\n").replace("#....#", "#...v#");
+        let level = Level::parse(&text).unwrap();
+        let state = level.new_game(1);
+        assert_eq!(state.spawn_rates().ghost, 5);
+        assert_eq!(state.spawn_rates().portal, 1);
+        assert!(state.object_iter().any(|(pos, obj, _)| pos == (4, 1) && *obj == GameObject::Obstacle(Direction::South)));
+
+        let text = TINY.replace("food: 1\n", "food: 1\npower-ups: cake 5\n");
+        assert_eq!(Level::parse(&text).unwrap_err().kind, ErrorKind::BadValue("power-ups like bonus-food 20, ghost 5"));
+    }
+
+    #[test]
+    fn campaign_progression() {
+        let mut campaign = Campaign::builtin();
+        assert_eq!(campaign.index(), 0);
+        assert!(!campaign.is_complete(9));
+        assert!(campaign.is_complete(10));
+        while campaign.advance().is_some() {}
+        assert_eq!(campaign.index(), campaign.levels().len() - 1);
+        campaign.restart();
+        assert_eq!(campaign.current().name(), "Open Field");
+    }
+}
@@ -1,19 +1,87 @@
 extern crate rand;
 
-use rand::Rng;
-use rand::distributions::range::{Range, SampleRange};
+use rand::distributions::range::Range;
 use rand::distributions::IndependentSample;
 
 use std::collections::{
-    BTreeMap,
-    btree_map,
     VecDeque,
     vec_deque,
 };
+use std::fmt;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::mem;
+use std::time::Duration;
 
+pub use self::rng::GameRng;
+
+use self::free_cells::FreeCells;
+use self::occupancy::Occupancy;
+use self::sorted_map::SortedMap;
+
+mod free_cells;
+mod occupancy;
+mod rng;
+mod sorted_map;
+pub mod ai;
+pub mod env;
+pub mod input;
+pub mod level;
+pub mod powerup;
+pub mod replay;
+pub mod rules;
+pub mod score;
+pub mod snapshot;
+
+/// Something on the board other than a snake.  See the `powerup` module
+/// for what the kinds beyond food and walls do.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum GameObject {
     Food,
     Wall,
+    /// Food worth more than the usual, which doesn't stay long.
+    BonusFood,
+    /// Takes cells off the tail of whoever eats it.
+    Shrink,
+    /// Speeds the game up for a while.
+    Fast,
+    /// Slows the game down for a while.
+    Slow,
+    /// Lets whoever eats it pass through their own body for a while.
+    Ghost,
+    /// One end of a portal; the other end is at this position.
+    Portal((usize, usize)),
+    /// A block that slides this way each tick, and kills like a wall.
+    Obstacle(Direction),
+}
+
+/// What happens when the snake reaches the edge of the arena.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub enum Topology {
+    /// The edges are deadly.
+    Bounded,
+    /// Leaving through the left or right edge comes back in the other.
+    WrapHorizontal,
+    /// Leaving through the top or bottom edge comes back in the other.
+    WrapVertical,
+    /// Every edge wraps around.
+    Torus,
+}
+
+impl Topology {
+    pub fn wraps_horizontally(&self) -> bool {
+        match *self {
+            Topology::WrapHorizontal | Topology::Torus => true,
+            Topology::Bounded | Topology::WrapVertical => false,
+        }
+    }
+
+    pub fn wraps_vertically(&self) -> bool {
+        match *self {
+            Topology::WrapVertical | Topology::Torus => true,
+            Topology::Bounded | Topology::WrapHorizontal => false,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
@@ -22,6 +90,7 @@ struct Area {
     y_off: usize,
     width: usize,
     height: usize,
+    topology: Topology,
 }
 
 impl Area {
@@ -33,13 +102,58 @@ impl Area {
         self.x_off <= pos.0 && pos.0 < x_end &&
         self.y_off <= pos.1 && pos.1 < y_end
     }
+
+    /// Where a cell comes in the area, row by row, if it's in the area.
+    fn index(&self, pos: &Position) -> Option<usize> {
+        if self.contains(pos) {
+            Some((pos.1 - self.y_off) * self.width + (pos.0 - self.x_off))
+        } else {
+            None
+        }
+    }
+
+    /// The cell at `index`, the other way round from `Area::index`.
+    fn position(&self, index: usize) -> Position {
+        Position(self.x_off + index % self.width, self.y_off + index / self.width)
+    }
+
+    /// The position one step from `pos` in `dir`, wrapping around the
+    /// edges the topology allows.  `pos` must be inside the area for
+    /// wrapping to happen; otherwise this is `Position::adjacent`.
+    fn step(&self, pos: Position, dir: Direction) -> Option<Position> {
+        if !self.contains(&pos) {
+            return pos.adjacent(dir);
+        }
+
+        let wrap = |value: usize, offset: usize, size: usize, forward: bool| {
+            let rel = value - offset;
+            let rel = if forward { (rel + 1) % size } else { (rel + size - 1) % size };
+            offset + rel
+        };
+
+        match dir {
+            Direction::North if self.topology.wraps_vertically() => {
+                Some(Position(pos.0, wrap(pos.1, self.y_off, self.height, false)))
+            },
+            Direction::South if self.topology.wraps_vertically() => {
+                Some(Position(pos.0, wrap(pos.1, self.y_off, self.height, true)))
+            },
+            Direction::West if self.topology.wraps_horizontally() => {
+                Some(Position(wrap(pos.0, self.x_off, self.width, false), pos.1))
+            },
+            Direction::East if self.topology.wraps_horizontally() => {
+                Some(Position(wrap(pos.0, self.x_off, self.width, true), pos.1))
+            },
+            _ => pos.adjacent(dir),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 struct Position(usize, usize);
 
 impl Position {
-    fn to_tuple(&self) -> (usize, usize) {
+    fn to_tuple(self) -> (usize, usize) {
         (self.0, self.1)
     }
 
@@ -65,54 +179,118 @@ pub struct Snake {
     head_pos: Position,
     // from head to tail
     body: VecDeque<Direction>,
+    // the cell under each segment of `body`; after a portal the body
+    // can't be walked back from the head.
+    cells: VecDeque<Position>,
+    // needed to step across a wrapped edge
+    area: Area,
 }
 
 impl Snake {
+    /// A snake lying along `body`, walking back from the head.
+    fn new(head_pos: Position, body: VecDeque<Direction>, area: Area) -> Snake {
+        let mut cells = VecDeque::with_capacity(body.len());
+        let mut pos = head_pos;
+        for (idx, &dir) in body.iter().enumerate() {
+            cells.push_back(pos);
+            // the tail may have come in from off the board.
+            if idx + 1 < body.len() {
+                pos = area.step(pos, dir.negate()).unwrap();
+            }
+        }
+
+        Snake::with_cells(head_pos, body, cells, area)
+    }
+
+    /// A snake whose cells are already known, as they must be once it
+    /// has been through a portal.
+    fn with_cells(head_pos: Position, body: VecDeque<Direction>, cells: VecDeque<Position>, area: Area) -> Snake {
+        let mut snake = Snake {
+            head_pos,
+            body,
+            cells,
+            area,
+        };
+        snake.make_room();
+        snake
+    }
+
+    /// Have room for the head of the next move, which comes before the
+    /// tail leaves, so that only a snake growing longer has to allocate.
+    fn make_room(&mut self) {
+        self.body.reserve(1);
+        self.cells.reserve(1);
+    }
+
+    /// Have room to grow until it's `len` long.
+    fn reserve_total(&mut self, len: usize) {
+        self.body.reserve(len.saturating_sub(self.body.len()));
+        self.cells.reserve(len.saturating_sub(self.cells.len()));
+    }
+
     /// The direction from which the head came from.
     /// i.e., the neck is Direction relative to the Head
     /// The player will not be able to cause the snake to move in this direction
     pub fn neck_direction(&self) -> Direction {
-        self.body.iter().nth(0).unwrap().clone()
+        *self.body.front().unwrap()
     }
 
     fn grow(&mut self, dir: Direction) -> Result<Position, ()> {
-        let next_pos = try!(self.head_pos
-            .adjacent(dir)
-            .ok_or(()));
+        let next_pos = self.area
+            .step(self.head_pos, dir)
+            .ok_or(())?;
 
         self.body.push_front(dir);
+        self.cells.push_front(next_pos);
         self.head_pos = next_pos;
 
         Ok(next_pos)
     }
 
+    /// Move the head, which has just arrived at a portal, to its other end.
+    fn jump(&mut self, to: Position) {
+        self.head_pos = to;
+        self.cells[0] = to;
+    }
+
+    /// The number of cells the snake occupies.
+    pub fn len(&self) -> usize {
+        self.body.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    /// Take the last segment off the tail.
+    #[allow(clippy::result_unit_err)]
     pub fn shrink(&mut self) -> Result<(), ()> {
-        self.body.pop_back().ok_or(()).map(|_| ())
+        self.pop_tail().map(|_| ())
+    }
+
+    /// Take the last segment off the tail, returning where it was.
+    fn pop_tail(&mut self) -> Result<Position, ()> {
+        self.body.pop_back().ok_or(())?;
+        self.cells.pop_back().ok_or(())
     }
 
-    pub fn positions(&self) -> SnakePositions {
+    pub fn positions(&self) -> SnakePositions<'_> {
         SnakePositions {
-            head: self.head_pos,
-            directions: self.body.iter(),
+            cells: self.cells.iter(),
         }
     }
 }
 
 #[derive(Clone)]
 pub struct SnakePositions<'a> {
-    head: Position,
-    directions: vec_deque::Iter<'a, Direction>,
+    cells: vec_deque::Iter<'a, Position>,
 }
 
 impl<'a> Iterator for SnakePositions<'a> {
     type Item = (usize, usize);
 
     fn next(&mut self) -> Option<(usize, usize)> {
-        self.directions.next().map(|&dir| {
-            let cur_head = self.head;
-            self.head = self.head.adjacent(dir.negate()).unwrap();
-            cur_head.to_tuple()
-        })
+        self.cells.next().map(|pos| pos.to_tuple())
     }
 }
 
@@ -126,7 +304,7 @@ pub enum Direction {
 
 impl Direction {
     #[inline]
-    fn negate(&self) -> Direction {
+    pub fn negate(&self) -> Direction {
         match *self {
             Direction::North => Direction::South,
             Direction::South => Direction::North,
@@ -145,45 +323,253 @@ impl Direction {
     }
 }
 
+/// Identifies one of the snakes in a game.  Players are numbered from zero
+/// in the order their snakes were placed.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct PlayerId(pub usize);
+
+/// How a game with more than one snake is decided.  A game with a single
+/// snake always ends when that snake dies.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WinCondition {
+    /// The game ends as soon as at most one snake is left alive, and that
+    /// snake wins.
+    LastSnakeStanding,
+    /// Dead snakes leave the board and everyone else plays on until no
+    /// snake is left.  The winner is whoever has the best score.
+    Elimination,
+}
+
+/// The most cells an arena may have, 4096 by 4096.  The engine keeps
+/// a little bookkeeping for every cell, so games which come from
+/// outside, such as snapshots, are refused beyond this rather than
+/// running out of memory.
+pub const MAX_ARENA_CELLS: usize = 1 << 24;
+
+struct Player {
+    snake: Snake,
+    direction: Direction,
+    /// Dead snakes stay where they died, but nothing collides with them.
+    death: Option<DeathCause>,
+    /// How many more ticks the snake can pass through itself.
+    ghost: u64,
+}
+
 pub struct GameState {
     arena_size: Area,
-    snake: Snake,
-    objects: BTreeMap<Position, GameObject>,
-    player_direction: Direction,
+    players: Vec<Player>,
+    win_condition: WinCondition,
+    objects: SortedMap<Position, GameObject>,
+    /// Ticks left for the objects which don't stay for good.
+    lifetimes: SortedMap<Position, u64>,
+    spawn_rates: powerup::SpawnRates,
     force_grow: bool,
+    /// How many pieces of food to keep on the board at once.
+    food_target: usize,
+    /// The empty cells, kept up to date as things move so that food can
+    /// be placed without searching the board.
+    free_cells: FreeCells,
+    /// Which living snake is in each cell.  Heads go on once they have
+    /// survived the move, so during a tick this is the board as it was
+    /// before the tick.
+    occupied: Occupancy,
+    /// Place food by drawing random cells until one is empty, as the
+    /// engine did before it kept `free_cells`, so that older replays
+    /// still verify.
+    legacy_food: bool,
+    rng: GameRng,
+    /// How fast the game goes, which the engine only reports; see the
+    /// `rules` module.
+    speed: rules::SpeedCurve,
+    speed_effect: Option<rules::SpeedEffect>,
+    /// How long the snakes started, and how much food has been eaten,
+    /// for ramping the speed.
+    start_length: usize,
+    food_eaten: u64,
+    scratch: Scratch,
+}
+
+/// Lists `tick` works in, kept from one tick to the next so that a tick
+/// needn't allocate once they've grown big enough.
+#[derive(Default)]
+struct Scratch {
+    heads: Vec<Option<Position>>,
+    deaths: Vec<Option<DeathCause>>,
+    ghosted: Vec<bool>,
+    grew: Vec<bool>,
+    shrunk: Vec<usize>,
+    vacated: Vec<Position>,
+    expired: Vec<Position>,
+    obstacles: Vec<(Position, Direction)>,
 }
 
 impl GameState {
+    /// Create a new game with a randomly chosen seed.  Use `seed` to
+    /// find out which one was picked.
     pub fn new(width: usize, height: usize) -> GameState {
-        let mut snake_body = VecDeque::new();
-        snake_body.push_front(Direction::West);
+        GameState::with_rng(width, height, Topology::Bounded, 1, GameRng::from_entropy())
+    }
 
+    /// Create a new game whose random events are entirely determined by
+    /// `seed`.  Two games with the same seed, arena size and inputs will
+    /// play out identically.
+    pub fn with_seed(width: usize, height: usize, seed: u64) -> GameState {
+        GameState::with_rng(width, height, Topology::Bounded, 1, GameRng::new(seed))
+    }
+
+    /// Like `with_seed`, but with a choice of what the arena's edges do.
+    pub fn with_topology(width: usize, height: usize, seed: u64, topology: Topology) -> GameState {
+        GameState::with_rng(width, height, topology, 1, GameRng::new(seed))
+    }
+
+    /// A game for `players` snakes, spread out down the arena in
+    /// alternating directions.  Panics if `players` is zero.
+    pub fn with_players(width: usize, height: usize, seed: u64, topology: Topology, players: usize) -> GameState {
+        assert!(0 < players);
+        GameState::with_rng(width, height, topology, players, GameRng::new(seed))
+    }
+
+    fn with_rng(width: usize, height: usize, topology: Topology, players: usize, rng: GameRng) -> GameState {
         let arena_size = Area {
             x_off: 0,
             y_off: 0,
-            width: width,
-            height: height,
+            width,
+            height,
+            topology,
         };
 
-        let start_x = arena_size.x_off + arena_size.width / 4;
-        let start_y = arena_size.y_off + arena_size.height / 2;
+        let players = (0..players).map(|idx| {
+            let (head_pos, direction) = start_position(&arena_size, idx, players);
 
-        let mut objects = BTreeMap::new();
-        GameState {
-            arena_size: arena_size,
-            snake: Snake {
-                head_pos: Position(start_x, start_y),
-                body: snake_body,
-            },
-            objects: objects,
-            player_direction: Direction::East,
+            // the body holds the direction each segment moved in, so a
+            // snake heading east must have arrived from the west.
+            let mut snake_body = VecDeque::new();
+            snake_body.push_front(direction);
+
+            Player {
+                snake: Snake::new(head_pos, snake_body, arena_size),
+                direction,
+                death: None,
+                ghost: 0,
+            }
+        }).collect();
+
+        let mut state = GameState {
+            arena_size,
+            players,
+            win_condition: WinCondition::LastSnakeStanding,
+            objects: SortedMap::new(),
+            lifetimes: SortedMap::new(),
+            spawn_rates: powerup::SpawnRates::default(),
             force_grow: false,
+            food_target: 1,
+            free_cells: FreeCells::new(0),
+            occupied: Occupancy::new(0, 0),
+            legacy_food: false,
+            scratch: Scratch::default(),
+            rng,
+            speed: rules::SpeedCurve::default(),
+            speed_effect: None,
+            start_length: 1,
+            food_eaten: 0,
+        };
+        state.index_cells();
+        state
+    }
+
+    /// Fill `free_cells` and `occupied` in from the board, for a game
+    /// which has just been set up.  After this, every change to the board
+    /// keeps them up to date.
+    /// Make room for every snake to fill the arena and for an object in
+    /// every cell, so that from here on a tick never allocates, however
+    /// long the game goes.  Environments do this, since they play
+    /// millions of ticks.
+    fn reserve_arena(&mut self) {
+        let cells = self.arena_size.width * self.arena_size.height;
+        for player in self.players.iter_mut() {
+            // the head moves on before the tail leaves.
+            player.snake.reserve_total(cells + 1);
+        }
+        self.objects.reserve_total(cells);
+        self.lifetimes.reserve_total(cells);
+        let scratch = &mut self.scratch;
+        scratch.vacated.reserve(cells);
+        scratch.expired.reserve(cells);
+        scratch.obstacles.reserve(cells);
+    }
+
+    fn index_cells(&mut self) {
+        let area = self.arena_size;
+        let mut free_cells = FreeCells::new(area.width * area.height);
+        let mut occupied = Occupancy::new(area.width * area.height, self.players.len());
+        for idx in self.objects.keys().filter_map(|pos| area.index(pos)) {
+            free_cells.occupy(idx);
+        }
+        for (snake, player) in self.players.iter().enumerate() {
+            if player.death.is_some() {
+                continue;
+            }
+            for idx in player.snake.cells.iter().filter_map(|pos| area.index(pos)) {
+                free_cells.occupy(idx);
+                occupied.add(idx, snake);
+            }
+        }
+        self.free_cells = free_cells;
+        self.occupied = occupied;
+    }
+
+    /// How many of player `idx`'s segments are at `pos`, without walking
+    /// the body.  Dead snakes are off the board, and have none.
+    fn segments_at(&self, idx: usize, pos: Position) -> u32 {
+        self.arena_size.index(&pos).map_or(0, |cell| self.occupied.count(cell, idx))
+    }
+
+    fn occupy(&mut self, pos: Position) {
+        if let Some(idx) = self.arena_size.index(&pos) {
+            self.free_cells.occupy(idx);
+        }
+    }
+
+    fn vacate(&mut self, pos: Position) {
+        if let Some(idx) = self.arena_size.index(&pos) {
+            self.free_cells.vacate(idx);
         }
     }
 
+    /// Put an object on the board, replacing whatever object was there.
+    fn put_object(&mut self, pos: Position, object: GameObject) -> Option<GameObject> {
+        let old = self.objects.insert(pos, object);
+        if old.is_none() {
+            self.occupy(pos);
+        }
+        old
+    }
+
+    fn take_object(&mut self, pos: Position) -> Option<GameObject> {
+        let old = self.objects.remove(&pos);
+        if old.is_some() {
+            self.vacate(pos);
+        }
+        old
+    }
+
+    /// The seed this game's random number generator was created from.
+    pub fn seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    /// Steer the first player's snake.
     pub fn set_user_direction(&mut self, direction: Direction) {
-        if self.snake.neck_direction() != direction.negate() {
-            self.player_direction = direction;
+        self.set_direction(PlayerId(0), direction);
+    }
+
+    /// Steer a player's snake from the next tick on.  Turning back into
+    /// the neck, steering a dead snake and unknown players are ignored.
+    pub fn set_direction(&mut self, player: PlayerId, direction: Direction) {
+        if let Some(player) = self.players.get_mut(player.0) {
+            if player.death.is_none() && player.snake.neck_direction() != direction.negate() {
+                player.direction = direction;
+            }
         }
     }
 
@@ -191,99 +577,858 @@ impl GameState {
         self.force_grow = grow;
     }
 
-    /// Cause a time quantum to pass.  Panicks if our snake is zero-sized.
-    pub fn tick(&mut self) -> Result<(), GameOver> {
-        assert!(!self.snake.body.is_empty());
+    /// How many pieces of food the game keeps on the board at once.
+    pub fn food_target(&self) -> usize {
+        self.food_target
+    }
 
-        let next_pos = try!(self.snake.grow(self.player_direction)
-            .map_err(|()| GameOver::Died));
+    /// Keep this many pieces of food on the board from the next tick on,
+    /// or as many as there's room for.
+    pub fn set_food_target(&mut self, food: usize) {
+        self.food_target = food;
+    }
 
-        if !self.arena_size.contains(&next_pos) {
-            return Err(GameOver::Died);
+    pub fn win_condition(&self) -> WinCondition {
+        self.win_condition
+    }
+
+    pub fn set_win_condition(&mut self, win_condition: WinCondition) {
+        self.win_condition = win_condition;
+    }
+
+    /// Cause a time quantum to pass.  Panicks if a living snake is
+    /// zero-sized.
+    ///
+    /// Every living snake moves at once: all the heads advance, then each
+    /// head is checked against the board as it stands with the heads moved
+    /// but before any tail has, so two snakes can't both claim a cell.
+    /// Heads meeting in the same cell kill both snakes.
+    ///
+    /// Food is topped up from the empty cells, chosen evenly.  Once there's
+    /// no food left and nowhere to put any, the game is over.
+    ///
+    /// Returns what happened during the tick, in the order it happened,
+    /// or how the game ended.  Once the game is over, `death` says how
+    /// each snake died.
+    pub fn tick(&mut self) -> Result<Vec<Event>, GameOver> {
+        let mut events = Vec::new();
+        self.tick_into(&mut events).map(|()| events)
+    }
+
+    /// `tick`, adding what happened to the end of `events` rather than to
+    /// a new list.  A caller reusing one list doesn't make the tick
+    /// allocate, and still finds out what happened on a tick which ended
+    /// the game.
+    pub fn tick_into(&mut self, events: &mut Vec<Event>) -> Result<(), GameOver> {
+        let mut scratch = mem::take(&mut self.scratch);
+        let result = self.tick_with(&mut scratch, events);
+        self.scratch = scratch;
+        result
+    }
+
+    fn tick_with(&mut self, scratch: &mut Scratch, events: &mut Vec<Event>) -> Result<(), GameOver> {
+        let Scratch {
+            ref mut heads,
+            ref mut deaths,
+            ref mut ghosted,
+            ref mut grew,
+            ref mut shrunk,
+            ref mut vacated,
+            ref mut expired,
+            ref mut obstacles,
+        } = *scratch;
+        let players = self.players.len();
+
+        heads.clear();
+        heads.resize(players, None);
+        deaths.clear();
+        deaths.resize(players, None);
+        let objects = &self.objects;
+        for (idx, player) in self.players.iter_mut().enumerate() {
+            if player.death.is_some() {
+                continue;
+            }
+            assert!(!player.snake.body.is_empty());
+
+            let head_pos = player.snake.head_pos;
+            match player.snake.grow(player.direction) {
+                Ok(next_pos) => {
+                    // a head arriving at a portal comes out of the other end.
+                    let next_pos = match objects.get(&next_pos) {
+                        Some(&GameObject::Portal(other)) => {
+                            let other = Position(other.0, other.1);
+                            player.snake.jump(other);
+                            other
+                        },
+                        _ => next_pos,
+                    };
+                    heads[idx] = Some(next_pos);
+                },
+                // stepping off the top or left edge of a bounded arena
+                // can't be represented, but is leaving it all the same.
+                Err(()) if self.arena_size.contains(&head_pos) => {
+                    deaths[idx] = Some(DeathCause::Boundary(head_pos.to_tuple()));
+                },
+                Err(()) => deaths[idx] = Some(DeathCause::Overflow(head_pos.to_tuple())),
+            }
+        }
+        for &head in heads.iter() {
+            if let Some(next_pos) = head {
+                self.occupy(next_pos);
+            }
+        }
+
+        for (idx, head) in heads.iter().enumerate() {
+            if let Some(next_pos) = *head {
+                deaths[idx] = self.collision(idx, next_pos, heads);
+            }
+        }
+
+        let area = self.arena_size;
+        for (idx, death) in deaths.iter().enumerate() {
+            if let Some(cause) = *death {
+                self.players[idx].death = Some(cause);
+                // a head which moved never made it onto the grid.
+                let moved = heads[idx].take().is_some();
+                events.push(Event::Died(PlayerId(idx), cause));
+
+                // nothing collides with the dead, so food can go there.
+                for (n, pos) in self.players[idx].snake.cells.iter().enumerate() {
+                    if let Some(cell) = area.index(pos) {
+                        self.free_cells.vacate(cell);
+                        if 0 < n || !moved {
+                            self.occupied.remove(cell, idx);
+                        }
+                    }
+                }
+            }
+        }
+        for (idx, head) in heads.iter().enumerate() {
+            if let Some(cell) = head.and_then(|pos| area.index(&pos)) {
+                self.occupied.add(cell, idx);
+            }
+        }
+
+        if let Some(game_over) = self.game_over() {
+            return Err(game_over);
+        }
+
+        // a ghost pill eaten this tick should last its full time.
+        ghosted.clear();
+        ghosted.resize(players, false);
+        for (idx, player) in self.players.iter_mut().enumerate() {
+            if player.death.is_none() && 0 < player.ghost {
+                player.ghost -= 1;
+                ghosted[idx] = true;
+            }
+        }
+        if let Some(mut effect) = self.speed_effect.take() {
+            effect.ticks -= 1;
+            if 0 < effect.ticks {
+                self.speed_effect = Some(effect);
+            }
         }
 
-        // determine if we hit ourself
-        for pos in self.snake.positions().skip(1) {
-            if next_pos.to_tuple() == pos {
-                return Err(GameOver::Died);
+        grew.clear();
+        grew.resize(players, self.force_grow);
+        shrunk.clear();
+        shrunk.resize(players, 0);
+        for (idx, head) in heads.iter().enumerate() {
+            let next_pos = match *head {
+                Some(next_pos) => next_pos,
+                None => continue,
+            };
+            // determine if we hit any food, or a power-up
+            let object = match self.objects.get(&next_pos) {
+                Some(&GameObject::Food) => {
+                    events.push(Event::AteFood(PlayerId(idx), next_pos.to_tuple()));
+                    GameObject::Food
+                },
+                Some(&object) => match object {
+                    GameObject::BonusFood => {
+                        grew[idx] = true;
+                        object
+                    },
+                    GameObject::Shrink => {
+                        shrunk[idx] = powerup::SHRINK_CELLS;
+                        object
+                    },
+                    GameObject::Fast => {
+                        self.apply_speed_effect(rules::SpeedEffect::fast(powerup::SPEED_TICKS));
+                        object
+                    },
+                    GameObject::Slow => {
+                        self.apply_speed_effect(rules::SpeedEffect::slow(powerup::SPEED_TICKS));
+                        object
+                    },
+                    GameObject::Ghost => {
+                        self.players[idx].ghost = powerup::GHOST_TICKS;
+                        object
+                    },
+                    _ => continue,
+                },
+                None => continue,
+            };
+
+            if object != GameObject::Food {
+                events.push(Event::Collected(PlayerId(idx), object, next_pos.to_tuple()));
+            }
+            if object == GameObject::Food || object == GameObject::BonusFood {
+                grew[idx] = true;
+                self.food_eaten += 1;
             }
+            self.take_object(next_pos);
+            self.lifetimes.remove(&next_pos);
+        }
+
+        self.expire_objects(expired, events);
+
+        // food goes down while the tails are still in the way, so it
+        // never lands right behind a snake.
+        let mut food = self.food_count();
+        while food < self.food_target {
+            let pos = match self.food_cell() {
+                Some(pos) => pos,
+                None => break,
+            };
+            self.put_object(pos, GameObject::Food);
+            events.push(Event::FoodSpawned(pos.to_tuple()));
+            food += 1;
         }
 
-        let mut hit_food = self.force_grow;
-        // determine if we will hit any food
-        if let Some(obj) = self.objects.remove(&next_pos) {
-            match obj {
-                GameObject::Food => hit_food = true,
-                GameObject::Wall => return Err(GameOver::Died),
+        vacated.clear();
+        for (idx, player) in self.players.iter_mut().enumerate() {
+            if player.death.is_some() {
+                continue;
+            }
+            let start = vacated.len();
+            if grew[idx] {
+                events.push(Event::Grew(PlayerId(idx), player.snake.len()));
+                player.snake.make_room();
+            } else {
+                // we must be of positive length afterwards if our initial length was positive.
+                vacated.push(player.snake.pop_tail().unwrap());
+            }
+            for _ in 0..shrunk[idx] {
+                if 1 < player.snake.len() {
+                    vacated.push(player.snake.pop_tail().unwrap());
+                }
+            }
+            for cell in vacated[start..].iter().filter_map(|pos| area.index(pos)) {
+                self.occupied.remove(cell, idx);
+            }
+
+            // a ghost doesn't wear off part way through itself.
+            if ghosted[idx] && player.ghost == 0 && self.occupied.overlaps(idx) {
+                player.ghost = 1;
             }
         }
 
-        while self.objects.len() < 1 {
+        for &pos in vacated.iter() {
+            self.vacate(pos);
+        }
+
+        // with no food left and nowhere to put any, there's nothing more
+        // to play for.
+        if food == 0 && 0 < self.food_target && self.free_cells.is_empty() {
+            return Err(GameOver::BoardFull);
+        }
+
+        self.move_obstacles(obstacles);
+        self.spawn_power_ups(events);
+
+        Ok(())
+    }
+
+    /// Somewhere empty to put food, chosen evenly from every empty cell,
+    /// or `None` if the board is full.
+    fn food_cell(&mut self) -> Option<Position> {
+        if self.free_cells.is_empty() {
+            return None;
+        }
+
+        if self.legacy_food {
             let x_range = Range::new(0, self.arena_size.width);
             let y_range = Range::new(0, self.arena_size.height);
+            loop {
+                let x = self.arena_size.x_off + x_range.ind_sample(&mut self.rng);
+                let y = self.arena_size.y_off + y_range.ind_sample(&mut self.rng);
+                let pos = Position(x, y);
+                if self.arena_size.index(&pos).is_some_and(|idx| self.free_cells.is_free(idx)) {
+                    return Some(pos);
+                }
+            }
+        }
 
-            let mut rng = rand::thread_rng();
-            let x = self.arena_size.x_off + x_range.ind_sample(&mut rng);
-            let y = self.arena_size.y_off + y_range.ind_sample(&mut rng);
-            let food_pos = (x, y);
+        let n = Range::new(0, self.free_cells.len()).ind_sample(&mut self.rng);
+        self.free_cells.nth(n).map(|idx| self.arena_size.position(idx))
+    }
 
-            println!("trying to place food: {:?}", food_pos);
-            let mut food_position_ok = true;
+    /// Count down the objects that don't stay for good, and take away
+    /// those whose time is up.  A portal closes at both ends.  `expired`
+    /// is somewhere to list them.
+    fn expire_objects(&mut self, expired: &mut Vec<Position>, events: &mut Vec<Event>) {
+        expired.clear();
+        for (&pos, ticks) in self.lifetimes.iter_mut() {
+            *ticks = ticks.saturating_sub(1);
+            if *ticks == 0 {
+                expired.push(pos);
+            }
+        }
 
-            for pos in self.snake.positions() {
-                if pos == food_pos {
-                    food_position_ok = false;
-                    break;
+        for &pos in expired.iter() {
+            self.lifetimes.remove(&pos);
+            if let Some(object) = self.take_object(pos) {
+                events.push(Event::Expired(object, pos.to_tuple()));
+                if let GameObject::Portal(other) = object {
+                    let other = Position(other.0, other.1);
+                    if self.objects.get(&other) == Some(&GameObject::Portal(pos.to_tuple())) {
+                        self.take_object(other);
+                        self.lifetimes.remove(&other);
+                        events.push(Event::Expired(GameObject::Portal(pos.to_tuple()), other.to_tuple()));
+                    }
                 }
             }
+        }
+    }
 
-            if food_position_ok {
-                self.objects.insert(Position(x, y), GameObject::Food);
+    /// Slide every obstacle one cell along.  One with something in its
+    /// way turns back, and stays put if it's boxed in.  `obstacles` is
+    /// somewhere to list them.
+    fn move_obstacles(&mut self, obstacles: &mut Vec<(Position, Direction)>) {
+        obstacles.clear();
+        obstacles.extend(self.objects.iter().filter_map(|(&pos, obj)| match *obj {
+            GameObject::Obstacle(dir) => Some((pos, dir)),
+            _ => None,
+        }));
+
+        for &(pos, dir) in obstacles.iter() {
+            let (to, dir) = match self.obstacle_step(pos, dir) {
+                Some(to) => (to, dir),
+                None => match self.obstacle_step(pos, dir.negate()) {
+                    Some(to) => (to, dir.negate()),
+                    None => (pos, dir.negate()),
+                },
+            };
+
+            self.take_object(pos);
+            self.put_object(to, GameObject::Obstacle(dir));
+            if let Some(ticks) = self.lifetimes.remove(&pos) {
+                self.lifetimes.insert(to, ticks);
             }
         }
+    }
+
+    fn obstacle_step(&self, pos: Position, dir: Direction) -> Option<Position> {
+        let to = self.arena_size.step(pos, dir)?;
+        let free = self.arena_size.contains(&to) &&
+            !self.objects.contains_key(&to) &&
+            !self.players.iter()
+                .enumerate()
+                .filter(|&(_, player)| player.death.is_none())
+                .any(|(idx, _)| 0 < self.segments_at(idx, to));
+        if free { Some(to) } else { None }
+    }
 
-        if !hit_food {
-            // we must be of positive length afterwards if our initial length was positive.
-            self.snake.shrink().unwrap();
+    /// Put new power-ups on the board, as the spawn rates say.
+    fn spawn_power_ups(&mut self, events: &mut Vec<Event>) {
+        let rates = self.spawn_rates;
+        let kinds = [
+            (rates.bonus_food, GameObject::BonusFood, Some(powerup::BONUS_FOOD_TICKS)),
+            (rates.shrink, GameObject::Shrink, Some(powerup::PILL_TICKS)),
+            (rates.fast, GameObject::Fast, Some(powerup::PILL_TICKS)),
+            (rates.slow, GameObject::Slow, Some(powerup::PILL_TICKS)),
+            (rates.ghost, GameObject::Ghost, Some(powerup::PILL_TICKS)),
+            (rates.portal, GameObject::Portal((0, 0)), Some(powerup::PORTAL_TICKS)),
+            (rates.obstacle, GameObject::Obstacle(Direction::North), None),
+        ];
+
+        let per_mille = Range::new(0, 1000);
+        for &(rate, kind, lifetime) in kinds.iter() {
+            let present = self.objects.values()
+                .filter(|obj| ::std::mem::discriminant(*obj) == ::std::mem::discriminant(&kind))
+                .count();
+            let room = match kind {
+                GameObject::Obstacle(_) => present < powerup::MAX_OBSTACLES,
+                _ => present == 0,
+            };
+            // a zero rate leaves the rng alone.
+            if rate == 0 || !room || rate <= per_mille.ind_sample(&mut self.rng) {
+                continue;
+            }
+
+            let pos = match self.random_free_cell() {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let object = match kind {
+                GameObject::Portal(_) => {
+                    let other = match self.random_free_cell() {
+                        Some(other) if other != pos => other,
+                        _ => continue,
+                    };
+                    self.place_object(other.to_tuple(), GameObject::Portal(pos.to_tuple()), lifetime);
+                    events.push(Event::Spawned(GameObject::Portal(pos.to_tuple()), other.to_tuple()));
+                    GameObject::Portal(other.to_tuple())
+                },
+                GameObject::Obstacle(_) => {
+                    let dirs = [Direction::North, Direction::South, Direction::West, Direction::East];
+                    GameObject::Obstacle(dirs[Range::new(0, dirs.len()).ind_sample(&mut self.rng)])
+                },
+                _ => kind,
+            };
+            self.place_object(pos.to_tuple(), object, lifetime);
+            events.push(Event::Spawned(object, pos.to_tuple()));
         }
+    }
 
-        Ok(())
+    /// An empty cell not right in front of a snake, or `None` if a few
+    /// tries don't find one.
+    fn random_free_cell(&mut self) -> Option<Position> {
+        for _ in 0..64 {
+            if self.free_cells.is_empty() {
+                return None;
+            }
+            let n = Range::new(0, self.free_cells.len()).ind_sample(&mut self.rng);
+            let pos = self.arena_size.position(self.free_cells.nth(n).unwrap());
+
+            let in_front = self.players.iter()
+                .filter(|player| player.death.is_none())
+                .any(|player| {
+                    let head = player.snake.head_pos;
+                    head.0.abs_diff(pos.0) + head.1.abs_diff(pos.1) <= 1
+                });
+            if !in_front {
+                return Some(pos);
+            }
+        }
+        None
     }
 
+    /// What player `idx`'s head runs into at `next_pos`, if anything.
+    /// `heads` is where every snake's head moved to this tick.
+    fn collision(&self, idx: usize, next_pos: Position, heads: &[Option<Position>]) -> Option<DeathCause> {
+        if !self.arena_size.contains(&next_pos) {
+            // where the head was before it left.
+            let last = self.players[idx].snake.cells[1];
+            return Some(DeathCause::Boundary(last.to_tuple()));
+        }
+
+        for (other, player) in self.players.iter().enumerate() {
+            if player.death.is_some() {
+                continue;
+            }
+            // a ghost passes through its own body.
+            if other == idx && 0 < player.ghost {
+                continue;
+            }
+            // the grid doesn't have this tick's heads yet, which are
+            // checked separately below; one that couldn't move is still
+            // in the way.
+            let segments = self.segments_at(other, next_pos);
+            if 0 < segments && other == idx {
+                return Some(DeathCause::SelfCollision(next_pos.to_tuple()));
+            }
+            if 0 < segments {
+                return Some(DeathCause::Snake(PlayerId(other), next_pos.to_tuple()));
+            }
+        }
+
+        for (other, head) in heads.iter().enumerate() {
+            if other != idx && *head == Some(next_pos) {
+                return Some(DeathCause::HeadOn(next_pos.to_tuple()));
+            }
+        }
+
+        match self.objects.get(&next_pos) {
+            Some(&GameObject::Wall) | Some(&GameObject::Obstacle(_)) => {
+                Some(DeathCause::Wall(next_pos.to_tuple()))
+            },
+            _ => None,
+        }
+    }
+
+    fn game_over(&self) -> Option<GameOver> {
+        if self.players.len() == 1 {
+            return self.players[0].death.map(GameOver::Died);
+        }
+
+        let mut living = self.players.iter()
+            .enumerate()
+            .filter(|&(_, player)| player.death.is_none())
+            .map(|(idx, _)| PlayerId(idx));
+
+        match (living.next(), living.next(), self.win_condition) {
+            (None, _, WinCondition::LastSnakeStanding) => Some(GameOver::Draw),
+            (None, _, WinCondition::Elimination) => Some(GameOver::Eliminated),
+            (Some(winner), None, WinCondition::LastSnakeStanding) => Some(GameOver::Winner(winner)),
+            _ => None,
+        }
+    }
+
+    fn food_count(&self) -> usize {
+        self.objects.values().filter(|obj| **obj == GameObject::Food).count()
+    }
+
+    /// The first player's snake.
     pub fn get_snake(&self) -> &Snake {
-        &self.snake
+        &self.players[0].snake
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// A player's snake, alive or dead.
+    pub fn snake(&self, player: PlayerId) -> Option<&Snake> {
+        self.players.get(player.0).map(|player| &player.snake)
+    }
+
+    /// A living snake with a segment at `pos`, if any.  This takes the
+    /// same time however long the snakes are.
+    pub fn occupant(&self, pos: (usize, usize)) -> Option<PlayerId> {
+        self.arena_size.index(&Position(pos.0, pos.1))
+            .and_then(|cell| self.occupied.owner(cell))
+            .map(PlayerId)
+    }
+
+    pub fn is_alive(&self, player: PlayerId) -> bool {
+        match self.players.get(player.0) {
+            Some(player) => player.death.is_none(),
+            None => false,
+        }
+    }
+
+    /// How a player's snake died, if it has.
+    pub fn death(&self, player: PlayerId) -> Option<DeathCause> {
+        self.players.get(player.0).and_then(|player| player.death)
+    }
+
+    pub fn arena_size(&self) -> (usize, usize) {
+        (self.arena_size.width, self.arena_size.height)
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.arena_size.topology
+    }
+
+    /// A fingerprint of the snakes and the objects on the board, used to
+    /// detect when two games which should be identical have diverged.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Fnv64::new();
+
+        for player in self.players.iter() {
+            hasher.write_u64(player.snake.head_pos.0 as u64);
+            hasher.write_u64(player.snake.head_pos.1 as u64);
+            hasher.write_u64(player.snake.body.len() as u64);
+            for &dir in player.snake.body.iter() {
+                hasher.write_u8(direction_code(dir));
+            }
+            // single player games hash as they always have, so that
+            // older replays still verify.
+            if 1 < self.players.len() {
+                hasher.write_u8(player.death.is_some() as u8);
+            }
+            // as do games without power-ups.
+            if 0 < player.ghost {
+                hasher.write_u64(player.ghost);
+            }
+        }
+
+        hasher.write_u64(self.objects.len() as u64);
+        for (pos, obj) in self.objects.iter() {
+            hasher.write_u64(pos.0 as u64);
+            hasher.write_u64(pos.1 as u64);
+            match *obj {
+                GameObject::Food => hasher.write_u8(0),
+                GameObject::Wall => hasher.write_u8(1),
+                GameObject::BonusFood => hasher.write_u8(2),
+                GameObject::Shrink => hasher.write_u8(3),
+                GameObject::Fast => hasher.write_u8(4),
+                GameObject::Slow => hasher.write_u8(5),
+                GameObject::Ghost => hasher.write_u8(6),
+                GameObject::Portal(other) => {
+                    hasher.write_u8(7);
+                    hasher.write_u64(other.0 as u64);
+                    hasher.write_u64(other.1 as u64);
+                },
+                GameObject::Obstacle(dir) => {
+                    hasher.write_u8(8);
+                    hasher.write_u8(direction_code(dir));
+                },
+            }
+        }
+        for (pos, ticks) in self.lifetimes.iter() {
+            hasher.write_u64(pos.0 as u64);
+            hasher.write_u64(pos.1 as u64);
+            hasher.write_u64(*ticks);
+        }
+
+        // and games at the classic speed.
+        if self.speed != rules::SpeedCurve::default() || self.speed_effect.is_some() || self.start_length != 1 {
+            let (ramp, every, step) = match self.speed.ramp {
+                rules::Ramp::Constant => (0, 0, Duration::from_secs(0)),
+                rules::Ramp::Length { every, step } => (1, every as u64, step),
+                rules::Ramp::Food { every, step } => (2, every, step),
+            };
+            hasher.write_u64(self.speed.start.as_nanos() as u64);
+            hasher.write_u64(self.speed.fastest.as_nanos() as u64);
+            hasher.write_u8(ramp);
+            hasher.write_u64(every);
+            hasher.write_u64(step.as_nanos() as u64);
+            if let Some(effect) = self.speed_effect {
+                hasher.write_u64(u64::from(effect.percent));
+                hasher.write_u64(effect.ticks);
+            }
+            hasher.write_u64(self.start_length as u64);
+            hasher.write_u64(self.food_eaten);
+        }
+
+        hasher.finish()
     }
 
-    pub fn object_iter(&self) -> ObjectIter {
+    pub fn object_iter(&self) -> ObjectIter<'_> {
         ObjectIter {
             objects: self.objects.iter(),
+            lifetimes: &self.lifetimes,
         }
     }
 }
 
+/// Where player `idx` of `players` starts: even players on the left
+/// heading east, odd ones on the right heading west, in evenly spaced
+/// rows.  A lone snake starts a quarter of the way in, half way down.
+fn start_position(area: &Area, idx: usize, players: usize) -> (Position, Direction) {
+    let y = area.y_off + area.height * (idx + 1) / (players + 1);
+    if idx.is_multiple_of(2) {
+        (Position(area.x_off + area.width / 4, y), Direction::East)
+    } else {
+        let x = (area.x_off + area.width).saturating_sub(area.width / 4 + 1);
+        (Position(x, y), Direction::West)
+    }
+}
+
 #[derive(Clone)]
 pub struct ObjectIter<'a> {
-    objects: btree_map::Iter<'a, Position, GameObject>,
+    objects: sorted_map::Iter<'a, Position, GameObject>,
+    lifetimes: &'a SortedMap<Position, u64>,
 }
 
 impl<'a> Iterator for ObjectIter<'a> {
-    type Item = ((usize, usize), &'a GameObject);
+    /// Where the object is, what it is, and how many ticks it has left
+    /// if it doesn't stay for good.
+    type Item = ((usize, usize), &'a GameObject, Option<u64>);
 
-    fn next(&mut self) -> Option<((usize, usize), &'a GameObject)> {
-        self.objects.next().map(|(pos, dir)| {
-            (pos.to_tuple(), dir)
+    fn next(&mut self) -> Option<((usize, usize), &'a GameObject, Option<u64>)> {
+        self.objects.next().map(|(pos, obj)| {
+            (pos.to_tuple(), obj, self.lifetimes.get(pos).cloned())
         })
     }
 }
 
-#[derive(Debug)]
+/// Something that happened during a tick, for frontends that want to
+/// react to it (sound, score, effects) without diffing the state.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event {
+    /// The player's head reached food at this position.
+    AteFood(PlayerId, (usize, usize)),
+    /// The player's snake grew, and is now this long.
+    Grew(PlayerId, usize),
+    /// New food was placed at this position.
+    FoodSpawned((usize, usize)),
+    /// The player's snake died, but the game goes on without it.
+    Died(PlayerId, DeathCause),
+    /// The player's head reached a power-up, or bonus food, at this
+    /// position.  Ordinary food is `AteFood`.
+    Collected(PlayerId, GameObject, (usize, usize)),
+    /// A power-up, portal end or obstacle appeared at this position.
+    Spawned(GameObject, (usize, usize)),
+    /// An object's time ran out and it left this position.
+    Expired(GameObject, (usize, usize)),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum GameOver {
-    Died,
+    /// The only snake in a single player game died.
+    Died(DeathCause),
+    /// Every other snake has died.
+    Winner(PlayerId),
+    /// The last snakes standing all died in the same tick.
+    Draw,
+    /// Every snake has died, under `WinCondition::Elimination`.
+    Eliminated,
+    /// The snakes left no room on the board for any more food.  Alone,
+    /// that's a win.
+    BoardFull,
+}
+
+/// What a snake ran into.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DeathCause {
+    /// The head ran into a wall at this position.
+    Wall((usize, usize)),
+    /// The head ran into its own body at this position.
+    SelfCollision((usize, usize)),
+    /// The head ran into this player's snake at this position.
+    Snake(PlayerId, (usize, usize)),
+    /// The head met another snake's head arriving at this position in
+    /// the same tick.
+    HeadOn((usize, usize)),
+    /// The head left a bounded arena from this position, the last one
+    /// inside it, through whichever edge.
+    Boundary((usize, usize)),
+    /// The head couldn't move from this position, which was already
+    /// outside the arena, without a coordinate overflowing.
+    Overflow((usize, usize)),
+}
+
+impl DeathCause {
+    /// Where the head was when the snake died.  It may be just outside
+    /// the arena.
+    pub fn position(self) -> (usize, usize) {
+        match self {
+            DeathCause::Wall(pos) |
+            DeathCause::SelfCollision(pos) |
+            DeathCause::Snake(_, pos) |
+            DeathCause::HeadOn(pos) |
+            DeathCause::Boundary(pos) |
+            DeathCause::Overflow(pos) => pos,
+        }
+    }
+}
+
+/// What happened, to follow the snake's name: "hit a wall at (3, 4)".
+/// Players are counted from one.
+impl fmt::Display for DeathCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DeathCause::Wall((x, y)) => write!(f, "hit a wall at ({}, {})", x, y),
+            DeathCause::SelfCollision((x, y)) => write!(f, "ran into itself at ({}, {})", x, y),
+            DeathCause::Snake(PlayerId(other), (x, y)) => write!(f, "ran into player {} at ({}, {})", other + 1, x, y),
+            DeathCause::HeadOn((x, y)) => write!(f, "met another snake head-on at ({}, {})", x, y),
+            DeathCause::Boundary((x, y)) => write!(f, "left the arena at ({}, {})", x, y),
+            DeathCause::Overflow((x, y)) => write!(f, "fell off the world at ({}, {})", x, y),
+        }
+    }
+}
+
+fn direction_code(dir: Direction) -> u8 {
+    match dir {
+        Direction::North => 0,
+        Direction::South => 1,
+        Direction::West => 2,
+        Direction::East => 3,
+    }
+}
+
+fn direction_from_code(code: u8) -> Option<Direction> {
+    match code {
+        0 => Some(Direction::North),
+        1 => Some(Direction::South),
+        2 => Some(Direction::West),
+        3 => Some(Direction::East),
+        _ => None,
+    }
+}
+
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::North => "north",
+        Direction::South => "south",
+        Direction::West => "west",
+        Direction::East => "east",
+    }
+}
+
+fn direction_from_name(name: &str) -> Option<Direction> {
+    match name {
+        "north" => Some(Direction::North),
+        "south" => Some(Direction::South),
+        "west" => Some(Direction::West),
+        "east" => Some(Direction::East),
+        _ => None,
+    }
+}
+
+fn read_u8<R: Read>(rd: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    rd.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(rd: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    rd.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(rd: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    rd.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// 64-bit FNV-1a.  `std`'s default hasher is randomly keyed, which is
+/// useless for fingerprints that must agree across processes.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Fnv64 {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
 }
 
 #[test]
 fn it_works() {
-    let mut state = GameState::new(64, 64);
+    let _state = GameState::new(64, 64);
+}
+
+#[test]
+fn seeded_games_are_reproducible() {
+    use ai::Controller;
+
+    // where the first few pieces of food turn up, with the snake steered
+    // into each one so that the next is placed.
+    fn play(seed: u64) -> Vec<(usize, usize)> {
+        let mut state = GameState::with_seed(16, 16, seed);
+        assert_eq!(state.seed(), seed);
+
+        let mut food = Vec::new();
+        let mut ai = ai::Greedy;
+        while food.len() < 6 {
+            if let Some(dir) = ai.choose(&state, PlayerId(0)) {
+                state.set_user_direction(dir);
+            }
+            for event in state.tick().unwrap() {
+                if let Event::FoodSpawned(pos) = event {
+                    food.push(pos);
+                }
+            }
+        }
+        food
+    }
+
+    let food = play(0xdeadbeef);
+    assert_eq!(food, vec![(7, 5), (9, 13), (10, 11), (0, 4), (11, 5), (0, 5)]);
+    assert_eq!(food, play(0xdeadbeef));
+    assert!(food != play(0xfeedface));
 }
 
 #[test]
@@ -291,10 +1436,13 @@ fn snake_iteration() {
     let mut snake_body = VecDeque::new();
     snake_body.push_front(Direction::West);
 
-    let mut snake = Snake {
-        head_pos: Position(1024, 1024),
-        body: snake_body,
-    };
+    let mut snake = Snake::new(Position(1024, 1024), snake_body, Area {
+        x_off: 0,
+        y_off: 0,
+        width: 2048,
+        height: 2048,
+        topology: Topology::Bounded,
+    });
     snake.grow(Direction::West).unwrap();
     snake.grow(Direction::West).unwrap();
 
@@ -304,3 +1452,215 @@ fn snake_iteration() {
     assert_eq!(positions.next().unwrap(), (1024, 1024));
     assert!(positions.next().is_none());
 }
+
+#[test]
+fn tick_reports_eating() {
+    let mut state = GameState::with_seed(16, 16, 1);
+    state.place_object((5, 8), GameObject::Food, None);
+
+    let events = state.tick().unwrap();
+    assert_eq!(events[0], Event::AteFood(PlayerId(0), (5, 8)));
+    assert!(events.contains(&Event::Grew(PlayerId(0), 2)));
+    assert!(events.iter().any(|ev| matches!(*ev, Event::FoodSpawned(_))));
+}
+
+#[test]
+fn tick_reports_cause_of_death() {
+    fn run(mut state: GameState) -> GameOver {
+        loop {
+            if let Err(err) = state.tick() {
+                return err;
+            }
+        }
+    }
+
+    let state = GameState::with_seed(8, 8, 1);
+    assert_eq!(run(state), GameOver::Died(DeathCause::Boundary((7, 4))));
+
+    // leaving through the top edge is the same as through the bottom.
+    let mut state = GameState::with_seed(8, 8, 1);
+    state.set_user_direction(Direction::North);
+    assert_eq!(run(state), GameOver::Died(DeathCause::Boundary((2, 0))));
+
+    let mut state = GameState::with_seed(8, 8, 1);
+    state.set_user_direction(Direction::South);
+    assert_eq!(run(state), GameOver::Died(DeathCause::Boundary((2, 7))));
+
+    let mut state = GameState::with_seed(8, 8, 1);
+    state.set_force_grow(true);
+    for &dir in [Direction::South, Direction::West, Direction::North].iter() {
+        state.set_user_direction(dir);
+        state.tick().unwrap();
+    }
+    state.set_user_direction(Direction::East);
+    assert_eq!(run(state), GameOver::Died(DeathCause::SelfCollision((2, 4))));
+
+    let mut state = GameState::with_seed(8, 8, 1);
+    state.place_object((3, 4), GameObject::Wall, None);
+    assert_eq!(run(state), GameOver::Died(DeathCause::Wall((3, 4))));
+}
+
+#[test]
+fn running_into_another_snake_says_whose() {
+    let mut state = GameState::with_players(9, 9, 1, Topology::Bounded, 2);
+    state.set_win_condition(WinCondition::Elimination);
+    state.set_force_grow(true);
+    // the second snake crosses the first one's row ahead of it.
+    state.set_direction(PlayerId(1), Direction::North);
+    for _ in 0..4 {
+        state.tick().unwrap();
+    }
+    assert_eq!(state.death(PlayerId(0)), Some(DeathCause::Snake(PlayerId(1), (6, 3))));
+}
+
+#[test]
+fn wrapping_across_the_seam() {
+    let mut state = GameState::with_topology(8, 8, 1, Topology::WrapHorizontal);
+    state.set_force_grow(true);
+    // head starts at (2, 4), heading east
+    for _ in 0..7 {
+        state.tick().unwrap();
+    }
+    let positions: Vec<_> = state.get_snake().positions().collect();
+    assert_eq!(&positions[..4], &[(1, 4), (0, 4), (7, 4), (6, 4)]);
+
+    state.set_user_direction(Direction::North);
+    let err = loop {
+        if let Err(err) = state.tick() {
+            break err;
+        }
+    };
+    assert_eq!(err, GameOver::Died(DeathCause::Boundary((1, 0))));
+}
+
+#[test]
+fn heads_meeting_is_a_draw() {
+    let mut state = GameState::with_players(9, 9, 1, Topology::Bounded, 2);
+    // line the second snake up on the first one's row
+    let body = state.players[1].snake.body.clone();
+    state.players[1].snake = Snake::new(Position(6, 3), body, state.arena_size);
+    state.index_cells();
+
+    state.tick().unwrap();
+    assert_eq!(state.tick(), Err(GameOver::Draw));
+    assert_eq!(state.death(PlayerId(0)), Some(DeathCause::HeadOn((4, 3))));
+    assert_eq!(state.death(PlayerId(1)), Some(DeathCause::HeadOn((4, 3))));
+}
+
+#[test]
+fn win_conditions() {
+    fn play(win_condition: WinCondition) -> (Vec<Event>, GameOver) {
+        let mut state = GameState::with_players(9, 9, 1, Topology::Bounded, 2);
+        state.set_win_condition(win_condition);
+        state.set_direction(PlayerId(0), Direction::North);
+
+        let mut events = Vec::new();
+        loop {
+            match state.tick() {
+                Ok(tick_events) => events.extend(tick_events),
+                Err(game_over) => return (events, game_over),
+            }
+        }
+    }
+
+    let (events, game_over) = play(WinCondition::LastSnakeStanding);
+    assert_eq!(game_over, GameOver::Winner(PlayerId(1)));
+    assert!(!events.iter().any(|ev| matches!(*ev, Event::Died(..))));
+
+    let (events, game_over) = play(WinCondition::Elimination);
+    assert_eq!(game_over, GameOver::Eliminated);
+    assert!(events.contains(&Event::Died(PlayerId(0), DeathCause::Boundary((2, 0)))));
+}
+
+#[test]
+fn filling_the_board_ends_the_game() {
+    // nothing but walls, bar the snake and the cell in front of it.
+    let mut state = GameState::with_seed(3, 3, 1);
+    for y in 0..3 {
+        for x in 0..3 {
+            if y != 1 || 1 < x {
+                state.place_object((x, y), GameObject::Wall, None);
+            }
+        }
+    }
+    state.set_force_grow(true);
+    assert_eq!(state.tick(), Err(GameOver::BoardFull));
+}
+
+#[test]
+fn food_goes_in_empty_cells() {
+    // only the snake's row is open.
+    let mut state = GameState::with_seed(8, 8, 3);
+    for y in (0..8).filter(|&y| y != 4) {
+        for x in 0..8 {
+            state.place_object((x, y), GameObject::Wall, None);
+        }
+    }
+    state.set_food_target(3);
+
+    let events = state.tick().unwrap();
+    assert_eq!(events.iter().filter(|ev| matches!(**ev, Event::FoodSpawned(_))).count(), 3);
+    let snake: Vec<_> = state.get_snake().positions().collect();
+    for (pos, obj, _) in state.object_iter() {
+        if *obj == GameObject::Food {
+            assert_eq!(pos.1, 4);
+            assert!(!snake.contains(&pos));
+        }
+    }
+}
+
+#[test]
+fn crowded_games_start_snakes_on_top_of_each_other() {
+    // more snakes than rows, so some share cells.
+    let mut state = GameState::with_players(4, 4, 1, Topology::Bounded, 10);
+    assert_eq!(state.snake(PlayerId(2)).unwrap().head_pos, state.snake(PlayerId(4)).unwrap().head_pos);
+    state.set_win_condition(WinCondition::Elimination);
+    let mut ticks = 0;
+    while state.tick().is_ok() {
+        ticks += 1;
+    }
+    assert!(ticks < 4);
+}
+
+#[test]
+fn free_cells_keep_up_with_the_board() {
+    let mut state = GameState::with_players(12, 12, 9, Topology::Torus, 3);
+    state.set_win_condition(WinCondition::Elimination);
+    state.set_food_target(4);
+    let mut rates = powerup::SpawnRates::default();
+    for name in powerup::NAMES.iter() {
+        rates.set(name, 100);
+    }
+    state.set_spawn_rates(rates);
+
+    let turns = [Direction::North, Direction::West, Direction::South, Direction::East];
+    for tick in 0..300 {
+        for idx in 0..3 {
+            state.set_direction(PlayerId(idx), turns[(tick / (3 + idx)) % 4]);
+        }
+        let over = state.tick().is_err();
+
+        // a restored game builds its index from scratch.
+        let rebuilt = GameState::restore(&state.snapshot()).unwrap();
+        assert_eq!(state.free_cells.len(), rebuilt.free_cells.len());
+        for cell in 0..12 * 12 {
+            assert_eq!(state.free_cells.is_free(cell), rebuilt.free_cells.is_free(cell));
+        }
+        // and so does the snakes' grid, which leaves the dead ones out.
+        for (idx, player) in state.players.iter().enumerate() {
+            let alive = player.death.is_none();
+            for cell in 0..12 * 12 {
+                let pos = state.arena_size.position(cell);
+                let segments = player.snake.cells.iter().filter(|&&at| at == pos && alive).count();
+                assert_eq!(state.segments_at(idx, pos) as usize, segments);
+                assert_eq!(state.occupied.count(cell, idx), rebuilt.occupied.count(cell, idx));
+            }
+            let cells: Vec<_> = player.snake.cells.iter().collect();
+            let overlaps = alive && (1..cells.len()).any(|idx| cells[..idx].contains(&cells[idx]));
+            assert_eq!(state.occupied.overlaps(idx), overlaps);
+        }
+        if over {
+            break;
+        }
+    }
+}
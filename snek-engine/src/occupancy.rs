@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+/// Marks a cell more than one snake is in.
+const SHARED: u32 = u32::MAX;
+
+/// Which living snakes are in each cell of the arena, and with how many
+/// segments, so asking what is somewhere doesn't mean walking every body.
+///
+/// The whole game shares one grid.  Each cell counts segments rather than
+/// holding a bit, since a ghost's body can cross itself.  Living snakes
+/// only share a cell when a crowded game starts them on top of each
+/// other, so a cell has one owner, and the few cells with more keep
+/// their counts to one side.
+#[derive(Clone, Debug)]
+pub struct Occupancy {
+    counts: Vec<u32>,
+    owners: Vec<u32>,
+    /// Each snake's segments in the cells marked `SHARED`, by cell and
+    /// then snake.
+    shared: BTreeMap<(usize, usize), u32>,
+    /// For each snake, how many of its segments are in a cell some earlier
+    /// segment of its own is in too.
+    overlaps: Vec<usize>,
+}
+
+impl Occupancy {
+    /// An empty grid of `cells` cells, for `snakes` snakes.
+    pub fn new(cells: usize, snakes: usize) -> Occupancy {
+        Occupancy {
+            counts: vec![0; cells],
+            owners: vec![0; cells],
+            shared: BTreeMap::new(),
+            overlaps: vec![0; snakes],
+        }
+    }
+
+    /// A snake in a cell, if any.
+    pub fn owner(&self, cell: usize) -> Option<usize> {
+        match (self.counts[cell], self.owners[cell]) {
+            (0, _) => None,
+            (_, SHARED) => self.shared.range((cell, 0)..(cell + 1, 0)).next().map(|(&(_, snake), _)| snake),
+            (_, owner) => Some(owner as usize),
+        }
+    }
+
+    /// The number of `snake`'s segments in a cell.
+    pub fn count(&self, cell: usize, snake: usize) -> u32 {
+        match (self.counts[cell], self.owners[cell]) {
+            (0, _) => 0,
+            (_, SHARED) => self.shared.get(&(cell, snake)).cloned().unwrap_or(0),
+            (count, owner) if owner as usize == snake => count,
+            _ => 0,
+        }
+    }
+
+    /// Whether any cell holds more than one of `snake`'s segments.
+    pub fn overlaps(&self, snake: usize) -> bool {
+        0 < self.overlaps[snake]
+    }
+
+    /// Put one of `snake`'s segments in a cell.
+    pub fn add(&mut self, cell: usize, snake: usize) {
+        let owner = self.owners[cell];
+        if self.counts[cell] == 0 {
+            self.owners[cell] = snake as u32;
+        } else if owner as usize == snake {
+            self.overlaps[snake] += 1;
+        } else {
+            if owner != SHARED {
+                self.shared.insert((cell, owner as usize), self.counts[cell]);
+                self.owners[cell] = SHARED;
+            }
+            let count = self.shared.entry((cell, snake)).or_insert(0);
+            if *count != 0 {
+                self.overlaps[snake] += 1;
+            }
+            *count += 1;
+        }
+        self.counts[cell] += 1;
+    }
+
+    /// Take one of `snake`'s segments out of a cell.  Panics if there's
+    /// none there.
+    pub fn remove(&mut self, cell: usize, snake: usize) {
+        assert!(self.count(cell, snake) != 0);
+        self.counts[cell] -= 1;
+        if self.owners[cell] == SHARED {
+            let count = self.shared.get_mut(&(cell, snake)).unwrap();
+            *count -= 1;
+            if *count != 0 {
+                self.overlaps[snake] -= 1;
+            } else {
+                self.shared.remove(&(cell, snake));
+            }
+        } else if self.counts[cell] != 0 {
+            self.overlaps[snake] -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Occupancy;
+
+    #[test]
+    fn counts_segments_in_each_cell() {
+        let mut grid = Occupancy::new(4, 2);
+        grid.add(1, 0);
+        grid.add(2, 1);
+        assert_eq!(grid.count(1, 0), 1);
+        assert_eq!(grid.count(1, 1), 0);
+        assert_eq!(grid.count(3, 0), 0);
+        assert_eq!(grid.owner(2), Some(1));
+        assert_eq!(grid.owner(3), None);
+        assert!(!grid.overlaps(0));
+
+        grid.add(1, 0);
+        assert_eq!(grid.count(1, 0), 2);
+        assert!(grid.overlaps(0));
+        assert!(!grid.overlaps(1));
+
+        grid.remove(1, 0);
+        assert_eq!(grid.count(1, 0), 1);
+        assert!(!grid.overlaps(0));
+        grid.remove(1, 0);
+        assert_eq!(grid.owner(1), None);
+
+        // an empty cell can go to anyone.
+        grid.add(1, 1);
+        assert_eq!(grid.count(1, 1), 1);
+    }
+
+    #[test]
+    fn snakes_can_share_a_cell() {
+        let mut grid = Occupancy::new(4, 2);
+        grid.add(1, 0);
+        grid.add(1, 1);
+        grid.add(1, 1);
+        assert_eq!(grid.count(1, 0), 1);
+        assert_eq!(grid.count(1, 1), 2);
+        assert!(!grid.overlaps(0));
+        assert!(grid.overlaps(1));
+
+        grid.remove(1, 0);
+        assert_eq!(grid.owner(1), Some(1));
+        grid.remove(1, 1);
+        grid.remove(1, 1);
+        assert_eq!(grid.owner(1), None);
+        assert!(!grid.overlaps(1));
+
+        grid.add(1, 0);
+        assert_eq!(grid.count(1, 0), 1);
+        assert_eq!(grid.count(1, 1), 0);
+    }
+}
@@ -0,0 +1,312 @@
+//! Power-ups, and the other objects that come and go during a game.
+//!
+//! Besides food and walls the board can hold:
+//!
+//! - bonus food, which is worth more than food but disappears if it isn't
+//!   eaten in time,
+//! - shrink pills, which take cells off the tail of whoever eats them,
+//! - fast and slow pills, which change the speed of the whole game for a
+//!   while,
+//! - ghost pills, which let whoever eats them pass through their own body
+//!   for a while,
+//! - portals, in linked pairs: a head arriving at one end comes out of
+//!   the other, still heading the same way,
+//! - obstacles, which kill like walls but slide along, turning back when
+//!   anything is in their way.
+//!
+//! Each game has `SpawnRates` saying how likely each kind is to appear on
+//! any tick, set by the level.  They're all zero unless asked for, and a
+//! kind with a zero rate never touches the random number generator, so
+//! games without power-ups play exactly as they always have.
+
+use super::{GameObject, GameState, PlayerId};
+
+/// How many ticks bonus food stays on the board.
+pub const BONUS_FOOD_TICKS: u64 = 60;
+/// How many ticks pills stay on the board.
+pub const PILL_TICKS: u64 = 100;
+/// How many ticks a pair of portals stays open.
+pub const PORTAL_TICKS: u64 = 300;
+/// How many cells a shrink pill takes off.  A snake is never shrunk to
+/// nothing.
+pub const SHRINK_CELLS: usize = 3;
+/// How many ticks fast and slow pills last.
+pub const SPEED_TICKS: u64 = 50;
+/// How many ticks a ghost pill lasts.  It keeps going after that until
+/// the snake is clear of itself.
+pub const GHOST_TICKS: u64 = 40;
+/// There are never more obstacles on the board than this, counting ones
+/// placed by the level.  Of every other kind, there's at most one at a
+/// time, or one pair of portals.
+pub const MAX_OBSTACLES: usize = 4;
+
+/// The chance of each kind of object appearing on any tick, in thousandths.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct SpawnRates {
+    pub bonus_food: u32,
+    pub shrink: u32,
+    pub fast: u32,
+    pub slow: u32,
+    pub ghost: u32,
+    pub portal: u32,
+    pub obstacle: u32,
+}
+
+/// The names of the kinds in `SpawnRates`, as levels and snapshots write
+/// them.
+pub const NAMES: [&str; 7] = ["bonus-food", "shrink", "fast", "slow", "ghost", "portal", "obstacle"];
+
+impl SpawnRates {
+    /// The rate for a kind, by name.
+    pub fn get(&self, name: &str) -> Option<u32> {
+        let mut rates = *self;
+        rates.field(name).map(|rate| *rate)
+    }
+
+    /// Set the rate for a kind, by name, and say whether there's such a
+    /// kind.
+    pub fn set(&mut self, name: &str, rate: u32) -> bool {
+        match self.field(name) {
+            Some(field) => {
+                *field = rate;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SpawnRates::default()
+    }
+
+    fn field(&mut self, name: &str) -> Option<&mut u32> {
+        match name {
+            "bonus-food" => Some(&mut self.bonus_food),
+            "shrink" => Some(&mut self.shrink),
+            "fast" => Some(&mut self.fast),
+            "slow" => Some(&mut self.slow),
+            "ghost" => Some(&mut self.ghost),
+            "portal" => Some(&mut self.portal),
+            "obstacle" => Some(&mut self.obstacle),
+            _ => None,
+        }
+    }
+}
+
+impl GameState {
+    pub fn spawn_rates(&self) -> SpawnRates {
+        self.spawn_rates
+    }
+
+    pub fn set_spawn_rates(&mut self, rates: SpawnRates) {
+        self.spawn_rates = rates;
+    }
+
+    /// How many more ticks a player's snake can pass through itself.
+    pub fn ghost_ticks(&self, player: PlayerId) -> u64 {
+        self.players.get(player.0).map_or(0, |player| player.ghost)
+    }
+
+    /// How many ticks an object will stay on the board, or `None` for
+    /// one that stays until it's eaten, or for good.
+    pub fn lifetime(&self, pos: (usize, usize)) -> Option<u64> {
+        self.lifetimes.get(&super::Position(pos.0, pos.1)).cloned()
+    }
+
+    /// Put an object on the board, for `lifetime` ticks if given.  Any
+    /// object already there is replaced.  A portal must be placed at both
+    /// ends.
+    pub fn place_object(&mut self, pos: (usize, usize), object: GameObject, lifetime: Option<u64>) {
+        let pos = super::Position(pos.0, pos.1);
+        self.put_object(pos, object);
+        match lifetime {
+            Some(ticks) => self.lifetimes.insert(pos, ticks),
+            None => self.lifetimes.remove(&pos),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{DeathCause, Direction, Event, GameOver, Position};
+
+    fn head(state: &GameState, player: PlayerId) -> (usize, usize) {
+        state.snake(player).unwrap().positions().next().unwrap()
+    }
+
+    #[test]
+    fn rates_by_name() {
+        let mut rates = SpawnRates::default();
+        assert!(rates.is_empty());
+        for (idx, name) in NAMES.iter().enumerate() {
+            assert!(rates.set(name, idx as u32 + 1));
+        }
+        assert_eq!(rates.get("portal"), Some(6));
+        assert_eq!(rates.obstacle, 7);
+        assert!(!rates.set("cake", 1));
+        assert_eq!(rates.get("cake"), None);
+    }
+
+    #[test]
+    fn bonus_food_expires() {
+        // the snake starts at (5, 5) heading east.
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.place_object((10, 2), GameObject::BonusFood, Some(2));
+        assert_eq!(state.lifetime((10, 2)), Some(2));
+        assert!(state.object_iter().any(|(pos, obj, lifetime)| {
+            pos == (10, 2) && *obj == GameObject::BonusFood && lifetime == Some(2)
+        }));
+
+        assert!(!state.tick().unwrap().contains(&Event::Expired(GameObject::BonusFood, (10, 2))));
+        assert_eq!(state.lifetime((10, 2)), Some(1));
+        assert!(state.tick().unwrap().contains(&Event::Expired(GameObject::BonusFood, (10, 2))));
+        assert!(!state.objects.contains_key(&Position(10, 2)));
+        assert_eq!(state.lifetime((10, 2)), None);
+    }
+
+    #[test]
+    fn pills_take_effect() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.set_force_grow(true);
+        for _ in 0..5 {
+            state.tick().unwrap();
+        }
+        state.set_force_grow(false);
+        assert_eq!(state.get_snake().len(), 6);
+
+        // the head is at (10, 5) now.
+        state.place_object((11, 5), GameObject::Shrink, Some(10));
+        state.place_object((12, 5), GameObject::Ghost, Some(10));
+        state.place_object((13, 5), GameObject::Slow, Some(10));
+
+        let events = state.tick().unwrap();
+        assert!(events.contains(&Event::Collected(PlayerId(0), GameObject::Shrink, (11, 5))));
+        assert_eq!(state.get_snake().len(), 6 - SHRINK_CELLS);
+
+        state.tick().unwrap();
+        assert_eq!(state.ghost_ticks(PlayerId(0)), GHOST_TICKS);
+
+        state.tick().unwrap();
+        assert_eq!(state.speed_effect().unwrap().percent, 150);
+    }
+
+    #[test]
+    fn shrinking_leaves_the_head() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.place_object((6, 5), GameObject::Shrink, None);
+        state.tick().unwrap();
+        assert_eq!(state.get_snake().len(), 1);
+    }
+
+    #[test]
+    fn ghosts_pass_through_themselves() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.set_force_grow(true);
+        for _ in 0..4 {
+            state.tick().unwrap();
+        }
+        state.set_force_grow(false);
+        state.players[0].ghost = 3;
+
+        // turn back on itself: south, west, then north into the body.
+        state.set_direction(PlayerId(0), Direction::South);
+        state.tick().unwrap();
+        state.set_direction(PlayerId(0), Direction::West);
+        state.tick().unwrap();
+        state.set_direction(PlayerId(0), Direction::North);
+        state.tick().unwrap();
+        assert_eq!(head(&state, PlayerId(0)), (8, 5));
+
+        // the ghost lasts until the snake is clear of itself.
+        assert_eq!(state.ghost_ticks(PlayerId(0)), 1);
+        for _ in 0..5 {
+            state.tick().unwrap();
+        }
+        assert_eq!(state.ghost_ticks(PlayerId(0)), 0);
+
+        // without it, the snake dies biting itself.
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.set_force_grow(true);
+        for _ in 0..4 {
+            state.tick().unwrap();
+        }
+        for &dir in [Direction::South, Direction::West].iter() {
+            state.set_direction(PlayerId(0), dir);
+            state.tick().unwrap();
+        }
+        state.set_direction(PlayerId(0), Direction::North);
+        assert_eq!(state.tick(), Err(GameOver::Died(DeathCause::SelfCollision((8, 5)))));
+    }
+
+    #[test]
+    fn portals_carry_snakes_across() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.set_force_grow(true);
+        state.place_object((7, 5), GameObject::Portal((15, 1)), Some(5));
+        state.place_object((15, 1), GameObject::Portal((7, 5)), Some(5));
+
+        state.tick().unwrap();
+        state.tick().unwrap();
+        assert_eq!(head(&state, PlayerId(0)), (15, 1));
+        state.tick().unwrap();
+        let positions: Vec<_> = state.get_snake().positions().collect();
+        assert_eq!(positions, vec![(16, 1), (15, 1), (6, 5), (5, 5)]);
+
+        // the jump survives a snapshot.
+        let restored = GameState::restore(&state.snapshot()).unwrap();
+        assert_eq!(restored.get_snake().positions().collect::<Vec<_>>(), positions);
+    }
+
+    #[test]
+    fn obstacles_bounce() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.place_object((18, 1), GameObject::Obstacle(Direction::East), None);
+        state.tick().unwrap();
+        assert_eq!(state.objects.get(&Position(19, 1)), Some(&GameObject::Obstacle(Direction::East)));
+        state.tick().unwrap();
+        assert_eq!(state.objects.get(&Position(18, 1)), Some(&GameObject::Obstacle(Direction::West)));
+
+        // and they kill like walls.
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.place_object((8, 5), GameObject::Obstacle(Direction::North), None);
+        state.place_object((8, 4), GameObject::Wall, None);
+        state.place_object((8, 6), GameObject::Wall, None);
+        let game_over = loop {
+            if let Err(game_over) = state.tick() {
+                break game_over;
+            }
+        };
+        assert_eq!(game_over, GameOver::Died(DeathCause::Wall((8, 5))));
+    }
+
+    #[test]
+    fn power_ups_spawn() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.set_spawn_rates(SpawnRates { bonus_food: 1000, portal: 1000, obstacle: 1000, ..SpawnRates::default() });
+        let events = state.tick().unwrap();
+
+        fn count(state: &GameState, kind: fn(&GameObject) -> bool) -> usize {
+            state.object_iter().filter(|&(_, obj, _)| kind(obj)).count()
+        }
+        assert_eq!(count(&state, |obj| *obj == GameObject::BonusFood), 1);
+        assert_eq!(count(&state, |obj| matches!(*obj, GameObject::Portal(_))), 2);
+        assert_eq!(count(&state, |obj| matches!(*obj, GameObject::Obstacle(_))), 1);
+        assert_eq!(events.iter().filter(|ev| matches!(**ev, Event::Spawned(..))).count(), 4);
+
+        // portals lead to each other.
+        for (pos, obj, lifetime) in state.object_iter() {
+            if let GameObject::Portal(other) = *obj {
+                assert_eq!(state.objects.get(&Position(other.0, other.1)), Some(&GameObject::Portal(pos)));
+                assert_eq!(lifetime, Some(PORTAL_TICKS));
+            }
+        }
+
+        // only one of each at a time, but more obstacles.
+        for _ in 0..3 {
+            state.tick().unwrap();
+        }
+        assert_eq!(count(&state, |obj| *obj == GameObject::BonusFood), 1);
+        assert_eq!(count(&state, |obj| matches!(*obj, GameObject::Obstacle(_))), MAX_OBSTACLES);
+    }
+}
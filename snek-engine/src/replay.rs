@@ -0,0 +1,488 @@
+//! Recording and playback of games.
+//!
+//! A replay is a snapshot of the game as it started, followed by every
+//! call made into the `GameState` while it was played.  Because the engine
+//! is deterministic given its seed, this is enough to rebuild the game
+//! tick for tick.  Each recorded tick also carries a checksum of the board
+//! so playback can point at the exact tick where it diverged.
+//!
+//! File layout (all integers little-endian):
+//!
+//! ```text
+//! magic    b"SNEKRPLY"
+//! version  u8
+//! initial  binary snapshot, see the `snapshot` module
+//! count    u32              number of events that follow
+//! events   count * event
+//! ```
+//!
+//! Version 1 files, which only knew how to start the default game, have
+//! `seed: u64, width: u32, height: u32` in place of the snapshot.
+//!
+//! An event is a single opcode byte: `0x10..=0x13` for the first
+//! player's direction, `0x18..=0x1b` for another player's direction
+//! followed by the player number as a u32, `0x20`/`0x21` for force-grow
+//! off/on, and `0x01` for a tick, which is followed by the low 32 bits of
+//! `GameState::checksum` after that tick.  Version 2 files only ever
+//! steered the first player.
+//!
+//! Versions before 4 were recorded when food was placed by drawing random
+//! cells until one was empty, and they're played back that way.
+
+use std::io::{self, Read, Write};
+
+use super::{
+    direction_code,
+    direction_from_code,
+    read_u8,
+    read_u32,
+    read_u64,
+    Direction,
+    Event,
+    GameOver,
+    GameState,
+    PlayerId,
+    MAX_ARENA_CELLS,
+};
+use super::snapshot::{Invalid, Snapshot, SnapshotError};
+
+const MAGIC: &[u8; 8] = b"SNEKRPLY";
+pub const VERSION: u8 = 4;
+
+const OP_TICK: u8 = 0x01;
+const OP_DIRECTION: u8 = 0x10;
+const OP_PLAYER_DIRECTION: u8 = 0x18;
+const OP_FORCE_GROW_OFF: u8 = 0x20;
+const OP_FORCE_GROW_ON: u8 = 0x21;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReplayEvent {
+    Direction(PlayerId, Direction),
+    ForceGrow(bool),
+    Tick { checksum: u32 },
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The starting position is unreadable or impossible.
+    Snapshot(SnapshotError),
+    InvalidEvent(u8),
+    /// The replayed game no longer matches the recording after `tick`
+    /// (counted from zero).
+    Desync { tick: u64, expected: u32, actual: u32 },
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> ReplayError {
+        ReplayError::Io(err)
+    }
+}
+
+impl From<SnapshotError> for ReplayError {
+    fn from(err: SnapshotError) -> ReplayError {
+        ReplayError::Snapshot(err)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Replay {
+    /// Always restorable: it either came from a live game or was checked
+    /// when the replay was read.
+    initial: Snapshot,
+    events: Vec<ReplayEvent>,
+    /// Recorded before food was placed from the free-cell index.
+    legacy_food: bool,
+}
+
+impl Replay {
+    pub fn seed(&self) -> u64 {
+        self.initial.rng_seed
+    }
+
+    pub fn arena_size(&self) -> (usize, usize) {
+        (self.initial.width, self.initial.height)
+    }
+
+    /// The game as it was when recording started.
+    pub fn initial(&self) -> &Snapshot {
+        &self.initial
+    }
+
+    pub fn events(&self) -> &[ReplayEvent] {
+        &self.events
+    }
+
+    /// The number of ticks in the recording.
+    pub fn ticks(&self) -> u64 {
+        self.events.iter()
+            .filter(|ev| matches!(**ev, ReplayEvent::Tick { .. }))
+            .count() as u64
+    }
+
+    pub fn write_to<W: Write>(&self, wr: &mut W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(64 + 2 * self.events.len());
+        buf.extend_from_slice(MAGIC);
+        // version 3 is laid out just as 4 is.
+        buf.push(if self.legacy_food { 3 } else { VERSION });
+        self.initial.write_binary(&mut buf)?;
+        buf.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+
+        for event in self.events.iter() {
+            match *event {
+                ReplayEvent::Direction(PlayerId(0), dir) => buf.push(OP_DIRECTION + direction_code(dir)),
+                ReplayEvent::Direction(PlayerId(player), dir) => {
+                    buf.push(OP_PLAYER_DIRECTION + direction_code(dir));
+                    buf.extend_from_slice(&(player as u32).to_le_bytes());
+                },
+                ReplayEvent::ForceGrow(false) => buf.push(OP_FORCE_GROW_OFF),
+                ReplayEvent::ForceGrow(true) => buf.push(OP_FORCE_GROW_ON),
+                ReplayEvent::Tick { checksum } => {
+                    buf.push(OP_TICK);
+                    buf.extend_from_slice(&checksum.to_le_bytes());
+                }
+            }
+        }
+
+        wr.write_all(&buf)
+    }
+
+    pub fn read_from<R: Read>(rd: &mut R) -> Result<Replay, ReplayError> {
+        let mut magic = [0; 8];
+        rd.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+
+        let version = read_u8(rd)?;
+        let initial = match version {
+            1 => {
+                let seed = read_u64(rd)?;
+                let width = read_u32(rd)? as usize;
+                let height = read_u32(rd)? as usize;
+                // sized as `restore` would allow, before building the game.
+                if width == 0 || height == 0 {
+                    return Err(SnapshotError::Invalid(Invalid::EmptyArena).into());
+                }
+                if width.checked_mul(height).is_none_or(|cells| MAX_ARENA_CELLS < cells) {
+                    return Err(SnapshotError::Invalid(Invalid::ArenaTooLarge).into());
+                }
+                GameState::with_seed(width, height, seed).snapshot()
+            },
+            2..=VERSION => Snapshot::read_binary(rd)?,
+            version => return Err(ReplayError::UnsupportedVersion(version)),
+        };
+        GameState::restore(&initial).map_err(SnapshotError::Invalid)?;
+
+        let count = read_u32(rd)?;
+
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let event = match read_u8(rd)? {
+                OP_TICK => ReplayEvent::Tick { checksum: read_u32(rd)? },
+                OP_FORCE_GROW_OFF => ReplayEvent::ForceGrow(false),
+                OP_FORCE_GROW_ON => ReplayEvent::ForceGrow(true),
+                op if OP_PLAYER_DIRECTION <= op => {
+                    let dir = direction_from_code(op - OP_PLAYER_DIRECTION)
+                        .ok_or(ReplayError::InvalidEvent(op))?;
+                    let player = read_u32(rd)? as usize;
+                    if initial.snakes.len() <= player {
+                        return Err(ReplayError::InvalidEvent(op));
+                    }
+                    ReplayEvent::Direction(PlayerId(player), dir)
+                },
+                op => {
+                    let dir = op.checked_sub(OP_DIRECTION).and_then(direction_from_code);
+                    match dir {
+                        Some(dir) => ReplayEvent::Direction(PlayerId(0), dir),
+                        None => return Err(ReplayError::InvalidEvent(op)),
+                    }
+                }
+            };
+            events.push(event);
+        }
+
+        Ok(Replay { initial, events, legacy_food: version < 4 })
+    }
+
+    /// Play the whole recording back, verifying every tick.  Returns the
+    /// final state of the game and why it ended, if it did.
+    pub fn play(&self) -> Result<(GameState, Option<GameOver>), ReplayError> {
+        let mut player = Player::new(self)?;
+        loop {
+            match player.step()? {
+                Step::Ticked(_) => (),
+                Step::GameOver(reason) => return Ok((player.state, Some(reason))),
+                Step::Finished => return Ok((player.state, None)),
+            }
+        }
+    }
+}
+
+/// Wraps a `GameState`, recording every call made into it.
+pub struct Recorder {
+    state: GameState,
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(width: usize, height: usize, seed: u64) -> Recorder {
+        Recorder::from_state(GameState::with_seed(width, height, seed))
+    }
+
+    /// Start recording a game from wherever it currently is.
+    pub fn from_state(state: GameState) -> Recorder {
+        Recorder {
+            replay: Replay {
+                initial: state.snapshot(),
+                events: Vec::new(),
+                legacy_food: state.legacy_food,
+            },
+            state,
+        }
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn set_user_direction(&mut self, direction: Direction) {
+        self.set_direction(PlayerId(0), direction);
+    }
+
+    pub fn set_direction(&mut self, player: PlayerId, direction: Direction) {
+        self.replay.events.push(ReplayEvent::Direction(player, direction));
+        self.state.set_direction(player, direction);
+    }
+
+    pub fn set_force_grow(&mut self, grow: bool) {
+        self.replay.events.push(ReplayEvent::ForceGrow(grow));
+        self.state.set_force_grow(grow);
+    }
+
+    pub fn tick(&mut self) -> Result<Vec<Event>, GameOver> {
+        let result = self.state.tick();
+        let checksum = self.state.checksum() as u32;
+        self.replay.events.push(ReplayEvent::Tick { checksum });
+        result
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn finish(self) -> Replay {
+        self.replay
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Step {
+    Ticked(Vec<Event>),
+    GameOver(GameOver),
+    /// There is nothing more in the recording.
+    Finished,
+}
+
+/// Steps a fresh `GameState` through a recording one tick at a time, so
+/// a frontend can show the replay at whatever pace it likes.
+pub struct Player<'a> {
+    events: &'a [ReplayEvent],
+    state: GameState,
+    tick: u64,
+    over: bool,
+}
+
+impl<'a> Player<'a> {
+    /// Fails if the recording's starting position can't be restored.
+    pub fn new(replay: &'a Replay) -> Result<Player<'a>, ReplayError> {
+        let mut state = GameState::restore(&replay.initial).map_err(SnapshotError::Invalid)?;
+        state.legacy_food = replay.legacy_food;
+        Ok(Player {
+            events: &replay.events,
+            state,
+            tick: 0,
+            over: false,
+        })
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    /// The number of ticks played back so far.
+    pub fn tick_count(&self) -> u64 {
+        self.tick
+    }
+
+    /// Apply recorded input up to and including the next tick.
+    pub fn step(&mut self) -> Result<Step, ReplayError> {
+        while let Some((&event, rest)) = self.events.split_first() {
+            self.events = rest;
+
+            let expected = match event {
+                ReplayEvent::Direction(player, dir) => {
+                    self.state.set_direction(player, dir);
+                    continue;
+                },
+                ReplayEvent::ForceGrow(grow) => {
+                    self.state.set_force_grow(grow);
+                    continue;
+                },
+                ReplayEvent::Tick { checksum } => checksum,
+            };
+
+            let tick = self.tick;
+            if self.over {
+                // the recording kept going after the game ended here, so
+                // it can't have come from this game.
+                return Err(ReplayError::Desync { tick, expected, actual: 0 });
+            }
+
+            let result = self.state.tick();
+            self.tick += 1;
+
+            let actual = self.state.checksum() as u32;
+            if actual != expected {
+                return Err(ReplayError::Desync { tick, expected, actual });
+            }
+
+            return match result {
+                Ok(events) => Ok(Step::Ticked(events)),
+                Err(reason) => {
+                    self.over = true;
+                    Ok(Step::GameOver(reason))
+                },
+            };
+        }
+        Ok(Step::Finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invalid, Recorder, Replay, ReplayError, ReplayEvent, SnapshotError};
+    use super::super::{Direction, GameState, PlayerId, Topology};
+
+    fn record() -> Recorder {
+        let mut recorder = Recorder::new(16, 16, 42);
+        recorder.set_force_grow(true);
+        recorder.tick().unwrap();
+        recorder.tick().unwrap();
+        recorder.set_force_grow(false);
+        recorder.set_user_direction(Direction::North);
+        recorder.tick().unwrap();
+        recorder.set_user_direction(Direction::West);
+        recorder.tick().unwrap();
+        recorder
+    }
+
+    #[test]
+    fn round_trip_and_play() {
+        let recorder = record();
+        let expected = recorder.state().checksum();
+
+        let mut buf = Vec::new();
+        recorder.replay().write_to(&mut buf).unwrap();
+        let replay = Replay::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(&replay, recorder.replay());
+        assert_eq!(replay.ticks(), 4);
+
+        let (state, game_over) = replay.play().unwrap();
+        assert!(game_over.is_none());
+        assert_eq!(state.checksum(), expected);
+    }
+
+    #[test]
+    fn desync_is_reported_at_tick() {
+        let mut replay = record().finish();
+        // drop the turn north, so the third tick goes somewhere else.
+        let idx = replay.events.iter()
+            .position(|ev| *ev == ReplayEvent::Direction(PlayerId(0), Direction::North))
+            .unwrap();
+        replay.events.remove(idx);
+
+        match replay.play() {
+            Err(ReplayError::Desync { tick, .. }) => assert_eq!(tick, 2),
+            other => panic!("expected desync, got {:?}", other.map(|(_, over)| over)),
+        }
+    }
+
+    #[test]
+    fn version_3_places_food_the_old_way() {
+        let mut recorder = Recorder::new(16, 16, 1);
+        recorder.state.legacy_food = true;
+        recorder.replay.legacy_food = true;
+        for _ in 0..6 {
+            recorder.tick().unwrap();
+        }
+
+        let mut buf = Vec::new();
+        recorder.replay().write_to(&mut buf).unwrap();
+        assert_eq!(buf[8], 3);
+        let replay = Replay::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(replay.play().unwrap().0.checksum(), recorder.state().checksum());
+
+        // the same recording as a version 4 file puts the food elsewhere.
+        buf[8] = 4;
+        let replay = Replay::read_from(&mut &buf[..]).unwrap();
+        assert!(matches!(replay.play(), Err(ReplayError::Desync { tick: 0, .. })));
+    }
+
+    #[test]
+    fn version_1_arenas_are_checked() {
+        let header = |width: u32, height: u32| {
+            let mut buf = b"SNEKRPLY\x01".to_vec();
+            buf.extend_from_slice(&7u64.to_le_bytes());
+            buf.extend_from_slice(&width.to_le_bytes());
+            buf.extend_from_slice(&height.to_le_bytes());
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf
+        };
+
+        let replay = Replay::read_from(&mut &header(16, 16)[..]).unwrap();
+        assert_eq!(replay.arena_size(), (16, 16));
+        assert!(replay.play().is_ok());
+
+        for &(width, height, invalid) in [
+            (0, 0, Invalid::EmptyArena),
+            (16, 0, Invalid::EmptyArena),
+            (u32::MAX, u32::MAX, Invalid::ArenaTooLarge),
+        ].iter() {
+            match Replay::read_from(&mut &header(width, height)[..]) {
+                Err(ReplayError::Snapshot(SnapshotError::Invalid(found))) => assert_eq!(found, invalid),
+                other => panic!("expected {:?}, got {:?}", invalid, other),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let buf = b"SNEKSAVE\x01";
+        match Replay::read_from(&mut &buf[..]) {
+            Err(ReplayError::BadMagic) => (),
+            other => panic!("expected bad magic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn multiplayer_round_trip() {
+        let mut recorder = Recorder::from_state(GameState::with_players(12, 12, 5, Topology::Bounded, 2));
+        recorder.set_direction(PlayerId(1), Direction::North);
+        recorder.tick().unwrap();
+        recorder.set_direction(PlayerId(0), Direction::South);
+        let game_over = loop {
+            if let Err(game_over) = recorder.tick() {
+                break game_over;
+            }
+        };
+
+        let mut buf = Vec::new();
+        recorder.replay().write_to(&mut buf).unwrap();
+        let replay = Replay::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(&replay, recorder.replay());
+        assert_eq!(replay.play().unwrap().1, Some(game_over));
+    }
+}
@@ -0,0 +1,99 @@
+use rand::{self, Rng};
+
+/// The random number generator owned by a `GameState`.
+///
+/// This is a xorshift64* generator, seeded through splitmix64.  It is
+/// deliberately tiny and fully owned by the engine so that a game can be
+/// reproduced from nothing more than its seed and its inputs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GameRng {
+    seed: u64,
+    state: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        let mut state = splitmix64(seed);
+        if state == 0 {
+            // xorshift gets stuck at zero forever.
+            state = 0x9E37_79B9_7F4A_7C15;
+        }
+        GameRng { seed, state }
+    }
+
+    /// Create a generator from a seed drawn from the thread-local RNG.
+    pub fn from_entropy() -> GameRng {
+        GameRng::new(rand::thread_rng().gen())
+    }
+
+    /// Rebuild a generator part way through its sequence, as saved by
+    /// `state`.  Returns `None` for the one state xorshift can't be in.
+    pub fn from_state(seed: u64, state: u64) -> Option<GameRng> {
+        if state == 0 {
+            return None;
+        }
+        Some(GameRng { seed, state })
+    }
+
+    /// The seed this generator was created from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The generator's current position in its sequence.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+}
+
+impl Rng for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use super::GameRng;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = GameRng::new(1234);
+        let mut b = GameRng::new(1234);
+        for _ in 0..64 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_eq!(a.seed(), 1234);
+    }
+
+    #[test]
+    fn different_seed_different_sequence() {
+        let mut a = GameRng::new(1);
+        let mut b = GameRng::new(2);
+        assert!(a.next_u64() != b.next_u64());
+    }
+
+    #[test]
+    fn resume_from_state() {
+        let mut a = GameRng::new(99);
+        a.next_u64();
+        let mut b = GameRng::from_state(a.seed(), a.state()).unwrap();
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert!(GameRng::from_state(99, 0).is_none());
+    }
+}
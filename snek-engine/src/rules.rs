@@ -0,0 +1,364 @@
+//! How a game is set up, and how fast it runs.
+//!
+//! `Rules` gathers what the frontends used to hard-code: the size of the
+//! arena, how long the snakes start out, how much food is about and how
+//! often the game ticks.
+//! The time between ticks follows a `SpeedCurve`, which can shorten as
+//! the snakes grow or eat, and a `SpeedEffect` can hurry the game up or
+//! slow it down for a while.  `Difficulty` names some ready-made rules.
+//!
+//! The speed only decides how often a frontend calls `GameState::tick`,
+//! never what a tick does, but it is part of snapshots and checksums so
+//! that a restored or resynced game keeps its pace.
+
+use std::cmp;
+use std::time::Duration;
+
+use super::{GameState, Snake, Topology};
+
+/// The longest a restored game may wait between ticks, before any speed
+/// effect.
+pub const SLOWEST: Duration = Duration::from_secs(60);
+
+/// The most a speed effect may scale the time between ticks by, in
+/// percent.
+pub const MAX_EFFECT_PERCENT: u32 = 1000;
+
+/// What makes the game speed up.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Ramp {
+    /// The speed never changes.
+    Constant,
+    /// Ticks come `step` sooner for every `every` cells the longest
+    /// living snake has grown.
+    Length { every: usize, step: Duration },
+    /// Ticks come `step` sooner for every `every` pieces of food eaten,
+    /// by anyone.  Most of a player's score comes from food, so this
+    /// speeds up as the scores go up.
+    Food { every: u64, step: Duration },
+}
+
+/// How the time between ticks changes over a game.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SpeedCurve {
+    /// The time between ticks when the game starts.
+    pub start: Duration,
+    /// The ramp stops once ticks are this close together.
+    pub fastest: Duration,
+    pub ramp: Ramp,
+}
+
+impl SpeedCurve {
+    /// A tick every `interval`, all game long.
+    pub fn constant(interval: Duration) -> SpeedCurve {
+        SpeedCurve {
+            start: interval,
+            fastest: interval,
+            ramp: Ramp::Constant,
+        }
+    }
+
+    /// The time between ticks once the longest snake has grown by
+    /// `grown` cells and `eaten` pieces of food have been eaten.
+    pub fn interval(&self, grown: usize, eaten: u64) -> Duration {
+        let (steps, step) = match self.ramp {
+            Ramp::Constant => (0, Duration::from_secs(0)),
+            Ramp::Length { every, step } => (grown.checked_div(every).unwrap_or(0) as u64, step),
+            Ramp::Food { every, step } => (eaten.checked_div(every).unwrap_or(0), step),
+        };
+
+        let cut = step.checked_mul(cmp::min(steps, u64::from(u32::MAX)) as u32);
+        let fastest = cmp::min(self.fastest, self.start);
+        match cut.and_then(|cut| self.start.checked_sub(cut)) {
+            Some(interval) => cmp::max(interval, fastest),
+            None => fastest,
+        }
+    }
+}
+
+impl Default for SpeedCurve {
+    /// Ten ticks a second, the speed snek has always had.
+    fn default() -> SpeedCurve {
+        SpeedCurve::constant(Duration::from_millis(100))
+    }
+}
+
+/// A temporary change of speed, as from a power-up.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SpeedEffect {
+    /// The time between ticks is scaled by this many percent, so less
+    /// than 100 is faster.
+    pub percent: u32,
+    /// How many more ticks the effect lasts.
+    pub ticks: u64,
+}
+
+impl SpeedEffect {
+    pub fn fast(ticks: u64) -> SpeedEffect {
+        SpeedEffect { percent: 60, ticks }
+    }
+
+    pub fn slow(ticks: u64) -> SpeedEffect {
+        SpeedEffect { percent: 150, ticks }
+    }
+}
+
+/// Everything needed to start a game, other than the seed and the number
+/// of players.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rules {
+    pub width: usize,
+    pub height: usize,
+    pub topology: Topology,
+    /// How many cells each snake starts with.  Snakes start in a straight
+    /// line, and no longer than a quarter of the arena's width allows.
+    pub start_length: usize,
+    /// How many pieces of food are kept on the board at once.
+    pub food: usize,
+    pub speed: SpeedCurve,
+}
+
+impl Default for Rules {
+    /// A single-cell snake chasing one piece of food in a bounded 64x64
+    /// arena at the classic speed, as snek has always been played.
+    fn default() -> Rules {
+        Rules {
+            width: 64,
+            height: 64,
+            topology: Topology::Bounded,
+            start_length: 1,
+            food: 1,
+            speed: SpeedCurve::default(),
+        }
+    }
+}
+
+/// Ready-made rules, from gentle to frantic.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    Insane,
+}
+
+/// The difficulties `Difficulty::by_name` knows.
+pub const NAMES: [&str; 4] = ["easy", "normal", "hard", "insane"];
+
+impl Difficulty {
+    pub fn by_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            "insane" => Some(Difficulty::Insane),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+            Difficulty::Insane => "insane",
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        let ms = Duration::from_millis;
+        let (size, start_length, food, speed) = match *self {
+            Difficulty::Easy => (48, 3, 2, SpeedCurve {
+                start: ms(150),
+                fastest: ms(100),
+                ramp: Ramp::Food { every: 5, step: ms(10) },
+            }),
+            Difficulty::Normal => (64, 4, 1, SpeedCurve {
+                start: ms(100),
+                fastest: ms(60),
+                ramp: Ramp::Food { every: 3, step: ms(5) },
+            }),
+            Difficulty::Hard => (64, 6, 1, SpeedCurve {
+                start: ms(80),
+                fastest: ms(45),
+                ramp: Ramp::Length { every: 4, step: ms(5) },
+            }),
+            Difficulty::Insane => (48, 10, 1, SpeedCurve {
+                start: ms(60),
+                fastest: ms(30),
+                ramp: Ramp::Length { every: 2, step: ms(3) },
+            }),
+        };
+
+        Rules {
+            width: size,
+            height: size,
+            topology: Topology::Bounded,
+            start_length,
+            food,
+            speed,
+        }
+    }
+}
+
+impl GameState {
+    /// A game for `players` snakes set up by `rules`.  Panics if
+    /// `players` is zero.
+    pub fn with_rules(rules: &Rules, seed: u64, players: usize) -> GameState {
+        let mut state = GameState::with_players(rules.width, rules.height, seed, rules.topology, players);
+
+        // the lone snake starts a quarter of the way in, and the others
+        // no nearer the edge, so that much room is behind every one.
+        let length = cmp::max(1, cmp::min(rules.start_length, rules.width / 4 + 1));
+        for player in state.players.iter_mut() {
+            let mut body = player.snake.body.clone();
+            while body.len() < length {
+                body.push_back(player.direction);
+            }
+            player.snake = Snake::new(player.snake.head_pos, body, state.arena_size);
+        }
+        state.index_cells();
+
+        state.start_length = length;
+        state.food_target = rules.food;
+        state.speed = rules.speed;
+        state
+    }
+
+    pub fn speed(&self) -> SpeedCurve {
+        self.speed
+    }
+
+    /// Change how fast the game goes from now on.  Food already eaten and
+    /// growth so far count towards the new curve's ramp.
+    pub fn set_speed(&mut self, speed: SpeedCurve) {
+        self.speed = speed;
+    }
+
+    /// The speed effect in force, with the ticks it has left.
+    pub fn speed_effect(&self) -> Option<SpeedEffect> {
+        self.speed_effect
+    }
+
+    /// Start a speed effect, replacing any already in force.
+    pub fn apply_speed_effect(&mut self, effect: SpeedEffect) {
+        self.speed_effect = if 0 < effect.ticks { Some(effect) } else { None };
+    }
+
+    /// How long a frontend should wait before the next tick.
+    pub fn tick_interval(&self) -> Duration {
+        let longest = self.players.iter()
+            .filter(|player| player.death.is_none())
+            .map(|player| player.snake.len())
+            .max()
+            .unwrap_or(0);
+        let interval = self.speed.interval(longest.saturating_sub(self.start_length), self.food_eaten);
+
+        match self.speed_effect {
+            Some(effect) => interval.checked_mul(effect.percent).map_or(Duration::MAX, |scaled| scaled / 100),
+            None => interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Direction, PlayerId};
+
+    #[test]
+    fn curves_ramp_down_to_the_fastest() {
+        let ms = Duration::from_millis;
+        let curve = SpeedCurve {
+            start: ms(100),
+            fastest: ms(60),
+            ramp: Ramp::Food { every: 3, step: ms(5) },
+        };
+        assert_eq!(curve.interval(50, 0), ms(100));
+        assert_eq!(curve.interval(0, 2), ms(100));
+        assert_eq!(curve.interval(0, 3), ms(95));
+        assert_eq!(curve.interval(0, 14), ms(80));
+        assert_eq!(curve.interval(0, 1000), ms(60));
+        assert_eq!(curve.interval(0, u64::MAX), ms(60));
+
+        let curve = SpeedCurve { ramp: Ramp::Length { every: 4, step: ms(5) }, ..curve };
+        assert_eq!(curve.interval(9, 100), ms(90));
+
+        assert_eq!(SpeedCurve::default().interval(1000, 1000), ms(100));
+        let broken = SpeedCurve { ramp: Ramp::Length { every: 0, step: ms(5) }, ..curve };
+        assert_eq!(broken.interval(9, 9), ms(100));
+    }
+
+    #[test]
+    fn rules_set_up_the_game() {
+        let rules = Difficulty::Hard.rules();
+        let state = GameState::with_rules(&rules, 1, 2);
+        assert_eq!(state.arena_size(), (64, 64));
+        for idx in 0..2 {
+            let snake = state.snake(PlayerId(idx)).unwrap();
+            assert_eq!(snake.len(), 6);
+            assert!(snake.positions().all(|(x, y)| x < 64 && y < 64));
+        }
+
+        // a narrow arena only has room for a short snake.
+        let rules = Rules { width: 8, start_length: 20, ..Rules::default() };
+        let state = GameState::with_rules(&rules, 1, 1);
+        let positions: Vec<_> = state.snake(PlayerId(0)).unwrap().positions().collect();
+        assert_eq!(positions, vec![(2, 32), (1, 32), (0, 32)]);
+
+        for name in NAMES.iter() {
+            assert_eq!(Difficulty::by_name(name).unwrap().name(), *name);
+        }
+    }
+
+    #[test]
+    fn the_game_speeds_up_as_snakes_grow() {
+        let ms = Duration::from_millis;
+        let rules = Rules {
+            width: 20,
+            height: 10,
+            start_length: 2,
+            speed: SpeedCurve {
+                start: ms(100),
+                fastest: ms(50),
+                ramp: Ramp::Length { every: 2, step: ms(10) },
+            },
+            ..Rules::default()
+        };
+        let mut state = GameState::with_rules(&rules, 1, 1);
+        state.set_direction(PlayerId(0), Direction::North);
+        state.set_force_grow(true);
+        assert_eq!(state.tick_interval(), ms(100));
+
+        state.tick().unwrap();
+        assert_eq!(state.tick_interval(), ms(100));
+        state.tick().unwrap();
+        assert_eq!(state.tick_interval(), ms(90));
+        state.tick().unwrap();
+        state.tick().unwrap();
+        assert_eq!(state.tick_interval(), ms(80));
+    }
+
+    #[test]
+    fn speed_effects_wear_off() {
+        let ms = Duration::from_millis;
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.apply_speed_effect(SpeedEffect::slow(2));
+        assert_eq!(state.tick_interval(), ms(150));
+
+        state.tick().unwrap();
+        assert_eq!(state.speed_effect(), Some(SpeedEffect { percent: 150, ticks: 1 }));
+        state.tick().unwrap();
+        assert_eq!(state.speed_effect(), None);
+        assert_eq!(state.tick_interval(), ms(100));
+
+        state.apply_speed_effect(SpeedEffect::fast(5));
+        assert_eq!(state.tick_interval(), ms(60));
+        state.apply_speed_effect(SpeedEffect::fast(0));
+        assert_eq!(state.tick_interval(), ms(100));
+
+        state.set_speed(SpeedCurve::constant(Duration::MAX));
+        state.apply_speed_effect(SpeedEffect { percent: u32::MAX, ticks: 1 });
+        assert_eq!(state.tick_interval(), Duration::MAX);
+    }
+}
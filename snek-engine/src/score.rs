@@ -0,0 +1,508 @@
+//! Scoring, per-game statistics and a persistent high-score table.
+//!
+//! A `Scorer` watches the events coming out of `GameState::tick` and turns
+//! one player's share of them into points.  Finished games go into a
+//! `HighScores` table, which is kept in a small text file under the
+//! user's data directory.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Event, Fnv64, GameObject, GameState, PlayerId};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ScoreRules {
+    /// Points for each piece of food eaten.
+    pub food_points: u64,
+    /// Points for each piece of bonus food, which also counts towards
+    /// combos but never earns a speed bonus.
+    pub bonus_food_points: u64,
+    /// Eating again within this many ticks of the last food continues a
+    /// combo.
+    pub combo_window: u64,
+    /// Extra points per step of an ongoing combo.
+    pub combo_points: u64,
+    /// The combo multiplier stops growing here.
+    pub max_combo: u64,
+    /// Food eaten within this many ticks of appearing earns a bonus of
+    /// one point per tick to spare.
+    pub speed_window: u64,
+}
+
+impl Default for ScoreRules {
+    fn default() -> ScoreRules {
+        ScoreRules {
+            food_points: 10,
+            bonus_food_points: 40,
+            combo_window: 20,
+            combo_points: 5,
+            max_combo: 10,
+            speed_window: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GameStats {
+    pub score: u64,
+    pub ticks_survived: u64,
+    pub food_eaten: u64,
+    pub max_length: usize,
+    pub best_combo: u64,
+    /// `(tick, length)` each time the snake grew.
+    pub length_history: Vec<(u64, usize)>,
+}
+
+pub struct Scorer {
+    rules: ScoreRules,
+    player: PlayerId,
+    stats: GameStats,
+    combo: u64,
+    last_food_tick: Option<u64>,
+    food_spawned: BTreeMap<(usize, usize), u64>,
+}
+
+impl Scorer {
+    /// Keep score for the first player of a game about to start.
+    pub fn new(rules: ScoreRules, state: &GameState) -> Scorer {
+        Scorer::for_player(rules, state, PlayerId(0))
+    }
+
+    /// Keep score for one player in a multiplayer game.  Each player
+    /// needs their own `Scorer`.  Their longest starts out as long as
+    /// their snake is in `state`.
+    pub fn for_player(rules: ScoreRules, state: &GameState, player: PlayerId) -> Scorer {
+        Scorer {
+            rules,
+            player,
+            stats: GameStats {
+                score: 0,
+                ticks_survived: 0,
+                food_eaten: 0,
+                max_length: state.snake(player).map_or(0, |snake| snake.len()),
+                best_combo: 0,
+                length_history: Vec::new(),
+            },
+            combo: 0,
+            last_food_tick: None,
+            food_spawned: BTreeMap::new(),
+        }
+    }
+
+    pub fn player(&self) -> PlayerId {
+        self.player
+    }
+
+    /// Account for one tick which the snake survived.  Events for other
+    /// players are ignored, except for food appearing.
+    pub fn record_tick(&mut self, events: &[Event]) {
+        let tick = self.stats.ticks_survived;
+        self.stats.ticks_survived += 1;
+
+        for event in events.iter() {
+            match *event {
+                Event::AteFood(player, pos) if player == self.player => self.ate_food(tick, pos),
+                Event::Collected(player, GameObject::BonusFood, _) if player == self.player => {
+                    let points = self.rules.bonus_food_points;
+                    self.eat(tick, points);
+                },
+                Event::Grew(player, length) if player == self.player => {
+                    self.stats.length_history.push((tick, length));
+                    if self.stats.max_length < length {
+                        self.stats.max_length = length;
+                    }
+                },
+                Event::FoodSpawned(pos) => {
+                    self.food_spawned.insert(pos, tick);
+                },
+                Event::AteFood(..) | Event::Grew(..) | Event::Died(..) => (),
+                Event::Collected(..) | Event::Spawned(..) | Event::Expired(..) => (),
+            }
+        }
+    }
+
+    fn ate_food(&mut self, tick: u64, pos: (usize, usize)) {
+        let speed_bonus = match self.food_spawned.remove(&pos) {
+            Some(spawned) => self.rules.speed_window.saturating_sub(tick - spawned),
+            None => 0,
+        };
+        let points = self.rules.food_points + speed_bonus;
+        self.eat(tick, points);
+    }
+
+    /// Score `points` for something eaten, plus any combo.
+    fn eat(&mut self, tick: u64, points: u64) {
+        let rules = self.rules;
+
+        let in_combo = match self.last_food_tick {
+            Some(last) => tick - last <= rules.combo_window,
+            None => false,
+        };
+        self.combo = if in_combo { (self.combo + 1).min(rules.max_combo) } else { 0 };
+        self.last_food_tick = Some(tick);
+        if self.stats.best_combo < self.combo {
+            self.stats.best_combo = self.combo;
+        }
+
+        self.stats.food_eaten += 1;
+        self.stats.score += points + self.combo * rules.combo_points;
+    }
+
+    pub fn stats(&self) -> &GameStats {
+        &self.stats
+    }
+
+    pub fn into_stats(self) -> GameStats {
+        self.stats
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HighScore {
+    pub name: String,
+    pub score: u64,
+    pub ticks_survived: u64,
+    pub max_length: usize,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+impl HighScore {
+    pub fn new(name: &str, stats: &GameStats) -> HighScore {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        HighScore {
+            // tabs and newlines would break the file format.
+            name: name.split_whitespace().collect::<Vec<_>>().join(" "),
+            score: stats.score,
+            ticks_survived: stats.ticks_survived,
+            max_length: stats.max_length,
+            timestamp,
+        }
+    }
+}
+
+/// How `HighScores::load` got its table.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Recovery {
+    /// The file was fine, or didn't exist yet.
+    Clean,
+    /// The file was damaged, and the previous copy was used instead.
+    FromBackup,
+    /// The file and its backup were both damaged; starting over.
+    Reset,
+}
+
+const TABLE_MAGIC: &str = "snek-highscores 1";
+pub const TABLE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HighScores {
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    pub fn new() -> HighScores {
+        HighScores { entries: Vec::new() }
+    }
+
+    /// Where the table lives by default: `$XDG_DATA_HOME/snek`, falling
+    /// back to `~/.local/share/snek`, or `%APPDATA%\snek` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let data_dir = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from));
+        data_dir.map(|dir| dir.join("snek").join("highscores.txt"))
+    }
+
+    pub fn entries(&self) -> &[HighScore] {
+        &self.entries
+    }
+
+    /// Whether a game with this score would make it into the table.
+    pub fn qualifies(&self, score: u64) -> bool {
+        self.entries.len() < TABLE_SIZE || self.entries.iter().any(|e| e.score < score)
+    }
+
+    /// Add an entry, returning its rank (from zero) if it made the cut.
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        let rank = self.entries.iter()
+            .position(|e| e.score < entry.score)
+            .unwrap_or(self.entries.len());
+        if TABLE_SIZE <= rank {
+            return None;
+        }
+        self.entries.insert(rank, entry);
+        self.entries.truncate(TABLE_SIZE);
+        Some(rank)
+    }
+
+    /// Load the table at `path`.  A damaged file is moved aside to
+    /// `<path>.corrupt` and the backup kept by `save` is used instead.
+    pub fn load(path: &Path) -> io::Result<(HighScores, Recovery)> {
+        match read_table(path) {
+            Ok(Some(table)) => return Ok((table, Recovery::Clean)),
+            Ok(None) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok((HighScores::new(), Recovery::Clean));
+            },
+            Err(err) => return Err(err),
+        }
+
+        fs::rename(path, with_suffix(path, "corrupt"))?;
+        match read_table(&with_suffix(path, "bak")) {
+            Ok(Some(table)) => Ok((table, Recovery::FromBackup)),
+            Ok(None) => Ok((HighScores::new(), Recovery::Reset)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                Ok((HighScores::new(), Recovery::Reset))
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Atomically replace the table at `path`, keeping the previous
+    /// version as `<path>.bak`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = with_suffix(path, "tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(self.encode().as_bytes())?;
+            file.sync_all()?;
+        }
+
+        if path.exists() {
+            fs::copy(path, with_suffix(path, "bak"))?;
+        }
+        fs::rename(&tmp_path, path)
+    }
+
+    fn encode(&self) -> String {
+        let mut out = String::new();
+        out.push_str(TABLE_MAGIC);
+        out.push('\n');
+        for e in self.entries.iter() {
+            out.push_str(&format!("{}\t{}\t{}\t{}\t{}\n",
+                e.score, e.ticks_survived, e.max_length, e.timestamp, e.name));
+        }
+        out.push_str(&format!("checksum {:016x}\n", table_checksum(&out)));
+        out
+    }
+}
+
+impl Default for HighScores {
+    fn default() -> HighScores {
+        HighScores::new()
+    }
+}
+
+/// `Ok(None)` means the file exists but can't be trusted.
+fn read_table(path: &Path) -> io::Result<Option<HighScores>> {
+    let file = File::open(path)?;
+
+    let mut body = String::new();
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
+        match line {
+            Ok(line) => lines.push(line),
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidData => return Ok(None),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let checksum = match lines.pop() {
+        Some(last) => last,
+        None => return Ok(None),
+    };
+    if lines.first().map(|l| &l[..]) != Some(TABLE_MAGIC) {
+        return Ok(None);
+    }
+    for line in lines.iter() {
+        body.push_str(line);
+        body.push('\n');
+    }
+    if checksum != format!("checksum {:016x}", table_checksum(&body)) {
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    for line in lines[1..].iter() {
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        if fields.len() != 5 {
+            return Ok(None);
+        }
+        let parsed = (fields[0].parse(), fields[1].parse(), fields[2].parse(), fields[3].parse());
+        match parsed {
+            (Ok(score), Ok(ticks_survived), Ok(max_length), Ok(timestamp)) => {
+                entries.push(HighScore {
+                    name: fields[4].to_string(),
+                    score,
+                    ticks_survived,
+                    max_length,
+                    timestamp,
+                });
+            },
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(HighScores { entries }))
+}
+
+/// Put a finished game in the table at `HighScores::default_path`, under
+/// the name the user logged in with, and save the table if it made the
+/// cut.  Returns its rank from zero, or `None` if it didn't make it or
+/// there's nowhere to keep a table.
+pub fn record_high_score(stats: &GameStats) -> io::Result<Option<usize>> {
+    let path = match HighScores::default_path() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let in_table = |err: io::Error| io::Error::new(err.kind(), format!("{}: {}", path.display(), err));
+
+    let (mut table, _recovery) = HighScores::load(&path).map_err(&in_table)?;
+    let name = env::var("USER").unwrap_or_else(|_| "player".to_string());
+    let rank = table.insert(HighScore::new(&name, stats));
+    if rank.is_some() {
+        table.save(&path).map_err(&in_table)?;
+    }
+    Ok(rank)
+}
+
+fn table_checksum(body: &str) -> u64 {
+    let mut hasher = Fnv64::new();
+    hasher.write(body.as_bytes());
+    hasher.finish()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::{GameStats, HighScore, HighScores, Recovery, ScoreRules, Scorer};
+    use super::super::rules::Rules;
+    use super::super::{Event, GameObject, GameState, PlayerId};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("snek-score-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn entry(name: &str, score: u64) -> HighScore {
+        let stats = GameStats {
+            score,
+            ticks_survived: 100,
+            food_eaten: 3,
+            max_length: 4,
+            best_combo: 0,
+            length_history: Vec::new(),
+        };
+        HighScore::new(name, &stats)
+    }
+
+    #[test]
+    fn combos_and_speed_bonus() {
+        let rules = ScoreRules {
+            food_points: 10,
+            bonus_food_points: 40,
+            combo_window: 5,
+            combo_points: 5,
+            max_combo: 10,
+            speed_window: 3,
+        };
+        let mut scorer = Scorer::new(rules, &GameState::with_seed(20, 20, 0));
+
+        scorer.record_tick(&[Event::FoodSpawned((1, 1))]);
+        scorer.record_tick(&[Event::AteFood(PlayerId(0), (1, 1)), Event::Grew(PlayerId(0), 2), Event::FoodSpawned((5, 5))]);
+        // one tick after spawning, two to spare: 10 + 2
+        assert_eq!(scorer.stats().score, 12);
+
+        for _ in 0..2 {
+            scorer.record_tick(&[]);
+        }
+        // someone else's food doesn't count
+        scorer.record_tick(&[Event::AteFood(PlayerId(1), (9, 9)), Event::Grew(PlayerId(1), 7)]);
+        scorer.record_tick(&[Event::AteFood(PlayerId(0), (5, 5)), Event::Grew(PlayerId(0), 3)]);
+        // a combo, too slow for a speed bonus: 10 + 5
+        assert_eq!(scorer.stats().score, 27);
+
+        // bonus food carries the combo on: 40 + 10
+        scorer.record_tick(&[Event::Collected(PlayerId(0), GameObject::BonusFood, (2, 2)), Event::Grew(PlayerId(0), 4)]);
+        assert_eq!(scorer.stats().score, 77);
+
+        let stats = scorer.into_stats();
+        assert_eq!(stats.ticks_survived, 7);
+        assert_eq!(stats.food_eaten, 3);
+        assert_eq!(stats.max_length, 4);
+        assert_eq!(stats.best_combo, 2);
+        assert_eq!(stats.length_history, vec![(1, 2), (5, 3), (6, 4)]);
+    }
+
+    #[test]
+    fn longest_counts_from_the_start() {
+        let state = GameState::with_rules(&Rules { start_length: 6, ..Rules::default() }, 0, 2);
+        let mut scorer = Scorer::for_player(ScoreRules::default(), &state, PlayerId(1));
+        scorer.record_tick(&[]);
+        assert_eq!(scorer.stats().max_length, 6);
+
+        scorer.record_tick(&[Event::AteFood(PlayerId(1), (1, 1)), Event::Grew(PlayerId(1), 7)]);
+        assert_eq!(scorer.into_stats().max_length, 7);
+    }
+
+    #[test]
+    fn table_keeps_the_best() {
+        let mut table = HighScores::new();
+        for score in 0..15 {
+            table.insert(entry("player", score * 10));
+        }
+        assert_eq!(table.entries().len(), 10);
+        assert_eq!(table.entries()[0].score, 140);
+        assert!(!table.qualifies(50));
+        assert_eq!(table.insert(entry("late", 95)), Some(5));
+    }
+
+    #[test]
+    fn save_load_and_recover() {
+        let dir = scratch_dir("recover");
+        let path = dir.join("highscores.txt");
+
+        assert_eq!(HighScores::load(&path).unwrap(), (HighScores::new(), Recovery::Clean));
+
+        let mut table = HighScores::new();
+        table.insert(entry("first", 10));
+        table.save(&path).unwrap();
+        table.insert(entry("second", 20));
+        table.save(&path).unwrap();
+        assert_eq!(HighScores::load(&path).unwrap(), (table.clone(), Recovery::Clean));
+
+        fs::write(&path, "snek-highscores 1\n20\tgarbage\n").unwrap();
+        let (recovered, how) = HighScores::load(&path).unwrap();
+        assert_eq!(how, Recovery::FromBackup);
+        assert_eq!(recovered.entries().len(), 1);
+        assert_eq!(recovered.entries()[0].name, "first");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1421 @@
+//! Saving and restoring a game in progress.
+//!
+//! `GameState::snapshot` captures everything needed to carry on playing
+//! later, including the random number generator, and `GameState::restore`
+//! turns a snapshot back into a game after checking that it describes a
+//! position the engine could actually have reached.  Snapshots can come
+//! from other players, so arenas over `MAX_ARENA_CELLS` cells and games
+//! with more than `MAX_SNAKES` snakes are refused before anything is
+//! built from them.
+//!
+//! Snapshots can be stored in a compact binary form or in a line-based
+//! text form meant for reading, diffing and hand-editing bug reports:
+//!
+//! ```text
+//! snek-snapshot 7
+//! arena 64 64
+//! topology torus
+//! win-condition last-snake-standing
+//! rng 12345 9e3779b97f4a7c15
+//! force-grow false
+//! food-target 1
+//! speed 80000 45000 length 4 5000
+//! speed-effect 60 12
+//! start-length 6
+//! food-eaten 14
+//! spawn bonus-food 20
+//! snake east 18 32 east east west
+//! jump 1 30 3
+//! ghost 12
+//! snake west 40 20 west west
+//! died snake 39 20 0
+//! food 40 7
+//! bonus-food 41 9 for 25
+//! portal 30 3 17 32 for 200
+//! portal 17 32 30 3 for 200
+//! obstacle 5 5 north
+//! wall 0 0
+//! ```
+//!
+//! Each snake line gives the direction the snake is heading, its head
+//! position, and then the direction each segment moved in, from the head
+//! towards the tail.  A `jump` line after a snake says that segment went
+//! through a portal, entering at the position given, and a `ghost` line
+//! how long it can still pass through itself.  A `died` line says how it
+//! died, and for a snake that ran into another, whose.  Object lines may
+//! end with how many ticks the object has left, and `spawn` lines give
+//! the game's spawn rates.
+//!
+//! The `speed` line gives the speed curve's start, fastest and ramp, with
+//! times in microseconds, and a `speed-effect` line any effect in force,
+//! as a percentage and the ticks it has left.  With `start-length` and
+//! `food-eaten` they decide `GameState::tick_interval`.  Restoring
+//! refuses ticks no time apart or more than `rules::SLOWEST` apart, and
+//! effects of nothing or beyond `rules::MAX_EFFECT_PERCENT`, since a
+//! frontend would either spin or stall on them.
+//!
+//! Versions before 4 had a single snake, with its heading on a separate
+//! `direction` line, versions before 6 didn't say whose snake a snake ran
+//! into, and versions before 7 had no speed, so restored games ran at the
+//! classic speed.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, Read, Write};
+use std::time::Duration;
+
+use super::{
+    direction_code,
+    direction_from_code,
+    direction_from_name,
+    direction_name,
+    read_u8,
+    read_u32,
+    read_u64,
+    Area,
+    DeathCause,
+    Direction,
+    FreeCells,
+    MAX_ARENA_CELLS,
+    GameObject,
+    GameRng,
+    GameState,
+    Occupancy,
+    Player,
+    PlayerId,
+    Position,
+    Scratch,
+    Snake,
+    Topology,
+    WinCondition,
+};
+use super::powerup::{self, SpawnRates};
+use super::rules::{Ramp, SpeedCurve, SpeedEffect, MAX_EFFECT_PERCENT, SLOWEST};
+
+const MAGIC: &[u8; 8] = b"SNEKSAVE";
+const TEXT_MAGIC: &str = "snek-snapshot";
+pub const VERSION: u8 = 7;
+
+/// The most snakes a snapshot may have.
+pub const MAX_SNAKES: usize = 256;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Snapshot {
+    pub width: usize,
+    pub height: usize,
+    pub topology: Topology,
+    pub win_condition: WinCondition,
+    /// One per player, in player order.
+    pub snakes: Vec<SnakeSnapshot>,
+    pub objects: Vec<((usize, usize), GameObject)>,
+    /// Ticks left for the objects which don't stay for good.
+    pub lifetimes: Vec<((usize, usize), u64)>,
+    pub spawn_rates: SpawnRates,
+    pub force_grow: bool,
+    /// How many pieces of food the game keeps on the board.
+    pub food_target: usize,
+    pub rng_seed: u64,
+    pub rng_state: u64,
+    pub speed: SpeedCurve,
+    pub speed_effect: Option<SpeedEffect>,
+    /// How long the snakes started, for ramping the speed.
+    pub start_length: usize,
+    pub food_eaten: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SnakeSnapshot {
+    pub head: (usize, usize),
+    /// The direction each segment moved in, from the head to the tail.
+    pub body: Vec<Direction>,
+    pub direction: Direction,
+    pub death: Option<DeathCause>,
+    /// The segments which came out of a portal, each with where the
+    /// portal was entered.
+    pub jumps: Vec<(usize, (usize, usize))>,
+    pub ghost: u64,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// A malformed line in the text format.
+    Syntax { line: usize, message: &'static str },
+    /// Well-formed, but not a position the game can be in.
+    Invalid(Invalid),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Invalid {
+    EmptyArena,
+    /// More than `MAX_ARENA_CELLS` cells.
+    ArenaTooLarge,
+    NoSnakes,
+    /// More than `MAX_SNAKES` snakes.
+    TooManySnakes,
+    EmptySnake,
+    /// Following the body from the head walks off the edge of the world.
+    Discontiguous,
+    SnakeOutOfBounds((usize, usize)),
+    SnakeOverlapsItself((usize, usize)),
+    /// Two living snakes are in the same place.
+    SnakesOverlap((usize, usize)),
+    SnakeOverlapsObject((usize, usize)),
+    ObjectOutOfBounds((usize, usize)),
+    /// More objects than there are cells to put them in.
+    TooManyObjects,
+    DuplicateObject((usize, usize)),
+    /// A portal end whose other end doesn't lead back to it.
+    UnlinkedPortal((usize, usize)),
+    /// A lifetime for a position with no object.
+    StrayLifetime((usize, usize)),
+    /// The pending direction would turn a snake back into its neck.
+    ReversedDirection,
+    /// A speed effect with no ticks left, or which scales the time
+    /// between ticks by nothing or by more than `MAX_EFFECT_PERCENT`.
+    SpeedEffect,
+    /// A speed curve whose ticks come no time apart, or more than
+    /// `SLOWEST` apart.
+    Speed,
+    RngState,
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<Invalid> for SnapshotError {
+    fn from(err: Invalid) -> SnapshotError {
+        SnapshotError::Invalid(err)
+    }
+}
+
+impl GameState {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            width: self.arena_size.width,
+            height: self.arena_size.height,
+            topology: self.arena_size.topology,
+            win_condition: self.win_condition,
+            snakes: self.players.iter()
+                .map(|player| SnakeSnapshot {
+                    head: player.snake.head_pos.to_tuple(),
+                    body: player.snake.body.iter().cloned().collect(),
+                    direction: player.direction,
+                    death: player.death,
+                    jumps: jumps(&player.snake),
+                    ghost: player.ghost,
+                })
+                .collect(),
+            objects: self.objects.iter()
+                .map(|(pos, obj)| (pos.to_tuple(), *obj))
+                .collect(),
+            lifetimes: self.lifetimes.iter()
+                .map(|(pos, ticks)| (pos.to_tuple(), *ticks))
+                .collect(),
+            spawn_rates: self.spawn_rates,
+            force_grow: self.force_grow,
+            food_target: self.food_target,
+            rng_seed: self.rng.seed(),
+            rng_state: self.rng.state(),
+            speed: self.speed,
+            speed_effect: self.speed_effect,
+            start_length: self.start_length,
+            food_eaten: self.food_eaten,
+        }
+    }
+
+    pub fn restore(snapshot: &Snapshot) -> Result<GameState, Invalid> {
+        let arena_size = Area {
+            x_off: 0,
+            y_off: 0,
+            width: snapshot.width,
+            height: snapshot.height,
+            topology: snapshot.topology,
+        };
+        if arena_size.width == 0 || arena_size.height == 0 {
+            return Err(Invalid::EmptyArena);
+        }
+        let cells = arena_size.width.checked_mul(arena_size.height)
+            .filter(|&cells| cells <= MAX_ARENA_CELLS)
+            .ok_or(Invalid::ArenaTooLarge)?;
+
+        let rng = GameRng::from_state(snapshot.rng_seed, snapshot.rng_state)
+            .ok_or(Invalid::RngState)?;
+
+        if snapshot.snakes.is_empty() {
+            return Err(Invalid::NoSnakes);
+        }
+        if MAX_SNAKES < snapshot.snakes.len() {
+            return Err(Invalid::TooManySnakes);
+        }
+        if cells < snapshot.objects.len() {
+            return Err(Invalid::TooManyObjects);
+        }
+
+        // cells taken by living snakes; dead ones are off the board, and
+        // may have died half outside it.
+        let mut occupied = BTreeMap::new();
+        for snake in snapshot.snakes.iter() {
+            if snake.body.is_empty() {
+                return Err(Invalid::EmptySnake);
+            }
+
+            let alive = snake.death.is_none();
+            let mut own = BTreeMap::new();
+            for pos in snake_cells(snake, arena_size)? {
+                if alive && !arena_size.contains(&pos) {
+                    return Err(Invalid::SnakeOutOfBounds(pos.to_tuple()));
+                }
+                if alive && occupied.contains_key(&pos) {
+                    return Err(Invalid::SnakesOverlap(pos.to_tuple()));
+                }
+                // a ghost can be across itself.
+                if alive && own.insert(pos, ()).is_some() && snake.ghost == 0 {
+                    return Err(Invalid::SnakeOverlapsItself(pos.to_tuple()));
+                }
+            }
+            occupied.extend(own);
+
+            if alive && snake.body[0] == snake.direction.negate() {
+                return Err(Invalid::ReversedDirection);
+            }
+        }
+
+        let mut objects = BTreeMap::new();
+        for &((x, y), obj) in snapshot.objects.iter() {
+            let pos = Position(x, y);
+            if !arena_size.contains(&pos) {
+                return Err(Invalid::ObjectOutOfBounds(pos.to_tuple()));
+            }
+            // a head that has just come out of a portal sits on it.
+            let portal = matches!(obj, GameObject::Portal(_));
+            if occupied.contains_key(&pos) && !portal {
+                return Err(Invalid::SnakeOverlapsObject(pos.to_tuple()));
+            }
+            if objects.insert(pos, obj).is_some() {
+                return Err(Invalid::DuplicateObject(pos.to_tuple()));
+            }
+        }
+        for (&pos, obj) in objects.iter() {
+            if let GameObject::Portal(other) = *obj {
+                if objects.get(&Position(other.0, other.1)) != Some(&GameObject::Portal(pos.to_tuple())) {
+                    return Err(Invalid::UnlinkedPortal(pos.to_tuple()));
+                }
+            }
+        }
+
+        let speed = snapshot.speed;
+        let zero = Duration::from_secs(0);
+        if [speed.start, speed.fastest].iter().any(|&interval| interval == zero || SLOWEST < interval) {
+            return Err(Invalid::Speed);
+        }
+        let effect_ok = |effect: &SpeedEffect| {
+            0 < effect.ticks && 0 < effect.percent && effect.percent <= MAX_EFFECT_PERCENT
+        };
+        if !snapshot.speed_effect.as_ref().is_none_or(effect_ok) {
+            return Err(Invalid::SpeedEffect);
+        }
+
+        let mut lifetimes = BTreeMap::new();
+        for &((x, y), ticks) in snapshot.lifetimes.iter() {
+            let pos = Position(x, y);
+            if !objects.contains_key(&pos) || ticks == 0 {
+                return Err(Invalid::StrayLifetime(pos.to_tuple()));
+            }
+            lifetimes.insert(pos, ticks);
+        }
+
+        let mut players = Vec::new();
+        for snake in snapshot.snakes.iter() {
+            players.push(Player {
+                snake: Snake::with_cells(
+                    Position(snake.head.0, snake.head.1),
+                    snake.body.iter().cloned().collect(),
+                    snake_cells(snake, arena_size)?,
+                    arena_size,
+                ),
+                direction: snake.direction,
+                death: snake.death,
+                ghost: snake.ghost,
+            });
+        }
+
+        let mut state = GameState {
+            arena_size,
+            players,
+            win_condition: snapshot.win_condition,
+            objects: objects.into_iter().collect(),
+            lifetimes: lifetimes.into_iter().collect(),
+            spawn_rates: snapshot.spawn_rates,
+            force_grow: snapshot.force_grow,
+            food_target: snapshot.food_target,
+            free_cells: FreeCells::new(0),
+            occupied: Occupancy::new(0, 0),
+            legacy_food: false,
+            scratch: Scratch::default(),
+            rng,
+            speed: snapshot.speed,
+            speed_effect: snapshot.speed_effect,
+            start_length: snapshot.start_length,
+            food_eaten: snapshot.food_eaten,
+        };
+        state.index_cells();
+        Ok(state)
+    }
+}
+
+impl Snapshot {
+    pub fn write_binary<W: Write>(&self, wr: &mut W) -> io::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        put_u32(&mut buf, self.width);
+        put_u32(&mut buf, self.height);
+        buf.extend_from_slice(&self.rng_seed.to_le_bytes());
+        buf.extend_from_slice(&self.rng_state.to_le_bytes());
+        buf.push(self.force_grow as u8);
+        put_u32(&mut buf, self.food_target);
+        buf.push(topology_code(self.topology));
+        buf.push(win_condition_code(self.win_condition));
+
+        put_u32(&mut buf, self.snakes.len());
+        for snake in self.snakes.iter() {
+            buf.push(direction_code(snake.direction));
+            match snake.death {
+                Some(cause) => {
+                    let (code, (x, y)) = death_code(cause);
+                    buf.push(code);
+                    put_u32(&mut buf, x);
+                    put_u32(&mut buf, y);
+                    if let DeathCause::Snake(PlayerId(other), _) = cause {
+                        put_u32(&mut buf, other);
+                    }
+                },
+                None => buf.push(0),
+            }
+            put_u32(&mut buf, snake.head.0);
+            put_u32(&mut buf, snake.head.1);
+            put_u32(&mut buf, snake.body.len());
+            for &dir in snake.body.iter() {
+                buf.push(direction_code(dir));
+            }
+            put_u32(&mut buf, snake.jumps.len());
+            for &(segment, (x, y)) in snake.jumps.iter() {
+                put_u32(&mut buf, segment);
+                put_u32(&mut buf, x);
+                put_u32(&mut buf, y);
+            }
+            buf.extend_from_slice(&snake.ghost.to_le_bytes());
+        }
+
+        put_u32(&mut buf, self.objects.len());
+        for &((x, y), obj) in self.objects.iter() {
+            put_u32(&mut buf, x);
+            put_u32(&mut buf, y);
+            buf.push(object_code(obj));
+            match obj {
+                GameObject::Portal((x, y)) => {
+                    put_u32(&mut buf, x);
+                    put_u32(&mut buf, y);
+                },
+                GameObject::Obstacle(dir) => buf.push(direction_code(dir)),
+                _ => (),
+            }
+        }
+
+        put_u32(&mut buf, self.lifetimes.len());
+        for &((x, y), ticks) in self.lifetimes.iter() {
+            put_u32(&mut buf, x);
+            put_u32(&mut buf, y);
+            buf.extend_from_slice(&ticks.to_le_bytes());
+        }
+        for name in powerup::NAMES.iter() {
+            put_u32(&mut buf, self.spawn_rates.get(name).unwrap_or(0) as usize);
+        }
+
+        let (ramp, every, step) = ramp_parts(self.speed.ramp);
+        put_micros(&mut buf, self.speed.start);
+        put_micros(&mut buf, self.speed.fastest);
+        buf.push(ramp);
+        buf.extend_from_slice(&every.to_le_bytes());
+        put_micros(&mut buf, step);
+        match self.speed_effect {
+            Some(effect) => {
+                buf.push(1);
+                put_u32(&mut buf, effect.percent as usize);
+                buf.extend_from_slice(&effect.ticks.to_le_bytes());
+            },
+            None => buf.push(0),
+        }
+        put_u32(&mut buf, self.start_length);
+        buf.extend_from_slice(&self.food_eaten.to_le_bytes());
+
+        wr.write_all(&buf)
+    }
+
+    pub fn read_binary<R: Read>(rd: &mut R) -> Result<Snapshot, SnapshotError> {
+        let mut magic = [0; 8];
+        rd.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = read_u8(rd)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let width = read_u32(rd)? as usize;
+        let height = read_u32(rd)? as usize;
+        let rng_seed = read_u64(rd)?;
+        let rng_state = read_u64(rd)?;
+        // versions before 4 had only the one snake, whose direction was
+        // stored here.
+        let direction = if version < 4 { Some(read_direction(rd)?) } else { None };
+        let force_grow = match read_u8(rd)? {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid_data("bad force-grow flag")),
+        };
+        // version 1 predates levels, and always had one piece of food.
+        let food_target = if 2 <= version { read_u32(rd)? as usize } else { 1 };
+        // Versions before 3 had no topology and were always bounded.
+        let topology = if 3 <= version {
+            topology_from_code(read_u8(rd)?).ok_or_else(|| invalid_data("bad topology"))?
+        } else {
+            Topology::Bounded
+        };
+
+        let mut snakes = Vec::new();
+        let win_condition = match direction {
+            Some(direction) => {
+                snakes.push(read_snake(rd, version, direction, None)?);
+                WinCondition::LastSnakeStanding
+            },
+            None => {
+                let win_condition = win_condition_from_code(read_u8(rd)?)
+                    .ok_or_else(|| invalid_data("bad win condition"))?;
+                let count = read_u32(rd)?;
+                for _ in 0..count {
+                    let direction = read_direction(rd)?;
+                    let death = match read_u8(rd)? {
+                        0 => None,
+                        code => {
+                            let pos = (read_u32(rd)? as usize, read_u32(rd)? as usize);
+                            let other = if code == SNAKE_DEATH && 6 <= version { Some(read_u32(rd)? as usize) } else { None };
+                            let cause = death_from_code(code, pos, other)
+                                .ok_or_else(|| invalid_data("bad cause of death"))?;
+                            Some(cause)
+                        },
+                    };
+                    snakes.push(read_snake(rd, version, direction, death)?);
+                }
+                win_condition
+            },
+        };
+
+        let object_count = read_u32(rd)?;
+        let mut objects = Vec::new();
+        for _ in 0..object_count {
+            let pos = (read_u32(rd)? as usize, read_u32(rd)? as usize);
+            let obj = read_object(rd)?;
+            objects.push((pos, obj));
+        }
+
+        // version 5 added power-ups.
+        let mut lifetimes = Vec::new();
+        let mut spawn_rates = SpawnRates::default();
+        if 5 <= version {
+            let count = read_u32(rd)?;
+            for _ in 0..count {
+                let pos = (read_u32(rd)? as usize, read_u32(rd)? as usize);
+                lifetimes.push((pos, read_u64(rd)?));
+            }
+            for name in powerup::NAMES.iter() {
+                spawn_rates.set(name, read_u32(rd)?);
+            }
+        }
+
+        // and version 7 the speed.
+        let mut speed = SpeedCurve::default();
+        let mut speed_effect = None;
+        let mut start_length = 1;
+        let mut food_eaten = 0;
+        if 7 <= version {
+            let start = read_micros(rd)?;
+            let fastest = read_micros(rd)?;
+            let code = read_u8(rd)?;
+            let every = read_u64(rd)?;
+            let step = read_micros(rd)?;
+            let ramp = ramp_from_parts(code, every, step).ok_or_else(|| invalid_data("bad speed ramp"))?;
+            speed = SpeedCurve { start, fastest, ramp };
+            speed_effect = match read_u8(rd)? {
+                0 => None,
+                1 => Some(SpeedEffect { percent: read_u32(rd)?, ticks: read_u64(rd)? }),
+                _ => return Err(invalid_data("bad speed effect flag")),
+            };
+            start_length = read_u32(rd)? as usize;
+            food_eaten = read_u64(rd)?;
+        }
+
+        let mut snapshot = Snapshot {
+            width,
+            height,
+            topology,
+            win_condition,
+            snakes,
+            objects,
+            lifetimes,
+            spawn_rates,
+            force_grow,
+            food_target,
+            rng_seed,
+            rng_state,
+            speed,
+            speed_effect,
+            start_length,
+            food_eaten,
+        };
+        if version < 6 {
+            guess_snake_deaths(&mut snapshot);
+        }
+        Ok(snapshot)
+    }
+
+    pub fn write_text<W: Write>(&self, wr: &mut W) -> io::Result<()> {
+        writeln!(wr, "{} {}", TEXT_MAGIC, VERSION)?;
+        writeln!(wr, "arena {} {}", self.width, self.height)?;
+        writeln!(wr, "topology {}", topology_name(self.topology))?;
+        writeln!(wr, "win-condition {}", win_condition_name(self.win_condition))?;
+        writeln!(wr, "rng {} {:016x}", self.rng_seed, self.rng_state)?;
+        writeln!(wr, "force-grow {}", self.force_grow)?;
+        writeln!(wr, "food-target {}", self.food_target)?;
+        let (ramp, every, step) = ramp_parts(self.speed.ramp);
+        writeln!(wr, "speed {} {} {} {} {}", micros(self.speed.start), micros(self.speed.fastest),
+                 RAMP_NAMES[ramp as usize], every, micros(step))?;
+        if let Some(effect) = self.speed_effect {
+            writeln!(wr, "speed-effect {} {}", effect.percent, effect.ticks)?;
+        }
+        writeln!(wr, "start-length {}", self.start_length)?;
+        writeln!(wr, "food-eaten {}", self.food_eaten)?;
+        for name in powerup::NAMES.iter() {
+            match self.spawn_rates.get(name) {
+                Some(rate) if 0 < rate => writeln!(wr, "spawn {} {}", name, rate)?,
+                _ => (),
+            }
+        }
+
+        for snake in self.snakes.iter() {
+            write!(wr, "snake {} {} {}", direction_name(snake.direction), snake.head.0, snake.head.1)?;
+            for &dir in snake.body.iter() {
+                write!(wr, " {}", direction_name(dir))?;
+            }
+            writeln!(wr)?;
+            for &(segment, (x, y)) in snake.jumps.iter() {
+                writeln!(wr, "jump {} {} {}", segment, x, y)?;
+            }
+            if 0 < snake.ghost {
+                writeln!(wr, "ghost {}", snake.ghost)?;
+            }
+
+            if let Some(cause) = snake.death {
+                let (code, (x, y)) = death_code(cause);
+                write!(wr, "died {} {} {}", DEATH_NAMES[code as usize - 1], x, y)?;
+                if let DeathCause::Snake(PlayerId(other), _) = cause {
+                    write!(wr, " {}", other)?;
+                }
+                writeln!(wr)?;
+            }
+        }
+
+        let lifetimes: BTreeMap<_, _> = self.lifetimes.iter().cloned().collect();
+        for &((x, y), obj) in self.objects.iter() {
+            write!(wr, "{} {} {}", OBJECT_NAMES[object_code(obj) as usize], x, y)?;
+            match obj {
+                GameObject::Portal((x, y)) => write!(wr, " {} {}", x, y)?,
+                GameObject::Obstacle(dir) => write!(wr, " {}", direction_name(dir))?,
+                _ => (),
+            }
+            if let Some(ticks) = lifetimes.get(&(x, y)) {
+                write!(wr, " for {}", ticks)?;
+            }
+            writeln!(wr)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_text<R: BufRead>(rd: R) -> Result<Snapshot, SnapshotError> {
+        let mut snapshot = Snapshot {
+            width: 0,
+            height: 0,
+            topology: Topology::Bounded,
+            win_condition: WinCondition::LastSnakeStanding,
+            snakes: Vec::new(),
+            objects: Vec::new(),
+            lifetimes: Vec::new(),
+            spawn_rates: SpawnRates::default(),
+            force_grow: false,
+            food_target: 1,
+            rng_seed: 0,
+            rng_state: 0,
+            speed: SpeedCurve::default(),
+            speed_effect: None,
+            start_length: 1,
+            food_eaten: 0,
+        };
+        let mut version = None;
+        // the separate direction line of versions before 4.
+        let mut old_direction = Direction::East;
+
+        for (idx, line) in rd.lines().enumerate() {
+            let line = line?;
+            let lineno = idx + 1;
+            let syntax = |message| SnapshotError::Syntax { line: lineno, message };
+
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(word) => word,
+                None => continue,
+            };
+            if keyword.starts_with('#') {
+                continue;
+            }
+
+            let version = match version {
+                Some(version) => version,
+                None => {
+                    if keyword != TEXT_MAGIC {
+                        return Err(SnapshotError::BadMagic);
+                    }
+                    let found: u8 = parse(words.next()).ok_or_else(|| syntax("expected a version"))?;
+                    if !(1..=VERSION).contains(&found) {
+                        return Err(SnapshotError::UnsupportedVersion(found));
+                    }
+                    version = Some(found);
+                    continue;
+                },
+            };
+
+            match keyword {
+                "arena" => {
+                    snapshot.width = parse(words.next()).ok_or_else(|| syntax("expected a width"))?;
+                    snapshot.height = parse(words.next()).ok_or_else(|| syntax("expected a height"))?;
+                },
+                "rng" => {
+                    snapshot.rng_seed = parse(words.next()).ok_or_else(|| syntax("expected a seed"))?;
+                    snapshot.rng_state = words.next()
+                        .and_then(|w| u64::from_str_radix(w, 16).ok())
+                        .ok_or_else(|| syntax("expected a hexadecimal rng state"))?;
+                },
+                "topology" => {
+                    snapshot.topology = words.next()
+                        .and_then(topology_from_name)
+                        .ok_or_else(|| syntax("expected bounded, horizontal, vertical or torus"))?;
+                },
+                "win-condition" => {
+                    snapshot.win_condition = words.next()
+                        .and_then(win_condition_from_name)
+                        .ok_or_else(|| syntax("expected last-snake-standing or elimination"))?;
+                },
+                "direction" if version < 4 => {
+                    old_direction = words.next()
+                        .and_then(direction_from_name)
+                        .ok_or_else(|| syntax("expected a direction"))?;
+                    if let Some(snake) = snapshot.snakes.first_mut() {
+                        snake.direction = old_direction;
+                    }
+                },
+                "force-grow" => {
+                    snapshot.force_grow = parse(words.next()).ok_or_else(|| syntax("expected true or false"))?;
+                },
+                "food-target" => {
+                    snapshot.food_target = parse(words.next()).ok_or_else(|| syntax("expected a number"))?;
+                },
+                "speed" => {
+                    let start = parse(words.next()).ok_or_else(|| syntax("expected a start time"))?;
+                    let fastest = parse(words.next()).ok_or_else(|| syntax("expected a fastest time"))?;
+                    let code = words.next()
+                        .and_then(|name| RAMP_NAMES.iter().position(|&n| n == name))
+                        .ok_or_else(|| syntax("expected constant, length or food"))?;
+                    let every = parse(words.next()).ok_or_else(|| syntax("expected how often the speed ramps"))?;
+                    let step = parse(words.next()).ok_or_else(|| syntax("expected a step"))?;
+                    snapshot.speed = SpeedCurve {
+                        start: Duration::from_micros(start),
+                        fastest: Duration::from_micros(fastest),
+                        ramp: ramp_from_parts(code as u8, every, Duration::from_micros(step))
+                            .ok_or_else(|| syntax("expected how often the speed ramps"))?,
+                    };
+                },
+                "speed-effect" => {
+                    let percent = parse(words.next()).ok_or_else(|| syntax("expected a percentage"))?;
+                    let ticks = parse(words.next()).ok_or_else(|| syntax("expected a number of ticks"))?;
+                    snapshot.speed_effect = Some(SpeedEffect { percent, ticks });
+                },
+                "start-length" => {
+                    snapshot.start_length = parse(words.next()).ok_or_else(|| syntax("expected a length"))?;
+                },
+                "food-eaten" => {
+                    snapshot.food_eaten = parse(words.next()).ok_or_else(|| syntax("expected a number"))?;
+                },
+                "snake" => {
+                    let direction = if version < 4 {
+                        // there was only ever one snake line.
+                        snapshot.snakes.clear();
+                        old_direction
+                    } else {
+                        words.next()
+                            .and_then(direction_from_name)
+                            .ok_or_else(|| syntax("expected the direction the snake is heading"))?
+                    };
+                    let x = parse(words.next()).ok_or_else(|| syntax("expected a head x position"))?;
+                    let y = parse(words.next()).ok_or_else(|| syntax("expected a head y position"))?;
+                    let mut body = Vec::new();
+                    for word in words.by_ref() {
+                        let dir = direction_from_name(word).ok_or_else(|| syntax("expected a direction"))?;
+                        body.push(dir);
+                    }
+                    snapshot.snakes.push(SnakeSnapshot {
+                        head: (x, y),
+                        body,
+                        direction,
+                        death: None,
+                        jumps: Vec::new(),
+                        ghost: 0,
+                    });
+                },
+                "jump" => {
+                    let segment = parse(words.next()).ok_or_else(|| syntax("expected a segment"))?;
+                    let x = parse(words.next()).ok_or_else(|| syntax("expected an x position"))?;
+                    let y = parse(words.next()).ok_or_else(|| syntax("expected a y position"))?;
+                    let snake = snapshot.snakes.last_mut().ok_or_else(|| syntax("jump before any snake"))?;
+                    snake.jumps.push((segment, (x, y)));
+                },
+                "ghost" => {
+                    let ticks = parse(words.next()).ok_or_else(|| syntax("expected a number of ticks"))?;
+                    let snake = snapshot.snakes.last_mut().ok_or_else(|| syntax("ghost before any snake"))?;
+                    snake.ghost = ticks;
+                },
+                "spawn" => {
+                    let name = words.next().ok_or_else(|| syntax("expected a kind of power-up"))?;
+                    let rate = parse(words.next()).ok_or_else(|| syntax("expected a rate"))?;
+                    if !snapshot.spawn_rates.set(name, rate) {
+                        return Err(syntax("expected a kind of power-up"));
+                    }
+                },
+                "died" => {
+                    let code = words.next()
+                        .and_then(|name| DEATH_NAMES.iter().position(|&n| n == name))
+                        .ok_or_else(|| syntax("expected a cause of death"))?;
+                    let x = parse(words.next()).ok_or_else(|| syntax("expected an x position"))?;
+                    let y = parse(words.next()).ok_or_else(|| syntax("expected a y position"))?;
+                    let code = code as u8 + 1;
+                    let other = if code == SNAKE_DEATH && 6 <= version {
+                        Some(parse(words.next()).ok_or_else(|| syntax("expected whose snake"))?)
+                    } else {
+                        None
+                    };
+                    let snake = snapshot.snakes.last_mut().ok_or_else(|| syntax("died before any snake"))?;
+                    snake.death = death_from_code(code, (x, y), other);
+                },
+                _ if OBJECT_NAMES.contains(&keyword) => {
+                    let x = parse(words.next()).ok_or_else(|| syntax("expected an x position"))?;
+                    let y = parse(words.next()).ok_or_else(|| syntax("expected a y position"))?;
+                    let obj = match keyword {
+                        "portal" => {
+                            let other_x = parse(words.next()).ok_or_else(|| syntax("expected the other end's x position"))?;
+                            let other_y = parse(words.next()).ok_or_else(|| syntax("expected the other end's y position"))?;
+                            GameObject::Portal((other_x, other_y))
+                        },
+                        "obstacle" => {
+                            let dir = words.next()
+                                .and_then(direction_from_name)
+                                .ok_or_else(|| syntax("expected a direction"))?;
+                            GameObject::Obstacle(dir)
+                        },
+                        _ => {
+                            let code = OBJECT_NAMES.iter().position(|&name| name == keyword).unwrap();
+                            object_from_code(code as u8).unwrap()
+                        },
+                    };
+                    snapshot.objects.push(((x, y), obj));
+
+                    match words.next() {
+                        Some("for") => {
+                            let ticks = parse(words.next()).ok_or_else(|| syntax("expected a number of ticks"))?;
+                            snapshot.lifetimes.push(((x, y), ticks));
+                        },
+                        Some(_) => return Err(syntax("trailing garbage")),
+                        None => (),
+                    }
+                },
+                _ => return Err(syntax("unknown keyword")),
+            }
+
+            if words.next().is_some() {
+                return Err(syntax("trailing garbage"));
+            }
+        }
+
+        match version {
+            None => return Err(SnapshotError::BadMagic),
+            Some(version) if version < 6 => guess_snake_deaths(&mut snapshot),
+            Some(_) => (),
+        }
+        Ok(snapshot)
+    }
+}
+
+fn parse<T: ::std::str::FromStr>(word: Option<&str>) -> Option<T> {
+    word.and_then(|w| w.parse().ok())
+}
+
+fn topology_name(topology: Topology) -> &'static str {
+    match topology {
+        Topology::Bounded => "bounded",
+        Topology::WrapHorizontal => "horizontal",
+        Topology::WrapVertical => "vertical",
+        Topology::Torus => "torus",
+    }
+}
+
+fn topology_from_name(name: &str) -> Option<Topology> {
+    match name {
+        "bounded" => Some(Topology::Bounded),
+        "horizontal" => Some(Topology::WrapHorizontal),
+        "vertical" => Some(Topology::WrapVertical),
+        "torus" => Some(Topology::Torus),
+        _ => None,
+    }
+}
+
+fn topology_code(topology: Topology) -> u8 {
+    match topology {
+        Topology::Bounded => 0,
+        Topology::WrapHorizontal => 1,
+        Topology::WrapVertical => 2,
+        Topology::Torus => 3,
+    }
+}
+
+fn topology_from_code(code: u8) -> Option<Topology> {
+    match code {
+        0 => Some(Topology::Bounded),
+        1 => Some(Topology::WrapHorizontal),
+        2 => Some(Topology::WrapVertical),
+        3 => Some(Topology::Torus),
+        _ => None,
+    }
+}
+
+fn win_condition_name(win_condition: WinCondition) -> &'static str {
+    match win_condition {
+        WinCondition::LastSnakeStanding => "last-snake-standing",
+        WinCondition::Elimination => "elimination",
+    }
+}
+
+fn win_condition_from_name(name: &str) -> Option<WinCondition> {
+    match name {
+        "last-snake-standing" => Some(WinCondition::LastSnakeStanding),
+        "elimination" => Some(WinCondition::Elimination),
+        _ => None,
+    }
+}
+
+fn win_condition_code(win_condition: WinCondition) -> u8 {
+    match win_condition {
+        WinCondition::LastSnakeStanding => 0,
+        WinCondition::Elimination => 1,
+    }
+}
+
+fn win_condition_from_code(code: u8) -> Option<WinCondition> {
+    match code {
+        0 => Some(WinCondition::LastSnakeStanding),
+        1 => Some(WinCondition::Elimination),
+        _ => None,
+    }
+}
+
+/// Text names for speed ramps, in code order.
+const RAMP_NAMES: &[&str] = &["constant", "length", "food"];
+
+/// A ramp's code, how often it steps and by how much.
+fn ramp_parts(ramp: Ramp) -> (u8, u64, Duration) {
+    match ramp {
+        Ramp::Constant => (0, 0, Duration::from_secs(0)),
+        Ramp::Length { every, step } => (1, every as u64, step),
+        Ramp::Food { every, step } => (2, every, step),
+    }
+}
+
+fn ramp_from_parts(code: u8, every: u64, step: Duration) -> Option<Ramp> {
+    match code {
+        0 => Some(Ramp::Constant),
+        1 => Some(Ramp::Length { every: every as usize, step }),
+        2 => Some(Ramp::Food { every, step }),
+        _ => None,
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+/// Text names for causes of death, in code order starting from 1.
+const DEATH_NAMES: &[&str] = &["wall", "snake", "head-on", "boundary", "overflow", "self"];
+
+/// The code for running into a snake, which is followed by whose.
+const SNAKE_DEATH: u8 = 2;
+
+fn death_code(cause: DeathCause) -> (u8, (usize, usize)) {
+    match cause {
+        DeathCause::Wall(pos) => (1, pos),
+        DeathCause::Snake(_, pos) => (SNAKE_DEATH, pos),
+        DeathCause::HeadOn(pos) => (3, pos),
+        DeathCause::Boundary(pos) => (4, pos),
+        DeathCause::Overflow(pos) => (5, pos),
+        DeathCause::SelfCollision(pos) => (6, pos),
+    }
+}
+
+/// A cause of death from its code and position.  Running into a snake
+/// needs `other`, whose snake it was; without it, as in versions before
+/// 6, it reads as running into itself until `guess_snake_deaths` looks.
+fn death_from_code(code: u8, pos: (usize, usize), other: Option<usize>) -> Option<DeathCause> {
+    match (code, other) {
+        (1, _) => Some(DeathCause::Wall(pos)),
+        (SNAKE_DEATH, Some(other)) => Some(DeathCause::Snake(PlayerId(other), pos)),
+        (SNAKE_DEATH, None) => Some(DeathCause::SelfCollision(pos)),
+        (3, _) => Some(DeathCause::HeadOn(pos)),
+        (4, _) => Some(DeathCause::Boundary(pos)),
+        (5, _) => Some(DeathCause::Overflow(pos)),
+        (6, _) => Some(DeathCause::SelfCollision(pos)),
+        _ => None,
+    }
+}
+
+/// Versions before 6 only said that a snake ran into a snake.  It was
+/// its own if its body is still there, and otherwise whichever other
+/// snake's body is.
+fn guess_snake_deaths(snapshot: &mut Snapshot) {
+    let area = Area {
+        x_off: 0,
+        y_off: 0,
+        width: snapshot.width,
+        height: snapshot.height,
+        topology: snapshot.topology,
+    };
+    let cells: Vec<_> = snapshot.snakes.iter()
+        .map(|snake| snake_cells(snake, area).unwrap_or_default())
+        .collect();
+
+    for (idx, snake) in snapshot.snakes.iter_mut().enumerate() {
+        let pos = match snake.death {
+            Some(DeathCause::SelfCollision((x, y))) => Position(x, y),
+            _ => continue,
+        };
+        if cells[idx].iter().skip(1).any(|&cell| cell == pos) {
+            continue;
+        }
+        if let Some(other) = (0..cells.len()).find(|&other| other != idx && cells[other].contains(&pos)) {
+            snake.death = Some(DeathCause::Snake(PlayerId(other), pos.to_tuple()));
+        }
+    }
+}
+
+/// Text names for objects, in code order.
+const OBJECT_NAMES: &[&str] = &[
+    "food", "wall", "bonus-food", "shrink-pill", "fast-pill", "slow-pill", "ghost-pill", "portal", "obstacle",
+];
+
+fn object_code(obj: GameObject) -> u8 {
+    match obj {
+        GameObject::Food => 0,
+        GameObject::Wall => 1,
+        GameObject::BonusFood => 2,
+        GameObject::Shrink => 3,
+        GameObject::Fast => 4,
+        GameObject::Slow => 5,
+        GameObject::Ghost => 6,
+        GameObject::Portal(_) => 7,
+        GameObject::Obstacle(_) => 8,
+    }
+}
+
+/// The objects without anything more to them than their kind.
+fn object_from_code(code: u8) -> Option<GameObject> {
+    match code {
+        0 => Some(GameObject::Food),
+        1 => Some(GameObject::Wall),
+        2 => Some(GameObject::BonusFood),
+        3 => Some(GameObject::Shrink),
+        4 => Some(GameObject::Fast),
+        5 => Some(GameObject::Slow),
+        6 => Some(GameObject::Ghost),
+        _ => None,
+    }
+}
+
+fn read_object<R: Read>(rd: &mut R) -> Result<GameObject, SnapshotError> {
+    match read_u8(rd)? {
+        7 => Ok(GameObject::Portal((read_u32(rd)? as usize, read_u32(rd)? as usize))),
+        8 => Ok(GameObject::Obstacle(read_direction(rd)?)),
+        code => object_from_code(code).ok_or_else(|| invalid_data("bad object kind")),
+    }
+}
+
+/// Where a snake's segments went through portals: each segment whose
+/// cell isn't a step back from the one before it, with the portal it
+/// went into.
+fn jumps(snake: &Snake) -> Vec<(usize, (usize, usize))> {
+    let mut jumps = Vec::new();
+    for (idx, (pair, &dir)) in snake.cells.iter().zip(snake.cells.iter().skip(1)).zip(snake.body.iter()).enumerate() {
+        let (&cell, &behind) = pair;
+        if snake.area.step(cell, dir.negate()) != Some(behind) {
+            if let Some(entrance) = snake.area.step(behind, dir) {
+                jumps.push((idx, entrance.to_tuple()));
+            }
+        }
+    }
+    jumps
+}
+
+/// Walk a snake's cells out from its head, through any portals.
+fn snake_cells(snake: &SnakeSnapshot, area: Area) -> Result<VecDeque<Position>, Invalid> {
+    let mut jumps = snake.jumps.iter().peekable();
+    let mut cells = VecDeque::with_capacity(snake.body.len());
+    let mut pos = Position(snake.head.0, snake.head.1);
+    for (idx, &dir) in snake.body.iter().enumerate() {
+        cells.push_back(pos);
+        if idx + 1 == snake.body.len() {
+            break;
+        }
+        if let Some(&&(_, entrance)) = jumps.peek().filter(|&&&(segment, _)| segment == idx) {
+            jumps.next();
+            pos = Position(entrance.0, entrance.1);
+        }
+        pos = area.step(pos, dir.negate()).ok_or(Invalid::Discontiguous)?;
+    }
+    // jumps out of order, or past the tail.
+    if jumps.next().is_some() {
+        return Err(Invalid::Discontiguous);
+    }
+    Ok(cells)
+}
+
+fn invalid_data(message: &'static str) -> SnapshotError {
+    SnapshotError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: usize) {
+    buf.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn put_micros(buf: &mut Vec<u8>, duration: Duration) {
+    buf.extend_from_slice(&micros(duration).to_le_bytes());
+}
+
+fn read_micros<R: Read>(rd: &mut R) -> io::Result<Duration> {
+    Ok(Duration::from_micros(read_u64(rd)?))
+}
+
+fn read_direction<R: Read>(rd: &mut R) -> Result<Direction, SnapshotError> {
+    direction_from_code(read_u8(rd)?).ok_or_else(|| invalid_data("bad direction"))
+}
+
+fn read_snake<R: Read>(rd: &mut R, version: u8, direction: Direction, death: Option<DeathCause>) -> Result<SnakeSnapshot, SnapshotError> {
+    let head = (read_u32(rd)? as usize, read_u32(rd)? as usize);
+    let body_len = read_u32(rd)?;
+    let mut body = Vec::new();
+    for _ in 0..body_len {
+        body.push(read_direction(rd)?);
+    }
+
+    let mut jumps = Vec::new();
+    let mut ghost = 0;
+    if 5 <= version {
+        let count = read_u32(rd)?;
+        for _ in 0..count {
+            let segment = read_u32(rd)? as usize;
+            jumps.push((segment, (read_u32(rd)? as usize, read_u32(rd)? as usize)));
+        }
+        ghost = read_u64(rd)?;
+    }
+    Ok(SnakeSnapshot { head, body, direction, death, jumps, ghost })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invalid, Snapshot, SnapshotError, MAX_SNAKES};
+    use std::time::Duration;
+
+    use super::super::powerup::SpawnRates;
+    use super::super::rules::{Difficulty, SpeedCurve, SpeedEffect, MAX_EFFECT_PERCENT, SLOWEST};
+    use super::super::{DeathCause, Direction, GameObject, GameState, PlayerId, Topology, WinCondition};
+
+    fn played() -> GameState {
+        let mut state = GameState::with_seed(20, 20, 7);
+        state.set_force_grow(true);
+        for _ in 0..3 {
+            state.tick().unwrap();
+        }
+        state.set_user_direction(Direction::South);
+        state.tick().unwrap();
+        state
+    }
+
+    #[test]
+    fn binary_round_trip_resumes_identically() {
+        let mut original = played();
+        let mut buf = Vec::new();
+        original.snapshot().write_binary(&mut buf).unwrap();
+
+        let snapshot = Snapshot::read_binary(&mut &buf[..]).unwrap();
+        assert_eq!(snapshot, original.snapshot());
+        let mut restored = GameState::restore(&snapshot).unwrap();
+
+        for _ in 0..5 {
+            original.tick().unwrap();
+            restored.tick().unwrap();
+            assert_eq!(original.checksum(), restored.checksum());
+        }
+    }
+
+    #[test]
+    fn wrapped_snake_round_trip() {
+        let mut state = GameState::with_topology(6, 6, 2, Topology::Torus);
+        state.set_force_grow(true);
+        for _ in 0..5 {
+            state.tick().unwrap();
+        }
+        let mut buf = Vec::new();
+        state.snapshot().write_text(&mut buf).unwrap();
+
+        let restored = GameState::restore(&Snapshot::read_text(&buf[..]).unwrap()).unwrap();
+        assert_eq!(restored.topology(), Topology::Torus);
+        assert_eq!(restored.checksum(), state.checksum());
+    }
+
+    #[test]
+    fn dead_snakes_round_trip() {
+        let mut state = GameState::with_players(9, 9, 3, Topology::Bounded, 2);
+        state.set_win_condition(WinCondition::Elimination);
+        state.set_direction(PlayerId(0), Direction::North);
+        while state.is_alive(PlayerId(0)) {
+            state.tick().unwrap();
+        }
+
+        let mut text = Vec::new();
+        state.snapshot().write_text(&mut text).unwrap();
+        let snapshot = Snapshot::read_text(&text[..]).unwrap();
+        assert_eq!(snapshot, state.snapshot());
+        assert_eq!(snapshot.snakes[0].death, Some(DeathCause::Boundary((2, 0))));
+
+        let mut binary = Vec::new();
+        snapshot.write_binary(&mut binary).unwrap();
+        let restored = GameState::restore(&Snapshot::read_binary(&mut &binary[..]).unwrap()).unwrap();
+        assert_eq!(restored.checksum(), state.checksum());
+        assert_eq!(restored.win_condition(), WinCondition::Elimination);
+    }
+
+    #[test]
+    fn reads_single_snake_version_3_text() {
+        let text = "snek-snapshot 3\narena 10 10\nrng 1 1\ndirection north\nsnake 4 4 north east\n";
+        let snapshot = Snapshot::read_text(text.as_bytes()).unwrap();
+        assert_eq!(snapshot.snakes.len(), 1);
+        assert_eq!(snapshot.snakes[0].direction, Direction::North);
+        assert_eq!(snapshot.snakes[0].body, vec![Direction::North, Direction::East]);
+        GameState::restore(&snapshot).unwrap();
+    }
+
+    #[test]
+    fn says_whose_snake_was_run_into() {
+        let text = "snek-snapshot 5\narena 10 10\nrng 1 1\n\
+                    snake east 4 4 east east\n\
+                    snake north 7 7 north north\ndied snake 3 4\n\
+                    snake north 1 7 north north\ndied snake 1 8\n";
+        let snapshot = Snapshot::read_text(text.as_bytes()).unwrap();
+        assert_eq!(snapshot.snakes[1].death, Some(DeathCause::Snake(PlayerId(0), (3, 4))));
+        assert_eq!(snapshot.snakes[2].death, Some(DeathCause::SelfCollision((1, 8))));
+
+        let mut text = Vec::new();
+        snapshot.write_text(&mut text).unwrap();
+        assert_eq!(Snapshot::read_text(&text[..]).unwrap(), snapshot);
+        let mut binary = Vec::new();
+        snapshot.write_binary(&mut binary).unwrap();
+        assert_eq!(Snapshot::read_binary(&mut &binary[..]).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let original = played().snapshot();
+        let mut buf = Vec::new();
+        original.write_text(&mut buf).unwrap();
+
+        let snapshot = Snapshot::read_text(&buf[..]).unwrap();
+        assert_eq!(snapshot, original);
+    }
+
+    #[test]
+    fn power_ups_round_trip() {
+        let mut state = GameState::with_seed(20, 10, 1);
+        state.set_force_grow(true);
+        state.set_spawn_rates(SpawnRates { ghost: 5, obstacle: 2, ..SpawnRates::default() });
+        state.place_object((7, 5), GameObject::Portal((15, 1)), Some(9));
+        state.place_object((15, 1), GameObject::Portal((7, 5)), Some(9));
+        state.place_object((3, 8), GameObject::Obstacle(Direction::West), None);
+        state.place_object((12, 7), GameObject::BonusFood, Some(30));
+        for _ in 0..3 {
+            state.tick().unwrap();
+        }
+        assert_eq!(state.snapshot().snakes[0].jumps, vec![(1, (7, 5))]);
+
+        let mut text = Vec::new();
+        state.snapshot().write_text(&mut text).unwrap();
+        let snapshot = Snapshot::read_text(&text[..]).unwrap();
+        assert_eq!(snapshot, state.snapshot());
+
+        let mut binary = Vec::new();
+        snapshot.write_binary(&mut binary).unwrap();
+        let snapshot = Snapshot::read_binary(&mut &binary[..]).unwrap();
+        assert_eq!(snapshot, state.snapshot());
+
+        let mut restored = GameState::restore(&snapshot).unwrap();
+        assert_eq!(restored.spawn_rates(), state.spawn_rates());
+        state.set_direction(PlayerId(0), Direction::South);
+        restored.set_direction(PlayerId(0), Direction::South);
+        for _ in 0..5 {
+            state.tick().unwrap();
+            restored.tick().unwrap();
+            assert_eq!(restored.checksum(), state.checksum());
+        }
+
+        let mut snapshot = state.snapshot();
+        snapshot.objects.push(((0, 0), GameObject::Portal((1, 1))));
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::UnlinkedPortal((0, 0))));
+    }
+
+    #[test]
+    fn speed_round_trips() {
+        let mut state = GameState::with_rules(&Difficulty::Hard.rules(), 3, 1);
+        state.set_force_grow(true);
+        for &dir in [Direction::North, Direction::East, Direction::North].iter() {
+            for _ in 0..6 {
+                state.set_direction(PlayerId(0), dir);
+                state.tick().unwrap();
+            }
+        }
+        // grown by 18 from the start, which takes 20ms off the curve.
+        assert_eq!(state.tick_interval(), Duration::from_millis(60));
+        state.apply_speed_effect(SpeedEffect::fast(9));
+        assert_eq!(state.tick_interval(), Duration::from_millis(36));
+
+        let mut text = Vec::new();
+        state.snapshot().write_text(&mut text).unwrap();
+        let snapshot = Snapshot::read_text(&text[..]).unwrap();
+        assert_eq!(snapshot, state.snapshot());
+        let mut binary = Vec::new();
+        snapshot.write_binary(&mut binary).unwrap();
+        let snapshot = Snapshot::read_binary(&mut &binary[..]).unwrap();
+        assert_eq!(snapshot, state.snapshot());
+
+        let mut restored = GameState::restore(&snapshot).unwrap();
+        for _ in 0..3 {
+            assert_eq!(restored.tick_interval(), state.tick_interval());
+            assert_eq!(restored.speed_effect(), state.speed_effect());
+            assert_eq!(restored.checksum(), state.checksum());
+            state.tick().unwrap();
+            restored.tick().unwrap();
+        }
+
+        // a game which has lost its speed no longer matches.
+        restored.set_speed(SpeedCurve::default());
+        assert!(restored.checksum() != state.checksum());
+
+        let mut snapshot = state.snapshot();
+        snapshot.speed_effect = Some(SpeedEffect::slow(0));
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::SpeedEffect));
+        for &percent in [0, MAX_EFFECT_PERCENT + 1, u32::MAX].iter() {
+            snapshot.speed_effect = Some(SpeedEffect { percent, ticks: 5 });
+            assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::SpeedEffect));
+        }
+
+        // nor ticks which come all at once, or hardly ever.
+        let mut snapshot = state.snapshot();
+        for &(start, fastest) in [(0, 0), (100, 0), (SLOWEST.as_millis() as u64 + 1, 100)].iter() {
+            snapshot.speed = SpeedCurve {
+                start: Duration::from_millis(start),
+                fastest: Duration::from_millis(fastest),
+                ..snapshot.speed
+            };
+            assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::Speed));
+        }
+    }
+
+    #[test]
+    fn text_syntax_errors_have_line_numbers() {
+        let text = "snek-snapshot 4\narena 10 10\nsnake sideways 1 1 east\n";
+        match Snapshot::read_text(text.as_bytes()) {
+            Err(SnapshotError::Syntax { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_impossible_positions() {
+        let good = played().snapshot();
+
+        let mut snapshot = good.clone();
+        snapshot.snakes[0].head = (19, 5);
+        snapshot.snakes[0].body = vec![Direction::West, Direction::West];
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::SnakeOutOfBounds((20, 5))));
+
+        let mut snapshot = good.clone();
+        let head = snapshot.snakes[0].head;
+        snapshot.objects.push((head, GameObject::Wall));
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::SnakeOverlapsObject(head)));
+
+        let mut snapshot = good.clone();
+        snapshot.objects.clear();
+        snapshot.snakes[0].body = vec![Direction::East, Direction::North, Direction::West, Direction::South, Direction::East];
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::SnakeOverlapsItself(head)));
+
+        let mut snapshot = good.clone();
+        snapshot.snakes[0].direction = snapshot.snakes[0].body[0].negate();
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::ReversedDirection));
+
+        let mut snapshot = good.clone();
+        let other = snapshot.snakes[0].clone();
+        snapshot.snakes.push(other);
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::SnakesOverlap(head)));
+
+        let mut snapshot = good.clone();
+        snapshot.snakes.clear();
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::NoSnakes));
+    }
+
+    #[test]
+    fn rejects_oversized_games() {
+        let text = "snek-snapshot 5\narena 2000000 2000000\nrng 1 1\nsnake east 1 1 east\n";
+        let snapshot = Snapshot::read_text(text.as_bytes()).unwrap();
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::ArenaTooLarge));
+
+        let mut snapshot = played().snapshot();
+        snapshot.width = usize::MAX;
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::ArenaTooLarge));
+
+        let mut snapshot = played().snapshot();
+        let mut dead = snapshot.snakes[0].clone();
+        dead.death = Some(DeathCause::Wall(dead.head));
+        snapshot.snakes.resize(MAX_SNAKES + 1, dead);
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::TooManySnakes));
+
+        let mut snapshot = played().snapshot();
+        snapshot.objects = vec![((0, 0), GameObject::Wall); 20 * 20 + 1];
+        assert_eq!(GameState::restore(&snapshot).err(), Some(Invalid::TooManyObjects));
+    }
+}
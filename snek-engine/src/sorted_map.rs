@@ -0,0 +1,134 @@
+use std::iter::FromIterator;
+use std::slice;
+
+/// A map kept as a list sorted by key, which unlike a `BTreeMap` can be
+/// given room up front, so that a game with its room reserved doesn't
+/// allocate however objects come and go.
+///
+/// Boards hold few enough objects that moving the rest of the list along
+/// on each insert costs less than the tree's allocations did.
+#[derive(Clone, Debug)]
+pub struct SortedMap<K, V> {
+    entries: Vec<(K, V)>,
+}
+
+impl<K: Ord + Copy, V> SortedMap<K, V> {
+    pub fn new() -> SortedMap<K, V> {
+        SortedMap {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Have room for `len` entries in all.
+    pub fn reserve_total(&mut self, len: usize) {
+        let more = len.saturating_sub(self.entries.len());
+        self.entries.reserve(more);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.find(key).ok().map(|idx| &self.entries[idx].1)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_ok()
+    }
+
+    /// Put `value` under `key`, returning what was there before.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.find(&key) {
+            Ok(idx) => Some(::std::mem::replace(&mut self.entries[idx].1, value)),
+            Err(idx) => {
+                self.entries.insert(idx, (key, value));
+                None
+            },
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.find(key).ok().map(|idx| self.entries.remove(idx).1)
+    }
+
+    /// The entries in order of their keys.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            entries: self.entries.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.entries.iter_mut().map(|(key, value)| (&*key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    fn find(&self, key: &K) -> Result<usize, usize> {
+        self.entries.binary_search_by(|(other, _)| other.cmp(key))
+    }
+}
+
+/// Quick when the entries come in order, as they do from a `BTreeMap`.
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for SortedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> SortedMap<K, V> {
+        let mut map = SortedMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+#[derive(Clone)]
+pub struct Iter<'a, K: 'a, V: 'a> {
+    entries: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.entries.next().map(|(key, value)| (key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SortedMap;
+
+    #[test]
+    fn keeps_entries_in_key_order() {
+        let mut map = SortedMap::new();
+        assert_eq!(map.insert(3, 'c'), None);
+        assert_eq!(map.insert(1, 'a'), None);
+        assert_eq!(map.insert(2, 'b'), None);
+        assert_eq!(map.insert(1, 'A'), Some('a'));
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&1, &'A'), (&2, &'b'), (&3, &'c')]);
+        assert_eq!(map.get(&2), Some(&'b'));
+        assert!(!map.contains_key(&4));
+
+        assert_eq!(map.remove(&2), Some('b'));
+        assert_eq!(map.remove(&2), None);
+        assert_eq!(map.keys().cloned().collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn reserved_room_is_kept() {
+        let mut map = SortedMap::new();
+        map.reserve_total(8);
+        let room = map.entries.capacity();
+        for key in (0..8).rev() {
+            map.insert(key, ());
+        }
+        assert_eq!(map.entries.capacity(), room);
+    }
+}
@@ -0,0 +1,140 @@
+//! A headless snek server for games on this machine.
+//!
+//! ```text
+//! snek-server [--port 7878] [--players 2] [--size 64x64] [--tick-ms 100] [--seed N]
+//! ```
+//!
+//! The game starts once every player has joined, and the server exits
+//! when it ends.
+
+extern crate snek_engine;
+extern crate snek_net;
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use snek_engine::{GameRng, GameState, Topology};
+use snek_net::{Server, ServerEvent, TcpTransport};
+
+struct Options {
+    port: u16,
+    players: usize,
+    width: usize,
+    height: usize,
+    tick: Duration,
+    seed: u64,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        port: 7878,
+        players: 2,
+        width: 64,
+        height: 64,
+        tick: Duration::from_millis(100),
+        seed: GameRng::from_entropy().seed(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        let bad = |_| format!("bad value for {}: {}", arg, value);
+        match &arg[..] {
+            "--port" => options.port = value.parse().map_err(bad)?,
+            "--players" => options.players = value.parse().map_err(bad)?,
+            "--tick-ms" => options.tick = Duration::from_millis(value.parse().map_err(bad)?),
+            "--seed" => options.seed = value.parse().map_err(bad)?,
+            "--size" => {
+                let mut parts = value.splitn(2, 'x');
+                let width = parts.next().and_then(|w| w.parse().ok());
+                let height = parts.next().and_then(|h| h.parse().ok());
+                match (width, height) {
+                    (Some(width), Some(height)) => {
+                        options.width = width;
+                        options.height = height;
+                    },
+                    _ => return Err(format!("bad value for --size: {}", value)),
+                }
+            },
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.players == 0 {
+        return Err("--players must be at least 1".to_string());
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("snek-server: {}", message);
+            process::exit(2);
+        },
+    };
+
+    if let Err(err) = serve(&options) {
+        eprintln!("snek-server: {}", err);
+        process::exit(1);
+    }
+}
+
+fn serve(options: &Options) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", options.port))?;
+    listener.set_nonblocking(true)?;
+    println!("listening on {} for {} players (seed {})", listener.local_addr()?, options.players, options.seed);
+
+    let state = GameState::with_players(options.width, options.height, options.seed, Topology::Bounded, options.players);
+    let mut server = Server::new(state);
+    let mut next_tick = None;
+
+    loop {
+        loop {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    println!("connection from {}", addr);
+                    stream.set_nonblocking(false)?;
+                    server.accept(TcpTransport::new(stream)?);
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        for event in server.poll() {
+            match event {
+                ServerEvent::Joined(player, name) => println!("{} joined as player {}", name, player.0 + 1),
+                ServerEvent::Left(player) => println!("player {} left", player.0 + 1),
+                ServerEvent::Rejected(reason) => println!("turned a client away: {:?}", reason),
+                ServerEvent::LateInput { player, sent_for, applied_at } => {
+                    println!("late input from player {}: tick {} applied at {}", player.0 + 1, sent_for, applied_at);
+                },
+                ServerEvent::Resynced(player, tick) => println!("resynced player {} at tick {}", player.0 + 1, tick),
+            }
+        }
+
+        // wait for a full house, then keep going whoever leaves.
+        if next_tick.is_none() && server.is_full() {
+            println!("starting");
+            next_tick = Some(Instant::now());
+        }
+
+        if let Some(at) = next_tick {
+            if at <= Instant::now() {
+                if let Err(game_over) = server.step() {
+                    println!("game over after {} ticks: {:?}", server.tick_count(), game_over);
+                    return Ok(());
+                }
+                next_tick = Some(at + options.tick);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(1));
+    }
+}
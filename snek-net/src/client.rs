@@ -0,0 +1,290 @@
+use snek_engine::{Direction, Event, GameOver, GameState, PlayerId};
+use snek_engine::snapshot::SnapshotError;
+
+use super::NetError;
+use super::protocol::{Message, RejectReason, PROTOCOL_VERSION};
+use super::transport::Transport;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ClientEvent {
+    /// The server gave us a seat, and the game as it stands.
+    Joined(PlayerId),
+    Rejected(RejectReason),
+    Ticked(Vec<Event>),
+    GameOver(GameOver),
+    /// Our game had diverged from the server's, and was replaced with the
+    /// server's copy as it was before this tick.
+    Resynced(u64),
+    PlayerLeft(PlayerId),
+    Disconnected,
+}
+
+/// Follows a server's game tick for tick.
+///
+/// The client never runs a tick on its own: input goes to the server,
+/// and comes back as part of the tick it was applied to.
+pub struct Client<T> {
+    transport: T,
+    player: Option<PlayerId>,
+    state: Option<GameState>,
+    /// The next tick to run.
+    tick: u64,
+    game_over: Option<GameOver>,
+    closed: bool,
+}
+
+impl<T: Transport> Client<T> {
+    /// Ask the server at the other end of `transport` for a seat.  Only
+    /// the first `u16::MAX` bytes of `name` are sent.
+    pub fn connect(mut transport: T, name: &str) -> Result<Client<T>, NetError> {
+        transport.send(&Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: name.to_string(),
+        })?;
+
+        Ok(Client {
+            transport,
+            player: None,
+            state: None,
+            tick: 0,
+            game_over: None,
+            closed: false,
+        })
+    }
+
+    /// Which player we are, once the server has said.
+    pub fn player(&self) -> Option<PlayerId> {
+        self.player
+    }
+
+    /// Our copy of the game, once the server has sent it.
+    pub fn state(&self) -> Option<&GameState> {
+        self.state.as_ref()
+    }
+
+    /// The number of ticks run so far, which is also the next tick's
+    /// number.
+    pub fn tick_count(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn game_over(&self) -> Option<GameOver> {
+        self.game_over
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.closed
+    }
+
+    /// Ask for our snake to turn, from the next tick on.  The turn takes
+    /// effect when the server sends it back.
+    pub fn steer(&mut self, direction: Direction) -> Result<(), NetError> {
+        self.transport.send(&Message::Input { tick: self.tick, direction })
+    }
+
+    /// Handle every message which has arrived.
+    pub fn poll(&mut self) -> Result<Vec<ClientEvent>, NetError> {
+        let mut events = Vec::new();
+
+        while !self.closed {
+            let message = match self.transport.recv() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(NetError::Disconnected) => {
+                    self.closed = true;
+                    events.push(ClientEvent::Disconnected);
+                    break;
+                },
+                Err(err) => return Err(err),
+            };
+
+            match message {
+                Message::Welcome { player, tick, snapshot, .. } => {
+                    self.state = Some(GameState::restore(&snapshot).map_err(SnapshotError::Invalid)?);
+                    self.player = Some(player);
+                    self.tick = tick;
+                    events.push(ClientEvent::Joined(player));
+                },
+                Message::Reject(reason) => {
+                    self.closed = true;
+                    events.push(ClientEvent::Rejected(reason));
+                },
+                Message::Tick { tick, inputs } => {
+                    let state = self.state.as_mut().ok_or(NetError::Protocol("tick before welcome"))?;
+                    if tick != self.tick {
+                        return Err(NetError::Protocol("tick out of order"));
+                    }
+
+                    for (player, direction) in inputs {
+                        state.set_direction(player, direction);
+                    }
+                    let result = state.tick();
+                    self.tick += 1;
+                    self.transport.send(&Message::Hash { tick, checksum: state.checksum() })?;
+
+                    match result {
+                        Ok(tick_events) => events.push(ClientEvent::Ticked(tick_events)),
+                        Err(game_over) => {
+                            self.game_over = Some(game_over);
+                            events.push(ClientEvent::GameOver(game_over));
+                        },
+                    }
+                },
+                Message::Resync { tick, snapshot } => {
+                    self.state = Some(GameState::restore(&snapshot).map_err(SnapshotError::Invalid)?);
+                    self.tick = tick;
+                    self.game_over = None;
+                    events.push(ClientEvent::Resynced(tick));
+                },
+                Message::PlayerLeft(player) => events.push(ClientEvent::PlayerLeft(player)),
+                Message::Disconnect => {
+                    self.closed = true;
+                    events.push(ClientEvent::Disconnected);
+                },
+                Message::Hello { .. } | Message::Input { .. } | Message::Hash { .. } => {
+                    return Err(NetError::Protocol("unexpected message"));
+                },
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Tell the server we're leaving, and close the connection.
+    pub fn disconnect(mut self) -> Result<(), NetError> {
+        self.transport.send(&Message::Disconnect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snek_engine::{Direction, GameState, PlayerId, Topology};
+
+    use super::{Client, ClientEvent};
+    use super::super::protocol::{Message, RejectReason};
+    use super::super::server::{Server, ServerEvent};
+    use super::super::transport::{loopback, Loopback, Transport};
+
+    fn game() -> (Server<Loopback>, Vec<Client<Loopback>>) {
+        let mut server = Server::new(GameState::with_players(32, 32, 11, Topology::Bounded, 2));
+        let mut clients = Vec::new();
+        for name in ["ana", "bo"].iter() {
+            let (near, far) = loopback();
+            server.accept(far);
+            clients.push(Client::connect(near, name).unwrap());
+        }
+        server.poll();
+        for client in clients.iter_mut() {
+            client.poll().unwrap();
+        }
+        (server, clients)
+    }
+
+    fn run(server: &mut Server<Loopback>, clients: &mut [Client<Loopback>], ticks: usize) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        for _ in 0..ticks {
+            events.extend(server.poll());
+            server.step().unwrap();
+            for client in clients.iter_mut() {
+                client.poll().unwrap();
+            }
+        }
+        events.extend(server.poll());
+        events
+    }
+
+    #[test]
+    fn clients_stay_in_lockstep() {
+        let (mut server, mut clients) = game();
+        assert!(server.is_full());
+        assert_eq!(clients[0].player(), Some(PlayerId(0)));
+        assert_eq!(clients[1].player(), Some(PlayerId(1)));
+
+        clients[0].steer(Direction::South).unwrap();
+        clients[1].steer(Direction::North).unwrap();
+        run(&mut server, &mut clients, 5);
+
+        for client in clients.iter() {
+            assert_eq!(client.tick_count(), 5);
+            assert_eq!(client.state().unwrap().checksum(), server.state().checksum());
+        }
+    }
+
+    #[test]
+    fn late_input_is_applied_next_tick() {
+        let (mut server, mut clients) = game();
+        run(&mut server, &mut clients, 3);
+
+        // as if it had been sent for tick 1, and held up on the way
+        clients[0].transport.send(&Message::Input { tick: 1, direction: Direction::North }).unwrap();
+        let events = run(&mut server, &mut clients, 2);
+        let late = ServerEvent::LateInput { player: PlayerId(0), sent_for: 1, applied_at: 3 };
+        assert!(events.contains(&late));
+
+        assert_eq!(server.state().snake(PlayerId(0)).unwrap().neck_direction(), Direction::North);
+        for client in clients.iter() {
+            assert_eq!(client.state().unwrap().checksum(), server.state().checksum());
+        }
+    }
+
+    #[test]
+    fn desynced_client_is_resynced() {
+        let (mut server, mut clients) = game();
+        run(&mut server, &mut clients, 2);
+
+        // steer locally without telling the server
+        clients[1].state.as_mut().unwrap().set_direction(PlayerId(1), Direction::South);
+        server.step().unwrap();
+        clients[1].poll().unwrap();
+        assert!(clients[1].state().unwrap().checksum() != server.state().checksum());
+
+        let events = server.poll();
+        assert_eq!(events, vec![ServerEvent::Resynced(PlayerId(1), 3)]);
+        assert_eq!(clients[1].poll().unwrap(), vec![ClientEvent::Resynced(3)]);
+
+        run(&mut server, &mut clients, 2);
+        for client in clients.iter() {
+            assert_eq!(client.state().unwrap().checksum(), server.state().checksum());
+        }
+    }
+
+    #[test]
+    fn leaving_frees_a_seat() {
+        let (mut server, mut clients) = game();
+
+        let (near, far) = loopback();
+        server.accept(far);
+        let mut third = Client::connect(near, "cy").unwrap();
+        assert_eq!(server.poll(), vec![ServerEvent::Rejected(RejectReason::GameFull)]);
+        assert_eq!(third.poll().unwrap(), vec![ClientEvent::Rejected(RejectReason::GameFull)]);
+
+        let leaving = clients.pop().unwrap();
+        leaving.disconnect().unwrap();
+        assert_eq!(server.poll(), vec![ServerEvent::Left(PlayerId(1))]);
+        assert!(!server.is_full());
+        assert_eq!(clients[0].poll().unwrap(), vec![ClientEvent::PlayerLeft(PlayerId(1))]);
+
+        run(&mut server, &mut clients, 2);
+        let (near, far) = loopback();
+        server.accept(far);
+        let mut replacement = Client::connect(near, "di").unwrap();
+        server.poll();
+        assert_eq!(replacement.poll().unwrap(), vec![ClientEvent::Joined(PlayerId(1))]);
+        assert_eq!(replacement.tick_count(), 2);
+        assert_eq!(replacement.state().unwrap().checksum(), server.state().checksum());
+    }
+
+    #[test]
+    fn old_clients_are_turned_away() {
+        let mut server: Server<Loopback> = Server::new(GameState::with_seed(16, 16, 1));
+        let (mut near, far) = loopback();
+        server.accept(far);
+        near.send(&Message::Hello { version: 0, name: "old".to_string() }).unwrap();
+
+        server.poll();
+        match near.recv().unwrap() {
+            Some(Message::Reject(RejectReason::VersionMismatch(_))) => (),
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Networked multiplayer for snek.
+//!
+//! The server is authoritative and runs the only `GameState` that
+//! matters.  Every tick it broadcasts the inputs it applied, and because
+//! the engine is deterministic each client can run the same tick on its
+//! own copy of the game.  Clients report a checksum after every tick; one
+//! that disagrees with the server is sent a fresh snapshot to carry on
+//! from.
+//!
+//! Messages travel over a `Transport`: TCP for real games, or an
+//! in-process loopback for tests.
+
+extern crate snek_engine;
+
+use std::io;
+
+use snek_engine::snapshot::SnapshotError;
+
+pub use self::client::{Client, ClientEvent};
+pub use self::protocol::{Message, RejectReason, PROTOCOL_VERSION};
+pub use self::server::{Server, ServerEvent};
+pub use self::transport::{loopback, Loopback, TcpTransport, Transport};
+
+pub mod protocol;
+mod client;
+mod server;
+mod transport;
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    /// The other end sent something that isn't a message.
+    Protocol(&'static str),
+    /// A snapshot inside a message is unreadable or impossible.
+    Snapshot(SnapshotError),
+    /// The other end went away.
+    Disconnected,
+}
+
+impl From<io::Error> for NetError {
+    fn from(err: io::Error) -> NetError {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof |
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::BrokenPipe => NetError::Disconnected,
+            _ => NetError::Io(err),
+        }
+    }
+}
+
+impl From<SnapshotError> for NetError {
+    fn from(err: SnapshotError) -> NetError {
+        NetError::Snapshot(err)
+    }
+}
@@ -0,0 +1,359 @@
+//! The wire protocol.
+//!
+//! Every message is framed as a little-endian `u32` length followed by
+//! that many bytes of body.  The body starts with a kind byte:
+//!
+//! ```text
+//! 0x01 hello          version u16, name (u16 length + utf-8)
+//! 0x02 welcome        version u16, player u32, tick u64, binary snapshot
+//! 0x03 reject         reason u8, version u16
+//! 0x10 input          tick u64, direction u8
+//! 0x11 tick           tick u64, count u32, count * (player u32, direction u8)
+//! 0x12 hash           tick u64, checksum u64
+//! 0x20 resync         tick u64, binary snapshot
+//! 0x30 disconnect
+//! 0x31 player-left    player u32
+//! ```
+//!
+//! A tick number always counts ticks from the start of the game; `tick`
+//! and `hash` are about the state just after that tick ran, while
+//! `welcome` and `resync` carry the state just before it.
+//!
+//! The version is checked once, when a client says hello.  Bump it for
+//! any change to the layout above.
+
+use std::cmp;
+use std::io::Read;
+
+use snek_engine::{Direction, PlayerId};
+use snek_engine::snapshot::Snapshot;
+
+use super::NetError;
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Refuse frames larger than this rather than allocating for them.
+const MAX_FRAME: usize = 16 << 20;
+
+const HELLO: u8 = 0x01;
+const WELCOME: u8 = 0x02;
+const REJECT: u8 = 0x03;
+const INPUT: u8 = 0x10;
+const TICK: u8 = 0x11;
+const HASH: u8 = 0x12;
+const RESYNC: u8 = 0x20;
+const DISCONNECT: u8 = 0x30;
+const PLAYER_LEFT: u8 = 0x31;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Message {
+    /// Client to server: the first message on a connection.  Names
+    /// longer than `u16::MAX` bytes are cut short to fit the frame.
+    Hello { version: u16, name: String },
+    /// Server to client: you are `player`, and this is the game as it is
+    /// before `tick` runs.
+    Welcome { version: u16, player: PlayerId, tick: u64, snapshot: Snapshot },
+    Reject(RejectReason),
+    /// Client to server: steer my snake from `tick` on.
+    Input { tick: u64, direction: Direction },
+    /// Server to client: `tick` ran with these inputs, applied in order.
+    Tick { tick: u64, inputs: Vec<(PlayerId, Direction)> },
+    /// Client to server: my `GameState::checksum` after `tick`.
+    Hash { tick: u64, checksum: u64 },
+    /// Server to client: your game has diverged; carry on from this
+    /// state, which is the game as it is before `tick` runs.
+    Resync { tick: u64, snapshot: Snapshot },
+    /// Either way: this connection is about to close.
+    Disconnect,
+    /// Server to client: nobody is steering this player any more.
+    PlayerLeft(PlayerId),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RejectReason {
+    /// The client speaks a different protocol version from the server,
+    /// whose version is given.
+    VersionMismatch(u16),
+    /// Every player already has someone steering them.
+    GameFull,
+}
+
+impl Message {
+    /// The message as a complete frame, ready to send.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0; 4];
+        match *self {
+            Message::Hello { version, ref name } => {
+                buf.push(HELLO);
+                put_u16(&mut buf, version);
+                let mut len = cmp::min(name.len(), u16::MAX as usize);
+                while !name.is_char_boundary(len) {
+                    len -= 1;
+                }
+                put_u16(&mut buf, len as u16);
+                buf.extend_from_slice(&name.as_bytes()[..len]);
+            },
+            Message::Welcome { version, player, tick, ref snapshot } => {
+                buf.push(WELCOME);
+                put_u16(&mut buf, version);
+                put_u32(&mut buf, player.0 as u32);
+                put_u64(&mut buf, tick);
+                snapshot.write_binary(&mut buf).unwrap();
+            },
+            Message::Reject(reason) => {
+                buf.push(REJECT);
+                match reason {
+                    RejectReason::VersionMismatch(version) => {
+                        buf.push(0);
+                        put_u16(&mut buf, version);
+                    },
+                    RejectReason::GameFull => {
+                        buf.push(1);
+                        put_u16(&mut buf, PROTOCOL_VERSION);
+                    },
+                }
+            },
+            Message::Input { tick, direction } => {
+                buf.push(INPUT);
+                put_u64(&mut buf, tick);
+                buf.push(direction_code(direction));
+            },
+            Message::Tick { tick, ref inputs } => {
+                buf.push(TICK);
+                put_u64(&mut buf, tick);
+                put_u32(&mut buf, inputs.len() as u32);
+                for &(player, direction) in inputs.iter() {
+                    put_u32(&mut buf, player.0 as u32);
+                    buf.push(direction_code(direction));
+                }
+            },
+            Message::Hash { tick, checksum } => {
+                buf.push(HASH);
+                put_u64(&mut buf, tick);
+                put_u64(&mut buf, checksum);
+            },
+            Message::Resync { tick, ref snapshot } => {
+                buf.push(RESYNC);
+                put_u64(&mut buf, tick);
+                snapshot.write_binary(&mut buf).unwrap();
+            },
+            Message::Disconnect => buf.push(DISCONNECT),
+            Message::PlayerLeft(player) => {
+                buf.push(PLAYER_LEFT);
+                put_u32(&mut buf, player.0 as u32);
+            },
+        }
+
+        let len = (buf.len() - 4) as u32;
+        buf[..4].copy_from_slice(&len.to_le_bytes());
+        buf
+    }
+
+    /// Decode the frame at the start of `buf`.  Returns the message and
+    /// how many bytes it took up, or `None` if the frame isn't all there
+    /// yet.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Message, usize)>, NetError> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        let mut len = [0; 4];
+        len.copy_from_slice(&buf[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+        if MAX_FRAME < len {
+            return Err(NetError::Protocol("frame too large"));
+        }
+        if buf.len() < 4 + len {
+            return Ok(None);
+        }
+
+        let mut body = &buf[4..4 + len];
+        let message = Message::read_body(&mut body)?;
+        if !body.is_empty() {
+            return Err(NetError::Protocol("trailing bytes after message"));
+        }
+        Ok(Some((message, 4 + len)))
+    }
+
+    fn read_body(rd: &mut &[u8]) -> Result<Message, NetError> {
+        let message = match read_u8(rd)? {
+            HELLO => {
+                let version = read_u16(rd)?;
+                let len = read_u16(rd)? as usize;
+                let mut name = vec![0; len];
+                rd.read_exact(&mut name)?;
+                let name = String::from_utf8(name).map_err(|_| NetError::Protocol("name isn't utf-8"))?;
+                Message::Hello { version, name }
+            },
+            WELCOME => {
+                let version = read_u16(rd)?;
+                let player = PlayerId(read_u32(rd)? as usize);
+                let tick = read_u64(rd)?;
+                let snapshot = Snapshot::read_binary(rd)?;
+                Message::Welcome { version, player, tick, snapshot }
+            },
+            REJECT => {
+                let reason = read_u8(rd)?;
+                let version = read_u16(rd)?;
+                match reason {
+                    0 => Message::Reject(RejectReason::VersionMismatch(version)),
+                    1 => Message::Reject(RejectReason::GameFull),
+                    _ => return Err(NetError::Protocol("unknown reject reason")),
+                }
+            },
+            INPUT => {
+                let tick = read_u64(rd)?;
+                let direction = read_direction(rd)?;
+                Message::Input { tick, direction }
+            },
+            TICK => {
+                let tick = read_u64(rd)?;
+                let count = read_u32(rd)?;
+                let mut inputs = Vec::new();
+                for _ in 0..count {
+                    let player = PlayerId(read_u32(rd)? as usize);
+                    inputs.push((player, read_direction(rd)?));
+                }
+                Message::Tick { tick, inputs }
+            },
+            HASH => {
+                let tick = read_u64(rd)?;
+                let checksum = read_u64(rd)?;
+                Message::Hash { tick, checksum }
+            },
+            RESYNC => {
+                let tick = read_u64(rd)?;
+                let snapshot = Snapshot::read_binary(rd)?;
+                Message::Resync { tick, snapshot }
+            },
+            DISCONNECT => Message::Disconnect,
+            PLAYER_LEFT => Message::PlayerLeft(PlayerId(read_u32(rd)? as usize)),
+            _ => return Err(NetError::Protocol("unknown message kind")),
+        };
+        Ok(message)
+    }
+}
+
+fn direction_code(dir: Direction) -> u8 {
+    match dir {
+        Direction::North => 0,
+        Direction::South => 1,
+        Direction::West => 2,
+        Direction::East => 3,
+    }
+}
+
+fn read_direction(rd: &mut &[u8]) -> Result<Direction, NetError> {
+    match read_u8(rd)? {
+        0 => Ok(Direction::North),
+        1 => Ok(Direction::South),
+        2 => Ok(Direction::West),
+        3 => Ok(Direction::East),
+        _ => Err(NetError::Protocol("bad direction")),
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+// a short body is a malformed message, not a connection that went away.
+fn short(_: ::std::io::Error) -> NetError {
+    NetError::Protocol("message too short")
+}
+
+fn read_u8(rd: &mut &[u8]) -> Result<u8, NetError> {
+    let mut buf = [0; 1];
+    rd.read_exact(&mut buf).map_err(short)?;
+    Ok(buf[0])
+}
+
+fn read_u16(rd: &mut &[u8]) -> Result<u16, NetError> {
+    let mut buf = [0; 2];
+    rd.read_exact(&mut buf).map_err(short)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(rd: &mut &[u8]) -> Result<u32, NetError> {
+    let mut buf = [0; 4];
+    rd.read_exact(&mut buf).map_err(short)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(rd: &mut &[u8]) -> Result<u64, NetError> {
+    let mut buf = [0; 8];
+    rd.read_exact(&mut buf).map_err(short)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use snek_engine::{Direction, GameState, PlayerId};
+
+    use super::{Message, RejectReason, PROTOCOL_VERSION};
+    use super::super::NetError;
+
+    #[test]
+    fn messages_round_trip() {
+        let snapshot = GameState::with_seed(16, 16, 3).snapshot();
+        let messages = vec![
+            Message::Hello { version: PROTOCOL_VERSION, name: "ana".to_string() },
+            Message::Welcome { version: PROTOCOL_VERSION, player: PlayerId(1), tick: 7, snapshot: snapshot.clone() },
+            Message::Reject(RejectReason::VersionMismatch(PROTOCOL_VERSION)),
+            Message::Reject(RejectReason::GameFull),
+            Message::Input { tick: 9, direction: Direction::North },
+            Message::Tick { tick: 9, inputs: vec![(PlayerId(0), Direction::West), (PlayerId(1), Direction::South)] },
+            Message::Hash { tick: 9, checksum: 0xdead_beef_f00d },
+            Message::Resync { tick: 10, snapshot },
+            Message::Disconnect,
+            Message::PlayerLeft(PlayerId(1)),
+        ];
+
+        let mut stream = Vec::new();
+        for message in messages.iter() {
+            stream.extend(message.encode());
+        }
+
+        let mut rest = &stream[..];
+        for message in messages.iter() {
+            let (decoded, used) = Message::decode(rest).unwrap().unwrap();
+            assert_eq!(&decoded, message);
+            rest = &rest[used..];
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn long_names_are_cut_between_characters() {
+        let name = "é".repeat(40_000);
+        let frame = Message::Hello { version: PROTOCOL_VERSION, name: name.clone() }.encode();
+        match Message::decode(&frame).unwrap() {
+            Some((Message::Hello { name: sent, .. }, used)) => {
+                assert_eq!(used, frame.len());
+                assert_eq!(sent.len(), u16::MAX as usize - 1);
+                assert!(name.starts_with(&sent));
+            },
+            other => panic!("expected a hello, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn partial_and_malformed_frames() {
+        let frame = Message::Hash { tick: 1, checksum: 2 }.encode();
+        assert!(Message::decode(&frame[..frame.len() - 1]).unwrap().is_none());
+
+        let mut bad = frame.clone();
+        bad[4] = 0x7f;
+        match Message::decode(&bad) {
+            Err(NetError::Protocol(_)) => (),
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+
+use snek_engine::{Direction, Event, GameOver, GameState, PlayerId};
+
+use super::NetError;
+use super::protocol::{Message, RejectReason, PROTOCOL_VERSION};
+use super::transport::Transport;
+
+/// How many ticks of checksums to keep for checking clients' hashes.
+const HISTORY: usize = 256;
+
+/// How far past the next tick an input may be sent for.  Clients steer
+/// for the tick they have reached, which is never ahead of the server,
+/// so anything further is a broken or hostile client.
+const INPUT_WINDOW: u64 = 16;
+
+/// Something the server did on its own while handling messages, worth
+/// telling whoever is running it about.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ServerEvent {
+    Joined(PlayerId, String),
+    /// The connection steering this player closed or broke.
+    Left(PlayerId),
+    /// A connection was refused before it joined.
+    Rejected(RejectReason),
+    /// Input for a tick which had already run was put off until this one.
+    LateInput { player: PlayerId, sent_for: u64, applied_at: u64 },
+    /// A client's hash disagreed, so it was sent a snapshot to carry on
+    /// from this tick.
+    Resynced(PlayerId, u64),
+}
+
+struct Connection<T> {
+    transport: T,
+    player: Option<PlayerId>,
+    /// Hashes for earlier ticks were computed before the last resync.
+    hashes_from: u64,
+    closed: bool,
+}
+
+/// Runs the authoritative game and keeps any number of clients in
+/// lockstep with it.
+pub struct Server<T> {
+    state: GameState,
+    /// The next tick to run.
+    tick: u64,
+    connections: Vec<Connection<T>>,
+    /// Inputs waiting for their tick, at most one for each player.
+    pending: BTreeMap<u64, Vec<(PlayerId, Direction)>>,
+    /// `(tick, checksum)` for recent ticks.
+    history: VecDeque<(u64, u64)>,
+    game_over: Option<GameOver>,
+}
+
+impl<T: Transport> Server<T> {
+    /// Serve `state`, with one seat for each of its players.
+    pub fn new(state: GameState) -> Server<T> {
+        Server {
+            state,
+            tick: 0,
+            connections: Vec::new(),
+            pending: BTreeMap::new(),
+            history: VecDeque::new(),
+            game_over: None,
+        }
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    /// The number of ticks run so far, which is also the next tick's
+    /// number.
+    pub fn tick_count(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn game_over(&self) -> Option<GameOver> {
+        self.game_over
+    }
+
+    /// Whether every player has someone steering them.
+    pub fn is_full(&self) -> bool {
+        self.free_player().is_none()
+    }
+
+    /// Take on a new connection.  It gets a seat once it says hello.
+    pub fn accept(&mut self, transport: T) {
+        self.connections.push(Connection {
+            transport,
+            player: None,
+            hashes_from: 0,
+            closed: false,
+        });
+    }
+
+    /// Handle every message which has arrived, and drop connections
+    /// which have gone away.
+    pub fn poll(&mut self) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+
+        for idx in 0..self.connections.len() {
+            loop {
+                let message = match self.connections[idx].transport.recv() {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(_) => {
+                        self.connections[idx].closed = true;
+                        break;
+                    },
+                };
+                if let Err(_) | Ok(false) = self.handle(idx, message, &mut events) {
+                    self.connections[idx].closed = true;
+                    break;
+                }
+            }
+        }
+
+        let mut left = Vec::new();
+        self.connections.retain(|conn| {
+            if conn.closed {
+                left.extend(conn.player);
+            }
+            !conn.closed
+        });
+        for player in left {
+            events.push(ServerEvent::Left(player));
+            self.broadcast(&Message::PlayerLeft(player));
+        }
+
+        events
+    }
+
+    /// Handle one message from connection `idx`.  Returns whether to keep
+    /// the connection open.
+    fn handle(&mut self, idx: usize, message: Message, events: &mut Vec<ServerEvent>) -> Result<bool, NetError> {
+        let player = self.connections[idx].player;
+
+        match (message, player) {
+            (Message::Hello { version, .. }, None) if version != PROTOCOL_VERSION => {
+                let reason = RejectReason::VersionMismatch(PROTOCOL_VERSION);
+                events.push(ServerEvent::Rejected(reason));
+                self.connections[idx].transport.send(&Message::Reject(reason))?;
+                Ok(false)
+            },
+            (Message::Hello { name, .. }, None) => {
+                let player = match self.free_player() {
+                    Some(player) => player,
+                    None => {
+                        events.push(ServerEvent::Rejected(RejectReason::GameFull));
+                        self.connections[idx].transport.send(&Message::Reject(RejectReason::GameFull))?;
+                        return Ok(false);
+                    },
+                };
+
+                let conn = &mut self.connections[idx];
+                conn.player = Some(player);
+                conn.hashes_from = self.tick;
+                conn.transport.send(&Message::Welcome {
+                    version: PROTOCOL_VERSION,
+                    player,
+                    tick: self.tick,
+                    snapshot: self.state.snapshot(),
+                })?;
+                events.push(ServerEvent::Joined(player, name));
+                Ok(true)
+            },
+            (Message::Input { tick, direction }, Some(player)) => {
+                if self.tick.saturating_add(INPUT_WINDOW) < tick {
+                    return Err(NetError::Protocol("input too far ahead"));
+                }
+                // too late for its tick, but better late than never.
+                let applied_at = cmp::max(tick, self.tick);
+                if applied_at != tick {
+                    events.push(ServerEvent::LateInput { player, sent_for: tick, applied_at });
+                }
+                // a later input for the same tick replaces an earlier one.
+                let inputs = self.pending.entry(applied_at).or_default();
+                match inputs.iter_mut().find(|input| input.0 == player) {
+                    Some(input) => input.1 = direction,
+                    None => inputs.push((player, direction)),
+                }
+                Ok(true)
+            },
+            (Message::Hash { tick, checksum }, Some(player)) => {
+                if tick < self.connections[idx].hashes_from {
+                    return Ok(true);
+                }
+                let expected = self.history.iter()
+                    .find(|&&(t, _)| t == tick)
+                    .map(|&(_, checksum)| checksum);
+
+                if expected.is_some() && expected != Some(checksum) {
+                    let conn = &mut self.connections[idx];
+                    conn.hashes_from = self.tick;
+                    conn.transport.send(&Message::Resync {
+                        tick: self.tick,
+                        snapshot: self.state.snapshot(),
+                    })?;
+                    events.push(ServerEvent::Resynced(player, self.tick));
+                }
+                Ok(true)
+            },
+            (Message::Disconnect, _) => Ok(false),
+            // anything else is out of turn.
+            _ => Err(NetError::Protocol("unexpected message")),
+        }
+    }
+
+    /// Run the next tick with whatever inputs have arrived for it, and
+    /// send it to every client.  Once the game is over this does nothing
+    /// but say so.
+    pub fn step(&mut self) -> Result<Vec<Event>, GameOver> {
+        if let Some(game_over) = self.game_over {
+            return Err(game_over);
+        }
+
+        let inputs = self.pending.remove(&self.tick).unwrap_or_default();
+        for &(player, direction) in inputs.iter() {
+            self.state.set_direction(player, direction);
+        }
+        let result = self.state.tick();
+
+        self.history.push_back((self.tick, self.state.checksum()));
+        if HISTORY < self.history.len() {
+            self.history.pop_front();
+        }
+        self.broadcast(&Message::Tick { tick: self.tick, inputs });
+        self.tick += 1;
+
+        if let Err(game_over) = result {
+            self.game_over = Some(game_over);
+        }
+        result
+    }
+
+    fn free_player(&self) -> Option<PlayerId> {
+        (0..self.state.player_count())
+            .map(PlayerId)
+            .find(|&player| !self.connections.iter().any(|conn| conn.player == Some(player)))
+    }
+
+    /// Send to every client which has joined.  Failures are noticed on
+    /// the next `poll`.
+    fn broadcast(&mut self, message: &Message) {
+        for conn in self.connections.iter_mut().filter(|conn| conn.player.is_some()) {
+            if conn.transport.send(message).is_err() {
+                conn.closed = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snek_engine::{Direction, GameState, PlayerId, Topology};
+
+    use super::{Server, ServerEvent, PROTOCOL_VERSION};
+    use super::super::protocol::Message;
+    use super::super::transport::{loopback, Transport};
+
+    #[test]
+    fn inputs_are_bounded() {
+        let mut server = Server::new(GameState::with_players(32, 32, 11, Topology::Bounded, 2));
+        let mut clients = Vec::new();
+        for name in ["ana", "bo"].iter() {
+            let (mut near, far) = loopback();
+            server.accept(far);
+            near.send(&Message::Hello { version: PROTOCOL_VERSION, name: name.to_string() }).unwrap();
+            clients.push(near);
+        }
+        server.poll();
+
+        // only the last of several turns for one tick is kept.
+        for &direction in [Direction::North, Direction::South, Direction::North].iter() {
+            clients[0].send(&Message::Input { tick: 3, direction }).unwrap();
+        }
+        server.poll();
+        assert_eq!(server.pending[&3], vec![(PlayerId(0), Direction::North)]);
+
+        // and inputs for ticks which won't come round for ages are refused.
+        clients[1].send(&Message::Input { tick: u64::MAX, direction: Direction::South }).unwrap();
+        assert_eq!(server.poll(), vec![ServerEvent::Left(PlayerId(1))]);
+        assert!(!server.pending.contains_key(&u64::MAX));
+    }
+}
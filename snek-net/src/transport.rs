@@ -0,0 +1,147 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+use super::NetError;
+use super::protocol::Message;
+
+/// A connection that carries whole messages.
+pub trait Transport {
+    fn send(&mut self, message: &Message) -> Result<(), NetError>;
+
+    /// The next message, if one has arrived.  Never blocks.
+    fn recv(&mut self) -> Result<Option<Message>, NetError>;
+}
+
+pub struct TcpTransport {
+    stream: TcpStream,
+    /// Bytes received but not yet decoded.
+    pending: Vec<u8>,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> io::Result<TcpTransport> {
+        // ticks are tiny and latency is everything.
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport {
+            stream,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpTransport> {
+        TcpTransport::new(TcpStream::connect(addr)?)
+    }
+
+    fn take_pending(&mut self) -> Result<Option<Message>, NetError> {
+        match Message::decode(&self.pending)? {
+            Some((message, used)) => {
+                self.pending.drain(..used);
+                Ok(Some(message))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        self.stream.write_all(&message.encode())?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Message>, NetError> {
+        if let Some(message) = self.take_pending()? {
+            return Ok(Some(message));
+        }
+
+        // only reads are non-blocking, so that sends always complete.
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0; 4096];
+        let result = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(NetError::Disconnected),
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => break Err(NetError::from(err)),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+
+        self.take_pending()
+    }
+}
+
+/// One end of an in-process connection, made by `loopback`.  Messages
+/// are encoded and decoded on the way through, just as they would be on
+/// a socket.
+pub struct Loopback {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+/// A connected pair of in-process transports.
+pub fn loopback() -> (Loopback, Loopback) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    (Loopback { tx: a_tx, rx: a_rx }, Loopback { tx: b_tx, rx: b_rx })
+}
+
+impl Transport for Loopback {
+    fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        self.tx.send(message.encode()).map_err(|_| NetError::Disconnected)
+    }
+
+    fn recv(&mut self) -> Result<Option<Message>, NetError> {
+        match self.rx.try_recv() {
+            Ok(frame) => match Message::decode(&frame)? {
+                Some((message, _)) => Ok(Some(message)),
+                None => Err(NetError::Protocol("truncated frame")),
+            },
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(NetError::Disconnected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{TcpTransport, Transport};
+    use super::super::NetError;
+    use super::super::protocol::Message;
+
+    fn recv_blocking(transport: &mut TcpTransport) -> Result<Message, NetError> {
+        for _ in 0..1000 {
+            if let Some(message) = transport.recv()? {
+                return Ok(message);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("nothing arrived");
+    }
+
+    #[test]
+    fn tcp_carries_messages_and_notices_hangups() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+
+        assert!(server.recv().unwrap().is_none());
+        client.send(&Message::Hash { tick: 1, checksum: 2 }).unwrap();
+        client.send(&Message::Disconnect).unwrap();
+        assert_eq!(recv_blocking(&mut server).unwrap(), Message::Hash { tick: 1, checksum: 2 });
+        assert_eq!(recv_blocking(&mut server).unwrap(), Message::Disconnect);
+
+        drop(client);
+        match recv_blocking(&mut server) {
+            Err(NetError::Disconnected) => (),
+            other => panic!("expected a hangup, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Turning the bytes a terminal sends into key presses.
+
+use snek_engine::Direction;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// W, A, S and D steer the second player.
+    W, A, S, D,
+    Space,
+    Tab,
+    G, H,
+    Quit,
+}
+
+impl Key {
+    pub fn direction(&self) -> Option<Direction> {
+        match *self {
+            Key::Up | Key::W => Some(Direction::North),
+            Key::Down | Key::S => Some(Direction::South),
+            Key::Left | Key::A => Some(Direction::West),
+            Key::Right | Key::D => Some(Direction::East),
+            _ => None,
+        }
+    }
+
+    /// Which key wins when several are pressed in one tick, as in the
+    /// wayland frontend.
+    pub fn priority(&self) -> u16 {
+        match *self {
+            Key::Space | Key::Tab => 2,
+            Key::Quit => 3,
+            // G and H; turns are queued instead, so never need ranking.
+            _ => 0,
+        }
+    }
+
+    pub fn is_wasd(&self) -> bool {
+        matches!(*self, Key::W | Key::A | Key::S | Key::D)
+    }
+}
+
+/// Take the key presses off the front of `buf`.  An escape sequence
+/// which hasn't finished arriving is left for next time.
+pub fn parse_keys(buf: &mut Vec<u8>) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut idx = 0;
+
+    while idx < buf.len() {
+        let key = match buf[idx] {
+            0x1b => {
+                // arrows are ESC [ A to D, or ESC O A to D in
+                // application cursor mode.
+                match (buf.get(idx + 1), buf.get(idx + 2)) {
+                    (None, _) | (Some(b'['), None) | (Some(b'O'), None) => break,
+                    (Some(b'['), Some(&code)) | (Some(b'O'), Some(&code)) => {
+                        idx += 2;
+                        match code {
+                            b'A' => Some(Key::Up),
+                            b'B' => Some(Key::Down),
+                            b'C' => Some(Key::Right),
+                            b'D' => Some(Key::Left),
+                            _ => None,
+                        }
+                    },
+                    _ => None,
+                }
+            },
+            b'w' | b'W' => Some(Key::W),
+            b'a' | b'A' => Some(Key::A),
+            b's' | b'S' => Some(Key::S),
+            b'd' | b'D' => Some(Key::D),
+            b' ' => Some(Key::Space),
+            b'\t' => Some(Key::Tab),
+            b'g' | b'G' => Some(Key::G),
+            b'h' | b'H' => Some(Key::H),
+            // ctrl-c doesn't interrupt in raw mode.
+            b'q' | b'Q' | 0x03 => Some(Key::Quit),
+            _ => None,
+        };
+        keys.extend(key);
+        idx += 1;
+    }
+
+    buf.drain(..idx);
+    keys
+}
+
+/// The key that wins out of everything but the turns pressed in a tick;
+/// turns go into the players' input queues in the order they came.
+pub fn command(keys: &[Key]) -> Option<Key> {
+    let mut out: Option<Key> = None;
+    for &key in keys.iter().filter(|key| key.direction().is_none()) {
+        if out.is_none_or(|prev| prev.priority() <= key.priority()) {
+            out = Some(key);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let mut buf = b"\x1b[A\x1bOBwq \x1b[5~x".to_vec();
+        assert_eq!(parse_keys(&mut buf), vec![Key::Up, Key::Down, Key::W, Key::Quit, Key::Space]);
+        assert!(buf.is_empty());
+
+        // an arrow split across two reads.
+        let mut buf = b"g\x1b[".to_vec();
+        assert_eq!(parse_keys(&mut buf), vec![Key::G]);
+        assert_eq!(buf, b"\x1b[");
+        buf.push(b'D');
+        assert_eq!(parse_keys(&mut buf), vec![Key::Left]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(command(&[Key::Up, Key::A, Key::G, Key::Left, Key::S]), Some(Key::G));
+        assert_eq!(command(&[Key::Space, Key::Up, Key::H]), Some(Key::Space));
+        assert_eq!(command(&[Key::Tab, Key::Quit, Key::Space]), Some(Key::Quit));
+        assert_eq!(command(&[Key::Up, Key::W]), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! snek in a terminal, for playing where there's no compositor.
+//!
+//! ```text
+//! snek-tty [--two-player] [--demo[=CONTROLLER]] [--difficulty NAME]
+//!          [--input-depth N]
+//! ```
+//!
+//! The arrows steer, and WASD steers the second player.  Space pauses, G
+//! and H turn forced growth on and off, tab hands the first snake to the
+//! computer and back, and Q quits.  Turns pressed faster than the snake
+//! moves are made one a tick, up to `--input-depth` of them (3).
+//!
+//! `--difficulty` picks the speed, and for two players the arena and how
+//! long the snakes start; the levels bring their own.
+
+extern crate libc;
+extern crate snek_engine;
+
+mod input;
+mod screen;
+mod term;
+
+use std::cmp;
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use snek_engine::{DeathCause, Event, GameObject, GameOver, GameRng, GameState, PlayerId};
+use snek_engine::ai::{self, Pilot};
+use snek_engine::input::{self as turns, InputQueue};
+use snek_engine::level::Campaign;
+use snek_engine::rules::{self, Difficulty, Rules};
+use snek_engine::score::{self, GameStats, ScoreRules, Scorer};
+
+use input::Key;
+use screen::{ColorMode, Rgb, Screen};
+use term::RawTerminal;
+
+const FRAME: Duration = Duration::from_millis(33);
+/// How long the end of a game stays on screen.
+const GAME_OVER: Duration = Duration::from_secs(2);
+
+const FLOOR: Rgb = Rgb(16, 16, 32);
+const WALL: Rgb = Rgb(128, 128, 128);
+const FOOD: Rgb = Rgb(80, 200, 80);
+const BONUS_FOOD: Rgb = Rgb(255, 200, 0);
+const SHRINK: Rgb = Rgb(200, 80, 200);
+const FAST: Rgb = Rgb(255, 128, 0);
+const SLOW: Rgb = Rgb(0, 128, 255);
+const GHOST: Rgb = Rgb(180, 180, 220);
+const PORTAL: Rgb = Rgb(0, 220, 220);
+const OBSTACLE: Rgb = Rgb(160, 96, 48);
+const DEATH: Rgb = Rgb(255, 0, 0);
+/// Colours for each player's snake, by player number.
+const PLAYER_COLORS: &[Rgb] = &[Rgb(255, 255, 255), Rgb(64, 192, 255)];
+
+/// The terminal, and what's been typed into it.
+struct Tty {
+    raw: RawTerminal,
+    mode: ColorMode,
+    input: Vec<u8>,
+    /// How many turns each player can have waiting.
+    input_depth: usize,
+    size: Option<(usize, usize)>,
+}
+
+enum Ending {
+    Completed,
+    Over,
+    Quit,
+}
+
+fn main() {
+    // `--demo[=CONTROLLER]` lets the computer play until someone takes
+    // over; tab hands the first snake back and forth either way.
+    let demo = env::args()
+        .find(|arg| arg == "--demo" || arg.starts_with("--demo="))
+        .map(|arg| arg["--demo".len()..].trim_start_matches('=').to_string());
+    let pilot_name = match demo {
+        Some(ref name) if !name.is_empty() => name.clone(),
+        _ => "astar".to_string(),
+    };
+    if ai::by_name(&pilot_name).is_none() {
+        eprintln!("snek-tty: unknown controller {}, try one of: {}", pilot_name, ai::NAMES.join(", "));
+        process::exit(2);
+    }
+    let new_pilots = |players: usize| -> Vec<Pilot> {
+        (0..players).map(|_| Pilot::new(ai::by_name(&pilot_name).unwrap(), demo.is_some())).collect()
+    };
+
+    let input_depth = match env::args().skip_while(|arg| arg != "--input-depth").nth(1) {
+        Some(depth) => match depth.parse() {
+            Ok(depth) if 0 < depth => depth,
+            _ => {
+                eprintln!("snek-tty: bad value for --input-depth: {}", depth);
+                process::exit(2);
+            },
+        },
+        None => turns::DEFAULT_DEPTH,
+    };
+
+    let rules = match env::args().skip_while(|arg| arg != "--difficulty").nth(1) {
+        Some(name) => match Difficulty::by_name(&name) {
+            Some(difficulty) => difficulty.rules(),
+            None => {
+                eprintln!("snek-tty: unknown difficulty {}, try one of: {}", name, rules::NAMES.join(", "));
+                process::exit(2);
+            },
+        },
+        None => Rules::default(),
+    };
+
+    let mode = ColorMode::detect(env::var("COLORTERM").ok().as_ref().map(|s| &s[..]),
+                                 env::var("TERM").ok().as_ref().map(|s| &s[..]));
+    let mut tty = match RawTerminal::enter() {
+        Ok(raw) => Tty { raw, mode, input: Vec::new(), input_depth, size: None },
+        Err(err) => {
+            eprintln!("snek-tty: unable to use the terminal: {}", err);
+            process::exit(1);
+        },
+    };
+
+    let result = if env::args().any(|arg| arg == "--two-player") {
+        let mut pilots = new_pilots(2);
+        loop {
+            let game_state = GameState::with_rules(&rules, GameRng::from_entropy().seed(), 2);
+            match run_game(&mut tty, &mut pilots, game_state, None, "Versus") {
+                Ok(Ending::Quit) => break Ok(()),
+                Ok(_) => (),
+                Err(err) => break Err(err),
+            }
+        }
+    } else {
+        let mut pilots = new_pilots(1);
+        let mut campaign = Campaign::builtin();
+        loop {
+            let ending = {
+                let level = campaign.current();
+                let mut game_state = level.new_game(GameRng::from_entropy().seed());
+                game_state.set_speed(rules.speed);
+                run_game(&mut tty, &mut pilots, game_state, level.goal(), level.name())
+            };
+            match ending {
+                Ok(Ending::Completed) => {
+                    if campaign.advance().is_none() {
+                        campaign.restart();
+                    }
+                },
+                Ok(Ending::Over) => (),
+                Ok(Ending::Quit) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        }
+    };
+
+    drop(tty);
+    if let Err(err) = result {
+        eprintln!("snek-tty: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Play a game until it's over or, for a single player, until `goal`
+/// pieces of food have been eaten.
+fn run_game(tty: &mut Tty, pilots: &mut [Pilot], mut game_state: GameState, goal: Option<u64>, name: &str)
+    -> io::Result<Ending>
+{
+    let (width, height) = game_state.arena_size();
+    let players = game_state.player_count();
+    let mut screen = Screen::new(tty.mode, width, height, (1, 1));
+    // force a clear on the first frame.
+    tty.size = None;
+
+    let mut scorers: Vec<Scorer> = (0..players)
+        .map(|idx| Scorer::for_player(ScoreRules::default(), &game_state, PlayerId(idx)))
+        .collect();
+    let mut queues: Vec<InputQueue> = (0..players)
+        .map(|_| InputQueue::with_depth(tty.input_depth))
+        .collect();
+    let wasd_player = turns::wasd_player(players);
+    let mut paused = false;
+    let mut fits = true;
+    let mut deaths: Vec<(PlayerId, DeathCause)> = Vec::new();
+    let mut message = String::new();
+    let mut game_over: Option<(Instant, Ending)> = None;
+    // high scores are for people.
+    let mut assisted = false;
+
+    let mut next_frame = Instant::now();
+    let mut next_tick = Instant::now();
+    loop {
+        let now = Instant::now();
+        if let Some((until, _)) = game_over.as_ref() {
+            if *until <= now {
+                return Ok(game_over.unwrap().1);
+            }
+        }
+
+        if next_tick <= now {
+            let interval = game_state.tick_interval();
+            while next_tick <= now {
+                next_tick += interval;
+            }
+
+            tty.raw.read_input(&mut tty.input)?;
+            let keys = input::parse_keys(&mut tty.input);
+            for key in keys.iter() {
+                if let Some(dir) = key.direction() {
+                    let player = if key.is_wasd() { wasd_player } else { PlayerId(0) };
+                    pilots[player.0].flying = false;
+                    queues[player.0].push(&game_state, player, dir);
+                }
+            }
+
+            match input::command(&keys) {
+                Some(Key::Quit) => return Ok(Ending::Quit),
+                Some(Key::Space) => paused = !paused,
+                Some(Key::Tab) => {
+                    pilots[0].flying = !pilots[0].flying;
+                    queues[0].clear();
+                },
+                Some(Key::G) => game_state.set_force_grow(true),
+                Some(Key::H) => game_state.set_force_grow(false),
+                _ => (),
+            }
+
+            if !paused && fits && game_over.is_none() {
+                for (idx, queue) in queues.iter_mut().enumerate() {
+                    queue.steer(&mut game_state, PlayerId(idx));
+                }
+                for (idx, pilot) in pilots.iter_mut().enumerate() {
+                    pilot.steer(&mut game_state, PlayerId(idx));
+                }
+                assisted |= pilots[0].flying;
+
+                match game_state.tick() {
+                    Ok(events) => {
+                        for scorer in scorers.iter_mut() {
+                            if game_state.is_alive(scorer.player()) {
+                                scorer.record_tick(&events);
+                            }
+                        }
+                        for event in events {
+                            if let Event::Died(player, cause) = event {
+                                message = format!("Player {} {}", player.0 + 1, cause);
+                                deaths.push((player, cause));
+                            }
+                        }
+                        if let Some(goal) = goal {
+                            let stats = scorers[0].stats();
+                            if players == 1 && goal <= stats.food_eaten {
+                                message = format!("Completed {} (score {})", name, stats.score);
+                                game_over = Some((now + GAME_OVER, Ending::Completed));
+                            }
+                        }
+                    },
+                    Err(result) => {
+                        for scorer in scorers.iter() {
+                            let player = scorer.player();
+                            if let Some(cause) = game_state.death(player) {
+                                if !deaths.iter().any(|&(dead, _)| dead == player) {
+                                    deaths.push((player, cause));
+                                }
+                            }
+                        }
+                        message = match result {
+                            GameOver::Died(cause) => {
+                                let stats = scorers[0].stats();
+                                let mut message = format!("Game over: {} (score {})", cause, stats.score);
+                                if !assisted {
+                                    message.push_str(&high_score_note(stats));
+                                }
+                                message
+                            },
+                            GameOver::BoardFull if scorers.len() == 1 => {
+                                let stats = scorers[0].stats();
+                                let mut message = format!("The board is full, you win! (score {})", stats.score);
+                                if !assisted {
+                                    message.push_str(&high_score_note(stats));
+                                }
+                                message
+                            },
+                            GameOver::Winner(PlayerId(idx)) => format!("Player {} wins!", idx + 1),
+                            GameOver::Draw | GameOver::Eliminated | GameOver::BoardFull => "A draw!".to_string(),
+                        };
+                        game_over = Some((now + GAME_OVER, Ending::Over));
+                    },
+                }
+            }
+        }
+
+        if next_frame <= now {
+            while next_frame <= now {
+                next_frame += FRAME;
+            }
+
+            let mut out = String::new();
+            let size = term::size();
+            if size != tty.size {
+                tty.size = size;
+                out.push_str("\x1b[0m\x1b[2J");
+                let (cols, rows) = size.unwrap_or((width, Screen::rows_for(height) + 1));
+                fits = width <= cols && Screen::rows_for(height) < rows;
+                let origin = ((cols.saturating_sub(width)) / 2 + 1, (rows.saturating_sub(Screen::rows_for(height) + 1)) / 2 + 1);
+                screen.invalidate(origin);
+                if !fits {
+                    out.push_str(&format!("\x1b[1;1Hmake the terminal at least {}x{}", width, Screen::rows_for(height) + 1));
+                }
+            }
+
+            if fits {
+                screen.draw(&paint(&game_state, &deaths, game_over.is_some()), FLOOR, &mut out);
+
+                let status = if paused {
+                    "paused: space to carry on".to_string()
+                } else if !message.is_empty() {
+                    message.clone()
+                } else {
+                    let scores: Vec<_> = scorers.iter()
+                        .map(|scorer| format!("{}", scorer.stats().score))
+                        .collect();
+                    let pilot = if pilots[0].flying { " (autopilot)" } else { "" };
+                    format!("{}  score {}{}", name, scores.join(" : "), pilot)
+                };
+                screen.draw_status(&status, &mut out);
+            }
+
+            let mut stdout = io::stdout();
+            stdout.write_all(out.as_bytes())?;
+            stdout.flush()?;
+        }
+
+        let wake = cmp::min(next_frame, next_tick);
+        let now = Instant::now();
+        if now < wake {
+            thread::sleep(wake - now);
+        }
+    }
+}
+
+/// A colour for each cell of the arena.
+fn paint(game_state: &GameState, deaths: &[(PlayerId, DeathCause)], game_over: bool) -> Vec<Rgb> {
+    let (width, height) = game_state.arena_size();
+    let mut pixels = vec![FLOOR; width * height];
+
+    for ((x, y), obj, lifetime) in game_state.object_iter() {
+        // blink on and off through the last few ticks.
+        if lifetime.is_some_and(|ticks| ticks < 10 && ticks % 2 == 1) {
+            continue;
+        }
+        pixels[y * width + x] = match *obj {
+            GameObject::Food => FOOD,
+            GameObject::Wall => WALL,
+            GameObject::BonusFood => BONUS_FOOD,
+            GameObject::Shrink => SHRINK,
+            GameObject::Fast => FAST,
+            GameObject::Slow => SLOW,
+            GameObject::Ghost => GHOST,
+            GameObject::Portal(_) => PORTAL,
+            GameObject::Obstacle(_) => OBSTACLE,
+        };
+    }
+
+    for idx in 0..game_state.player_count() {
+        let player = PlayerId(idx);
+        // dead snakes have left the board, until the game's over
+        if !game_state.is_alive(player) && !game_over {
+            continue;
+        }
+        let mut color = PLAYER_COLORS[idx % PLAYER_COLORS.len()];
+        // ghosts are faded.
+        if 0 < game_state.ghost_ticks(player) {
+            color = Rgb(color.0 / 2, color.1 / 2, color.2 / 2);
+        }
+        for (x, y) in game_state.snake(player).unwrap().positions() {
+            if x < width && y < height {
+                pixels[y * width + x] = color;
+            }
+        }
+    }
+
+    // positions just outside the arena are pulled back onto its edge.
+    for (_, cause) in deaths.iter() {
+        let (x, y) = cause.position();
+        pixels[cmp::min(y, height - 1) * width + cmp::min(x, width - 1)] = DEATH;
+    }
+
+    pixels
+}
+
+/// Returns something to add to the game over message.
+fn high_score_note(stats: &GameStats) -> String {
+    match score::record_high_score(stats) {
+        Ok(Some(rank)) => format!(", new high score #{}!", rank + 1),
+        Ok(None) => String::new(),
+        Err(err) => format!(", unable to keep high scores: {}", err),
+    }
+}
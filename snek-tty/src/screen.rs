@@ -0,0 +1,192 @@
+//! Drawing the arena with ANSI escapes.
+//!
+//! Each character cell shows two arena cells, one above the other: an
+//! upper half block in the top cell's colour on a background of the
+//! bottom cell's.  The screen remembers what it last drew and only
+//! rewrites the characters which changed.
+
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// How many colours the terminal understands.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ColorMode {
+    TrueColor,
+    Palette256,
+    /// The eight standard colours.
+    Basic,
+}
+
+impl ColorMode {
+    /// Guess from `$COLORTERM` and `$TERM`.
+    pub fn detect(colorterm: Option<&str>, term: Option<&str>) -> ColorMode {
+        match (colorterm, term) {
+            (Some("truecolor"), _) | (Some("24bit"), _) => ColorMode::TrueColor,
+            (_, Some(term)) if term.contains("256color") => ColorMode::Palette256,
+            _ => ColorMode::Basic,
+        }
+    }
+
+    /// Write the SGR parameters selecting `color`, as the foreground or
+    /// the background.
+    fn write_sgr(&self, out: &mut String, color: Rgb, background: bool) {
+        let Rgb(r, g, b) = color;
+        let _ = match *self {
+            ColorMode::TrueColor => write!(out, "{};2;{};{};{}", if background { 48 } else { 38 }, r, g, b),
+            ColorMode::Palette256 => {
+                let level = |v: u8| (u16::from(v) * 5 + 127) / 255;
+                let index = 16 + 36 * level(r) + 6 * level(g) + level(b);
+                write!(out, "{};5;{}", if background { 48 } else { 38 }, index)
+            },
+            ColorMode::Basic => {
+                let bit = |v: u8, value: u8| if 128 <= v { value } else { 0 };
+                let index = bit(r, 1) | bit(g, 2) | bit(b, 4);
+                write!(out, "{}", if background { 40 } else { 30 } + index)
+            },
+        };
+    }
+}
+
+const UPPER_HALF: char = '\u{2580}';
+
+pub struct Screen {
+    mode: ColorMode,
+    width: usize,
+    height: usize,
+    /// The terminal column and row, from 1, of the arena's top left.
+    origin: (usize, usize),
+    /// The `(top, bottom)` colours of each character, or `None` where
+    /// we don't know what's on the terminal.
+    shown: Vec<Option<(Rgb, Rgb)>>,
+    status: Option<String>,
+}
+
+impl Screen {
+    /// A screen for an arena of `width` by `height` cells.
+    pub fn new(mode: ColorMode, width: usize, height: usize, origin: (usize, usize)) -> Screen {
+        Screen {
+            mode,
+            width,
+            height,
+            origin,
+            shown: vec![None; width * Screen::rows_for(height)],
+            status: None,
+        }
+    }
+
+    /// The terminal rows the arena takes up.
+    pub fn rows_for(height: usize) -> usize {
+        height.div_ceil(2)
+    }
+
+    /// Forget what's on the terminal, after it was cleared, and draw the
+    /// arena from `origin` from now on.
+    pub fn invalidate(&mut self, origin: (usize, usize)) {
+        self.origin = origin;
+        for cell in self.shown.iter_mut() {
+            *cell = None;
+        }
+        self.status = None;
+    }
+
+    /// Bring the terminal up to date with `pixels`, a colour for each
+    /// arena cell, row by row.  `fill` colours the bottom half of the
+    /// last row when the height is odd.
+    pub fn draw(&mut self, pixels: &[Rgb], fill: Rgb, out: &mut String) {
+        assert_eq!(pixels.len(), self.width * self.height);
+        // where the terminal's cursor is, and its colours.
+        let mut cursor = None;
+        let mut pen = None;
+
+        for row in 0..Screen::rows_for(self.height) {
+            for col in 0..self.width {
+                let top = pixels[2 * row * self.width + col];
+                let bottom = if 2 * row + 1 < self.height { pixels[(2 * row + 1) * self.width + col] } else { fill };
+                let cell = &mut self.shown[row * self.width + col];
+                if *cell == Some((top, bottom)) {
+                    continue;
+                }
+                *cell = Some((top, bottom));
+
+                if cursor != Some((row, col)) {
+                    let _ = write!(out, "\x1b[{};{}H", self.origin.1 + row, self.origin.0 + col);
+                }
+                if pen != Some((top, bottom)) {
+                    out.push_str("\x1b[");
+                    self.mode.write_sgr(out, top, false);
+                    out.push(';');
+                    self.mode.write_sgr(out, bottom, true);
+                    out.push('m');
+                    pen = Some((top, bottom));
+                }
+                out.push(UPPER_HALF);
+                cursor = Some((row, col + 1));
+            }
+        }
+
+        if pen.is_some() {
+            out.push_str("\x1b[0m");
+        }
+    }
+
+    /// Show `text` on the line below the arena, if it isn't already.
+    pub fn draw_status(&mut self, text: &str, out: &mut String) {
+        if self.status.as_ref().map(|shown| &shown[..]) == Some(text) {
+            return;
+        }
+        let row = self.origin.1 + Screen::rows_for(self.height);
+        let _ = write!(out, "\x1b[{};{}H\x1b[2K{}", row, self.origin.0, text);
+        self.status = Some(text.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgb = Rgb(0, 0, 0);
+    const WHITE: Rgb = Rgb(255, 255, 255);
+
+    #[test]
+    fn only_changes_are_redrawn() {
+        let mut screen = Screen::new(ColorMode::TrueColor, 3, 3, (1, 1));
+        let mut pixels = vec![BLACK; 9];
+
+        let mut out = String::new();
+        screen.draw(&pixels, BLACK, &mut out);
+        assert_eq!(out.matches(UPPER_HALF).count(), 6);
+
+        out.clear();
+        screen.draw(&pixels, BLACK, &mut out);
+        assert_eq!(out, "");
+
+        // the middle of the second row is the bottom half of the first.
+        pixels[4] = WHITE;
+        out.clear();
+        screen.draw(&pixels, BLACK, &mut out);
+        assert_eq!(out, "\x1b[1;2H\x1b[38;2;0;0;0;48;2;255;255;255m\u{2580}\x1b[0m");
+
+        screen.invalidate((1, 1));
+        out.clear();
+        screen.draw(&pixels, BLACK, &mut out);
+        assert_eq!(out.matches(UPPER_HALF).count(), 6);
+    }
+
+    #[test]
+    fn color_modes() {
+        assert_eq!(ColorMode::detect(Some("truecolor"), Some("xterm")), ColorMode::TrueColor);
+        assert_eq!(ColorMode::detect(None, Some("screen-256color")), ColorMode::Palette256);
+        assert_eq!(ColorMode::detect(None, Some("vt100")), ColorMode::Basic);
+
+        let sgr = |mode: ColorMode, color: Rgb| {
+            let mut out = String::new();
+            mode.write_sgr(&mut out, color, true);
+            out
+        };
+        assert_eq!(sgr(ColorMode::Palette256, Rgb(255, 0, 0)), "48;5;196");
+        assert_eq!(sgr(ColorMode::Palette256, WHITE), "48;5;231");
+        assert_eq!(sgr(ColorMode::Basic, Rgb(0, 200, 200)), "46");
+    }
+}
@@ -0,0 +1,75 @@
+//! Raw mode, and what the terminal can do.
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use libc;
+
+/// Keeps the terminal in raw mode on the alternate screen, with the
+/// cursor hidden, until it's dropped.
+pub struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    /// Reads from stdin return straight away, with whatever has been
+    /// typed so far.
+    pub fn enter() -> io::Result<RawTerminal> {
+        let original = unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+
+            libc::cfmakeraw(&mut termios);
+            termios.c_cc[libc::VMIN] = 0;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            original
+        };
+
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        stdout.flush()?;
+        Ok(RawTerminal { original })
+    }
+
+    /// Append whatever has been typed since the last call to `buf`.
+    pub fn read_input(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let mut chunk = [0; 64];
+        loop {
+            match io::stdin().read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(len) => buf.extend_from_slice(&chunk[..len]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// The terminal's size in `(columns, rows)`.
+pub fn size() -> Option<(usize, usize)> {
+    unsafe {
+        let mut size: libc::winsize = mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 || size.ws_col == 0 {
+            return None;
+        }
+        Some((size.ws_col as usize, size.ws_row as usize))
+    }
+}
@@ -12,6 +12,7 @@ extern crate surface;
 use byteorder::{WriteBytesExt, NativeEndian};
 
 use std::collections::VecDeque;
+use std::env;
 use std::sync::{Arc, Mutex};
 use std::io::{self, Seek, SeekFrom, Write, BufWriter};
 use std::os::unix::io::AsRawFd;
@@ -30,8 +31,10 @@ use surface::colorspace::ColorARGB;
 use surface::compositing::{
     porter_duff,
     porter_duff_inplace_dst,
-    porter_duff_inplace_src,
+    porter_duff_premultiplied_inplace_src,
+    premultiply,
     Mode as PorterDuffMode,
+    PremultipliedARGB,
 };
 
 use mmap::{MapOption, MemoryMap};
@@ -39,10 +42,20 @@ use mmap::{MapOption, MemoryMap};
 use snek_engine::{
     Snake,
     GameState,
+    GameOver,
+    DeathCause,
     Direction,
+    Event,
     GameObject,
+    PlayerId,
     SnakePositions,
 };
+use snek_engine::GameRng;
+use snek_engine::ai::{self, Pilot};
+use snek_engine::input::{self as turns, InputQueue};
+use snek_engine::level::Campaign;
+use snek_engine::rules::{self, Difficulty, Rules};
+use snek_engine::score::{self, GameStats, ScoreRules, Scorer};
 
 wayland_env!(WaylandEnv,
     compositor: WlCompositor,
@@ -51,6 +64,9 @@ wayland_env!(WaylandEnv,
     seat: WlSeat
 );
 
+/// Colours for each player's snake, by player number.
+const PLAYER_COLORS: &[u32] = &[0xFFFFFFFF, 0xFF40C0FF];
+
 #[derive(Copy, Clone)]
 enum KeyboardEvent {
     Up,
@@ -58,19 +74,30 @@ enum KeyboardEvent {
     Left,
     Right,
     Space,
+    Tab,
     G, H,
 }
 
 impl KeyboardEvent {
+    fn direction(&self) -> Option<Direction> {
+        match *self {
+            KeyboardEvent::Up => Some(Direction::North),
+            KeyboardEvent::Down => Some(Direction::South),
+            KeyboardEvent::Left => Some(Direction::West),
+            KeyboardEvent::Right => Some(Direction::East),
+            _ => None,
+        }
+    }
+}
+
+impl KeyboardEvent {
+    /// Which of the commands pressed since the last frame wins.  Turns
+    /// are queued instead, so never need ranking.
     pub fn priority(&self) -> u16 {
         match *self {
-            KeyboardEvent::Up => 1,
-            KeyboardEvent::Down => 1,
-            KeyboardEvent::Left => 1,
-            KeyboardEvent::Right => 1,
             KeyboardEvent::Space => 2,
-            KeyboardEvent::G => 0,
-            KeyboardEvent::H => 0,
+            KeyboardEvent::Tab => 2,
+            _ => 0,
         }
     }
 }
@@ -85,31 +112,50 @@ fn replace_state(evt: &mut Option<KeyboardEvent>, new_event: KeyboardEvent) {
     }
 }
 
-fn flush_keyboard_buf(evt_iter: &mut EventIterator) -> Option<KeyboardEvent> {
+/// Drain the pending key presses.  The command that wins comes back first,
+/// then every turn in the order it was pressed, as the matching arrow and
+/// whether it was WASD, which steers the second player.
+fn flush_keyboard_buf(evt_iter: &mut EventIterator) -> (Option<KeyboardEvent>, Vec<(KeyboardEvent, bool)>) {
     use wayland_client::Event;
     use wayland_client::wayland::WaylandProtocolEvent as WPE;
     use wayland_client::wayland::seat::WlKeyboardEvent as KE;
     use wayland_client::wayland::seat::WlKeyboardKeyState::Pressed;
 
     let mut out = None;
+    let mut turns = Vec::new();
     loop {
         if let Some(Event::Wayland(event)) = evt_iter.next() {
             match event {
                 WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 103, Pressed)) => {
-                    replace_state(&mut out, KeyboardEvent::Up);
+                    turns.push((KeyboardEvent::Up, false));
                 },
                 WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 105, Pressed)) => {
-                    replace_state(&mut out, KeyboardEvent::Left);
+                    turns.push((KeyboardEvent::Left, false));
                 },
                 WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 106, Pressed)) => {
-                    replace_state(&mut out, KeyboardEvent::Right);
+                    turns.push((KeyboardEvent::Right, false));
                 },
                 WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 108, Pressed)) => {
-                    replace_state(&mut out, KeyboardEvent::Down);
+                    turns.push((KeyboardEvent::Down, false));
+                },
+                WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 17, Pressed)) => {
+                    turns.push((KeyboardEvent::Up, true));
+                },
+                WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 30, Pressed)) => {
+                    turns.push((KeyboardEvent::Left, true));
+                },
+                WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 32, Pressed)) => {
+                    turns.push((KeyboardEvent::Right, true));
+                },
+                WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 31, Pressed)) => {
+                    turns.push((KeyboardEvent::Down, true));
                 },
                 WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 57, Pressed)) => {
                     replace_state(&mut out, KeyboardEvent::Space);
                 },
+                WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 15, Pressed)) => {
+                    replace_state(&mut out, KeyboardEvent::Tab);
+                },
                 WPE::WlKeyboard(_proxy_id, KE::Key(ser, ts, 34, Pressed)) => {
                     replace_state(&mut out, KeyboardEvent::G);
                 },
@@ -122,10 +168,49 @@ fn flush_keyboard_buf(evt_iter: &mut EventIterator) -> Option<KeyboardEvent> {
             }
         } else { break }
     }
-    out
+    (out, turns)
 }
 
 fn main() {
+    // `--demo[=CONTROLLER]` lets the computer play until someone takes
+    // over; tab hands the first snake back and forth either way.
+    let demo = env::args()
+        .find(|arg| arg == "--demo" || arg.starts_with("--demo="))
+        .map(|arg| arg["--demo".len()..].trim_start_matches('=').to_string());
+    let pilot_name = match demo {
+        Some(ref name) if !name.is_empty() => name.clone(),
+        _ => "astar".to_string(),
+    };
+    if ai::by_name(&pilot_name).is_none() {
+        eprintln!("unknown controller {}, try one of: {}", pilot_name, ai::NAMES.join(", "));
+        ::std::process::exit(2);
+    }
+    let input_depth = match env::args().skip_while(|arg| arg != "--input-depth").nth(1) {
+        Some(depth) => match depth.parse() {
+            Ok(depth) if 0 < depth => depth,
+            _ => {
+                eprintln!("bad value for --input-depth: {}", depth);
+                ::std::process::exit(2);
+            },
+        },
+        None => turns::DEFAULT_DEPTH,
+    };
+    // `--difficulty NAME` sets the speed, and the arena and starting
+    // length for two players; the levels have their own.
+    let rules = match env::args().skip_while(|arg| arg != "--difficulty").nth(1) {
+        Some(name) => match Difficulty::by_name(&name) {
+            Some(difficulty) => difficulty.rules(),
+            None => {
+                eprintln!("unknown difficulty {}, try one of: {}", name, rules::NAMES.join(", "));
+                ::std::process::exit(2);
+            },
+        },
+        None => Rules::default(),
+    };
+    let new_pilots = |players: usize| -> Vec<Pilot> {
+        (0..players).map(|_| Pilot::new(ai::by_name(&pilot_name).unwrap(), demo.is_some())).collect()
+    };
+
     let display = match get_display() {
         Some(d) => d,
         None => panic!("Unable to connect to a wayland compositor.")
@@ -152,29 +237,75 @@ fn main() {
     shell_surface.set_toplevel();
 
     let mut painter = GamePainter::new(shm, 512, 512, 3).unwrap();
+
+    // arrows against WASD on an open board, round after round.
+    if env::args().any(|arg| arg == "--two-player") {
+        let mut pilots = new_pilots(2);
+        loop {
+            let game_state = GameState::with_rules(&rules, GameRng::from_entropy().seed(), 2);
+            run_game(&mut env.display, &surface, &mut painter, &mut evt_iter, &mut pilots, input_depth,
+                     game_state, None, "Versus");
+        }
+    }
+
+    let mut pilots = new_pilots(1);
+    let mut campaign = Campaign::builtin();
     loop {
-        run_game(&mut env.display, &surface, &mut painter, &mut evt_iter);
+        let completed = {
+            let level = campaign.current();
+            let mut game_state = level.new_game(GameRng::from_entropy().seed());
+            game_state.set_speed(rules.speed);
+            run_game(&mut env.display, &surface, &mut painter, &mut evt_iter, &mut pilots, input_depth,
+                     game_state, level.goal(), level.name())
+        };
+        if completed {
+            match campaign.advance() {
+                Some(level) => println!("Next level: {}", level.name()),
+                None => {
+                    println!("You finished every level!");
+                    campaign.restart();
+                },
+            }
+        }
     }
 }
 
 
-fn run_game(display: &mut WlDisplay, surface: &WlSurface, painter: &mut GamePainter, evt_iter: &mut EventIterator) {
+/// Play a game until it's over or, for a single player, until `goal`
+/// pieces of food have been eaten.  Returns whether the goal was reached.
+/// `pilots` has one for each player, flying whichever snakes no one is
+/// steering.  Each player can have `input_depth` turns waiting.
+fn run_game(display: &mut WlDisplay, surface: &WlSurface, painter: &mut GamePainter, evt_iter: &mut EventIterator,
+            pilots: &mut [Pilot], input_depth: usize, mut game_state: GameState, goal: Option<u64>, name: &str) -> bool {
     use std::cmp::{min, max};
     use time::{SteadyTime, Duration as TimeDuration};
     use std::time::Duration;
 
     const FRAME_NANOS: i64 = 2 * 16_666_666;
-    const TICK_NANOS: i64 = 100_000_000;
+    const FLASH_FRAMES: u32 = 3;
 
     let background = get_background_surface();
     let food = get_food_surface();
     let snake = get_snake_surface();
+    let pause = load_pause_overlay();
 
     let frame_duration = TimeDuration::nanoseconds(FRAME_NANOS);
-    let tick_duration = TimeDuration::nanoseconds(TICK_NANOS);
     let mut next_frame = SteadyTime::now();
     let mut next_tick = SteadyTime::now();
     let mut paused = false;
+    let players = game_state.player_count();
+    let mut scorers: Vec<Scorer> = (0..players)
+        .map(|idx| Scorer::for_player(ScoreRules::default(), &game_state, PlayerId(idx)))
+        .collect();
+    let mut queues: Vec<InputQueue> = (0..players)
+        .map(|_| InputQueue::with_depth(input_depth))
+        .collect();
+    let wasd_player = turns::wasd_player(players);
+    let mut flash_frames: u32 = 0;
+    let mut deaths: Vec<(PlayerId, DeathCause)> = Vec::new();
+    let mut game_over: Option<SteadyTime> = None;
+    // high scores are for people.
+    let mut assisted = false;
 
     let bg_width = background.width() as isize;
     let bg_height = background.height() as isize;
@@ -186,11 +317,16 @@ fn run_game(display: &mut WlDisplay, surface: &WlSurface, painter: &mut GamePain
     let mut bg_yoff_dir: isize = 1;
 
     // let game_painter = GamePainter::new();
-    let mut game_state = GameState::new(64, 64);
     loop {
         let mut sleep_dur = TimeDuration::seconds(1);
         let now = SteadyTime::now();
 
+        if let Some(until) = game_over {
+            if until <= now {
+                return false;
+            }
+        }
+
         let mut emit_frame = false;
         while next_frame <= now {
             emit_frame = true;
@@ -203,6 +339,9 @@ fn run_game(display: &mut WlDisplay, surface: &WlSurface, painter: &mut GamePain
             }
         }
 
+        // the game decides how far apart ticks are, and that changes as
+        // it goes on.
+        let tick_duration = TimeDuration::from_std(game_state.tick_interval()).unwrap();
         let mut emit_tick = false;
         while next_tick <= now {
             emit_tick = true;
@@ -216,29 +355,107 @@ fn run_game(display: &mut WlDisplay, surface: &WlSurface, painter: &mut GamePain
         }
 
         if emit_tick {
-            let keyboard_event = flush_keyboard_buf(evt_iter);
+            let (keyboard_event, turn_events) = flush_keyboard_buf(evt_iter);
+
+            for (arrow, wasd) in turn_events {
+                if let Some(dir) = arrow.direction() {
+                    let player = if wasd { wasd_player } else { PlayerId(0) };
+                    pilots[player.0].flying = false;
+                    queues[player.0].push(&game_state, player, dir);
+                }
+            }
 
-            let mut direction = None;
             match keyboard_event {
-                Some(KeyboardEvent::Up) => direction = Some(Direction::North),
-                Some(KeyboardEvent::Down) => direction = Some(Direction::South),
-                Some(KeyboardEvent::Left) => direction = Some(Direction::West),
-                Some(KeyboardEvent::Right) => direction = Some(Direction::East),
                 Some(KeyboardEvent::Space) => paused = !paused,
+                Some(KeyboardEvent::Tab) => {
+                    pilots[0].flying = !pilots[0].flying;
+                    queues[0].clear();
+                    println!("Autopilot {}", if pilots[0].flying { "on" } else { "off" });
+                },
                 Some(KeyboardEvent::G) => game_state.set_force_grow(true),
                 Some(KeyboardEvent::H) => game_state.set_force_grow(false),
-                None => (),
+                _ => (),
             };
 
-            if let Some(dir) = direction {
-                game_state.set_user_direction(dir);
+            if !paused && game_over.is_none() {
+                for (idx, queue) in queues.iter_mut().enumerate() {
+                    queue.steer(&mut game_state, PlayerId(idx));
+                }
+                for (idx, pilot) in pilots.iter_mut().enumerate() {
+                    pilot.steer(&mut game_state, PlayerId(idx));
+                }
+                assisted |= pilots[0].flying;
             }
         }
 
-        if emit_tick && !paused {
-            if let Err(err) = game_state.tick() {
-                println!("Game Over: {:?}", err);
-                break;
+        if emit_tick && !paused && game_over.is_none() {
+            match game_state.tick() {
+                Ok(events) => {
+                    for scorer in scorers.iter_mut() {
+                        if game_state.is_alive(scorer.player()) {
+                            scorer.record_tick(&events);
+                        }
+                    }
+                    for event in events {
+                        match event {
+                            Event::AteFood(..) => flash_frames = FLASH_FRAMES,
+                            Event::Died(player, cause) => {
+                                println!("Player {} {}", player.0 + 1, cause);
+                                deaths.push((player, cause));
+                            },
+                            _ => (),
+                        }
+                    }
+                    if let Some(goal) = goal {
+                        let stats = scorers[0].stats();
+                        if players == 1 && goal <= stats.food_eaten {
+                            println!("Completed {} (score {})", name, stats.score);
+                            return true;
+                        }
+                    }
+                },
+                Err(GameOver::BoardFull) if players == 1 => {
+                    println!("The board is full, you win! (score {})", scorers[0].stats().score);
+                    if !assisted {
+                        announce_high_score(scorers[0].stats());
+                    }
+                    game_over = Some(now + TimeDuration::seconds(2));
+                },
+                Err(GameOver::Died(cause)) => {
+                    println!("Game Over: {} (score {})", cause, scorers[0].stats().score);
+                    if !assisted {
+                        announce_high_score(scorers[0].stats());
+                    }
+                    deaths.push((PlayerId(0), cause));
+                    game_over = Some(now + TimeDuration::seconds(2));
+                },
+                Err(result) => {
+                    for scorer in scorers.iter() {
+                        let player = scorer.player();
+                        let reported = deaths.iter().any(|&(dead, _)| dead == player);
+                        if let (Some(cause), false) = (game_state.death(player), reported) {
+                            println!("Player {} {}", player.0 + 1, cause);
+                            deaths.push((player, cause));
+                        }
+                        println!("Player {}: {} points", player.0 + 1, scorer.stats().score);
+                    }
+                    match result {
+                        GameOver::Winner(PlayerId(idx)) => println!("Player {} wins!", idx + 1),
+                        _ => {
+                            let best = scorers.iter().map(|scorer| scorer.stats().score).max().unwrap();
+                            let leaders: Vec<_> = scorers.iter()
+                                .filter(|scorer| scorer.stats().score == best)
+                                .map(|scorer| scorer.player().0 + 1)
+                                .collect();
+                            if leaders.len() == 1 {
+                                println!("Player {} wins on points!", leaders[0]);
+                            } else {
+                                println!("A draw!");
+                            }
+                        },
+                    }
+                    game_over = Some(now + TimeDuration::seconds(2));
+                },
             }
         }
 
@@ -264,19 +481,33 @@ fn run_game(display: &mut WlDisplay, surface: &WlSurface, painter: &mut GamePain
             let mut buffer = painter.create_buffer();
             draw_background(&background, bg_xoff as usize, bg_yoff as usize, &mut buffer);
 
-            {
+            for idx in 0..players {
+                let player = PlayerId(idx);
+                // dead snakes have left the board, until the game's over
+                if !game_state.is_alive(player) && game_over.is_none() {
+                    continue;
+                }
+                let color = PLAYER_COLORS[idx % PLAYER_COLORS.len()];
                 let mut painter = SnakePainter::new(&mut buffer);
-                painter.paint(&snake, game_state.get_snake());
+                painter.paint(&snake, game_state.snake(player).unwrap(), color);
             }
             {
                 let mut painter = ObjectPainter::new(&mut buffer);
-                for (pos, object) in game_state.object_iter() {
+                for (pos, object, _) in game_state.object_iter() {
                     painter.paint(&food, pos, object);
                 }
             }
 
+            for &(_, ref cause) in deaths.iter() {
+                draw_death_marker(&mut buffer, cause);
+            }
+            if flash_frames > 0 {
+                draw_flash(&mut buffer);
+                flash_frames -= 1;
+            }
+
             if paused {
-                draw_paused_screen(&mut buffer);
+                draw_paused_screen(&pause, &mut buffer);
             }
 
             surface.attach(Some(&buffer.wl_buffer), 0, 0);
@@ -365,12 +596,52 @@ fn draw_background(background: &Surface<ColorARGB<u8>>, xoff: usize, yoff: usize
     }
 }
 
-fn draw_paused_screen(buffer: &mut Buffer) {
-    let pause = u8_slice_to_u32_slice(include_bytes!("../pause.bin"));
-    unsafe {
-        porter_duff_inplace_src(&mut buffer.memory, pause, PorterDuffMode::Over)
-    }.unwrap();
+fn load_pause_overlay() -> Vec<u32> {
+    let mut pause = u8_slice_to_u32_slice(include_bytes!("../pause.bin")).to_vec();
+    premultiply(&mut pause);
+    pause
+}
+
+fn draw_paused_screen(pause: &[u32], buffer: &mut Buffer) {
+    porter_duff_premultiplied_inplace_src(&mut buffer.memory, pause, PorterDuffMode::SrcOver).unwrap();
+}
 
+fn announce_high_score(stats: &GameStats) {
+    match score::record_high_score(stats) {
+        Ok(Some(rank)) => println!("New high score! #{}", rank + 1),
+        Ok(None) => (),
+        Err(err) => println!("unable to keep high scores: {}", err),
+    }
+}
+
+/// Mark the cell the snake died on in red.  Positions just outside the
+/// arena are pulled back onto its edge.
+fn draw_death_marker(buffer: &mut Buffer, cause: &DeathCause) {
+    let (x, y) = cause.position();
+    let cells_x = buffer.width / 8;
+    let cells_y = buffer.height / 8;
+    let (x, y) = (clamp(x, 0, cells_x - 1), clamp(y, 0, cells_y - 1));
+
+    for y_p in y * 8..(y + 1) * 8 {
+        for x_p in x * 8..(x + 1) * 8 {
+            buffer.set_color((x_p, y_p), 0xFFFF0000);
+        }
+    }
+}
+
+/// A thin white border, shown for a few frames when food is eaten.
+fn draw_flash(buffer: &mut Buffer) {
+    let (width, height) = (buffer.width, buffer.height);
+    for x in 0..width {
+        for y in (0..2).chain(height - 2..height) {
+            buffer.set_color((x, y), 0xFFFFFFFF);
+        }
+    }
+    for y in 0..height {
+        for x in (0..2).chain(width - 2..width) {
+            buffer.set_color((x, y), 0xFFFFFFFF);
+        }
+    }
 }
 
 struct GamePainter {
@@ -516,12 +787,24 @@ impl<'a> Iterator for SnakeJointer<'a> {
             }
             (Some(new_pos), Some(ppos)) => {
                 self.previous = Some(new_pos);
-                Some(SnakeJoint::Joint((new_pos, ppos)))
+                if is_adjacent(new_pos, ppos) {
+                    Some(SnakeJoint::Joint((new_pos, ppos)))
+                } else {
+                    // The snake crosses a wrapping edge here; joining the
+                    // two cells would paint a bar right across the arena.
+                    Some(SnakeJoint::Endpoint(new_pos))
+                }
             }
         }
     }
 }
 
+fn is_adjacent((x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> bool {
+    let dx = if x0 < x1 { x1 - x0 } else { x0 - x1 };
+    let dy = if y0 < y1 { y1 - y0 } else { y0 - y1 };
+    dx + dy <= 1
+}
+
 struct SnakePainter<'a, 'b: 'a> {
     buffer: &'a mut Buffer<'b>,
 }
@@ -531,7 +814,7 @@ impl<'a, 'b: 'a> SnakePainter<'a, 'b> {
         SnakePainter { buffer: buffer }
     }
 
-    pub fn paint(&mut self, paint: &Surface<ColorARGB<u8>>, snake: &Snake) {
+    pub fn paint(&mut self, paint: &Surface<ColorARGB<u8>>, snake: &Snake, color: u32) {
         use std::cmp::{min, max};
 
         for part in SnakeJointer::new(snake.positions()) {
@@ -549,7 +832,7 @@ impl<'a, 'b: 'a> SnakePainter<'a, 'b> {
 
             for x_p in x_start..x_end {
                 for y_p in y_start..y_end {
-                    self.buffer.set_color((x_p, y_p), 0xFFFFFFFF);
+                    self.buffer.set_color((x_p, y_p), color);
                 }
             }
         }
@@ -569,17 +852,36 @@ impl<'a, 'b: 'a> ObjectPainter<'a, 'b> {
         let x_start = x * 8;
         let y_start = y * 8;
 
+        let solid = match *obj {
+            GameObject::Food => None,
+            GameObject::Wall => Some(0xFF606060),
+            GameObject::BonusFood => Some(0xFFFFC800),
+            GameObject::Shrink => Some(0xFFC850C8),
+            GameObject::Fast => Some(0xFFFF8000),
+            GameObject::Slow => Some(0xFF0080FF),
+            GameObject::Ghost => Some(0xFFB4B4DC),
+            GameObject::Portal(_) => Some(0xFF00DCDC),
+            GameObject::Obstacle(_) => Some(0xFFA06030),
+        };
+        if let Some(color) = solid {
+            for y_p in y_start..y_start + 8 {
+                for x_p in x_start..x_start + 8 {
+                    self.buffer.set_color((x_p, y_p), color);
+                }
+            }
+            return;
+        }
+
         let mut buf = [0; 1024];
         for (pixel, buf) in paint.pixels_raw().iter().zip(buf.iter_mut()) {
-            *buf = pixel.packed();
+            *buf = PremultipliedARGB::from_straight(*pixel).packed();
         }
 
         for yoff in 0..8 {
             let row = &mut self.buffer.memory[(yoff + y_start) * 512..];
             let segment = &mut row[x_start..][..8];
-            unsafe {
-                porter_duff_inplace_src(segment, &buf[yoff * 128..][..8], PorterDuffMode::Over)
-            }.unwrap();
+            porter_duff_premultiplied_inplace_src(segment, &buf[yoff * 128..][..8], PorterDuffMode::SrcOver)
+                .unwrap();
         }
     }
 }
@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use snek_engine::{Direction, GameState, PlayerId};
+use snek_engine::ai::{self, Controller};
+
+/// Never turns.
+pub struct Straight;
+
+impl Controller for Straight {
+    fn name(&self) -> &'static str {
+        "straight"
+    }
+
+    fn choose(&mut self, _: &GameState, _: PlayerId) -> Option<Direction> {
+        None
+    }
+}
+
+/// Turns at fixed ticks, read from a file of `TICK DIRECTION` lines.
+pub struct Script {
+    turns: Vec<(u64, Direction)>,
+    tick: u64,
+}
+
+impl Controller for Script {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn choose(&mut self, _: &GameState, _: PlayerId) -> Option<Direction> {
+        let tick = self.tick;
+        self.tick += 1;
+        self.turns.iter().find(|&&(at, _)| at == tick).map(|&(_, dir)| dir)
+    }
+}
+
+/// `straight`, `script:PATH` or the name of one of the engine's AIs.
+pub fn parse(name: &str) -> Result<Box<dyn Controller>, String> {
+    if name == "straight" {
+        return Ok(Box::new(Straight));
+    }
+    if let Some(path) = name.strip_prefix("script:") {
+        let turns = load_script(path)?;
+        return Ok(Box::new(Script { turns, tick: 0 }));
+    }
+    ai::by_name(name).ok_or_else(|| {
+        format!("unknown controller {} (try straight, script:PATH, {})", name, ai::NAMES.join(", "))
+    })
+}
+
+fn load_script(path: &str) -> Result<Vec<(u64, Direction)>, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    read_script(path, BufReader::new(file))
+}
+
+/// The turns in a script, with `path` to say where any error is.
+fn read_script<R: BufRead>(path: &str, rd: R) -> Result<Vec<(u64, Direction)>, String> {
+    let mut turns = Vec::new();
+
+    for (idx, line) in rd.lines().enumerate() {
+        let line = line.map_err(|err| format!("{}: {}", path, err))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let bad = || format!("{}:{}: expected a tick and a direction", path, idx + 1);
+        let mut words = line.split_whitespace();
+        let tick = words.next().and_then(|w| w.parse().ok()).ok_or_else(bad)?;
+        let direction = match words.next() {
+            Some("north") => Direction::North,
+            Some("south") => Direction::South,
+            Some("west") => Direction::West,
+            Some("east") => Direction::East,
+            _ => return Err(bad()),
+        };
+        if words.next().is_some() {
+            return Err(bad());
+        }
+        turns.push((tick, direction));
+    }
+    Ok(turns)
+}
+
+#[cfg(test)]
+mod tests {
+    use snek_engine::Direction;
+
+    use super::{load_script, read_script};
+
+    #[test]
+    fn scripts_parse() {
+        let script = "# a square\n0 north\n\n  3 west  \n6 south\n";
+        let turns = read_script("square", script.as_bytes()).unwrap();
+        assert_eq!(turns, vec![(0, Direction::North), (3, Direction::West), (6, Direction::South)]);
+
+        let err = read_script("bad", "0 north\n1 up\n".as_bytes()).unwrap_err();
+        assert_eq!(err, "bad:2: expected a tick and a direction");
+        assert!(read_script("bad", "north 0\n".as_bytes()).is_err());
+        assert!(read_script("bad", "0 north east\n".as_bytes()).is_err());
+
+        assert!(load_script("/nonexistent/script").unwrap_err().starts_with("/nonexistent/script: "));
+    }
+}
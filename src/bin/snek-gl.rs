@@ -0,0 +1,565 @@
+//! snek in a Piston window, drawn with OpenGL.
+//!
+//! ```text
+//! snek-gl [--two-player] [--demo[=CONTROLLER]] [--difficulty NAME]
+//!         [--input-depth N] [--opengl 2.1|3.2] [--seed N]
+//!         [--screenshot PATH [--ticks N]]
+//! ```
+//!
+//! The arrows steer, and WASD steers the second player.  Space pauses, G
+//! and H turn forced growth on and off, tab hands the first snake to the
+//! computer and back, and escape quits.  Turns pressed faster than the
+//! snake moves are made one a tick, up to `--input-depth` of them (3).
+//! The window can be resized; the arena keeps square cells and stays in
+//! the middle.
+//!
+//! `--difficulty` picks the speed, and for two players the arena and how
+//! long the snakes start; the levels bring their own.
+//!
+//! `--screenshot` plays the first game for `--ticks` ticks (50) without
+//! waiting for real time, saves the window as a PPM image and exits.  With
+//! a fixed `--seed` and `--demo` the picture is the same every time, which
+//! is what the screenshot tests rely on.  On a box without a GPU run it
+//! under Xvfb with `LIBGL_ALWAYS_SOFTWARE=1` and `--opengl 2.1` or 3.2,
+//! whichever the installed llvmpipe offers.
+
+extern crate gl;
+extern crate glutin_window;
+extern crate graphics;
+extern crate opengl_graphics;
+extern crate piston;
+extern crate snek_engine;
+
+use std::cmp;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use glutin_window::GlutinWindow;
+use opengl_graphics::{GlGraphics, OpenGL};
+use piston::event_loop::*;
+use piston::input::*;
+use piston::window::WindowSettings;
+
+use snek_engine::{DeathCause, GameObject, GameOver, GameRng, GameState, PlayerId};
+use snek_engine::Event as GameEvent;
+use snek_engine::ai::{self, Pilot};
+use snek_engine::input::{self as turns, InputQueue};
+use snek_engine::level::Campaign;
+use snek_engine::rules::{self, Difficulty, Rules};
+use snek_engine::score::{self, GameStats, ScoreRules, Scorer};
+
+/// How often the clock is checked for a tick being due.  The game decides
+/// how far apart ticks are.
+const UPDATES_PER_SECOND: u64 = 120;
+const FRAMES_PER_SECOND: u64 = 30;
+/// How many ticks the end of a game stays on screen.
+const GAME_OVER_TICKS: u32 = 20;
+
+type Color = [f32; 4];
+
+const BACKGROUND: Color = [0.02, 0.02, 0.05, 1.0];
+const FLOOR: Color = [0.06, 0.06, 0.12, 1.0];
+const WALL: Color = [0.5, 0.5, 0.5, 1.0];
+const FOOD: Color = [0.3, 0.8, 0.3, 1.0];
+const BONUS_FOOD: Color = [1.0, 0.8, 0.0, 1.0];
+const SHRINK: Color = [0.8, 0.3, 0.8, 1.0];
+const FAST: Color = [1.0, 0.5, 0.0, 1.0];
+const SLOW: Color = [0.0, 0.5, 1.0, 1.0];
+const GHOST: Color = [0.7, 0.7, 0.85, 1.0];
+const PORTAL: Color = [0.0, 0.85, 0.85, 1.0];
+const OBSTACLE: Color = [0.63, 0.38, 0.19, 1.0];
+const DEATH: Color = [1.0, 0.0, 0.0, 1.0];
+const PAUSED: Color = [0.0, 0.0, 0.0, 0.6];
+/// Colours for each player's snake, by player number.
+const PLAYER_COLORS: &[Color] = &[[1.0, 1.0, 1.0, 1.0], [0.25, 0.75, 1.0, 1.0]];
+
+struct Options {
+    two_player: bool,
+    demo: Option<String>,
+    rules: Rules,
+    input_depth: usize,
+    opengl: OpenGL,
+    seed: Option<u64>,
+    screenshot: Option<String>,
+    ticks: u64,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        two_player: false,
+        demo: None,
+        rules: Rules::default(),
+        input_depth: turns::DEFAULT_DEPTH,
+        opengl: OpenGL::V3_2,
+        seed: None,
+        screenshot: None,
+        ticks: 50,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--two-player" {
+            options.two_player = true;
+            continue;
+        }
+        // `--demo[=CONTROLLER]` lets the computer play until someone
+        // takes over.
+        if arg == "--demo" || arg.starts_with("--demo=") {
+            let name = arg["--demo".len()..].trim_start_matches('=');
+            let name = if name.is_empty() { "astar" } else { name };
+            if ai::by_name(name).is_none() {
+                return Err(format!("unknown controller {}, try one of: {}", name, ai::NAMES.join(", ")));
+            }
+            options.demo = Some(name.to_string());
+            continue;
+        }
+
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        let bad = || format!("bad value for {}: {}", arg, value);
+        match &arg[..] {
+            "--difficulty" => {
+                let difficulty = Difficulty::by_name(&value).ok_or_else(|| {
+                    format!("unknown difficulty {}, try one of: {}", value, rules::NAMES.join(", "))
+                })?;
+                options.rules = difficulty.rules();
+            },
+            "--input-depth" => {
+                options.input_depth = value.parse().map_err(|_| bad())?;
+                if options.input_depth == 0 {
+                    return Err(bad());
+                }
+            },
+            "--opengl" => {
+                options.opengl = match &value[..] {
+                    "2.1" => OpenGL::V2_1,
+                    "3.2" => OpenGL::V3_2,
+                    _ => return Err(bad()),
+                };
+            },
+            "--seed" => options.seed = Some(value.parse().map_err(|_| bad())?),
+            "--screenshot" => options.screenshot = Some(value.clone()),
+            "--ticks" => options.ticks = value.parse().map_err(|_| bad())?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+enum Mode {
+    Campaign(Campaign),
+    Versus,
+}
+
+struct App {
+    mode: Mode,
+    rules: Rules,
+    seed: Option<u64>,
+    game_state: GameState,
+    goal: Option<u64>,
+    name: String,
+    scorers: Vec<Scorer>,
+    pilots: Vec<Pilot>,
+    queues: Vec<InputQueue>,
+    input_depth: usize,
+    /// Keys other than turns pressed since the last tick.
+    keys: Vec<Key>,
+    paused: bool,
+    deaths: Vec<(PlayerId, DeathCause)>,
+    /// Ticks left showing how the game ended, and whether it ended by
+    /// reaching the goal.
+    game_over: Option<(u32, bool)>,
+    // high scores are for people.
+    assisted: bool,
+    ticks: u64,
+    /// Seconds since the last tick.
+    clock: f64,
+}
+
+impl App {
+    fn new(options: &Options) -> App {
+        let players = if options.two_player { 2 } else { 1 };
+        let pilot_name = options.demo.as_ref().map_or("astar", |name| &name[..]);
+        let pilots = (0..players)
+            .map(|_| Pilot::new(ai::by_name(pilot_name).unwrap(), options.demo.is_some()))
+            .collect();
+
+        let mode = if options.two_player { Mode::Versus } else { Mode::Campaign(Campaign::builtin()) };
+        let mut app = App {
+            mode,
+            rules: options.rules,
+            seed: options.seed,
+            game_state: GameState::new(1, 1),
+            goal: None,
+            name: String::new(),
+            scorers: Vec::new(),
+            pilots,
+            queues: Vec::new(),
+            input_depth: options.input_depth,
+            keys: Vec::new(),
+            paused: false,
+            deaths: Vec::new(),
+            game_over: None,
+            assisted: false,
+            ticks: 0,
+            clock: 0.0,
+        };
+        app.new_game();
+        app
+    }
+
+    fn new_game(&mut self) {
+        // a fixed seed is only for the first game.
+        let seed = self.seed.take().unwrap_or_else(|| GameRng::from_entropy().seed());
+        match self.mode {
+            Mode::Campaign(ref campaign) => {
+                let level = campaign.current();
+                self.game_state = level.new_game(seed);
+                self.game_state.set_speed(self.rules.speed);
+                self.goal = level.goal();
+                self.name = level.name().to_string();
+            },
+            Mode::Versus => {
+                self.game_state = GameState::with_rules(&self.rules, seed, 2);
+                self.goal = None;
+                self.name = "Versus".to_string();
+            },
+        }
+
+        self.scorers = (0..self.game_state.player_count())
+            .map(|idx| Scorer::for_player(ScoreRules::default(), &self.game_state, PlayerId(idx)))
+            .collect();
+        self.queues = (0..self.game_state.player_count())
+            .map(|_| InputQueue::with_depth(self.input_depth))
+            .collect();
+        self.deaths.clear();
+        self.game_over = None;
+        self.assisted = false;
+        self.ticks = 0;
+    }
+
+    /// Let `dt` seconds pass, ticking whenever the game says one is due.
+    fn update(&mut self, dt: f64) {
+        self.clock += dt;
+        loop {
+            let interval = self.game_state.tick_interval().as_secs_f64();
+            if self.clock < interval {
+                break;
+            }
+            self.clock -= interval;
+            self.tick();
+        }
+    }
+
+    /// Turns are queued as soon as they're pressed, and everything else
+    /// waits for the tick.
+    fn press(&mut self, key: Key) {
+        let dir = match direction(key) {
+            Some(dir) => dir,
+            None => {
+                if priority(key).is_some() {
+                    self.keys.push(key);
+                }
+                return;
+            },
+        };
+
+        let player = match key {
+            Key::W | Key::A | Key::S | Key::D => turns::wasd_player(self.game_state.player_count()),
+            _ => PlayerId(0),
+        };
+        self.pilots[player.0].flying = false;
+        self.queues[player.0].push(&self.game_state, player, dir);
+    }
+
+    fn tick(&mut self) {
+        let key = command(&self.keys);
+        self.keys.clear();
+
+        let players = self.game_state.player_count();
+        match key {
+            Some(Key::Space) => self.paused = !self.paused,
+            Some(Key::Tab) => {
+                self.pilots[0].flying = !self.pilots[0].flying;
+                self.queues[0].clear();
+                println!("Autopilot {}", if self.pilots[0].flying { "on" } else { "off" });
+            },
+            Some(Key::G) => self.game_state.set_force_grow(true),
+            Some(Key::H) => self.game_state.set_force_grow(false),
+            _ => (),
+        }
+
+        if let Some((left, completed)) = self.game_over {
+            if left == 0 {
+                if let Mode::Campaign(ref mut campaign) = self.mode {
+                    if completed {
+                        match campaign.advance() {
+                            Some(level) => println!("Next level: {}", level.name()),
+                            None => {
+                                println!("You finished every level!");
+                                campaign.restart();
+                            },
+                        }
+                    }
+                }
+                self.new_game();
+            } else {
+                self.game_over = Some((left - 1, completed));
+            }
+            return;
+        }
+        if self.paused {
+            return;
+        }
+
+        for (idx, queue) in self.queues.iter_mut().enumerate() {
+            queue.steer(&mut self.game_state, PlayerId(idx));
+        }
+        for (idx, pilot) in self.pilots.iter_mut().enumerate() {
+            pilot.steer(&mut self.game_state, PlayerId(idx));
+        }
+        self.assisted |= self.pilots[0].flying;
+        self.ticks += 1;
+
+        match self.game_state.tick() {
+            Ok(events) => {
+                for scorer in self.scorers.iter_mut() {
+                    if self.game_state.is_alive(scorer.player()) {
+                        scorer.record_tick(&events);
+                    }
+                }
+                for event in events {
+                    if let GameEvent::Died(player, cause) = event {
+                        println!("Player {} {}", player.0 + 1, cause);
+                        self.deaths.push((player, cause));
+                    }
+                }
+                if let Some(goal) = self.goal {
+                    let stats = self.scorers[0].stats();
+                    if players == 1 && goal <= stats.food_eaten {
+                        println!("Completed {} (score {})", self.name, stats.score);
+                        self.game_over = Some((GAME_OVER_TICKS, true));
+                    }
+                }
+            },
+            Err(result) => {
+                for scorer in self.scorers.iter() {
+                    let player = scorer.player();
+                    let reported = self.deaths.iter().any(|&(dead, _)| dead == player);
+                    if let (Some(cause), false) = (self.game_state.death(player), reported) {
+                        println!("Player {} {}", player.0 + 1, cause);
+                        self.deaths.push((player, cause));
+                    }
+                }
+                match result {
+                    GameOver::Died(_) => {
+                        let stats = self.scorers[0].stats();
+                        println!("Game Over (score {})", stats.score);
+                        if !self.assisted {
+                            announce_high_score(stats);
+                        }
+                    },
+                    GameOver::BoardFull if self.scorers.len() == 1 => {
+                        let stats = self.scorers[0].stats();
+                        println!("The board is full, you win! (score {})", stats.score);
+                        if !self.assisted {
+                            announce_high_score(stats);
+                        }
+                    },
+                    GameOver::Winner(PlayerId(idx)) => println!("Player {} wins!", idx + 1),
+                    GameOver::Draw | GameOver::Eliminated | GameOver::BoardFull => println!("A draw!"),
+                }
+                self.game_over = Some((GAME_OVER_TICKS, false));
+            },
+        }
+    }
+
+    fn render(&self, args: &RenderArgs, gl: &mut GlGraphics) {
+        use graphics::*;
+
+        let (width, height) = self.game_state.arena_size();
+        // the biggest square cells which fit, with the arena centred.
+        let cell = f64::min(args.width as f64 / width as f64, args.height as f64 / height as f64).floor().max(1.0);
+        let x_off = ((args.width as f64 - cell * width as f64) / 2.0).floor();
+        let y_off = ((args.height as f64 - cell * height as f64) / 2.0).floor();
+        let square = |(x, y): (usize, usize)| [x_off + x as f64 * cell, y_off + y as f64 * cell, cell, cell];
+
+        let game_state = &self.game_state;
+        let deaths = &self.deaths;
+        let game_over = self.game_over.is_some();
+        let paused = self.paused;
+
+        gl.draw(args.viewport(), |c, gl| {
+            clear(BACKGROUND, gl);
+            rectangle(FLOOR, [x_off, y_off, cell * width as f64, cell * height as f64], c.transform, gl);
+
+            for (pos, obj, lifetime) in game_state.object_iter() {
+                let mut color = match *obj {
+                    GameObject::Food => FOOD,
+                    GameObject::Wall => WALL,
+                    GameObject::BonusFood => BONUS_FOOD,
+                    GameObject::Shrink => SHRINK,
+                    GameObject::Fast => FAST,
+                    GameObject::Slow => SLOW,
+                    GameObject::Ghost => GHOST,
+                    GameObject::Portal(_) => PORTAL,
+                    GameObject::Obstacle(_) => OBSTACLE,
+                };
+                // fade out through the last few ticks.
+                if let Some(ticks) = lifetime.filter(|&ticks| ticks < 10) {
+                    color[3] = (ticks + 1) as f32 / 10.0;
+                }
+                rectangle(color, square(pos), c.transform, gl);
+            }
+
+            for idx in 0..game_state.player_count() {
+                let player = PlayerId(idx);
+                // dead snakes have left the board, until the game's over
+                if !game_state.is_alive(player) && !game_over {
+                    continue;
+                }
+                let mut color = PLAYER_COLORS[idx % PLAYER_COLORS.len()];
+                // ghosts are see-through.
+                if 0 < game_state.ghost_ticks(player) {
+                    color[3] = 0.5;
+                }
+                for (x, y) in game_state.snake(player).unwrap().positions() {
+                    if x < width && y < height {
+                        rectangle(color, square((x, y)), c.transform, gl);
+                    }
+                }
+            }
+
+            // positions just outside the arena are pulled back onto its edge.
+            for &(_, cause) in deaths.iter() {
+                let (x, y) = cause.position();
+                rectangle(DEATH, square((cmp::min(x, width - 1), cmp::min(y, height - 1))), c.transform, gl);
+            }
+
+            if paused {
+                rectangle(PAUSED, [0.0, 0.0, args.width as f64, args.height as f64], c.transform, gl);
+            }
+        });
+    }
+}
+
+/// Which command wins when several are pressed in one tick, as in the
+/// wayland frontend, or `None` for keys which aren't commands.
+fn priority(key: Key) -> Option<u16> {
+    match key {
+        Key::G | Key::H => Some(0),
+        Key::Space | Key::Tab => Some(2),
+        _ => None,
+    }
+}
+
+fn direction(key: Key) -> Option<snek_engine::Direction> {
+    use snek_engine::Direction;
+
+    match key {
+        Key::Up | Key::W => Some(Direction::North),
+        Key::Down | Key::S => Some(Direction::South),
+        Key::Left | Key::A => Some(Direction::West),
+        Key::Right | Key::D => Some(Direction::East),
+        _ => None,
+    }
+}
+
+/// The command that wins out of those pressed in a tick.
+fn command(keys: &[Key]) -> Option<Key> {
+    let mut out: Option<Key> = None;
+    for &key in keys.iter() {
+        if out.is_none_or(|prev| priority(prev) <= priority(key)) {
+            out = Some(key);
+        }
+    }
+    out
+}
+
+fn announce_high_score(stats: &GameStats) {
+    match score::record_high_score(stats) {
+        Ok(Some(rank)) => println!("New high score! #{}", rank + 1),
+        Ok(None) => (),
+        Err(err) => println!("unable to keep high scores: {}", err),
+    }
+}
+
+/// Save what's in the framebuffer as a binary PPM.
+fn save_screenshot(path: &str, width: u32, height: u32) -> io::Result<()> {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0u8; width * height * 3];
+    unsafe {
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(0, 0, width as i32, height as i32, gl::RGB, gl::UNSIGNED_BYTE,
+                       pixels.as_mut_ptr() as *mut _);
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    // OpenGL counts rows from the bottom.
+    for row in pixels.chunks(width * 3).rev() {
+        out.write_all(row)?;
+    }
+    out.flush()
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("snek-gl: {}", message);
+            process::exit(2);
+        },
+    };
+
+    let window: GlutinWindow = match WindowSettings::new("snek", [512, 512])
+        .opengl(options.opengl)
+        .exit_on_esc(true)
+        .build()
+    {
+        Ok(window) => window,
+        Err(err) => {
+            eprintln!("snek-gl: unable to open a window: {}", err);
+            process::exit(1);
+        },
+    };
+    let mut gl = GlGraphics::new(options.opengl);
+    let mut app = App::new(&options);
+
+    // taking a screenshot doesn't wait for the clock: every frame is a
+    // tick until there have been enough.
+    let ups = match options.screenshot {
+        Some(_) => FRAMES_PER_SECOND,
+        None => UPDATES_PER_SECOND,
+    };
+
+    for e in window.events().ups(ups).max_fps(FRAMES_PER_SECOND) {
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            app.press(key);
+        }
+
+        if let Some(args) = e.update_args() {
+            if options.screenshot.is_none() {
+                app.update(args.dt);
+            } else if app.ticks < options.ticks && app.game_over.is_none() {
+                app.tick();
+            }
+        }
+
+        if let Some(args) = e.render_args() {
+            app.render(&args, &mut gl);
+
+            if let Some(ref path) = options.screenshot {
+                if options.ticks <= app.ticks || app.game_over.is_some() {
+                    if let Err(err) = save_screenshot(path, args.draw_width, args.draw_height) {
+                        eprintln!("snek-gl: unable to save {}: {}", path, err);
+                        process::exit(1);
+                    }
+                    println!("saved {} after {} ticks", path, app.ticks);
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,294 @@
+//! Headless snek: plays games without a window, for balancing the rules
+//! and benchmarking the engine.
+//!
+//! ```text
+//! snek [--games N] [--ticks N] [--size WxH] [--length N] [--food N]
+//!      [--seed N] [--level N|PATH] [--controller C[,C...]]
+//!      [--format text|json|csv] [--board]
+//! ```
+//!
+//! Game `i` is played with seed `seed + i`, so a run can be repeated
+//! exactly by passing the same seed again.  Giving several controllers
+//! puts a snake for each in the same arena, to see which lasts longest.
+
+extern crate snek_engine;
+
+mod autopilot;
+mod report;
+
+use std::env;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+use snek_engine::{Event, GameOver, GameRng, GameState, PlayerId, MAX_ARENA_CELLS};
+use snek_engine::level::{self, Level};
+use snek_engine::rules::Rules;
+use snek_engine::score::{ScoreRules, Scorer};
+
+use report::{Format, GameResult, Outcome, PlayerResult};
+
+const USAGE: &str = "\
+usage: snek [options]
+
+  --games N           number of games to play (1)
+  --ticks N           give up on a game after this many ticks (10000)
+  --size WxH          arena size, when not playing a level (32x32)
+  --length N          how long snakes start, when not playing a level (1)
+  --food N            pieces of food on the board, when not playing a
+                      level (1)
+  --seed N            seed for the first game (random)
+  --level N|PATH      play built-in level N, or a level file
+  --controller C,...  straight, script:PATH, greedy, astar or hamiltonian,
+                      one snake each (greedy)
+  --format F          text, json or csv (text)
+  --board             show the board as each game ended";
+
+struct Options {
+    games: u64,
+    ticks: u64,
+    /// The arena, starting length and food; the speed doesn't matter
+    /// here.
+    rules: Rules,
+    seed: u64,
+    level: Option<Level>,
+    controllers: Vec<String>,
+    format: Format,
+    board: bool,
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options {
+        games: 1,
+        ticks: 10_000,
+        rules: Rules { width: 32, height: 32, ..Rules::default() },
+        seed: GameRng::from_entropy().seed(),
+        level: None,
+        controllers: vec!["greedy".to_string()],
+        format: Format::Text,
+        board: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--board" {
+            options.board = true;
+            continue;
+        }
+        if arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        let bad = || format!("bad value for {}: {}", arg, value);
+        match &arg[..] {
+            "--games" => {
+                let games = value.parse().ok().filter(|&games| 0 < games);
+                options.games = games.ok_or_else(bad)?;
+            },
+            "--ticks" => options.ticks = value.parse().map_err(|_| bad())?,
+            "--seed" => options.seed = value.parse().map_err(|_| bad())?,
+            "--size" => {
+                let mut parts = value.splitn(2, 'x');
+                let width = parts.next().and_then(|w| w.parse().ok()).filter(|&w| 0 < w);
+                let height = parts.next().and_then(|h| h.parse().ok()).filter(|&h| 0 < h);
+                options.rules.width = width.ok_or_else(bad)?;
+                options.rules.height = height.ok_or_else(bad)?;
+            },
+            "--length" => {
+                let length = value.parse().ok().filter(|&length| 0 < length);
+                options.rules.start_length = length.ok_or_else(bad)?;
+            },
+            "--food" => options.rules.food = value.parse().map_err(|_| bad())?,
+            "--level" => {
+                let level = match value.parse::<usize>() {
+                    Ok(number) => level::builtin().into_iter().nth(number.wrapping_sub(1)).ok_or_else(bad)?,
+                    Err(_) => Level::load(Path::new(&value))
+                        .map_err(|err| format!("{}:{}:{}: {:?}", value, err.line, err.column, err.kind))?,
+                };
+                options.level = Some(level);
+            },
+            "--controller" => {
+                let names: Vec<_> = value.split(',').map(str::to_string).collect();
+                for name in names.iter() {
+                    autopilot::parse(name)?;
+                }
+                options.controllers = names;
+            },
+            "--format" => {
+                options.format = match &value[..] {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "csv" => Format::Csv,
+                    _ => return Err(bad()),
+                };
+            },
+            _ => return Err(format!("unknown option {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    if options.level.is_some() && 1 < options.controllers.len() {
+        return Err("levels are for a single snake".to_string());
+    }
+    if options.level.is_none() {
+        let rules = &options.rules;
+        if rules.width.checked_mul(rules.height).is_none_or(|cells| MAX_ARENA_CELLS < cells) {
+            return Err(format!("a {}x{} arena is too big", rules.width, rules.height));
+        }
+        // every snake starts a quarter of the way in from an edge.
+        let longest = rules.width / 4 + 1;
+        if longest < rules.start_length {
+            return Err(format!("snakes can't start longer than {} in a {}x{} arena",
+                               longest, rules.width, rules.height));
+        }
+    }
+    Ok(options)
+}
+
 fn main() {
-    println!("hello worlD");
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("snek: {}", message);
+            process::exit(2);
+        },
+    };
+
+    let started = Instant::now();
+    let results: Vec<GameResult> = (0..options.games)
+        .map(|game| play(&options, options.seed.wrapping_add(game)))
+        .collect();
+
+    if let Err(err) = report::write(&options.format, &results, started.elapsed(), options.board) {
+        eprintln!("snek: {}", err);
+        process::exit(1);
+    }
+}
+
+fn play(options: &Options, seed: u64) -> GameResult {
+    let players = options.controllers.len();
+    let mut state = match options.level {
+        Some(ref level) => level.new_game(seed),
+        None => GameState::with_rules(&options.rules, seed, players),
+    };
+    let goal = options.level.as_ref().and_then(|level| level.goal());
+    // the names were checked when the options were parsed.
+    let mut controllers: Vec<_> = options.controllers.iter()
+        .map(|name| autopilot::parse(name).unwrap())
+        .collect();
+    let mut scorers: Vec<_> = (0..players)
+        .map(|idx| Scorer::for_player(ScoreRules::default(), &state, PlayerId(idx)))
+        .collect();
+    let mut outcomes: Vec<Option<Outcome>> = (0..players).map(|_| None).collect();
+    // the fatal tick leaves a snake with its head in whatever killed it.
+    let mut lengths: Vec<_> = (0..players)
+        .map(|idx| state.snake(PlayerId(idx)).unwrap().len())
+        .collect();
+
+    let started = Instant::now();
+    let mut ticks = 0;
+    while ticks < options.ticks {
+        for (idx, controller) in controllers.iter_mut().enumerate() {
+            let player = PlayerId(idx);
+            if !state.is_alive(player) {
+                continue;
+            }
+            lengths[idx] = state.snake(player).unwrap().len();
+            if let Some(direction) = controller.choose(&state, player) {
+                state.set_direction(player, direction);
+            }
+        }
+
+        let result = state.tick();
+        ticks += 1;
+        let events = match result {
+            Ok(events) => events,
+            Err(game_over) => {
+                match game_over {
+                    GameOver::Winner(winner) => outcomes[winner.0] = Some(Outcome::Won),
+                    GameOver::BoardFull => {
+                        for (idx, outcome) in outcomes.iter_mut().enumerate() {
+                            if state.is_alive(PlayerId(idx)) {
+                                *outcome = Some(Outcome::Filled);
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+                break;
+            },
+        };
+
+        for event in events.iter() {
+            if let Event::Died(player, cause) = *event {
+                outcomes[player.0] = Some(Outcome::Died(cause));
+            }
+        }
+        for (idx, scorer) in scorers.iter_mut().enumerate() {
+            if outcomes[idx].is_none() {
+                scorer.record_tick(&events);
+            }
+        }
+
+        if let Some(goal) = goal {
+            if goal <= scorers[0].stats().food_eaten {
+                outcomes[0] = Some(Outcome::Completed);
+                break;
+            }
+        }
+    }
+
+    let players = scorers.into_iter()
+        .zip(controllers.iter())
+        .enumerate()
+        .map(|(idx, (scorer, controller))| {
+            let player = PlayerId(idx);
+            let outcome = outcomes[idx].take()
+                .or_else(|| state.death(player).map(Outcome::Died))
+                .unwrap_or(Outcome::OutOfTime);
+            let final_length = match outcome {
+                Outcome::Died(_) => lengths[idx],
+                _ => state.snake(player).unwrap().len(),
+            };
+            PlayerResult {
+                controller: controller.name(),
+                outcome,
+                stats: scorer.into_stats(),
+                final_length,
+            }
+        })
+        .collect();
+
+    GameResult {
+        seed,
+        ticks,
+        players,
+        elapsed: started.elapsed(),
+        board: report::render_board(&state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_args;
+
+    fn parse(args: &[&str]) -> Result<super::Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_are_checked() {
+        let options = parse(&["--games", "3", "--size", "20x10", "--length", "6", "--seed", "7"]).unwrap();
+        assert_eq!((options.games, options.seed), (3, 7));
+        assert_eq!((options.rules.width, options.rules.height, options.rules.start_length), (20, 10, 6));
+
+        assert!(parse(&["--games", "0"]).is_err());
+        assert!(parse(&["--size", "5x5", "--length", "100"]).is_err());
+        assert!(parse(&["--size", "5x5", "--length", "3"]).is_err());
+        assert!(parse(&["--size", "100000x100000"]).is_err());
+        assert!(parse(&["--size", "0x5"]).is_err());
+        assert!(parse(&["--controller", "greedy,astar", "--level", "1"]).is_err());
+        assert!(parse(&["--games"]).is_err());
+    }
 }
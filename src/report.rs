@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use snek_engine::{DeathCause, GameObject, GameState, PlayerId};
+use snek_engine::score::GameStats;
+
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+pub enum Outcome {
+    Died(DeathCause),
+    /// Outlived every other snake.
+    Won,
+    /// Reached the level's goal.
+    Completed,
+    /// Left no room for any more food.
+    Filled,
+    /// Still alive when the tick limit ran out.
+    OutOfTime,
+}
+
+pub struct GameResult {
+    pub seed: u64,
+    pub ticks: u64,
+    pub players: Vec<PlayerResult>,
+    pub elapsed: Duration,
+    /// The board as the game ended, one string per row.
+    pub board: Vec<String>,
+}
+
+pub struct PlayerResult {
+    pub controller: &'static str,
+    pub outcome: Outcome,
+    pub stats: GameStats,
+    pub final_length: usize,
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Died(DeathCause::Wall(_)) => "wall",
+            Outcome::Died(DeathCause::SelfCollision(_)) => "self",
+            Outcome::Died(DeathCause::Snake(..)) => "snake",
+            Outcome::Died(DeathCause::HeadOn(_)) => "head-on",
+            Outcome::Died(DeathCause::Boundary(_)) => "boundary",
+            Outcome::Died(DeathCause::Overflow(_)) => "overflow",
+            Outcome::Won => "won",
+            Outcome::Completed => "completed",
+            Outcome::Filled => "filled",
+            Outcome::OutOfTime => "out-of-time",
+        }
+    }
+}
+
+/// How one seat did over every game.
+struct Summary {
+    controller: &'static str,
+    mean_score: f64,
+    min_score: u64,
+    max_score: u64,
+    mean_ticks: f64,
+    mean_length: f64,
+    max_length: usize,
+    outcomes: BTreeMap<&'static str, usize>,
+}
+
+fn summarize(results: &[GameResult], player: usize) -> Summary {
+    let players: Vec<_> = results.iter().map(|result| &result.players[player]).collect();
+    let mean = |total: f64| if players.is_empty() { 0.0 } else { total / players.len() as f64 };
+
+    let mut outcomes = BTreeMap::new();
+    for result in players.iter() {
+        *outcomes.entry(result.outcome.name()).or_insert(0) += 1;
+    }
+
+    Summary {
+        controller: players.first().map_or("", |result| result.controller),
+        mean_score: mean(players.iter().map(|r| r.stats.score as f64).sum()),
+        min_score: players.iter().map(|r| r.stats.score).min().unwrap_or(0),
+        max_score: players.iter().map(|r| r.stats.score).max().unwrap_or(0),
+        mean_ticks: mean(players.iter().map(|r| r.stats.ticks_survived as f64).sum()),
+        mean_length: mean(players.iter().map(|r| r.final_length as f64).sum()),
+        max_length: players.iter().map(|r| r.stats.max_length).max().unwrap_or(0),
+        outcomes,
+    }
+}
+
+/// Print the results to stdout.  Boards go to stderr for csv, which has
+/// nowhere to put them.
+pub fn write(format: &Format, results: &[GameResult], elapsed: Duration, board: bool) -> io::Result<()> {
+    let players = results.first().map_or(0, |result| result.players.len());
+    let summaries: Vec<_> = (0..players).map(|player| summarize(results, player)).collect();
+
+    let ticks: u64 = results.iter().map(|result| result.ticks).sum();
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    let ticks_per_second = if 0.0 < seconds { ticks as f64 / seconds } else { 0.0 };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match *format {
+        Format::Text => write_text(&mut out, results, &summaries, ticks_per_second, board),
+        Format::Json => write_json(&mut out, results, &summaries, ticks_per_second, board),
+        Format::Csv => {
+            write_csv(&mut out, results, &summaries, ticks_per_second)?;
+            if board {
+                for result in results.iter() {
+                    eprintln!("seed {}:", result.seed);
+                    for row in result.board.iter() {
+                        eprintln!("{}", row);
+                    }
+                }
+            }
+            Ok(())
+        },
+    }
+}
+
+fn write_text<W: Write>(out: &mut W, results: &[GameResult], summaries: &[Summary], ticks_per_second: f64, board: bool) -> io::Result<()> {
+    for (idx, result) in results.iter().enumerate() {
+        let players: Vec<_> = result.players.iter().map(|player| {
+            let outcome = match player.outcome {
+                Outcome::Died(cause) => format!("died ({}) at {:?}", player.outcome.name(), cause.position()),
+                Outcome::Won => "won".to_string(),
+                Outcome::Completed => "completed the level".to_string(),
+                Outcome::Filled => "filled the board".to_string(),
+                Outcome::OutOfTime => "ran out of time".to_string(),
+            };
+            format!("{} score {}, length {}, {}", player.controller, player.stats.score, player.final_length, outcome)
+        }).collect();
+        writeln!(out, "game {}: seed {}, {} ticks: {}", idx + 1, result.seed, result.ticks, players.join("; "))?;
+        if board {
+            for row in result.board.iter() {
+                writeln!(out, "  {}", row)?;
+            }
+        }
+    }
+
+    writeln!(out, "{} games, {:.0} ticks/s", results.len(), ticks_per_second)?;
+    for (idx, summary) in summaries.iter().enumerate() {
+        writeln!(out, "player {} ({}): score {:.1} mean ({}..{}), {:.1} ticks, length {:.1} mean ({} max)",
+                      idx + 1, summary.controller, summary.mean_score, summary.min_score, summary.max_score,
+                      summary.mean_ticks, summary.mean_length, summary.max_length)?;
+        for (outcome, count) in summary.outcomes.iter() {
+            writeln!(out, "  {}: {}", outcome, count)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json<W: Write>(out: &mut W, results: &[GameResult], summaries: &[Summary], ticks_per_second: f64, board: bool) -> io::Result<()> {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"games\": [")?;
+    for (idx, result) in results.iter().enumerate() {
+        let players: Vec<_> = result.players.iter().map(|player| {
+            let mut fields = vec![
+                format!("\"controller\": \"{}\"", player.controller),
+                format!("\"outcome\": \"{}\"", player.outcome.name()),
+                format!("\"ticks\": {}", player.stats.ticks_survived),
+                format!("\"score\": {}", player.stats.score),
+                format!("\"food_eaten\": {}", player.stats.food_eaten),
+                format!("\"max_length\": {}", player.stats.max_length),
+                format!("\"final_length\": {}", player.final_length),
+                format!("\"best_combo\": {}", player.stats.best_combo),
+            ];
+            if let Outcome::Died(cause) = player.outcome {
+                let (x, y) = cause.position();
+                fields.push(format!("\"death_position\": [{}, {}]", x, y));
+            }
+            format!("{{{}}}", fields.join(", "))
+        }).collect();
+
+        let mut fields = vec![
+            format!("\"seed\": {}", result.seed),
+            format!("\"ticks\": {}", result.ticks),
+            format!("\"elapsed_us\": {}", micros(result.elapsed)),
+            format!("\"players\": [{}]", players.join(", ")),
+        ];
+        if board {
+            let rows: Vec<_> = result.board.iter().map(|row| format!("\"{}\"", row)).collect();
+            fields.push(format!("\"board\": [{}]", rows.join(", ")));
+        }
+
+        let comma = if idx + 1 < results.len() { "," } else { "" };
+        writeln!(out, "    {{{}}}{}", fields.join(", "), comma)?;
+    }
+    writeln!(out, "  ],")?;
+
+    let players: Vec<_> = summaries.iter().map(|summary| {
+        let outcomes: Vec<_> = summary.outcomes.iter()
+            .map(|(outcome, count)| format!("\"{}\": {}", outcome, count))
+            .collect();
+        format!("{{\"controller\": \"{}\", \"mean_score\": {:.3}, \"min_score\": {}, \"max_score\": {}, \
+                 \"mean_ticks\": {:.3}, \"mean_length\": {:.3}, \"max_length\": {}, \"outcomes\": {{{}}}}}",
+                summary.controller, summary.mean_score, summary.min_score, summary.max_score,
+                summary.mean_ticks, summary.mean_length, summary.max_length, outcomes.join(", "))
+    }).collect();
+    writeln!(out, "  \"summary\": {{\"games\": {}, \"ticks_per_second\": {:.1}, \"players\": [{}]}}",
+                  results.len(), ticks_per_second, players.join(", "))?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// One row per player per game, then a blank line and a row of totals
+/// for each player.
+fn write_csv<W: Write>(out: &mut W, results: &[GameResult], summaries: &[Summary], ticks_per_second: f64) -> io::Result<()> {
+    writeln!(out, "seed,player,controller,outcome,ticks,score,food_eaten,max_length,final_length,best_combo,elapsed_us")?;
+    for result in results.iter() {
+        for (idx, player) in result.players.iter().enumerate() {
+            writeln!(out, "{},{},{},{},{},{},{},{},{},{},{}",
+                          result.seed, idx + 1, player.controller, player.outcome.name(), player.stats.ticks_survived,
+                          player.stats.score, player.stats.food_eaten, player.stats.max_length, player.final_length,
+                          player.stats.best_combo, micros(result.elapsed))?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "player,controller,games,wins,mean_score,min_score,max_score,mean_ticks,mean_length,max_length,\
+                   ticks_per_second")?;
+    for (idx, summary) in summaries.iter().enumerate() {
+        writeln!(out, "{},{},{},{},{:.3},{},{},{:.3},{:.3},{},{:.1}",
+                      idx + 1, summary.controller, results.len(), summary.outcomes.get("won").unwrap_or(&0),
+                      summary.mean_score, summary.min_score, summary.max_score, summary.mean_ticks,
+                      summary.mean_length, summary.max_length, ticks_per_second)?;
+    }
+    Ok(())
+}
+
+/// The board as ASCII: `#` wall, `*` food and `.` empty floor, with `$`
+/// bonus food, `-` a shrink pill, `+` fast, `~` slow, `?` ghost, `O` a
+/// portal and `%` an obstacle.  A lone snake is drawn with `@` for its
+/// head and `o` for the rest; with more than one, the first is `A` and
+/// `a`, the second `B` and `b`, and so on.
+pub fn render_board(state: &GameState) -> Vec<String> {
+    let (width, height) = state.arena_size();
+    let mut cells = vec![vec!['.'; width]; height];
+
+    for ((x, y), obj, _) in state.object_iter() {
+        cells[y][x] = match *obj {
+            GameObject::Food => '*',
+            GameObject::Wall => '#',
+            GameObject::BonusFood => '$',
+            GameObject::Shrink => '-',
+            GameObject::Fast => '+',
+            GameObject::Slow => '~',
+            GameObject::Ghost => '?',
+            GameObject::Portal(_) => 'O',
+            GameObject::Obstacle(_) => '%',
+        };
+    }
+
+    let players = state.player_count();
+    for player in 0..players {
+        let (head, body) = if players == 1 {
+            ('@', 'o')
+        } else {
+            let letter = (b'a' + (player % 26) as u8) as char;
+            (letter.to_ascii_uppercase(), letter)
+        };
+
+        // drawn tail first so the head shows where it ran into the body.
+        // A snake which died leaving the arena has its head outside it.
+        let positions: Vec<_> = state.snake(PlayerId(player)).unwrap().positions().collect();
+        for (idx, &(x, y)) in positions.iter().enumerate().rev() {
+            if x < width && y < height {
+                cells[y][x] = if idx == 0 { head } else { body };
+            }
+        }
+    }
+
+    cells.into_iter().map(|row| row.into_iter().collect()).collect()
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use snek_engine::{DeathCause, Direction, GameObject, GameState, Topology};
+    use snek_engine::score::GameStats;
+
+    use super::{render_board, summarize, write_csv, write_json, GameResult, Outcome, PlayerResult};
+
+    fn result(seed: u64, outcome: Outcome, score: u64) -> GameResult {
+        GameResult {
+            seed,
+            ticks: 12,
+            players: vec![PlayerResult {
+                controller: "greedy",
+                outcome,
+                stats: GameStats {
+                    score,
+                    ticks_survived: 12,
+                    food_eaten: 2,
+                    max_length: 3,
+                    best_combo: 1,
+                    length_history: Vec::new(),
+                },
+                final_length: 3,
+            }],
+            elapsed: Duration::from_micros(250),
+            board: vec!["@*".to_string(), "..".to_string()],
+        }
+    }
+
+    fn results() -> Vec<GameResult> {
+        vec![result(1, Outcome::Died(DeathCause::Wall((4, 5))), 20), result(2, Outcome::Won, 40)]
+    }
+
+    #[test]
+    fn boards_are_drawn() {
+        let mut state = GameState::with_seed(6, 3, 1);
+        state.place_object((0, 0), GameObject::Wall, None);
+        state.place_object((5, 2), GameObject::Food, None);
+        state.place_object((4, 0), GameObject::Obstacle(Direction::North), None);
+        assert_eq!(render_board(&state), vec!["#...%.", ".@....", ".....*"]);
+
+        let state = GameState::with_players(8, 3, 1, Topology::Bounded, 2);
+        assert_eq!(render_board(&state), vec!["........", "..A.....", ".....B.."]);
+    }
+
+    #[test]
+    fn json_report() {
+        let results = results();
+        let summaries = vec![summarize(&results, 0)];
+        let mut out = Vec::new();
+        write_json(&mut out, &results, &summaries, 1000.0, true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.lines().collect::<Vec<_>>(), vec![
+            "{",
+            "  \"games\": [",
+            "    {\"seed\": 1, \"ticks\": 12, \"elapsed_us\": 250, \"players\": [{\"controller\": \"greedy\", \
+             \"outcome\": \"wall\", \"ticks\": 12, \"score\": 20, \"food_eaten\": 2, \"max_length\": 3, \
+             \"final_length\": 3, \"best_combo\": 1, \"death_position\": [4, 5]}], \"board\": [\"@*\", \"..\"]},",
+            "    {\"seed\": 2, \"ticks\": 12, \"elapsed_us\": 250, \"players\": [{\"controller\": \"greedy\", \
+             \"outcome\": \"won\", \"ticks\": 12, \"score\": 40, \"food_eaten\": 2, \"max_length\": 3, \
+             \"final_length\": 3, \"best_combo\": 1}], \"board\": [\"@*\", \"..\"]}",
+            "  ],",
+            "  \"summary\": {\"games\": 2, \"ticks_per_second\": 1000.0, \"players\": [{\"controller\": \"greedy\", \
+             \"mean_score\": 30.000, \"min_score\": 20, \"max_score\": 40, \"mean_ticks\": 12.000, \
+             \"mean_length\": 3.000, \"max_length\": 3, \"outcomes\": {\"wall\": 1, \"won\": 1}}]}",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn csv_report() {
+        let results = results();
+        let summaries = vec![summarize(&results, 0)];
+        let mut out = Vec::new();
+        write_csv(&mut out, &results, &summaries, 1000.0).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.lines().collect::<Vec<_>>(), vec![
+            "seed,player,controller,outcome,ticks,score,food_eaten,max_length,final_length,best_combo,elapsed_us",
+            "1,1,greedy,wall,12,20,2,3,3,1,250",
+            "2,1,greedy,won,12,40,2,3,3,1,250",
+            "",
+            "player,controller,games,wins,mean_score,min_score,max_score,mean_ticks,mean_length,max_length,\
+             ticks_per_second",
+            "1,greedy,2,1,30.000,20,40,12.000,3.000,3,1000.0",
+        ]);
+    }
+}
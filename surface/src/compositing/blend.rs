@@ -0,0 +1,179 @@
+use super::super::colorspace::ColorARGB;
+use super::quantize;
+
+/// The W3C/PDF blend modes, which decide the colour where source and
+/// destination overlap before a Porter-Duff operator composites them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The source colour, unchanged.
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    /// The source's hue, with the destination's saturation and luminosity.
+    Hue,
+    /// The source's saturation, with the destination's hue and luminosity.
+    Saturation,
+    /// The source's hue and saturation, with the destination's luminosity.
+    Color,
+    /// The source's luminosity, with the destination's hue and saturation.
+    Luminosity,
+}
+
+type Rgb = (f64, f64, f64);
+
+impl BlendMode {
+    /// The source with its colour blended against the destination, in
+    /// proportion to how opaque the destination is.  Alpha is untouched;
+    /// compositing the result decides what covers what.
+    pub fn blend_f64(&self, src: ColorARGB<f64>, dst: ColorARGB<f64>) -> ColorARGB<f64> {
+        if *self == BlendMode::Normal {
+            return src;
+        }
+
+        let (r, g, b) = self.mix((dst.r, dst.g, dst.b), (src.r, src.g, src.b));
+        let mix = |cs: f64, blended: f64| (1.0 - dst.a) * cs + dst.a * blended;
+
+        ColorARGB {
+            a: src.a,
+            r: mix(src.r, r),
+            g: mix(src.g, g),
+            b: mix(src.b, b),
+        }
+    }
+
+    pub fn blend(&self, src: ColorARGB<u8>, dst: ColorARGB<u8>) -> ColorARGB<u8> {
+        if *self == BlendMode::Normal {
+            return src;
+        }
+        let out = self.blend_f64(src.channel_f64(), dst.channel_f64());
+        ColorARGB::new_argb(src.a, quantize(out.r), quantize(out.g), quantize(out.b))
+    }
+
+    /// The blend function B(Cb, Cs) on unpremultiplied colours, with `cb`
+    /// the destination (backdrop) and `cs` the source.
+    fn mix(&self, cb: Rgb, cs: Rgb) -> Rgb {
+        let separable: fn(f64, f64) -> f64 = match *self {
+            BlendMode::Normal => |_, cs| cs,
+            BlendMode::Multiply => multiply,
+            BlendMode::Screen => screen,
+            BlendMode::Overlay => |cb, cs| hard_light(cs, cb),
+            BlendMode::Darken => f64::min,
+            BlendMode::Lighten => f64::max,
+            BlendMode::ColorDodge => color_dodge,
+            BlendMode::ColorBurn => color_burn,
+            BlendMode::HardLight => hard_light,
+            BlendMode::SoftLight => soft_light,
+            BlendMode::Difference => |cb, cs| (cb - cs).abs(),
+            BlendMode::Exclusion => |cb, cs| cb + cs - 2.0 * cb * cs,
+            BlendMode::Hue => return set_lum(set_sat(cs, sat(cb)), lum(cb)),
+            BlendMode::Saturation => return set_lum(set_sat(cb, sat(cs)), lum(cb)),
+            BlendMode::Color => return set_lum(cs, lum(cb)),
+            BlendMode::Luminosity => return set_lum(cb, lum(cs)),
+        };
+        (separable(cb.0, cs.0), separable(cb.1, cs.1), separable(cb.2, cs.2))
+    }
+}
+
+fn multiply(cb: f64, cs: f64) -> f64 {
+    cb * cs
+}
+
+fn screen(cb: f64, cs: f64) -> f64 {
+    cb + cs - cb * cs
+}
+
+fn hard_light(cb: f64, cs: f64) -> f64 {
+    if cs <= 0.5 {
+        multiply(cb, 2.0 * cs)
+    } else {
+        screen(cb, 2.0 * cs - 1.0)
+    }
+}
+
+fn soft_light(cb: f64, cs: f64) -> f64 {
+    if cs <= 0.5 {
+        return cb - (1.0 - 2.0 * cs) * cb * (1.0 - cb);
+    }
+    let d = if cb <= 0.25 {
+        ((16.0 * cb - 12.0) * cb + 4.0) * cb
+    } else {
+        cb.sqrt()
+    };
+    cb + (2.0 * cs - 1.0) * (d - cb)
+}
+
+fn color_dodge(cb: f64, cs: f64) -> f64 {
+    if cb == 0.0 {
+        0.0
+    } else if cs == 1.0 {
+        1.0
+    } else {
+        (cb / (1.0 - cs)).min(1.0)
+    }
+}
+
+fn color_burn(cb: f64, cs: f64) -> f64 {
+    if cb == 1.0 {
+        1.0
+    } else if cs == 0.0 {
+        0.0
+    } else {
+        1.0 - ((1.0 - cb) / cs).min(1.0)
+    }
+}
+
+fn lum((r, g, b): Rgb) -> f64 {
+    0.3 * r + 0.59 * g + 0.11 * b
+}
+
+fn sat((r, g, b): Rgb) -> f64 {
+    r.max(g).max(b) - r.min(g).min(b)
+}
+
+/// Pulls a colour back into gamut, keeping its luminosity.
+fn clip_color(c: Rgb) -> Rgb {
+    let l = lum(c);
+    let n = c.0.min(c.1).min(c.2);
+    let x = c.0.max(c.1).max(c.2);
+
+    let mut c = [c.0, c.1, c.2];
+    if n < 0.0 {
+        for v in c.iter_mut() {
+            *v = l + (*v - l) * l / (l - n);
+        }
+    }
+    if x > 1.0 {
+        for v in c.iter_mut() {
+            *v = l + (*v - l) * (1.0 - l) / (x - l);
+        }
+    }
+    (c[0], c[1], c[2])
+}
+
+fn set_lum(c: Rgb, l: f64) -> Rgb {
+    let d = l - lum(c);
+    clip_color((c.0 + d, c.1 + d, c.2 + d))
+}
+
+fn set_sat(c: Rgb, s: f64) -> Rgb {
+    let c = [c.0, c.1, c.2];
+    let mut order = [0, 1, 2];
+    order.sort_by(|&i, &j| c[i].partial_cmp(&c[j]).unwrap());
+    let (min, mid, max) = (order[0], order[1], order[2]);
+
+    let mut out = [0.0; 3];
+    if c[max] > c[min] {
+        out[mid] = (c[mid] - c[min]) * s / (c[max] - c[min]);
+        out[max] = s;
+    }
+    (out[0], out[1], out[2])
+}
@@ -1,4 +1,22 @@
+use std::{error, fmt, mem, slice};
+
 use super::colorspace::ColorARGB;
+use super::Surface;
+
+pub use self::blend::BlendMode;
+pub use self::premultiplied::{
+    PremultipliedARGB,
+    premultiply,
+    unpremultiply,
+    porter_duff_premultiplied,
+    porter_duff_premultiplied_inplace_dst,
+    porter_duff_premultiplied_inplace_src,
+};
+pub use self::simd::Kernel;
+
+mod blend;
+mod premultiplied;
+mod simd;
 
 fn clamp<T: PartialOrd>(value: T, min_value: T, max_value: T) -> T {
     if max_value < value {
@@ -10,17 +28,62 @@ fn clamp<T: PartialOrd>(value: T, min_value: T, max_value: T) -> T {
     value
 }
 
-fn channels_float(arg: ColorARGB<u8>) -> (f64, f64, f64, f64) {
-    (
-        arg.a as f64 / 255.0,
-        arg.r as f64 / 255.0,
-        arg.g as f64 / 255.0,
-        arg.b as f64 / 255.0,
-    )
+/// The Porter-Duff operators, with the source drawn onto the destination.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Clear,
+    Src,
+    Dst,
+    SrcOver,
+    DstOver,
+    SrcIn,
+    DstIn,
+    SrcOut,
+    DstOut,
+    SrcAtop,
+    DstAtop,
+    Xor,
+    /// Adds source and destination together, saturating, for glows.
+    Plus,
 }
 
-pub enum Mode {
-    Over,
+/// A Porter-Duff factor: the fraction of one side an operator keeps,
+/// in terms of the source's and destination's alphas.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Factor {
+    Zero,
+    One,
+    SrcAlpha,
+    DstAlpha,
+    InvSrcAlpha,
+    InvDstAlpha,
+}
+
+impl Factor {
+    #[inline]
+    fn value(&self, aal: f64, bal: f64) -> f64 {
+        match *self {
+            Factor::Zero => 0.0,
+            Factor::One => 1.0,
+            Factor::SrcAlpha => aal,
+            Factor::DstAlpha => bal,
+            Factor::InvSrcAlpha => 1.0 - aal,
+            Factor::InvDstAlpha => 1.0 - bal,
+        }
+    }
+
+    /// The factor as a fraction of 255.
+    #[inline]
+    fn value_u8(&self, aal: u8, bal: u8) -> u8 {
+        match *self {
+            Factor::Zero => 0,
+            Factor::One => 255,
+            Factor::SrcAlpha => aal,
+            Factor::DstAlpha => bal,
+            Factor::InvSrcAlpha => 255 - aal,
+            Factor::InvDstAlpha => 255 - bal,
+        }
+    }
 }
 
 pub type Operation = fn(ColorARGB<u8>, ColorARGB<u8>) -> ColorARGB<u8>;
@@ -28,90 +91,261 @@ pub type Operation = fn(ColorARGB<u8>, ColorARGB<u8>) -> ColorARGB<u8>;
 impl Mode {
     fn operation(&self) -> Operation {
         match *self {
-            Mode::Over => porter_duff_over,
+            Mode::Clear => porter_duff_clear,
+            Mode::Src => porter_duff_src,
+            Mode::Dst => porter_duff_dst,
+            Mode::SrcOver => porter_duff_over,
+            Mode::DstOver => porter_duff_dst_over,
+            Mode::SrcIn => porter_duff_src_in,
+            Mode::DstIn => porter_duff_dst_in,
+            Mode::SrcOut => porter_duff_src_out,
+            Mode::DstOut => porter_duff_dst_out,
+            Mode::SrcAtop => porter_duff_src_atop,
+            Mode::DstAtop => porter_duff_dst_atop,
+            Mode::Xor => porter_duff_xor,
+            Mode::Plus => porter_duff_plus,
+        }
+    }
+
+    /// How much of the source and of the destination the operator keeps.
+    fn factor_kinds(&self) -> (Factor, Factor) {
+        use self::Factor::*;
+        match *self {
+            Mode::Clear => (Zero, Zero),
+            Mode::Src => (One, Zero),
+            Mode::Dst => (Zero, One),
+            Mode::SrcOver => (One, InvSrcAlpha),
+            Mode::DstOver => (InvDstAlpha, One),
+            Mode::SrcIn => (DstAlpha, Zero),
+            Mode::DstIn => (Zero, SrcAlpha),
+            Mode::SrcOut => (InvDstAlpha, Zero),
+            Mode::DstOut => (Zero, InvSrcAlpha),
+            Mode::SrcAtop => (DstAlpha, InvSrcAlpha),
+            Mode::DstAtop => (InvDstAlpha, SrcAlpha),
+            Mode::Xor => (InvDstAlpha, InvSrcAlpha),
+            Mode::Plus => (One, One),
         }
     }
+
+    fn factors(&self, aal: f64, bal: f64) -> (f64, f64) {
+        let (fa, fb) = self.factor_kinds();
+        (fa.value(aal, bal), fb.value(aal, bal))
+    }
+
+    /// Composites `src` onto `dst`, without quantizing.  Sums saturate,
+    /// which only `Plus` can reach.
+    pub fn composite_f64(&self, src: ColorARGB<f64>, dst: ColorARGB<f64>) -> ColorARGB<f64> {
+        let (fa, fb) = self.factors(src.a, dst.a);
+
+        let a = clamp(src.a * fa + dst.a * fb, 0.0, 1.0);
+        if a <= 0.0 {
+            return ColorARGB { a: 0.0, r: 0.0, g: 0.0, b: 0.0 };
+        }
+
+        let channel = |ac: f64, bc: f64| {
+            clamp(ac * src.a * fa + bc * dst.a * fb, 0.0, 1.0) / a
+        };
+
+        ColorARGB {
+            a,
+            r: channel(src.r, dst.r),
+            g: channel(src.g, dst.g),
+            b: channel(src.b, dst.b),
+        }
+    }
+
+    /// Blends `src` against `dst`, then composites the result onto it.
+    pub fn blend_f64(&self, src: ColorARGB<f64>, dst: ColorARGB<f64>, blend: BlendMode) -> ColorARGB<f64> {
+        self.composite_f64(blend.blend_f64(src, dst), dst)
+    }
+
+    pub fn blend(&self, apx: ColorARGB<u8>, bpx: ColorARGB<u8>, blend: BlendMode) -> ColorARGB<u8> {
+        let bpx = bpx.channel_f64();
+        quantize_argb(self.composite_f64(blend.blend_f64(apx.channel_f64(), bpx), bpx))
+    }
+}
+
+fn composite(apx: ColorARGB<u8>, bpx: ColorARGB<u8>, mode: Mode) -> ColorARGB<u8> {
+    quantize_argb(mode.composite_f64(apx.channel_f64(), bpx.channel_f64()))
+}
+
+fn quantize_argb(px: ColorARGB<f64>) -> ColorARGB<u8> {
+    ColorARGB::new_argb(quantize(px.a), quantize(px.r), quantize(px.g), quantize(px.b))
+}
+
+fn quantize(value: f64) -> u8 {
+    clamp((255.0 * value).round(), 0.0, 255.0) as u8
+}
+
+pub fn porter_duff_clear(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::Clear)
+}
+
+pub fn porter_duff_src(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::Src)
+}
+
+pub fn porter_duff_dst(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::Dst)
 }
 
 pub fn porter_duff_over(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
-    let (aal, are, agr, abl) = channels_float(apx);
-    let (bal, bre, bgr, bbl) = channels_float(bpx);
+    composite(apx, bpx, Mode::SrcOver)
+}
 
-    let a = aal + bal * (1.0 - aal);
+pub fn porter_duff_dst_over(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::DstOver)
+}
 
-    let r = (are * aal + bre * bal * (1.0 - aal)) / a;
-    let g = (agr * aal + bgr * bal * (1.0 - aal)) / a;
-    let b = (abl * aal + bbl * bal * (1.0 - aal)) / a;
+pub fn porter_duff_src_in(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::SrcIn)
+}
 
-    assert!(0.0 <= r);
-    assert!(0.0 <= g);
-    assert!(0.0 <= b);
-    assert!(r <= 1.0);
-    assert!(g <= 1.0);
-    assert!(b <= 1.0);
+pub fn porter_duff_dst_in(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::DstIn)
+}
 
-    let a = clamp((255.0 * a) as u32, 0, 255) as u8;
-    let r = clamp((255.0 * r) as u32, 0, 255) as u8;
-    let g = clamp((255.0 * g) as u32, 0, 255) as u8;
-    let b = clamp((255.0 * b) as u32, 0, 255) as u8;
+pub fn porter_duff_src_out(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::SrcOut)
+}
 
-    ColorARGB::new_argb(a, r, g, b)
+pub fn porter_duff_dst_out(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::DstOut)
 }
 
-pub unsafe fn porter_duff(tgt: &mut [u32], src: &[u32], dst: &[u32], mode: Mode) -> Result<(), &'static str> {
-    use std::mem::transmute;
+pub fn porter_duff_src_atop(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::SrcAtop)
+}
 
-    if tgt.len() != src.len() {
-        return Err("tgt/src len mismatch");
+pub fn porter_duff_dst_atop(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::DstAtop)
+}
+
+pub fn porter_duff_xor(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::Xor)
+}
+
+pub fn porter_duff_plus(apx: ColorARGB<u8>, bpx: ColorARGB<u8>) -> ColorARGB<u8> {
+    composite(apx, bpx, Mode::Plus)
+}
+
+/// Why spans couldn't be composited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The source isn't as long as the target.
+    SourceLength { expected: usize, found: usize },
+    /// The destination isn't as long as the target.
+    DestinationLength { expected: usize, found: usize },
+    /// The surfaces aren't the same width and height.
+    SurfaceSize { expected: (usize, usize), found: (usize, usize) },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::SourceLength { expected, found } =>
+                write!(f, "source has {} pixels, but the target has {}", found, expected),
+            Error::DestinationLength { expected, found } =>
+                write!(f, "destination has {} pixels, but the target has {}", found, expected),
+            Error::SurfaceSize { expected, found } =>
+                write!(f, "surface is {}x{}, but the target is {}x{}", found.0, found.1, expected.0, expected.1),
+        }
     }
-    if dst.len() != src.len() {
-        return Err("dst/src len mismatch");
+}
+
+impl error::Error for Error {}
+
+/// A pixel format spans can be composited in: `ColorARGB<u8>`, or `u32`
+/// holding it packed.
+pub trait Pixel: Sized {
+    fn as_argb(span: &[Self]) -> &[ColorARGB<u8>];
+
+    fn as_argb_mut(span: &mut [Self]) -> &mut [ColorARGB<u8>];
+}
+
+impl Pixel for ColorARGB<u8> {
+    fn as_argb(span: &[ColorARGB<u8>]) -> &[ColorARGB<u8>] {
+        span
     }
 
-    let tgt: &mut [ColorARGB<u8>] = transmute(tgt);
-    let src: &[ColorARGB<u8>] = transmute(src);
-    let dst: &[ColorARGB<u8>] = transmute(dst);
+    fn as_argb_mut(span: &mut [ColorARGB<u8>]) -> &mut [ColorARGB<u8>] {
+        span
+    }
+}
 
-    let op_func = mode.operation();
+// `ColorARGB<u8>` lays its bytes out in the order this target stores a
+// packed `u32`, and any four bytes are a pixel, so a span of one is a
+// span of the other once size and alignment agree.
+fn check_packed_layout() {
+    assert_eq!(mem::size_of::<ColorARGB<u8>>(), mem::size_of::<u32>());
+    assert!(mem::align_of::<ColorARGB<u8>>() <= mem::align_of::<u32>());
+}
 
-    for (tpx, (spx, dpx)) in tgt.iter_mut().zip(src.iter().zip(dst.iter())) {
-        *tpx = op_func(*spx, *dpx);
+impl Pixel for u32 {
+    fn as_argb(span: &[u32]) -> &[ColorARGB<u8>] {
+        check_packed_layout();
+        unsafe { slice::from_raw_parts(span.as_ptr() as *const ColorARGB<u8>, span.len()) }
     }
 
-    Ok(())
+    fn as_argb_mut(span: &mut [u32]) -> &mut [ColorARGB<u8>] {
+        check_packed_layout();
+        unsafe { slice::from_raw_parts_mut(span.as_mut_ptr() as *mut ColorARGB<u8>, span.len()) }
+    }
 }
 
-pub unsafe fn porter_duff_inplace_dst(tgt: &mut [u32], dst: &[u32], mode: Mode) -> Result<(), &'static str> {
-    use std::mem::transmute;
+fn check_source(tgt: usize, src: usize) -> Result<(), Error> {
+    if tgt != src {
+        return Err(Error::SourceLength { expected: tgt, found: src });
+    }
+    Ok(())
+}
 
-    if tgt.len() != dst.len() {
-        return Err("tgt/dst len mismatch");
+fn check_destination(tgt: usize, dst: usize) -> Result<(), Error> {
+    if tgt != dst {
+        return Err(Error::DestinationLength { expected: tgt, found: dst });
     }
+    Ok(())
+}
 
-    let tgt: &mut [ColorARGB<u8>] = transmute(tgt);
-    let dst: &[ColorARGB<u8>] = transmute(dst);
+fn apply<T, S, D, F>(tgt: &mut [T], src: &[S], dst: &[D], op_func: F) -> Result<(), Error>
+    where T: Pixel, S: Pixel, D: Pixel, F: Fn(ColorARGB<u8>, ColorARGB<u8>) -> ColorARGB<u8>
+{
+    check_source(tgt.len(), src.len())?;
+    check_destination(tgt.len(), dst.len())?;
 
-    let op_func = mode.operation();
+    let tgt = T::as_argb_mut(tgt);
+    let src = S::as_argb(src);
+    let dst = D::as_argb(dst);
 
-    for (tpx, dpx) in tgt.iter_mut().zip(dst.iter()) {
-        *tpx = op_func(*tpx, *dpx);
+    for (tpx, (spx, dpx)) in tgt.iter_mut().zip(src.iter().zip(dst.iter())) {
+        *tpx = op_func(*spx, *dpx);
     }
 
     Ok(())
 }
 
+fn apply_inplace_dst<T, D, F>(tgt: &mut [T], dst: &[D], op_func: F) -> Result<(), Error>
+    where T: Pixel, D: Pixel, F: Fn(ColorARGB<u8>, ColorARGB<u8>) -> ColorARGB<u8>
+{
+    check_destination(tgt.len(), dst.len())?;
 
-pub unsafe fn porter_duff_inplace_src(tgt: &mut [u32], src: &[u32], mode: Mode) -> Result<(), &'static str> {
-    use std::mem::transmute;
+    let tgt = T::as_argb_mut(tgt);
+    let dst = D::as_argb(dst);
 
-    if tgt.len() != src.len() {
-        return Err("tgt/src len mismatch");
+    for (tpx, dpx) in tgt.iter_mut().zip(dst.iter()) {
+        *tpx = op_func(*tpx, *dpx);
     }
 
-    let tgt: &mut [ColorARGB<u8>] = transmute(tgt);
-    let src: &[ColorARGB<u8>] = transmute(src);
+    Ok(())
+}
+
+fn apply_inplace_src<T, S, F>(tgt: &mut [T], src: &[S], op_func: F) -> Result<(), Error>
+    where T: Pixel, S: Pixel, F: Fn(ColorARGB<u8>, ColorARGB<u8>) -> ColorARGB<u8>
+{
+    check_source(tgt.len(), src.len())?;
 
-    let op_func = mode.operation();
+    let tgt = T::as_argb_mut(tgt);
+    let src = S::as_argb(src);
 
     for (tpx, spx) in tgt.iter_mut().zip(src.iter()) {
         *tpx = op_func(*spx, *tpx);
@@ -120,4 +354,222 @@ pub unsafe fn porter_duff_inplace_src(tgt: &mut [u32], src: &[u32], mode: Mode)
     Ok(())
 }
 
+/// Composites `src` onto `dst`, writing the result to `tgt`.  Straight
+/// pixels are composited one at a time in floating point, so that colour
+/// survives however transparent they are; only the premultiplied spans,
+/// `porter_duff_premultiplied` and its in-place forms, use the SIMD
+/// kernels.
+pub fn porter_duff<T: Pixel, S: Pixel, D: Pixel>(tgt: &mut [T], src: &[S], dst: &[D], mode: Mode) -> Result<(), Error> {
+    apply(tgt, src, dst, mode.operation())
+}
+
+/// Composites `tgt` onto `dst`, in place of the source.
+pub fn porter_duff_inplace_dst<T: Pixel, D: Pixel>(tgt: &mut [T], dst: &[D], mode: Mode) -> Result<(), Error> {
+    apply_inplace_dst(tgt, dst, mode.operation())
+}
+
+/// Composites `src` onto `tgt`, in place of the destination.
+pub fn porter_duff_inplace_src<T: Pixel, S: Pixel>(tgt: &mut [T], src: &[S], mode: Mode) -> Result<(), Error> {
+    apply_inplace_src(tgt, src, mode.operation())
+}
+
+pub fn porter_duff_blend<T: Pixel, S: Pixel, D: Pixel>(tgt: &mut [T], src: &[S], dst: &[D], mode: Mode, blend: BlendMode) -> Result<(), Error> {
+    apply(tgt, src, dst, |spx, dpx| mode.blend(spx, dpx, blend))
+}
+
+pub fn porter_duff_blend_inplace_dst<T: Pixel, D: Pixel>(tgt: &mut [T], dst: &[D], mode: Mode, blend: BlendMode) -> Result<(), Error> {
+    apply_inplace_dst(tgt, dst, |spx, dpx| mode.blend(spx, dpx, blend))
+}
 
+pub fn porter_duff_blend_inplace_src<T: Pixel, S: Pixel>(tgt: &mut [T], src: &[S], mode: Mode, blend: BlendMode) -> Result<(), Error> {
+    apply_inplace_src(tgt, src, |spx, dpx| mode.blend(spx, dpx, blend))
+}
+
+/// Composites the surface `src` onto `tgt`, which must be the same size.
+pub fn porter_duff_surface(tgt: &mut Surface<ColorARGB<u8>>, src: &Surface<ColorARGB<u8>>, mode: Mode) -> Result<(), Error> {
+    let expected = (tgt.width(), tgt.height());
+    let found = (src.width(), src.height());
+    if expected != found {
+        return Err(Error::SurfaceSize { expected, found });
+    }
+    porter_duff_inplace_src(tgt.pixels_raw_mut(), src.pixels_raw(), mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::colorspace::ColorARGB;
+    use super::{
+        porter_duff, porter_duff_inplace_dst, porter_duff_inplace_src,
+        porter_duff_blend, porter_duff_blend_inplace_dst, porter_duff_blend_inplace_src,
+        porter_duff_surface, BlendMode, Error, Mode,
+    };
+    use super::super::Surface;
+
+    // Half-transparent red drawn onto three-quarters-opaque blue.
+    const SRC: u32 = 0x80FF0000;
+    const DST: u32 = 0xC00000FF;
+
+    const GOLDEN: [(Mode, u32); 13] = [
+        (Mode::Clear, 0x00000000),
+        (Mode::Src, 0x80FF0000),
+        (Mode::Dst, 0xC00000FF),
+        (Mode::SrcOver, 0xE092006D),
+        (Mode::DstOver, 0xE02400DB),
+        (Mode::SrcIn, 0x60FF0000),
+        (Mode::DstIn, 0x600000FF),
+        (Mode::SrcOut, 0x20FF0000),
+        (Mode::DstOut, 0x600000FF),
+        (Mode::SrcAtop, 0xC080007F),
+        (Mode::DstAtop, 0x803F00C0),
+        (Mode::Xor, 0x7F3F00C0),
+        (Mode::Plus, 0xFF8000C0),
+    ];
+
+    #[test]
+    fn operators_match_golden_values() {
+        for &(mode, expected) in GOLDEN.iter() {
+            let mut tgt = [0];
+            porter_duff(&mut tgt, &[SRC], &[DST], mode).unwrap();
+            assert_eq!(tgt[0], expected, "{:?}", mode);
+
+            let mut tgt = [DST];
+            porter_duff_inplace_src(&mut tgt, &[SRC], mode).unwrap();
+            assert_eq!(tgt[0], expected, "{:?} in place of the destination", mode);
+
+            let mut tgt = [SRC];
+            porter_duff_inplace_dst(&mut tgt, &[DST], mode).unwrap();
+            assert_eq!(tgt[0], expected, "{:?} in place of the source", mode);
+        }
+    }
+
+    #[test]
+    fn plus_saturates() {
+        let mut tgt = [0];
+        porter_duff(&mut tgt, &[0xFF808000], &[0xFFC04020], Mode::Plus).unwrap();
+        assert_eq!(tgt[0], 0xFFFFC020);
+    }
+
+    #[test]
+    fn transparent_pixels_stay_transparent() {
+        for &(mode, _) in GOLDEN.iter() {
+            let mut tgt = [0xDEADBEEF];
+            porter_duff(&mut tgt, &[0x00FF0000], &[0x000000FF], mode).unwrap();
+            assert_eq!(tgt[0], 0, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn mismatched_lengths_are_rejected() {
+        let mut tgt = [0; 2];
+        assert_eq!(
+            porter_duff(&mut tgt, &[SRC], &[DST, DST], Mode::SrcOver),
+            Err(Error::SourceLength { expected: 2, found: 1 }));
+        assert_eq!(
+            porter_duff(&mut tgt, &[SRC, SRC], &[DST], Mode::SrcOver),
+            Err(Error::DestinationLength { expected: 2, found: 1 }));
+        assert_eq!(
+            porter_duff_inplace_src(&mut tgt, &[SRC], Mode::SrcOver),
+            Err(Error::SourceLength { expected: 2, found: 1 }));
+        assert_eq!(
+            porter_duff_inplace_dst(&mut tgt, &[DST], Mode::SrcOver),
+            Err(Error::DestinationLength { expected: 2, found: 1 }));
+        assert_eq!(tgt, [0; 2]);
+    }
+
+    #[test]
+    fn packed_and_unpacked_pixels_mix() {
+        let src = [ColorARGB::from_packed_argb(SRC)];
+        let mut tgt = [DST];
+        porter_duff_inplace_src(&mut tgt, &src, Mode::SrcOver).unwrap();
+        assert_eq!(tgt[0], 0xE092006D);
+
+        let mut tgt = [ColorARGB::from_packed_argb(DST)];
+        porter_duff_inplace_src(&mut tgt, &[SRC], Mode::Xor).unwrap();
+        assert_eq!(tgt[0].packed(), 0x7F3F00C0);
+    }
+
+    #[test]
+    fn surfaces_composite_when_the_same_size() {
+        let mut tgt: Surface<ColorARGB<u8>> = Surface::new(16, 16, ColorARGB::from_packed_argb(DST));
+        let src = Surface::new(16, 16, ColorARGB::from_packed_argb(SRC));
+        porter_duff_surface(&mut tgt, &src, Mode::SrcOver).unwrap();
+        assert_eq!(tgt[(15, 15)].packed(), 0xE092006D);
+
+        let small = Surface::new(8, 16, ColorARGB::from_packed_argb(SRC));
+        assert_eq!(
+            porter_duff_surface(&mut tgt, &small, Mode::SrcOver),
+            Err(Error::SurfaceSize { expected: (16, 16), found: (8, 16) }));
+    }
+
+    // Opaque pixels, so that `SrcOver` leaves just the blend function.
+    const OPAQUE_SRC: u32 = 0xFFCC4D8C;
+    const OPAQUE_DST: u32 = 0xFF33B380;
+
+    const BLEND_GOLDEN: [(BlendMode, u32); 16] = [
+        (BlendMode::Normal, 0xFFCC4D8C),
+        (BlendMode::Multiply, 0xFF293646),
+        (BlendMode::Screen, 0xFFD6CAC6),
+        (BlendMode::Overlay, 0xFF52958C),
+        (BlendMode::Darken, 0xFF334D80),
+        (BlendMode::Lighten, 0xFFCCB38C),
+        (BlendMode::ColorDodge, 0xFFFFFFFF),
+        (BlendMode::ColorBurn, 0xFF000318),
+        (BlendMode::HardLight, 0xFFAD6C8C),
+        (BlendMode::SoftLight, 0xFF599E85),
+        (BlendMode::Difference, 0xFF99660C),
+        (BlendMode::Exclusion, 0xFFAD947F),
+        (BlendMode::Hue, 0xFFDA5A99),
+        (BlendMode::Saturation, 0xFF34B380),
+        (BlendMode::Color, 0xFFD95A99),
+        (BlendMode::Luminosity, 0xFF26A673),
+    ];
+
+    #[test]
+    fn blend_modes_match_golden_values() {
+        for &(blend, expected) in BLEND_GOLDEN.iter() {
+            let mut tgt = [0];
+            porter_duff_blend(&mut tgt, &[OPAQUE_SRC], &[OPAQUE_DST], Mode::SrcOver, blend).unwrap();
+            assert_eq!(tgt[0], expected, "{:?}", blend);
+
+            let mut tgt = [OPAQUE_DST];
+            porter_duff_blend_inplace_src(&mut tgt, &[OPAQUE_SRC], Mode::SrcOver, blend).unwrap();
+            assert_eq!(tgt[0], expected, "{:?} in place of the destination", blend);
+
+            let mut tgt = [OPAQUE_SRC];
+            porter_duff_blend_inplace_dst(&mut tgt, &[OPAQUE_DST], Mode::SrcOver, blend).unwrap();
+            assert_eq!(tgt[0], expected, "{:?} in place of the source", blend);
+        }
+    }
+
+    #[test]
+    fn blends_combine_with_operators() {
+        let src = 0x80CC4D8C;
+        let dst = 0xC033B380;
+        let cases = [
+            (Mode::SrcOver, BlendMode::Multiply, 0xE0446F69),
+            (Mode::SrcIn, BlendMode::Multiply, 0x60513C58),
+            (Mode::SrcOver, BlendMode::Screen, 0xE08FAEA0),
+        ];
+        for &(mode, blend, expected) in cases.iter() {
+            let mut tgt = [0];
+            porter_duff_blend(&mut tgt, &[src], &[dst], mode, blend).unwrap();
+            assert_eq!(tgt[0], expected, "{:?} {:?}", mode, blend);
+        }
+
+        // Blending normally is plain compositing.
+        for &(mode, expected) in GOLDEN.iter() {
+            let mut tgt = [0];
+            porter_duff_blend(&mut tgt, &[SRC], &[DST], mode, BlendMode::Normal).unwrap();
+            assert_eq!(tgt[0], expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn blends_in_floating_point() {
+        let src = ColorARGB { a: 1.0, r: 0.5, g: 0.25, b: 1.0 };
+        let dst = ColorARGB { a: 0.5, r: 0.5, g: 1.0, b: 0.0 };
+        let out = Mode::SrcOver.blend_f64(src, dst, BlendMode::Multiply);
+        // Half the source, half the product, since the destination is half there.
+        assert_eq!((out.a, out.r, out.g, out.b), (1.0, 0.375, 0.25, 0.5));
+    }
+}
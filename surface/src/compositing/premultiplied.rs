@@ -0,0 +1,223 @@
+use super::super::colorspace::ColorARGB;
+use super::{check_destination, check_source, Error, Mode};
+use super::simd::{composite_span, Kernel};
+
+/// An ARGB pixel with its colour already multiplied by its alpha, so
+/// that compositing needs no division.  Each channel is at most `a`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PremultipliedARGB {
+    pub a: u8,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// `x * y / 255`, rounded to nearest, without dividing.
+#[inline]
+fn mul_div255(x: u8, y: u8) -> u8 {
+    let t = x as u32 * y as u32 + 128;
+    ((t + (t >> 8)) >> 8) as u8
+}
+
+impl PremultipliedARGB {
+    pub fn from_straight(px: ColorARGB<u8>) -> PremultipliedARGB {
+        PremultipliedARGB {
+            a: px.a,
+            r: mul_div255(px.r, px.a),
+            g: mul_div255(px.g, px.a),
+            b: mul_div255(px.b, px.a),
+        }
+    }
+
+    /// Divides the alpha back out.  Colour is lost from nearly
+    /// transparent pixels, and fully transparent ones come out black.
+    pub fn to_straight(&self) -> ColorARGB<u8> {
+        if self.a == 0 {
+            return ColorARGB::new_argb(0, 0, 0, 0);
+        }
+        let a = self.a as u32;
+        let unmultiply = |c: u8| ::std::cmp::min(255, (c as u32 * 255 + a / 2) / a) as u8;
+        ColorARGB::new_argb(self.a, unmultiply(self.r), unmultiply(self.g), unmultiply(self.b))
+    }
+
+    #[inline]
+    pub fn from_packed(color: u32) -> PremultipliedARGB {
+        PremultipliedARGB {
+            a: (color >> 24) as u8,
+            r: (color >> 16) as u8,
+            g: (color >> 8) as u8,
+            b: color as u8,
+        }
+    }
+
+    #[inline]
+    pub fn packed(&self) -> u32 {
+        (self.a as u32) << 24 | (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+}
+
+impl Mode {
+    /// Composites premultiplied pixels in integer arithmetic, within one
+    /// of what `composite_f64` gives on the same pixels.
+    #[inline]
+    pub fn composite_premultiplied(&self, src: PremultipliedARGB, dst: PremultipliedARGB) -> PremultipliedARGB {
+        let (fa, fb) = self.factor_kinds();
+        let (fa, fb) = (fa.value_u8(src.a, dst.a), fb.value_u8(src.a, dst.a));
+        let channel = |sc: u8, dc: u8| mul_div255(sc, fa).saturating_add(mul_div255(dc, fb));
+        PremultipliedARGB {
+            a: channel(src.a, dst.a),
+            r: channel(src.r, dst.r),
+            g: channel(src.g, dst.g),
+            b: channel(src.b, dst.b),
+        }
+    }
+}
+
+/// Premultiplies packed straight ARGB pixels in place.
+pub fn premultiply(pixels: &mut [u32]) {
+    for px in pixels.iter_mut() {
+        *px = PremultipliedARGB::from_straight(ColorARGB::from_packed_argb(*px)).packed();
+    }
+}
+
+/// Turns packed premultiplied pixels back into straight ARGB in place.
+pub fn unpremultiply(pixels: &mut [u32]) {
+    for px in pixels.iter_mut() {
+        *px = PremultipliedARGB::from_packed(*px).to_straight().packed();
+    }
+}
+
+/// Composites spans of packed premultiplied pixels, several at a time
+/// with the widest `Kernel` the CPU runs.
+pub fn porter_duff_premultiplied(tgt: &mut [u32], src: &[u32], dst: &[u32], mode: Mode) -> Result<(), Error> {
+    check_source(tgt.len(), src.len())?;
+    check_destination(tgt.len(), dst.len())?;
+
+    unsafe { composite_span(Kernel::detect(), mode, tgt.as_mut_ptr(), src.as_ptr(), dst.as_ptr(), tgt.len()) };
+    Ok(())
+}
+
+pub fn porter_duff_premultiplied_inplace_dst(tgt: &mut [u32], dst: &[u32], mode: Mode) -> Result<(), Error> {
+    check_destination(tgt.len(), dst.len())?;
+
+    let out = tgt.as_mut_ptr();
+    unsafe { composite_span(Kernel::detect(), mode, out, out, dst.as_ptr(), tgt.len()) };
+    Ok(())
+}
+
+pub fn porter_duff_premultiplied_inplace_src(tgt: &mut [u32], src: &[u32], mode: Mode) -> Result<(), Error> {
+    check_source(tgt.len(), src.len())?;
+
+    let out = tgt.as_mut_ptr();
+    unsafe { composite_span(Kernel::detect(), mode, out, src.as_ptr(), out, tgt.len()) };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::colorspace::ColorARGB;
+    use super::super::Mode;
+    use super::{
+        mul_div255, porter_duff_premultiplied, porter_duff_premultiplied_inplace_src,
+        premultiply, unpremultiply, PremultipliedARGB,
+    };
+
+    const MODES: [Mode; 13] = [
+        Mode::Clear, Mode::Src, Mode::Dst, Mode::SrcOver, Mode::DstOver,
+        Mode::SrcIn, Mode::DstIn, Mode::SrcOut, Mode::DstOut,
+        Mode::SrcAtop, Mode::DstAtop, Mode::Xor, Mode::Plus,
+    ];
+
+    #[test]
+    fn mul_div255_rounds_exactly() {
+        for x in 0..256u32 {
+            for y in 0..256u32 {
+                let expected = (x as f64 * y as f64 / 255.0).round() as u8;
+                assert_eq!(mul_div255(x as u8, y as u8), expected, "{} * {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn conversions_match_float() {
+        for a in 0..256u32 {
+            for c in 0..256u32 {
+                let straight = ColorARGB::new_argb(a as u8, c as u8, c as u8, c as u8);
+                let px = PremultipliedARGB::from_straight(straight);
+                assert_eq!(px.r as f64, (c as f64 * a as f64 / 255.0).round(), "{} at {}", c, a);
+
+                if c <= a && a > 0 {
+                    let px = PremultipliedARGB { a: a as u8, r: c as u8, g: c as u8, b: c as u8 };
+                    let expected = c as f64 * 255.0 / a as f64;
+                    assert!((px.to_straight().r as f64 - expected).abs() <= 0.5, "{} at {}", c, a);
+                }
+            }
+            // Opaque pixels go through unchanged.
+            let opaque = 0xFF000000 | (a << 16) | ((255 - a) << 8) | (a / 2);
+            let mut pixels = [opaque];
+            premultiply(&mut pixels);
+            assert_eq!(pixels[0], opaque);
+            unpremultiply(&mut pixels);
+            assert_eq!(pixels[0], opaque);
+        }
+    }
+
+    #[test]
+    fn operators_match_float_reference() {
+        for &mode in MODES.iter() {
+            for sa in 0..256u32 {
+                for da in 0..256u32 {
+                    // A channel at each extreme and one between, which
+                    // stays within the alpha it's premultiplied by.
+                    let src = PremultipliedARGB { a: sa as u8, r: sa as u8, g: (sa / 2) as u8, b: 0 };
+                    let dst = PremultipliedARGB { a: da as u8, r: (da / 3) as u8, g: da as u8, b: 0 };
+                    let out = mode.composite_premultiplied(src, dst);
+
+                    let (fa, fb) = mode.factors(sa as f64 / 255.0, da as f64 / 255.0);
+                    let reference = |sc: u8, dc: u8| (sc as f64 * fa + dc as f64 * fb).min(255.0);
+                    for &(got, sc, dc) in [
+                        (out.a, src.a, dst.a),
+                        (out.r, src.r, dst.r),
+                        (out.g, src.g, dst.g),
+                        (out.b, src.b, dst.b),
+                    ].iter() {
+                        let expected = reference(sc, dc);
+                        assert!(
+                            (got as f64 - expected).abs() <= 1.0,
+                            "{:?}: {:?} onto {:?} gave {}, expected {}", mode, src, dst, got, expected);
+                    }
+                    assert!(out.r <= out.a && out.g <= out.a && out.b <= out.a, "{:?}: {:?}", mode, out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spans_agree_with_the_float_path() {
+        // Opaque destinations, as in a window's buffer, for which the two
+        // paths differ only in rounding.
+        let src = [0x80FF0000, 0x00123456, 0xFF00FF00, 0x4020C040];
+        let dst = [0xFF0000FF, 0xFF808080, 0xFF102030, 0xFFFFFFFF];
+        for &mode in MODES.iter() {
+            let mut expected = [0; 4];
+            super::super::porter_duff(&mut expected, &src, &dst, mode).unwrap();
+
+            let mut premultiplied = src;
+            premultiply(&mut premultiplied);
+            let mut tgt = dst;
+            porter_duff_premultiplied_inplace_src(&mut tgt, &premultiplied, mode).unwrap();
+            let mut out = [0; 4];
+            porter_duff_premultiplied(&mut out, &premultiplied, &dst, mode).unwrap();
+            assert_eq!(out, tgt);
+            unpremultiply(&mut tgt);
+
+            for (&got, &expected) in tgt.iter().zip(expected.iter()) {
+                for shift in [0, 8, 16, 24].iter() {
+                    let got = (got >> shift & 0xFF) as i32;
+                    let expected = (expected >> shift & 0xFF) as i32;
+                    assert!((got - expected).abs() <= 1, "{:?}: {} vs {}", mode, got, expected);
+                }
+            }
+        }
+    }
+}
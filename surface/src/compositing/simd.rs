@@ -0,0 +1,420 @@
+use super::{Factor, Mode};
+use super::premultiplied::PremultipliedARGB;
+
+/// A way of compositing spans of premultiplied pixels.  Every kernel
+/// gives exactly the same pixels; they differ only in how many they
+/// take at a time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kernel {
+    /// One pixel at a time.
+    Scalar,
+    /// Four pixels at a time in plain Rust, shaped for the compiler to
+    /// vectorize, as it does with NEON.
+    Portable,
+    /// Four pixels at a time, on any x86_64.
+    Sse2,
+    /// Eight pixels at a time.
+    Avx2,
+}
+
+impl Kernel {
+    /// The widest kernel this CPU runs.
+    pub fn detect() -> Kernel {
+        if Kernel::Avx2.is_available() {
+            Kernel::Avx2
+        } else if Kernel::Sse2.is_available() {
+            Kernel::Sse2
+        } else {
+            Kernel::Portable
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        match *self {
+            Kernel::Scalar | Kernel::Portable => true,
+            Kernel::Sse2 => cfg!(target_arch = "x86_64"),
+            Kernel::Avx2 => avx2_detected(),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn avx2_detected() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn avx2_detected() -> bool {
+    false
+}
+
+/// Composites `len` premultiplied pixels of `src` onto `dst`, writing
+/// them to `out`, which may be the same span as either.
+///
+/// # Safety
+///
+/// Each pointer must be valid for `len` pixels, and `kernel` available.
+pub unsafe fn composite_span(kernel: Kernel, mode: Mode, out: *mut u32, src: *const u32, dst: *const u32, len: usize) {
+    let done = match kernel {
+        Kernel::Scalar => 0,
+        Kernel::Portable => portable_span(mode, out, src, dst, len),
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Sse2 => x86::sse2_span(mode, out, src, dst, len),
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => x86::avx2_span(mode, out, src, dst, len),
+        #[cfg(not(target_arch = "x86_64"))]
+        Kernel::Sse2 | Kernel::Avx2 => unreachable!("{:?} isn't available", kernel),
+    };
+    scalar_span(mode, out.add(done), src.add(done), dst.add(done), len - done);
+}
+
+unsafe fn scalar_span(mode: Mode, out: *mut u32, src: *const u32, dst: *const u32, len: usize) {
+    for i in 0..len {
+        let spx = PremultipliedARGB::from_packed(*src.add(i));
+        let dpx = PremultipliedARGB::from_packed(*dst.add(i));
+        *out.add(i) = mode.composite_premultiplied(spx, dpx).packed();
+    }
+}
+
+/// A factor as bitmasks, so that choosing it is arithmetic rather than
+/// a branch: `((sa & src) | (da & dst) | constant) ^ invert`.
+#[derive(Copy, Clone)]
+struct FactorMasks {
+    src: u16,
+    dst: u16,
+    constant: u16,
+    invert: u16,
+}
+
+impl FactorMasks {
+    fn new(factor: Factor) -> FactorMasks {
+        let (src, dst, constant, invert) = match factor {
+            Factor::Zero => (0, 0, 0, 0),
+            Factor::One => (0, 0, 0xFF, 0),
+            Factor::SrcAlpha => (0xFF, 0, 0, 0),
+            Factor::DstAlpha => (0, 0xFF, 0, 0),
+            Factor::InvSrcAlpha => (0xFF, 0, 0, 0xFF),
+            Factor::InvDstAlpha => (0, 0xFF, 0, 0xFF),
+        };
+        FactorMasks { src, dst, constant, invert }
+    }
+
+    #[inline(always)]
+    fn select(&self, sa: u16, da: u16) -> u16 {
+        ((sa & self.src) | (da & self.dst) | self.constant) ^ self.invert
+    }
+}
+
+/// Composites whole runs of four pixels, returning how many it did.
+unsafe fn portable_span(mode: Mode, out: *mut u32, src: *const u32, dst: *const u32, len: usize) -> usize {
+    const LANES: usize = 4;
+    let (fa, fb) = mode.factor_kinds();
+    let (fa, fb) = (FactorMasks::new(fa), FactorMasks::new(fb));
+
+    let chunks = len / LANES;
+    for chunk in 0..chunks {
+        let offset = chunk * LANES;
+
+        // Every channel of every pixel side by side, beside its pixel's
+        // alpha, so that the arithmetic is one loop over sixteen lanes.
+        let mut sc = [0u16; LANES * 4];
+        let mut dc = [0u16; LANES * 4];
+        let mut sa = [0u16; LANES * 4];
+        let mut da = [0u16; LANES * 4];
+        for lane in 0..LANES {
+            let spx = (*src.add(offset + lane)).to_le_bytes();
+            let dpx = (*dst.add(offset + lane)).to_le_bytes();
+            for channel in 0..4 {
+                let i = lane * 4 + channel;
+                sc[i] = spx[channel] as u16;
+                dc[i] = dpx[channel] as u16;
+                sa[i] = spx[3] as u16;
+                da[i] = dpx[3] as u16;
+            }
+        }
+
+        let mut channels = [0u16; LANES * 4];
+        for i in 0..LANES * 4 {
+            let s = sc[i] * fa.select(sa[i], da[i]) + 128;
+            let d = dc[i] * fb.select(sa[i], da[i]) + 128;
+            let sum = ((s + (s >> 8)) >> 8) + ((d + (d >> 8)) >> 8);
+            channels[i] = if sum > 255 { 255 } else { sum };
+        }
+
+        for lane in 0..LANES {
+            let c = &channels[lane * 4..][..4];
+            *out.add(offset + lane) = u32::from_le_bytes([c[0] as u8, c[1] as u8, c[2] as u8, c[3] as u8]);
+        }
+    }
+    chunks * LANES
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::super::{Factor, Mode};
+    use super::FactorMasks;
+
+    // Pixels are packed ARGB, so each one's alpha is its top byte, and
+    // `x * y / 255` is rounded as `(t + (t >> 8)) >> 8` with
+    // `t = x * y + 128`, which fits in sixteen bits.
+
+    #[inline(always)]
+    unsafe fn broadcast_alpha(px: __m128i) -> __m128i {
+        let a = _mm_srli_epi32(px, 24);
+        let a = _mm_or_si128(a, _mm_slli_epi32(a, 8));
+        _mm_or_si128(a, _mm_slli_epi32(a, 16))
+    }
+
+    /// `FactorMasks`, in every byte of a register.
+    #[derive(Copy, Clone)]
+    struct Masks {
+        src: __m128i,
+        dst: __m128i,
+        constant: __m128i,
+        invert: __m128i,
+    }
+
+    #[inline(always)]
+    unsafe fn masks(factor: Factor) -> Masks {
+        let masks = FactorMasks::new(factor);
+        Masks {
+            src: _mm_set1_epi8(masks.src as i8),
+            dst: _mm_set1_epi8(masks.dst as i8),
+            constant: _mm_set1_epi8(masks.constant as i8),
+            invert: _mm_set1_epi8(masks.invert as i8),
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn select(masks: &Masks, sa: __m128i, da: __m128i) -> __m128i {
+        let f = _mm_or_si128(_mm_and_si128(sa, masks.src), _mm_and_si128(da, masks.dst));
+        _mm_xor_si128(_mm_or_si128(f, masks.constant), masks.invert)
+    }
+
+    #[inline(always)]
+    unsafe fn mul_div255(x: __m128i, y: __m128i) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let bias = _mm_set1_epi16(128);
+        let lo = _mm_add_epi16(_mm_mullo_epi16(_mm_unpacklo_epi8(x, zero), _mm_unpacklo_epi8(y, zero)), bias);
+        let hi = _mm_add_epi16(_mm_mullo_epi16(_mm_unpackhi_epi8(x, zero), _mm_unpackhi_epi8(y, zero)), bias);
+        let lo = _mm_srli_epi16(_mm_add_epi16(lo, _mm_srli_epi16(lo, 8)), 8);
+        let hi = _mm_srli_epi16(_mm_add_epi16(hi, _mm_srli_epi16(hi, 8)), 8);
+        _mm_packus_epi16(lo, hi)
+    }
+
+    pub unsafe fn sse2_span(mode: Mode, out: *mut u32, src: *const u32, dst: *const u32, len: usize) -> usize {
+        let (fa, fb) = mode.factor_kinds();
+        let (fa, fb) = (masks(fa), masks(fb));
+
+        let chunks = len / 4;
+        for chunk in 0..chunks {
+            let offset = chunk * 4;
+            let s = _mm_loadu_si128(src.add(offset) as *const __m128i);
+            let d = _mm_loadu_si128(dst.add(offset) as *const __m128i);
+            let (sa, da) = (broadcast_alpha(s), broadcast_alpha(d));
+
+            let s = mul_div255(s, select(&fa, sa, da));
+            let d = mul_div255(d, select(&fb, sa, da));
+            _mm_storeu_si128(out.add(offset) as *mut __m128i, _mm_adds_epu8(s, d));
+        }
+        chunks * 4
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn broadcast_alpha_avx2(px: __m256i) -> __m256i {
+        let alphas = _mm256_setr_epi8(
+            3, 3, 3, 3, 7, 7, 7, 7, 11, 11, 11, 11, 15, 15, 15, 15,
+            3, 3, 3, 3, 7, 7, 7, 7, 11, 11, 11, 11, 15, 15, 15, 15);
+        _mm256_shuffle_epi8(px, alphas)
+    }
+
+    #[derive(Copy, Clone)]
+    struct MasksAvx2 {
+        src: __m256i,
+        dst: __m256i,
+        constant: __m256i,
+        invert: __m256i,
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn masks_avx2(factor: Factor) -> MasksAvx2 {
+        let masks = FactorMasks::new(factor);
+        MasksAvx2 {
+            src: _mm256_set1_epi8(masks.src as i8),
+            dst: _mm256_set1_epi8(masks.dst as i8),
+            constant: _mm256_set1_epi8(masks.constant as i8),
+            invert: _mm256_set1_epi8(masks.invert as i8),
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn select_avx2(masks: &MasksAvx2, sa: __m256i, da: __m256i) -> __m256i {
+        let f = _mm256_or_si256(_mm256_and_si256(sa, masks.src), _mm256_and_si256(da, masks.dst));
+        _mm256_xor_si256(_mm256_or_si256(f, masks.constant), masks.invert)
+    }
+
+    /// As `mul_div255`.  Unpacking and packing both work within each
+    /// 128-bit half, so the pixels come back in order.
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn mul_div255_avx2(x: __m256i, y: __m256i) -> __m256i {
+        let zero = _mm256_setzero_si256();
+        let bias = _mm256_set1_epi16(128);
+        let lo = _mm256_add_epi16(_mm256_mullo_epi16(_mm256_unpacklo_epi8(x, zero), _mm256_unpacklo_epi8(y, zero)), bias);
+        let hi = _mm256_add_epi16(_mm256_mullo_epi16(_mm256_unpackhi_epi8(x, zero), _mm256_unpackhi_epi8(y, zero)), bias);
+        let lo = _mm256_srli_epi16(_mm256_add_epi16(lo, _mm256_srli_epi16(lo, 8)), 8);
+        let hi = _mm256_srli_epi16(_mm256_add_epi16(hi, _mm256_srli_epi16(hi, 8)), 8);
+        _mm256_packus_epi16(lo, hi)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn avx2_span(mode: Mode, out: *mut u32, src: *const u32, dst: *const u32, len: usize) -> usize {
+        let (fa, fb) = mode.factor_kinds();
+        let (fa, fb) = (masks_avx2(fa), masks_avx2(fb));
+
+        let chunks = len / 8;
+        for chunk in 0..chunks {
+            let offset = chunk * 8;
+            let s = _mm256_loadu_si256(src.add(offset) as *const __m256i);
+            let d = _mm256_loadu_si256(dst.add(offset) as *const __m256i);
+            let (sa, da) = (broadcast_alpha_avx2(s), broadcast_alpha_avx2(d));
+
+            let s = mul_div255_avx2(s, select_avx2(&fa, sa, da));
+            let d = mul_div255_avx2(d, select_avx2(&fb, sa, da));
+            _mm256_storeu_si256(out.add(offset) as *mut __m256i, _mm256_adds_epu8(s, d));
+        }
+        chunks * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{porter_duff, porter_duff_inplace_dst, porter_duff_inplace_src, premultiply, Mode};
+    use super::{composite_span, Kernel};
+    use test::Bencher;
+
+    const KERNELS: [Kernel; 4] = [Kernel::Scalar, Kernel::Portable, Kernel::Sse2, Kernel::Avx2];
+
+    const MODES: [Mode; 13] = [
+        Mode::Clear, Mode::Src, Mode::Dst, Mode::SrcOver, Mode::DstOver,
+        Mode::SrcIn, Mode::DstIn, Mode::SrcOut, Mode::DstOut,
+        Mode::SrcAtop, Mode::DstAtop, Mode::Xor, Mode::Plus,
+    ];
+
+    /// Premultiplied pixels from a fixed xorshift sequence.
+    fn pixels(len: usize, mut seed: u32) -> Vec<u32> {
+        let mut out: Vec<u32> = (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        }).collect();
+        premultiply(&mut out);
+        out
+    }
+
+    #[test]
+    fn kernels_agree_with_scalar() {
+        // Enough pixels for every kernel's tail to be some length.
+        let src = pixels(67, 0x1234_5678);
+        let dst = pixels(67, 0x9ABC_DEF0);
+        for &mode in MODES.iter() {
+            let mut expected = vec![0; src.len()];
+            unsafe {
+                composite_span(Kernel::Scalar, mode, expected.as_mut_ptr(), src.as_ptr(), dst.as_ptr(), src.len())
+            };
+
+            for &kernel in KERNELS.iter().filter(|kernel| kernel.is_available()) {
+                let mut out = vec![0; src.len()];
+                unsafe { composite_span(kernel, mode, out.as_mut_ptr(), src.as_ptr(), dst.as_ptr(), src.len()) };
+                assert_eq!(out, expected, "{:?} {:?}", kernel, mode);
+
+                let mut onto = dst.clone();
+                let ptr = onto.as_mut_ptr();
+                unsafe { composite_span(kernel, mode, ptr, src.as_ptr(), ptr, src.len()) };
+                assert_eq!(onto, expected, "{:?} {:?} onto the destination", kernel, mode);
+
+                let mut under = src.clone();
+                let ptr = under.as_mut_ptr();
+                unsafe { composite_span(kernel, mode, ptr, ptr, dst.as_ptr(), src.len()) };
+                assert_eq!(under, expected, "{:?} {:?} under the source", kernel, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn detected_kernel_is_available() {
+        assert!(Kernel::detect().is_available());
+        assert!(Kernel::Scalar.is_available());
+    }
+
+    fn bench_kernel(b: &mut Bencher, kernel: Kernel) {
+        if !kernel.is_available() {
+            return;
+        }
+        // A full-window overlay, as the pause screen is.
+        let src = pixels(512 * 512, 0x1234_5678);
+        let mut tgt = pixels(512 * 512, 0x9ABC_DEF0);
+        b.iter(|| {
+            let ptr = tgt.as_mut_ptr();
+            unsafe { composite_span(kernel, Mode::SrcOver, ptr, src.as_ptr(), ptr, src.len()) };
+            ::test::black_box(&mut tgt);
+        });
+    }
+
+    #[bench]
+    fn bench_over_512x512_scalar(b: &mut Bencher) {
+        bench_kernel(b, Kernel::Scalar);
+    }
+
+    #[bench]
+    fn bench_over_512x512_portable(b: &mut Bencher) {
+        bench_kernel(b, Kernel::Portable);
+    }
+
+    #[bench]
+    fn bench_over_512x512_sse2(b: &mut Bencher) {
+        bench_kernel(b, Kernel::Sse2);
+    }
+
+    #[bench]
+    fn bench_over_512x512_avx2(b: &mut Bencher) {
+        bench_kernel(b, Kernel::Avx2);
+    }
+
+    #[bench]
+    fn bench_over_512x512_float(b: &mut Bencher) {
+        let src = pixels(512 * 512, 0x1234_5678);
+        let dst = pixels(512 * 512, 0x9ABC_DEF0);
+        let mut tgt = vec![0; src.len()];
+        b.iter(|| {
+            porter_duff(&mut tgt, &src, &dst, Mode::SrcOver).unwrap();
+            ::test::black_box(&mut tgt);
+        });
+    }
+
+    #[bench]
+    fn bench_over_512x512_float_inplace_src(b: &mut Bencher) {
+        let src = pixels(512 * 512, 0x1234_5678);
+        let mut tgt = pixels(512 * 512, 0x9ABC_DEF0);
+        b.iter(|| {
+            porter_duff_inplace_src(&mut tgt, &src, Mode::SrcOver).unwrap();
+            ::test::black_box(&mut tgt);
+        });
+    }
+
+    #[bench]
+    fn bench_over_512x512_float_inplace_dst(b: &mut Bencher) {
+        let dst = pixels(512 * 512, 0x9ABC_DEF0);
+        let mut tgt = pixels(512 * 512, 0x1234_5678);
+        b.iter(|| {
+            porter_duff_inplace_dst(&mut tgt, &dst, Mode::SrcOver).unwrap();
+            ::test::black_box(&mut tgt);
+        });
+    }
+}
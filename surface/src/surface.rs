@@ -175,6 +175,10 @@ impl<CS> Surface<CS> where CS: Colorspace {
         &self.buffer
     }
 
+    pub fn pixels_raw_mut(&mut self) -> &mut [CS] {
+        &mut self.buffer
+    }
+
     pub fn pixels(&self) -> Vec<CS> {
         let mut out = Vec::with_capacity(self.rect.width * self.rect.height);
         for x in 0..self.rect.width {